@@ -0,0 +1,355 @@
+//! `#[derive(Serializable, Deserializable)]` for CarapaceDB's binary serialization.
+//!
+//! Structs with named fields are serialized as tagged objects, one field per struct
+//! field. Every field needs a stable id, `#[serialize(id = N)]`, that must never change
+//! or be reused once written to a database file. Further field attributes:
+//!
+//! - `#[serialize(default)]`: the field is omitted when it equals `Default::default()`
+//!   and read as the default when missing, so it can be added to an existing struct
+//! - `#[serialize(skip)]`: the field is not serialized and deserialized as the default
+//!
+//! Fields of type `Option<T>` are omitted when `None` and read as `None` when missing.
+//!
+//! Fieldless `#[repr(u8)]` enums are serialized as their discriminant, which is
+//! validated on deserialization. Enums with fields are serialized as a `u16` variant id
+//! followed by the variant's fields as a tagged object; every variant needs a stable
+//! `#[serialize(id = N)]`, and the fields of non-unit variants take the same attributes as
+//! struct fields.
+//!
+//! Every type parameter of a generic type must itself be `Serializable` or
+//! `Deserializable` respectively.
+
+use proc_macro::TokenStream;
+use proc_macro2::TokenStream as TokenStream2;
+use quote::{format_ident, quote, ToTokens};
+use syn::{parse_macro_input, parse_quote, Attribute, Data, DeriveInput, Fields, Generics, Ident, Index, LitInt, Member, Type};
+
+#[proc_macro_derive(Serializable, attributes(serialize))]
+pub fn derive_serializable(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    expand_serializable(&input).unwrap_or_else(syn::Error::into_compile_error).into()
+}
+
+#[proc_macro_derive(Deserializable, attributes(serialize))]
+pub fn derive_deserializable(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    expand_deserializable(&input).unwrap_or_else(syn::Error::into_compile_error).into()
+}
+
+/// How a struct field is serialized
+enum FieldKind {
+    Required,
+    Optional,
+    Default,
+    Skip,
+}
+
+struct FieldInfo {
+    /// The field as accessed on a struct, e.g. `name` or `0`
+    member: Member,
+    /// The variable the field is bound to when matching an enum variant
+    binding: Ident,
+    id: Option<u16>,
+    kind: FieldKind,
+}
+
+impl FieldInfo {
+    fn label(&self) -> String {
+        match &self.member {
+            Member::Named(ident) => ident.to_string(),
+            Member::Unnamed(index) => index.index.to_string(),
+        }
+    }
+}
+
+fn is_option(ty: &Type) -> bool {
+    match ty {
+        Type::Path(path) => path.qself.is_none()
+            && path.path.segments.last().is_some_and(|segment| segment.ident == "Option"),
+        _ => false,
+    }
+}
+
+/// Parses `#[serialize(id = N)]`, returning `None` if the attribute is missing
+fn parse_id(attrs: &[Attribute], allow_kinds: bool, mut kind: impl FnMut(FieldKind)) -> syn::Result<Option<u16>> {
+    let mut id = None;
+    for attr in attrs.iter().filter(|attr| attr.path().is_ident("serialize")) {
+        attr.parse_nested_meta(|meta| {
+            if meta.path.is_ident("id") {
+                let value: LitInt = meta.value()?.parse()?;
+                let value: u16 = value.base10_parse()?;
+                if value == u16::MAX {
+                    return Err(meta.error("field id 65535 is reserved for the end of an object"));
+                }
+                id = Some(value);
+                Ok(())
+            } else if allow_kinds && meta.path.is_ident("default") {
+                kind(FieldKind::Default);
+                Ok(())
+            } else if allow_kinds && meta.path.is_ident("skip") {
+                kind(FieldKind::Skip);
+                Ok(())
+            } else if allow_kinds {
+                Err(meta.error("expected `id = ...`, `default` or `skip`"))
+            } else {
+                Err(meta.error("expected `id = ...`"))
+            }
+        })?;
+    }
+    Ok(id)
+}
+
+/// The fields of a struct or enum variant, which are serialized as a tagged object
+fn object_fields(fields: &Fields) -> syn::Result<Vec<FieldInfo>> {
+    let mut infos: Vec<FieldInfo> = Vec::new();
+    for (index, field) in fields.iter().enumerate() {
+        let member = match &field.ident {
+            Some(ident) => Member::Named(ident.clone()),
+            None => Member::Unnamed(Index::from(index)),
+        };
+        let binding = format_ident!("field_{}", index);
+        let mut kind = if is_option(&field.ty) { FieldKind::Optional } else { FieldKind::Required };
+        let id = parse_id(&field.attrs, true, |parsed| kind = parsed)?;
+
+        if !matches!(kind, FieldKind::Skip) {
+            let Some(field_id) = id else {
+                return Err(syn::Error::new_spanned(field, "missing `#[serialize(id = ...)]`"));
+            };
+            if let Some(other) = infos.iter().find(|other| other.id == Some(field_id)) {
+                return Err(syn::Error::new_spanned(
+                    field,
+                    format!("field id {} is already used by `{}`", field_id, other.label())
+                ));
+            }
+        }
+        infos.push(FieldInfo { member, binding, id, kind });
+    }
+    Ok(infos)
+}
+
+fn struct_fields(input: &DeriveInput) -> syn::Result<Vec<FieldInfo>> {
+    let Data::Struct(data) = &input.data else {
+        unreachable!();
+    };
+    if !matches!(data.fields, Fields::Named(_)) {
+        return Err(syn::Error::new_spanned(input, "only structs with named fields can be serialized"));
+    }
+    object_fields(&data.fields)
+}
+
+/// Whether the enum has a variant with fields, and so is serialized by variant id
+/// rather than as a `#[repr(u8)]` discriminant
+fn has_variant_fields(input: &DeriveInput) -> bool {
+    let Data::Enum(data) = &input.data else {
+        unreachable!();
+    };
+    data.variants.iter().any(|variant| !matches!(variant.fields, Fields::Unit))
+}
+
+/// The variants of a fieldless `#[repr(u8)]` enum
+fn enum_variants(input: &DeriveInput) -> syn::Result<Vec<&Ident>> {
+    let Data::Enum(data) = &input.data else {
+        unreachable!();
+    };
+    let mut repr_u8 = false;
+    for attr in input.attrs.iter().filter(|attr| attr.path().is_ident("repr")) {
+        attr.parse_nested_meta(|meta| {
+            repr_u8 |= meta.path.is_ident("u8");
+            if meta.input.peek(syn::token::Paren) {
+                // e.g. `align(8)`
+                let _content;
+                syn::parenthesized!(_content in meta.input);
+            }
+            Ok(())
+        })?;
+    }
+    if !repr_u8 {
+        return Err(syn::Error::new_spanned(input, "only `#[repr(u8)]` enums can be serialized"));
+    }
+    Ok(data.variants.iter().map(|variant| &variant.ident).collect())
+}
+
+struct VariantInfo<'a> {
+    ident: &'a Ident,
+    id: u16,
+    fields: &'a Fields,
+    infos: Vec<FieldInfo>,
+}
+
+/// The variants of an enum with fields, each with a stable `#[serialize(id = N)]`
+fn data_variants(input: &DeriveInput) -> syn::Result<Vec<VariantInfo<'_>>> {
+    let Data::Enum(data) = &input.data else {
+        unreachable!();
+    };
+    let mut variants: Vec<VariantInfo<'_>> = Vec::new();
+    for variant in &data.variants {
+        let Some(id) = parse_id(&variant.attrs, false, |_| ())? else {
+            return Err(syn::Error::new_spanned(variant, "missing `#[serialize(id = ...)]` on an enum with fields"));
+        };
+        if let Some(other) = variants.iter().find(|other| other.id == id) {
+            return Err(syn::Error::new_spanned(
+                variant,
+                format!("variant id {} is already used by `{}`", id, other.ident)
+            ));
+        }
+        let infos = object_fields(&variant.fields)?;
+        variants.push(VariantInfo { ident: &variant.ident, id, fields: &variant.fields, infos });
+    }
+    Ok(variants)
+}
+
+/// The generics of `input` with `bound` added to every type parameter
+fn bounded_generics(input: &DeriveInput, bound: TokenStream2) -> Generics {
+    let mut generics = input.generics.clone();
+    let params: Vec<Ident> = generics.type_params().map(|param| param.ident.clone()).collect();
+    let where_clause = generics.make_where_clause();
+    for param in params {
+        where_clause.predicates.push(parse_quote! { #param: #bound });
+    }
+    generics
+}
+
+/// Writes `fields` as a tagged object, each accessed through `access`
+fn write_object(fields: &[FieldInfo], access: impl Fn(&FieldInfo) -> TokenStream2) -> TokenStream2 {
+    let writes = fields.iter().filter_map(|field| {
+        let label = field.label();
+        let id = field.id?;
+        let value = access(field);
+        Some(match field.kind {
+            FieldKind::Required => quote! { serializer.write_field(#id, #label, #value)?; },
+            FieldKind::Optional => quote! { serializer.write_optional_field(#id, #label, #value)?; },
+            FieldKind::Default => quote! { serializer.write_field_with_default(#id, #label, #value)?; },
+            FieldKind::Skip => return None,
+        })
+    });
+    quote! {
+        serializer.begin_object()?;
+        #(#writes)*
+        serializer.end_object()
+    }
+}
+
+/// Reads a tagged object written by `write_object` into `constructor { ... }`
+fn read_object(constructor: TokenStream2, fields: &[FieldInfo]) -> TokenStream2 {
+    let reads = fields.iter().map(|field| {
+        let member = &field.member;
+        let label = field.label();
+        let id = field.id.unwrap_or_default();
+        match field.kind {
+            FieldKind::Required => quote! { #member: object.read_required_field(#id, #label)? },
+            FieldKind::Optional => quote! { #member: object.read_field(#id, #label)? },
+            FieldKind::Default => quote! { #member: object.read_field_or_default(#id, #label)? },
+            FieldKind::Skip => quote! { #member: ::std::default::Default::default() },
+        }
+    });
+    quote! {
+        use ::carapacedb::common::serializer::ObjectReader as _;
+        let mut object = deserializer.read_object()?;
+        Ok(#constructor { #(#reads,)* })
+    }
+}
+
+fn expand_serializable(input: &DeriveInput) -> syn::Result<TokenStream2> {
+    let name = &input.ident;
+    let generics = bounded_generics(input, quote! { ::carapacedb::common::serializer::Serializable });
+    let (impl_generics, ty_generics, where_clause) = generics.split_for_impl();
+    let body = match &input.data {
+        Data::Struct(_) => {
+            let fields = struct_fields(input)?;
+            write_object(&fields, |field| {
+                let member = &field.member;
+                quote! { &self.#member }
+            })
+        }
+        Data::Enum(_) if has_variant_fields(input) => {
+            let arms = data_variants(input)?.into_iter().map(|variant| {
+                let ident = variant.ident;
+                let id = variant.id;
+                let patterns = variant.infos.iter().map(|field| {
+                    let member = &field.member;
+                    let binding = &field.binding;
+                    match field.kind {
+                        FieldKind::Skip => quote! { #member: _ },
+                        _ => quote! { #member: #binding },
+                    }
+                });
+                let write = match variant.fields {
+                    Fields::Unit => quote! { Ok(()) },
+                    _ => write_object(&variant.infos, |field| field.binding.to_token_stream()),
+                };
+                quote! {
+                    #name::#ident { #(#patterns,)* } => {
+                        serializer.write::<u16>(#id)?;
+                        #write
+                    }
+                }
+            });
+            quote! {
+                match self {
+                    #(#arms)*
+                }
+            }
+        }
+        Data::Enum(_) => {
+            let variants = enum_variants(input)?;
+            quote! {
+                let value: u8 = match self {
+                    #(#name::#variants => #name::#variants as u8,)*
+                };
+                serializer.write::<u8>(value)
+            }
+        }
+        Data::Union(_) => return Err(syn::Error::new_spanned(input, "unions cannot be serialized")),
+    };
+    Ok(quote! {
+        impl #impl_generics ::carapacedb::common::serializer::Serializable for #name #ty_generics #where_clause {
+            fn serialize<S: ::carapacedb::common::serializer::Serializer>(&self, serializer: &mut S) -> ::std::io::Result<()> {
+                #body
+            }
+        }
+    })
+}
+
+fn expand_deserializable(input: &DeriveInput) -> syn::Result<TokenStream2> {
+    let name = &input.ident;
+    let generics = bounded_generics(input, quote! { ::carapacedb::common::serializer::Deserializable });
+    let (impl_generics, ty_generics, where_clause) = generics.split_for_impl();
+    let body = match &input.data {
+        Data::Struct(_) => read_object(quote! { #name }, &struct_fields(input)?),
+        Data::Enum(_) if has_variant_fields(input) => {
+            let arms = data_variants(input)?.into_iter().map(|variant| {
+                let ident = variant.ident;
+                let id = variant.id;
+                let read = match variant.fields {
+                    Fields::Unit => quote! { Ok(#name::#ident) },
+                    _ => read_object(quote! { #name::#ident }, &variant.infos),
+                };
+                quote! { #id => { #read } }
+            });
+            let message = format!("invalid {} variant {{}}", name);
+            quote! {
+                match deserializer.read::<u16>()? {
+                    #(#arms)*
+                    value => Err(::std::io::Error::new(::std::io::ErrorKind::InvalidData, format!(#message, value))),
+                }
+            }
+        }
+        Data::Enum(_) => {
+            let variants = enum_variants(input)?;
+            let message = format!("invalid {} value {{}}", name);
+            quote! {
+                let value = deserializer.read::<u8>()?;
+                #(if value == #name::#variants as u8 { return Ok(#name::#variants); })*
+                Err(::std::io::Error::new(::std::io::ErrorKind::InvalidData, format!(#message, value)))
+            }
+        }
+        Data::Union(_) => return Err(syn::Error::new_spanned(input, "unions cannot be deserialized")),
+    };
+    Ok(quote! {
+        impl #impl_generics ::carapacedb::common::serializer::Deserializable for #name #ty_generics #where_clause {
+            fn deserialize<D: ::carapacedb::common::serializer::Deserializer>(deserializer: &mut D) -> ::std::io::Result<Self> {
+                #body
+            }
+        }
+    })
+}
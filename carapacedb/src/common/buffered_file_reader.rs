@@ -0,0 +1,124 @@
+use std::io::{Error, ErrorKind, Result};
+use std::path::Path;
+use std::sync::Arc;
+use crate::common::file_system::{FileFlags, OwnedFileHandle, UnifiedFileHandle, UnifiedFileSystem};
+use crate::common::serializer::{BinaryObjectReader, Deserializer};
+
+const FILE_BUFFER_SIZE: usize = 4096;
+
+/// Reads a file through an in-memory buffer, which is refilled with the next
+/// `FILE_BUFFER_SIZE` bytes of the file when it is exhausted
+pub struct BufferedFileReader {
+    handle: OwnedFileHandle,
+    buffer: [u8; FILE_BUFFER_SIZE],
+    /// The read position within `buffer`
+    offset: usize,
+    /// The number of valid bytes in `buffer`
+    read_data: usize,
+    /// The file offset of the start of `buffer`
+    buffer_start: u64,
+    file_size: u64,
+}
+
+impl BufferedFileReader {
+    /// Opens `path` for reading, with `flags` in addition to `FileFlags::READ`
+    pub fn new(fs: Arc<UnifiedFileSystem>, path: &Path, flags: FileFlags) -> Result<Self> {
+        let handle = OwnedFileHandle::open(fs, path, flags | FileFlags::READ, None)?;
+        let file_size = handle.file_size()?;
+        Ok(BufferedFileReader {
+            handle,
+            buffer: [0; FILE_BUFFER_SIZE],
+            offset: 0,
+            read_data: 0,
+            buffer_start: 0,
+            file_size,
+        })
+    }
+
+    pub fn file_system(&self) -> &Arc<UnifiedFileSystem> {
+        self.handle.file_system()
+    }
+
+    pub fn handle(&self) -> &UnifiedFileHandle<'static> {
+        &self.handle
+    }
+
+    pub fn file_size(&self) -> u64 {
+        self.file_size
+    }
+
+    /// The file offset of the next byte to be read
+    pub fn position(&self) -> u64 {
+        self.buffer_start + self.offset as u64
+    }
+
+    /// Whether the whole file has been read
+    pub fn finished(&self) -> bool {
+        self.position() >= self.file_size
+    }
+
+    /// Moves the read position to `location`, reusing the buffered data if it covers it
+    pub fn seek(&mut self, location: u64) -> Result<()> {
+        if location > self.file_size {
+            return Err(self.eof_error(location));
+        }
+        if location >= self.buffer_start && location <= self.buffer_start + self.read_data as u64 {
+            self.offset = (location - self.buffer_start) as usize;
+        } else {
+            self.buffer_start = location;
+            self.offset = 0;
+            self.read_data = 0;
+        }
+        Ok(())
+    }
+
+    /// Reads the next chunk of the file into the buffer
+    fn refill(&mut self) -> Result<()> {
+        let position = self.position();
+        let to_read = (self.file_size - position).min(FILE_BUFFER_SIZE as u64) as usize;
+        self.handle.read_at(&mut self.buffer[..], to_read as i64, position)?;
+        self.buffer_start = position;
+        self.offset = 0;
+        self.read_data = to_read;
+        Ok(())
+    }
+
+    fn eof_error(&self, location: u64) -> Error {
+        Error::new(
+            ErrorKind::UnexpectedEof,
+            format!(
+                "read past the end of file '{}': offset {} of {} bytes",
+                self.handle.path().display(), location, self.file_size
+            )
+        )
+    }
+}
+
+impl Deserializer for BufferedFileReader {
+    type Object = BinaryObjectReader;
+
+    fn read_data(&mut self, mut buffer: &mut [u8]) -> Result<()> {
+        let end = self.position() + buffer.len() as u64;
+        if end > self.file_size {
+            return Err(self.eof_error(end));
+        }
+        while !buffer.is_empty() {
+            if self.offset == self.read_data {
+                self.refill()?;
+            }
+            let to_copy = buffer.len().min(self.read_data - self.offset);
+            buffer[..to_copy].copy_from_slice(&self.buffer[self.offset..self.offset + to_copy]);
+            self.offset += to_copy;
+            buffer = &mut buffer[to_copy..];
+        }
+        Ok(())
+    }
+
+    fn remaining(&self) -> Option<u64> {
+        Some(self.file_size - self.position())
+    }
+
+    fn read_object(&mut self) -> Result<BinaryObjectReader> {
+        BinaryObjectReader::read(self)
+    }
+}
@@ -1,11 +1,118 @@
-use std::sync::Weak;
-use crate::common::file_system::{UnifiedFileHandle, UnifiedFileSystem};
+use std::io::{Error, ErrorKind, Result};
+use std::path::Path;
+use std::sync::Arc;
+use crate::common::file_system::{FileFlags, OwnedFileHandle, UnifiedFileHandle, UnifiedFileSystem};
+use crate::common::serializer::Serializer;
 
 const FILE_BUFFER_SIZE: usize = 4096;
 
+/// Writes to a file through an in-memory buffer, which is written out at the current
+/// file pointer when it is full or on `flush`
 pub struct BufferedFileWriter {
-    fs: Weak<UnifiedFileSystem>,
+    handle: OwnedFileHandle,
     buffer: [u8; FILE_BUFFER_SIZE],
     offset: usize,
-    handle: Box<UnifiedFileHandle<'static>>,
+    total_written: u64,
+}
+
+impl BufferedFileWriter {
+    /// Opens `path` for writing, with `flags` in addition to `FileFlags::WRITE`
+    pub fn new(fs: Arc<UnifiedFileSystem>, path: &Path, flags: FileFlags) -> Result<Self> {
+        let handle = OwnedFileHandle::open(fs, path, flags | FileFlags::WRITE, None)?;
+        Ok(BufferedFileWriter {
+            handle,
+            buffer: [0; FILE_BUFFER_SIZE],
+            offset: 0,
+            total_written: 0,
+        })
+    }
+
+    pub fn file_system(&self) -> &Arc<UnifiedFileSystem> {
+        self.handle.file_system()
+    }
+
+    pub fn handle(&self) -> &UnifiedFileHandle<'static> {
+        &self.handle
+    }
+
+    /// Writes the buffered data to the file
+    pub fn flush(&mut self) -> Result<()> {
+        if self.offset == 0 {
+            return Ok(());
+        }
+        self.handle.write(&self.buffer, self.offset as i64)?;
+        self.total_written += self.offset as u64;
+        self.offset = 0;
+        Ok(())
+    }
+
+    /// Flushes the buffered data and syncs the file to disk
+    pub fn sync(&mut self) -> Result<()> {
+        self.flush()?;
+        self.handle.fsync()
+    }
+
+    /// The size of the file including the data that has not been flushed yet
+    pub fn file_size(&self) -> Result<u64> {
+        Ok(self.handle.file_size()? + self.offset as u64)
+    }
+
+    /// The number of bytes written through this writer, including the data that has
+    /// not been flushed yet
+    pub fn total_written(&self) -> u64 {
+        self.total_written + self.offset as u64
+    }
+
+    /// Truncates the file to `size` bytes, which must not exceed `file_size`
+    pub fn truncate(&mut self, size: u64) -> Result<()> {
+        let persistent = self.handle.file_size()?;
+        let file_size = persistent + self.offset as u64;
+        if size > file_size {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                format!(
+                    "cannot truncate '{}' to {} bytes: the file has only {} bytes",
+                    self.handle.path().display(), size, file_size
+                )
+            ));
+        }
+        if persistent <= size {
+            // truncating into the pending write buffer
+            self.offset = (size - persistent) as usize;
+        } else {
+            // truncate the physical file and discard anything in the buffer
+            self.handle.truncate(size)?;
+            self.handle.set_file_pointer(size)?;
+            self.offset = 0;
+        }
+        Ok(())
+    }
+}
+
+impl Serializer for BufferedFileWriter {
+    fn write_data(&mut self, mut buffer: &[u8]) -> Result<()> {
+        if self.offset == 0 && buffer.len() >= FILE_BUFFER_SIZE {
+            // bypass the buffer for large writes
+            self.handle.write(buffer, buffer.len() as i64)?;
+            self.total_written += buffer.len() as u64;
+            return Ok(());
+        }
+        while !buffer.is_empty() {
+            let to_copy = buffer.len().min(FILE_BUFFER_SIZE - self.offset);
+            self.buffer[self.offset..self.offset + to_copy].copy_from_slice(&buffer[..to_copy]);
+            self.offset += to_copy;
+            buffer = &buffer[to_copy..];
+            if self.offset == FILE_BUFFER_SIZE {
+                self.flush()?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Drop for BufferedFileWriter {
+    /// Flushes the buffered data; errors are ignored, call `flush` or `sync` to observe them
+    fn drop(&mut self) {
+        let _ = self.flush();
+    }
 }
@@ -0,0 +1,130 @@
+use std::io::{Error, ErrorKind, Result};
+use std::str;
+use crate::common::serializer::{BinaryObjectReader, Deserializer, Serializer};
+
+/// Serializes into an in-memory buffer, e.g. to build a record before writing it out
+#[derive(Debug, Default, Clone)]
+pub struct BufferedSerializer {
+    data: Vec<u8>,
+}
+
+impl BufferedSerializer {
+    pub fn new() -> Self {
+        BufferedSerializer { data: Vec::new() }
+    }
+
+    pub fn with_capacity(capacity: usize) -> Self {
+        BufferedSerializer { data: Vec::with_capacity(capacity) }
+    }
+
+    /// The bytes serialized so far
+    pub fn data(&self) -> &[u8] {
+        &self.data
+    }
+
+    pub fn into_data(self) -> Vec<u8> {
+        self.data
+    }
+
+    pub fn len(&self) -> usize {
+        self.data.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.data.is_empty()
+    }
+
+    /// Discards the serialized bytes, keeping the allocation for the next record
+    pub fn clear(&mut self) {
+        self.data.clear();
+    }
+}
+
+impl Serializer for BufferedSerializer {
+    fn write_data(&mut self, buffer: &[u8]) -> Result<()> {
+        self.data.extend_from_slice(buffer);
+        Ok(())
+    }
+}
+
+/// Deserializes from an in-memory buffer
+#[derive(Debug, Clone)]
+pub struct BufferedDeserializer<'a> {
+    data: &'a [u8],
+    position: usize,
+}
+
+impl<'a> BufferedDeserializer<'a> {
+    pub fn new(data: &'a [u8]) -> Self {
+        BufferedDeserializer { data, position: 0 }
+    }
+
+    /// The offset of the next byte to be read
+    pub fn position(&self) -> usize {
+        self.position
+    }
+
+    /// Whether all of the data has been read
+    pub fn finished(&self) -> bool {
+        self.position == self.data.len()
+    }
+
+    /// Moves the read position to `position`
+    pub fn seek(&mut self, position: usize) -> Result<()> {
+        if position > self.data.len() {
+            return Err(self.eof_error(position - self.position));
+        }
+        self.position = position;
+        Ok(())
+    }
+
+    /// Returns the next `len` bytes without copying them
+    pub fn read_bytes(&mut self, len: usize) -> Result<&'a [u8]> {
+        if len > self.data.len() - self.position {
+            return Err(self.eof_error(len));
+        }
+        let bytes = &self.data[self.position..self.position + len];
+        self.position += len;
+        Ok(bytes)
+    }
+
+    /// Reads a string written by `Serializer::write_string` without copying it
+    pub fn read_str(&mut self) -> Result<&'a str> {
+        let len = self.read_varint()?;
+        if len > (self.data.len() - self.position) as u64 {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                format!("string length {} exceeds the {} remaining bytes", len, self.data.len() - self.position)
+            ));
+        }
+        let bytes = self.read_bytes(len as usize)?;
+        str::from_utf8(bytes).map_err(|e| Error::new(ErrorKind::InvalidData, e))
+    }
+
+    fn eof_error(&self, len: usize) -> Error {
+        Error::new(
+            ErrorKind::UnexpectedEof,
+            format!(
+                "read of {} bytes at offset {} past the end of a {} byte buffer",
+                len, self.position, self.data.len()
+            )
+        )
+    }
+}
+
+impl Deserializer for BufferedDeserializer<'_> {
+    type Object = BinaryObjectReader;
+
+    fn read_data(&mut self, buffer: &mut [u8]) -> Result<()> {
+        buffer.copy_from_slice(self.read_bytes(buffer.len())?);
+        Ok(())
+    }
+
+    fn remaining(&self) -> Option<u64> {
+        Some((self.data.len() - self.position) as u64)
+    }
+
+    fn read_object(&mut self) -> Result<BinaryObjectReader> {
+        BinaryObjectReader::read(self)
+    }
+}
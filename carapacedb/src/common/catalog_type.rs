@@ -1,5 +1,7 @@
+use crate::common::serializer::{Deserializable, Serializable};
+
 #[repr(u8)]
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, Serializable, Deserializable)]
 pub enum CatalogType {
     Invalid = 0,
     Table = 1,
@@ -13,3 +15,30 @@ pub enum CatalogType {
     PreparedStatement = 12,
     Sequence = 13,
 }
+
+impl From<CatalogType> for u8 {
+    fn from(catalog_type: CatalogType) -> u8 {
+        catalog_type as u8
+    }
+}
+
+impl TryFrom<u8> for CatalogType {
+    type Error = u8;
+
+    fn try_from(value: u8) -> Result<Self, u8> {
+        Ok(match value {
+            0 => CatalogType::Invalid,
+            1 => CatalogType::Table,
+            2 => CatalogType::Schema,
+            3 => CatalogType::TableFunction,
+            4 => CatalogType::ScalarFunction,
+            5 => CatalogType::View,
+            6 => CatalogType::Index,
+            10 => CatalogType::UpdatedEntry,
+            11 => CatalogType::DeletedEntry,
+            12 => CatalogType::PreparedStatement,
+            13 => CatalogType::Sequence,
+            _ => return Err(value),
+        })
+    }
+}
@@ -0,0 +1,26 @@
+/// The checksum of a block of data: the hashes of its 8-byte little-endian words
+/// combined with xor. A trailing partial word is zero-padded.
+pub fn checksum(data: &[u8]) -> u64 {
+    let mut result: u64 = 5381;
+    let mut words = data.chunks_exact(8);
+    for word in &mut words {
+        result ^= hash(u64::from_le_bytes(word.try_into().unwrap()));
+    }
+    let remainder = words.remainder();
+    if !remainder.is_empty() {
+        let mut word = [0u8; 8];
+        word[..remainder.len()].copy_from_slice(remainder);
+        result ^= hash(u64::from_le_bytes(word));
+    }
+    result
+}
+
+/// The 64-bit finalizer of MurmurHash3
+fn hash(mut x: u64) -> u64 {
+    x ^= x >> 33;
+    x = x.wrapping_mul(0xff51afd7ed558ccd);
+    x ^= x >> 33;
+    x = x.wrapping_mul(0xc4ceb9fe1a85ec53);
+    x ^= x >> 33;
+    x
+}
@@ -1,18 +1,126 @@
+use std::alloc::{self, Layout};
+use std::io::{Error, ErrorKind, Result};
 use std::mem;
+use std::slice;
 
+use crate::common::checksum::checksum;
+use crate::common::file_system::UnifiedFileHandle;
 
-
-const FILE_BUFFER_BLOCK_SIZE: usize = 4096;
-const FILE_BUFFER_HEADER_SIZE: usize = mem::size_of::<u64>();
+/// File buffers are aligned to and sized in multiples of this, so that they can be
+/// read and written through handles opened with `FileFlags::DIRECT_IO`
+pub const FILE_BUFFER_BLOCK_SIZE: usize = 4096;
+/// The header of a file buffer holds the checksum of the rest of the buffer
+pub const FILE_BUFFER_HEADER_SIZE: usize = mem::size_of::<u64>();
 
 pub struct FileBuffer {
-    pub buffer: *mut u8,
-    pub size: usize,
-    /// The pointer to the internal buffer that will be read or written, 
+    /// The part of the buffer that holds data, following the header
+    buffer: *mut u8,
+    size: usize,
+    /// The pointer to the internal buffer that will be read or written,
     ///  including the buffer header
     internal_buffer: *mut u8,
     internal_size: usize,
-    /// The buffer that was actually malloc'd, i.e. 
-    ///  the pointer that must be freed when the FileBuffer is destroyed
-    malloced_buffer: *mut u8,
+    /// The layout the internal buffer was allocated with, needed to free it
+    layout: Layout,
+}
+
+// SAFETY: the buffer owns its allocation, like a `Vec<u8>`
+unsafe impl Send for FileBuffer {}
+unsafe impl Sync for FileBuffer {}
+
+impl FileBuffer {
+    /// Allocates a zeroed buffer of `internal_size` bytes including the header, rounded
+    /// up to a multiple of `FILE_BUFFER_BLOCK_SIZE`
+    pub fn new(internal_size: usize) -> Self {
+        let internal_size = internal_size.max(1).next_multiple_of(FILE_BUFFER_BLOCK_SIZE);
+        let layout = Layout::from_size_align(internal_size, FILE_BUFFER_BLOCK_SIZE)
+            .expect("file buffer size overflows");
+        // SAFETY: the layout has a non-zero size
+        let internal_buffer = unsafe { alloc::alloc_zeroed(layout) };
+        if internal_buffer.is_null() {
+            alloc::handle_alloc_error(layout);
+        }
+        FileBuffer {
+            // SAFETY: the allocation is at least one block, which is larger than the header
+            buffer: unsafe { internal_buffer.add(FILE_BUFFER_HEADER_SIZE) },
+            size: internal_size - FILE_BUFFER_HEADER_SIZE,
+            internal_buffer,
+            internal_size,
+            layout,
+        }
+    }
+
+    /// The number of data bytes, excluding the header
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
+    /// The number of bytes read and written, including the header
+    pub fn internal_size(&self) -> usize {
+        self.internal_size
+    }
+
+    pub fn buffer(&self) -> &[u8] {
+        // SAFETY: `buffer` points to `size` initialized bytes owned by `self`
+        unsafe { slice::from_raw_parts(self.buffer, self.size) }
+    }
+
+    pub fn buffer_mut(&mut self) -> &mut [u8] {
+        // SAFETY: as in `buffer`, and `&mut self` guarantees exclusive access
+        unsafe { slice::from_raw_parts_mut(self.buffer, self.size) }
+    }
+
+    fn internal_buffer(&self) -> &[u8] {
+        // SAFETY: `internal_buffer` points to `internal_size` initialized bytes owned by `self`
+        unsafe { slice::from_raw_parts(self.internal_buffer, self.internal_size) }
+    }
+
+    fn internal_buffer_mut(&mut self) -> &mut [u8] {
+        // SAFETY: as in `internal_buffer`, and `&mut self` guarantees exclusive access
+        unsafe { slice::from_raw_parts_mut(self.internal_buffer, self.internal_size) }
+    }
+
+    /// Zeroes the buffer, including the header
+    pub fn clear(&mut self) {
+        self.internal_buffer_mut().fill(0);
+    }
+
+    /// Reads the buffer from `location` and verifies the checksum in its header
+    pub fn read(&mut self, handle: &UnifiedFileHandle<'_>, location: u64) -> Result<()> {
+        let internal_size = self.internal_size as i64;
+        handle.read_at(self.internal_buffer_mut(), internal_size, location)?;
+        verify_checksum(self.internal_buffer(), location).map(|_| ())
+    }
+
+    /// Stores the checksum of the data in the header and writes the buffer to `location`
+    pub fn write(&mut self, handle: &UnifiedFileHandle<'_>, location: u64) -> Result<()> {
+        let computed_checksum = checksum(self.buffer());
+        self.internal_buffer_mut()[..FILE_BUFFER_HEADER_SIZE].copy_from_slice(&computed_checksum.to_le_bytes());
+        handle.write_at(self.internal_buffer(), self.internal_size as i64, location)
+    }
+}
+
+impl Drop for FileBuffer {
+    fn drop(&mut self) {
+        // SAFETY: allocated in `new` with `layout`
+        unsafe { alloc::dealloc(self.internal_buffer, self.layout) };
+    }
+}
+
+/// Verifies the checksum in the header of `internal_buffer`, a file buffer read from
+/// `location`, and returns the data following the header
+pub(crate) fn verify_checksum(internal_buffer: &[u8], location: u64) -> Result<&[u8]> {
+    let (header, data) = internal_buffer.split_at(FILE_BUFFER_HEADER_SIZE);
+    let stored_checksum = u64::from_le_bytes(header.try_into().unwrap());
+    let computed_checksum = checksum(data);
+    if computed_checksum != stored_checksum {
+        return Err(Error::new(
+            ErrorKind::InvalidData,
+            format!(
+                "corrupt block at offset {}: computed checksum {} does not match stored checksum {}",
+                location, computed_checksum, stored_checksum
+            )
+        ));
+    }
+    Ok(data)
 }
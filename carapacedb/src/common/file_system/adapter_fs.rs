@@ -0,0 +1,367 @@
+use std::any::TypeId;
+use std::fmt::{self, Debug};
+use std::io::{Error, ErrorKind, Result};
+use std::path::{Path, PathBuf};
+
+use super::dynamic_fs::{downcast_handle, BoxedFileSystem, DynFileHandle, DynFileSystem, Token};
+use super::static_fs::{SFileHandle, SFileSystem};
+use super::{transfer_size, FileFlags, FileLockType};
+
+/// Exposes any `SFileSystem` through the object-safe `DynFileSystem` interface, so
+/// that it can be used as a plugin or registered in a `VirtualFileSystem`.
+///
+/// `read` and `write` transfer exactly `nr_bytes`, and a missing lock type opens the
+/// file with `FileLockType::NoLock`.
+#[derive(Debug, Clone)]
+pub struct DynFileSystemAdapter<FS: SFileSystem> {
+    inner: FS,
+}
+
+pub struct DynAdapterHandle<'fs, FS: SFileSystem + 'fs> {
+    fs: &'fs DynFileSystemAdapter<FS>,
+    inner: FS::Handle<'fs>,
+}
+
+/// Exposes a `DynFileSystem` through the statically dispatched `SFileSystem`
+/// interface, the inverse of `DynFileSystemAdapter`.
+///
+/// `read` and `write` always transfer the requested number of bytes: reading past the
+/// end of the file fails with `UnexpectedEof` instead of returning a short count.
+#[derive(Debug)]
+pub struct SFileSystemAdapter {
+    inner: BoxedFileSystem,
+}
+
+#[derive(Debug)]
+pub struct SAdapterHandle<'fs> {
+    fs: &'fs SFileSystemAdapter,
+    inner: Box<dyn DynFileHandle<'fs> + 'fs>,
+}
+
+impl<FS: SFileSystem + Debug + 'static> DynFileSystemAdapter<FS> {
+    pub const fn new(inner: FS) -> Self {
+        DynFileSystemAdapter { inner }
+    }
+
+    /// Opens `path` like `DynFileSystem::open_file`, without boxing the handle
+    pub fn open_handle(&self, path: &Path, flags: FileFlags, lock: Option<FileLockType>) -> Result<DynAdapterHandle<'_, FS>> {
+        let inner = self.inner.open_file(path, flags, lock.unwrap_or(FileLockType::NoLock))?;
+        Ok(DynAdapterHandle { fs: self, inner })
+    }
+
+    /// Wraps `inner` into a plugin file system
+    pub fn boxed(inner: FS) -> BoxedFileSystem {
+        Box::new(Self::new(inner))
+    }
+
+    pub fn inner(&self) -> &FS {
+        &self.inner
+    }
+
+    pub fn into_inner(self) -> FS {
+        self.inner
+    }
+}
+
+impl<'fs, FS: SFileSystem + 'fs> DynAdapterHandle<'fs, FS> {
+    /// The handle of the wrapped file system
+    pub fn inner(&self) -> &FS::Handle<'fs> {
+        &self.inner
+    }
+}
+
+impl<'fs, FS: SFileSystem + 'fs> Debug for DynAdapterHandle<'fs, FS> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("DynAdapterHandle")
+            .field("path", &self.inner.path())
+            .finish()
+    }
+}
+
+impl<'fs, FS: SFileSystem + Debug + 'static> DynFileHandle<'fs> for DynAdapterHandle<'fs, FS> {
+    fn file_system(&self) -> &dyn DynFileSystem<'fs> {
+        self.fs
+    }
+
+    fn path(&self) -> &Path {
+        self.inner.path()
+    }
+
+    fn close(&mut self) -> Result<()> {
+        self.inner.close()
+    }
+
+    fn handle_type_id(&self, _token: Token) -> Option<TypeId> {
+        Some(TypeId::of::<DynAdapterHandle<'static, FS>>())
+    }
+
+    fn mapped_slice(&self, location: u64, length: usize) -> Option<&[u8]> {
+        self.inner.mapped_slice(location, length)
+    }
+}
+
+/// Recovers the `DynAdapterHandle` behind a handle passed to the `DynFileSystem` interface.
+fn adapter_handle<'h, 'fs, FS: SFileSystem + Debug + 'static>(handle: &'h dyn DynFileHandle<'fs>) -> Result<&'h DynAdapterHandle<'fs, FS>> {
+    downcast_handle(handle, TypeId::of::<DynAdapterHandle<'static, FS>>())
+}
+
+impl<'fs, FS: SFileSystem + Debug + 'static> DynFileSystem<'fs> for DynFileSystemAdapter<FS> {
+    fn read_at(&self, handle: &dyn DynFileHandle<'fs>, buffer: &mut [u8], nr_bytes: i64, location: u64) -> Result<()> {
+        self.inner.read_at(&adapter_handle::<FS>(handle)?.inner, buffer, nr_bytes, location)
+    }
+
+    fn write_at(&self, handle: &dyn DynFileHandle<'fs>, buffer: &[u8], nr_bytes: i64, location: u64) -> Result<()> {
+        self.inner.write_at(&adapter_handle::<FS>(handle)?.inner, buffer, nr_bytes, location)
+    }
+
+    fn open_file(&'fs self, path: &Path, flags: FileFlags, lock: Option<FileLockType>) -> Result<Box<dyn DynFileHandle<'fs> + 'fs>> {
+        Ok(Box::new(self.open_handle(path, flags, lock)?))
+    }
+
+    fn set_file_pointer(&self, handle: &dyn DynFileHandle<'fs>, location: u64) -> Result<()> {
+        self.inner.set_file_pointer(&adapter_handle::<FS>(handle)?.inner, location)
+    }
+
+    /// Reads exactly `nr_bytes` from the current file pointer.
+    fn read(&self, handle: &dyn DynFileHandle<'fs>, buffer: &mut [u8], nr_bytes: i64) -> Result<()> {
+        let handle = &adapter_handle::<FS>(handle)?.inner;
+        let count = transfer_size(buffer.len(), nr_bytes)?;
+        let mut bytes_read = 0;
+        while bytes_read < count {
+            let result = self.inner.read(handle, &mut buffer[bytes_read..], (count - bytes_read) as i64)?;
+            if result == 0 {
+                return Err(Error::new(
+                    ErrorKind::UnexpectedEof,
+                    format!("read failed: expected {} bytes, but read {}", nr_bytes, bytes_read)
+                ));
+            }
+            bytes_read += result as usize;
+        }
+        Ok(())
+    }
+
+    /// Writes exactly `nr_bytes` at the current file pointer.
+    fn write(&self, handle: &dyn DynFileHandle<'fs>, buffer: &[u8], nr_bytes: i64) -> Result<()> {
+        let handle = &adapter_handle::<FS>(handle)?.inner;
+        let count = transfer_size(buffer.len(), nr_bytes)?;
+        let mut bytes_written = 0;
+        while bytes_written < count {
+            let result = self.inner.write(handle, &buffer[bytes_written..], (count - bytes_written) as i64)?;
+            if result == 0 {
+                return Err(Error::new(
+                    ErrorKind::WriteZero,
+                    format!("write failed: expected {} bytes, but wrote {}", nr_bytes, bytes_written)
+                ));
+            }
+            bytes_written += result as usize;
+        }
+        Ok(())
+    }
+
+    fn file_size(&self, handle: &dyn DynFileHandle<'fs>) -> Result<u64> {
+        self.inner.file_size(&adapter_handle::<FS>(handle)?.inner)
+    }
+
+    fn file_version(&self, handle: &dyn DynFileHandle<'fs>) -> Result<Option<String>> {
+        self.inner.file_version(&adapter_handle::<FS>(handle)?.inner)
+    }
+
+    fn directory_exists(&self, path: &Path) -> Result<bool> {
+        self.inner.directory_exists(path)
+    }
+
+    fn file_exists(&self, file_name: &Path) -> Result<bool> {
+        self.inner.file_exists(file_name)
+    }
+
+    fn create_directory(&self, path: &Path) -> Result<()> {
+        self.inner.create_directory(path)
+    }
+
+    fn remove_directory(&self, path: &Path) -> Result<()> {
+        self.inner.remove_directory(path)
+    }
+
+    fn remove_file(&self, file_name: &Path) -> Result<()> {
+        self.inner.remove_file(file_name)
+    }
+
+    fn list_files(&self, directory: &Path, callback: &mut dyn FnMut(String)) -> Result<bool> {
+        self.inner.list_files(directory, callback)
+    }
+
+    fn path_separator(&self) -> &'static str {
+        self.inner.path_separator()
+    }
+
+    fn fsync(&self, handle: &dyn DynFileHandle<'fs>) -> Result<()> {
+        self.inner.fsync(&adapter_handle::<FS>(handle)?.inner)
+    }
+
+    fn truncate(&self, handle: &dyn DynFileHandle<'fs>, new_size: u64) -> Result<()> {
+        self.inner.truncate(&adapter_handle::<FS>(handle)?.inner, new_size)
+    }
+
+    fn allocate(&self, handle: &dyn DynFileHandle<'fs>, offset: u64, length: u64) -> Result<()> {
+        self.inner.allocate(&adapter_handle::<FS>(handle)?.inner, offset, length)
+    }
+
+    fn punch_hole(&self, handle: &dyn DynFileHandle<'fs>, offset: u64, length: u64) -> Result<()> {
+        self.inner.punch_hole(&adapter_handle::<FS>(handle)?.inner, offset, length)
+    }
+
+    fn sync_directory(&self, directory: &Path) -> Result<()> {
+        self.inner.sync_directory(directory)
+    }
+
+    fn move_file(&self, src: &Path, dst: &Path) -> Result<()> {
+        self.inner.move_file(src, dst)
+    }
+
+    fn join_path(&self, l: &Path, r: &Path) -> Result<PathBuf> {
+        self.inner.join_path(l, r)
+    }
+
+    fn glob(&self, pattern: &str) -> Result<Vec<PathBuf>> {
+        self.inner.glob(pattern)
+    }
+}
+
+impl SFileSystemAdapter {
+    pub fn new(inner: BoxedFileSystem) -> Self {
+        SFileSystemAdapter { inner }
+    }
+
+    pub fn inner(&self) -> &dyn for<'fs> DynFileSystem<'fs> {
+        self.inner.as_ref()
+    }
+
+    pub fn into_inner(self) -> BoxedFileSystem {
+        self.inner
+    }
+}
+
+impl<'fs> SAdapterHandle<'fs> {
+    /// The handle of the wrapped file system
+    pub fn inner(&self) -> &dyn DynFileHandle<'fs> {
+        self.inner.as_ref()
+    }
+}
+
+impl<'fs> SFileHandle<SFileSystemAdapter> for SAdapterHandle<'fs> {
+    fn file_system(&self) -> &SFileSystemAdapter {
+        self.fs
+    }
+
+    fn path(&self) -> &Path {
+        self.inner.path()
+    }
+
+    fn close(&mut self) -> Result<()> {
+        self.inner.close()
+    }
+
+    fn mapped_slice(&self, location: u64, length: usize) -> Option<&[u8]> {
+        self.inner.mapped_slice(location, length)
+    }
+}
+
+impl SFileSystem for SFileSystemAdapter {
+    type Handle<'a> = SAdapterHandle<'a>;
+
+    fn read_at(&self, handle: &Self::Handle<'_>, buffer: &mut [u8], nr_bytes: i64, location: u64) -> Result<()> {
+        self.inner.read_at(handle.inner.as_ref(), buffer, nr_bytes, location)
+    }
+
+    fn write_at(&self, handle: &Self::Handle<'_>, buffer: &[u8], nr_bytes: i64, location: u64) -> Result<()> {
+        self.inner.write_at(handle.inner.as_ref(), buffer, nr_bytes, location)
+    }
+
+    fn open_file<'a>(&'a self, path: &Path, flags: FileFlags, lock: FileLockType) -> Result<Self::Handle<'a>> {
+        let inner = self.inner.open_file(path, flags, Some(lock))?;
+        Ok(SAdapterHandle { fs: self, inner })
+    }
+
+    fn set_file_pointer(&self, handle: &Self::Handle<'_>, location: u64) -> Result<()> {
+        self.inner.set_file_pointer(handle.inner.as_ref(), location)
+    }
+
+    fn read(&self, handle: &Self::Handle<'_>, buffer: &mut [u8], nr_bytes: i64) -> Result<u64> {
+        self.inner.read(handle.inner.as_ref(), buffer, nr_bytes)?;
+        Ok(nr_bytes as u64)
+    }
+
+    fn write(&self, handle: &Self::Handle<'_>, buffer: &[u8], nr_bytes: i64) -> Result<u64> {
+        self.inner.write(handle.inner.as_ref(), buffer, nr_bytes)?;
+        Ok(nr_bytes as u64)
+    }
+
+    fn file_size(&self, handle: &Self::Handle<'_>) -> Result<u64> {
+        self.inner.file_size(handle.inner.as_ref())
+    }
+
+    fn file_version(&self, handle: &Self::Handle<'_>) -> Result<Option<String>> {
+        self.inner.file_version(handle.inner.as_ref())
+    }
+
+    fn directory_exists(&self, path: &Path) -> Result<bool> {
+        self.inner.directory_exists(path)
+    }
+
+    fn file_exists(&self, file_name: &Path) -> Result<bool> {
+        self.inner.file_exists(file_name)
+    }
+
+    fn create_directory(&self, path: &Path) -> Result<()> {
+        self.inner.create_directory(path)
+    }
+
+    fn remove_directory(&self, path: &Path) -> Result<()> {
+        self.inner.remove_directory(path)
+    }
+
+    fn remove_file(&self, file_name: &Path) -> Result<()> {
+        self.inner.remove_file(file_name)
+    }
+
+    fn list_files<F>(&self, directory: &Path, mut callback: F) -> Result<bool>
+    where F: FnMut(String) {
+        self.inner.list_files(directory, &mut callback)
+    }
+
+    fn path_separator(&self) -> &'static str {
+        self.inner.path_separator()
+    }
+
+    fn fsync(&self, handle: &Self::Handle<'_>) -> Result<()> {
+        self.inner.fsync(handle.inner.as_ref())
+    }
+
+    fn truncate(&self, handle: &Self::Handle<'_>, new_size: u64) -> Result<()> {
+        self.inner.truncate(handle.inner.as_ref(), new_size)
+    }
+
+    fn allocate(&self, handle: &Self::Handle<'_>, offset: u64, length: u64) -> Result<()> {
+        self.inner.allocate(handle.inner.as_ref(), offset, length)
+    }
+
+    fn punch_hole(&self, handle: &Self::Handle<'_>, offset: u64, length: u64) -> Result<()> {
+        self.inner.punch_hole(handle.inner.as_ref(), offset, length)
+    }
+
+    fn sync_directory(&self, directory: &Path) -> Result<()> {
+        self.inner.sync_directory(directory)
+    }
+
+    fn move_file(&self, src: &Path, dst: &Path) -> Result<()> {
+        self.inner.move_file(src, dst)
+    }
+
+    fn join_path(&self, l: &Path, r: &Path) -> Result<PathBuf> {
+        self.inner.join_path(l, r)
+    }
+
+    fn glob(&self, pattern: &str) -> Result<Vec<PathBuf>> {
+        self.inner.glob(pattern)
+    }
+}
@@ -0,0 +1,618 @@
+use std::any::TypeId;
+use std::collections::HashMap;
+use std::fs;
+use std::io::{Error, ErrorKind, Result};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::SystemTime;
+
+use crate::common::sha256::{sha256, to_hex};
+use super::dynamic_fs::{downcast_handle, BoxedFileSystem, DynFileHandle, DynFileSystem, Token};
+use super::virtual_fs::split_scheme;
+use super::{transfer_size, FileFlags, FileLockType};
+
+const BLOCK_EXTENSION: &str = "block";
+const META_EXTENSION: &str = "meta";
+
+#[derive(Debug, Clone)]
+pub struct DiskCacheConfig {
+    pub directory: PathBuf,
+    /// Files are cached in blocks of this many bytes
+    pub block_size: u64,
+    /// The least recently used blocks are evicted once the cache grows beyond this
+    pub max_size: u64,
+    /// The URI schemes whose file systems `DBConfig` wraps in a `CachingFileSystem`
+    pub schemes: Vec<String>,
+}
+
+impl Default for DiskCacheConfig {
+    fn default() -> Self {
+        DiskCacheConfig {
+            directory: std::env::temp_dir().join("carapacedb_cache"),
+            block_size: 1 << 20,
+            max_size: 1 << 30,
+            schemes: vec!["http".to_string(), "https".to_string(), "s3".to_string()],
+        }
+    }
+}
+
+/// A file with cached blocks, see `DiskCache::entries`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DiskCacheEntry {
+    pub path: PathBuf,
+    pub file_size: u64,
+    pub version: Option<String>,
+    pub blocks: usize,
+    pub cached_bytes: u64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct DiskCacheStatistics {
+    pub hits: u64,
+    pub misses: u64,
+    pub size: u64,
+    pub max_size: u64,
+}
+
+/// Blocks of remote files stored in a local directory. A cached file is identified by
+/// its path, size and version (see `DynFileSystem::file_version`: the ETag of remote
+/// files, the modification time of local and plugin files), so a file that changes is
+/// cached anew and its stale blocks age out of the cache.
+///
+/// Each file has a `<key>.meta` file describing it and a `<key>-<index>.block` file
+/// per cached block; the cache survives restarts. The index of cached blocks is kept
+/// in memory, and the cache files are written and removed outside of its lock.
+#[derive(Debug)]
+pub struct DiskCache {
+    config: DiskCacheConfig,
+    state: Mutex<CacheState>,
+    hits: AtomicU64,
+    misses: AtomicU64,
+    temporary_files: AtomicU64,
+}
+
+#[derive(Debug, Default)]
+struct CacheState {
+    tick: u64,
+    size: u64,
+    files: HashMap<String, CachedFile>,
+}
+
+#[derive(Debug)]
+struct CachedFile {
+    path: PathBuf,
+    file_size: u64,
+    version: Option<String>,
+    blocks: HashMap<u64, CachedBlock>,
+}
+
+#[derive(Debug)]
+struct CachedBlock {
+    length: u64,
+    last_use: u64,
+}
+
+/// A file system that caches the files of the file system it wraps on local disk.
+/// Handles opened for writing bypass the cache.
+#[derive(Debug)]
+pub struct CachingFileSystem {
+    inner: BoxedFileSystem,
+    cache: Arc<DiskCache>,
+    /// The URI scheme the cache entries of this file system are recorded under
+    scheme: String,
+}
+
+#[derive(Debug)]
+pub struct CachingFileHandle<'fs> {
+    fs: &'fs CachingFileSystem,
+    inner: Box<dyn DynFileHandle<'fs> + 'fs>,
+    path: PathBuf,
+    /// The cache key and size of the file; `None` if reads bypass the cache
+    cached: Option<(String, u64)>,
+    position: Mutex<u64>,
+}
+
+fn corrupt_entry(path: &Path) -> Error {
+    Error::new(ErrorKind::InvalidData, format!("corrupt cache entry '{}'", path.display()))
+}
+
+/// Parses `<key>-<index>.block`
+fn parse_block_name(name: &str) -> Option<(&str, u64)> {
+    let stem = name.strip_suffix(BLOCK_EXTENSION)?.strip_suffix('.')?;
+    let (key, index) = stem.rsplit_once('-')?;
+    Some((key, index.parse().ok()?))
+}
+
+impl DiskCache {
+    /// Opens the cache in `config.directory`, creating the directory if needed and
+    /// picking up the blocks cached by earlier runs. The block size and the maximum
+    /// size must not be 0.
+    pub fn open(config: DiskCacheConfig) -> Result<Self> {
+        if config.block_size == 0 || config.max_size == 0 {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                format!(
+                    "cannot open the disk cache in '{}': block size {} and maximum size {} must not be 0",
+                    config.directory.display(), config.block_size, config.max_size
+                )
+            ));
+        }
+        fs::create_dir_all(&config.directory)?;
+        let cache = DiskCache {
+            config,
+            state: Mutex::new(CacheState::default()),
+            hits: AtomicU64::new(0),
+            misses: AtomicU64::new(0),
+            temporary_files: AtomicU64::new(0),
+        };
+        cache.load()?;
+        Ok(cache)
+    }
+
+    pub fn config(&self) -> &DiskCacheConfig {
+        &self.config
+    }
+
+    /// The cached files, ordered by path
+    pub fn entries(&self) -> Vec<DiskCacheEntry> {
+        let state = self.state.lock().unwrap();
+        let mut entries: Vec<DiskCacheEntry> = state.files.values()
+            .filter(|file| !file.blocks.is_empty())
+            .map(|file| DiskCacheEntry {
+                path: file.path.clone(),
+                file_size: file.file_size,
+                version: file.version.clone(),
+                blocks: file.blocks.len(),
+                cached_bytes: file.blocks.values().map(|block| block.length).sum(),
+            })
+            .collect();
+        entries.sort_by(|a, b| a.path.cmp(&b.path));
+        entries
+    }
+
+    pub fn statistics(&self) -> DiskCacheStatistics {
+        DiskCacheStatistics {
+            hits: self.hits.load(Ordering::Relaxed),
+            misses: self.misses.load(Ordering::Relaxed),
+            size: self.state.lock().unwrap().size,
+            max_size: self.config.max_size,
+        }
+    }
+
+    /// Removes every cached file
+    pub fn clear(&self) -> Result<()> {
+        let mut state = self.state.lock().unwrap();
+        let keys: Vec<String> = state.files.keys().cloned().collect();
+        let paths = keys.iter().flat_map(|key| self.forget_file(&mut state, key)).collect();
+        drop(state);
+        remove_files(paths)
+    }
+
+    /// Removes the cached blocks of `path`, whatever their version
+    pub fn invalidate(&self, path: &Path) -> Result<()> {
+        let mut state = self.state.lock().unwrap();
+        let keys: Vec<String> = state.files.iter()
+            .filter(|(_, file)| file.path == path)
+            .map(|(key, _)| key.clone())
+            .collect();
+        let paths = keys.iter().flat_map(|key| self.forget_file(&mut state, key)).collect();
+        drop(state);
+        remove_files(paths)
+    }
+
+    fn meta_path(&self, key: &str) -> PathBuf {
+        self.config.directory.join(format!("{}.{}", key, META_EXTENSION))
+    }
+
+    fn block_path(&self, key: &str, index: u64) -> PathBuf {
+        self.config.directory.join(format!("{}-{}.{}", key, index, BLOCK_EXTENSION))
+    }
+
+    /// Rebuilds the index from the cache directory. Blocks are ordered by modification
+    /// time; leftovers of interrupted writes, unreadable `.meta` files and blocks of
+    /// unknown files are removed.
+    fn load(&self) -> Result<()> {
+        let mut state = self.state.lock().unwrap();
+        let mut blocks = Vec::new();
+        for entry in fs::read_dir(&self.config.directory)? {
+            let entry = entry?;
+            let name = entry.file_name().to_string_lossy().into_owned();
+            if let Some(key) = name.strip_suffix(META_EXTENSION).and_then(|stem| stem.strip_suffix('.')) {
+                match read_meta(&entry.path()) {
+                    Ok(file) => {
+                        state.files.insert(key.to_string(), file);
+                    }
+                    Err(e) if e.kind() == ErrorKind::InvalidData => fs::remove_file(entry.path())?,
+                    Err(e) => return Err(e),
+                }
+            } else if let Some((key, index)) = parse_block_name(&name) {
+                let metadata = entry.metadata()?;
+                let modified = metadata.modified().unwrap_or(SystemTime::UNIX_EPOCH);
+                blocks.push((modified, key.to_string(), index, metadata.len()));
+            } else if name.contains(".tmp") {
+                fs::remove_file(entry.path())?;
+            }
+        }
+
+        blocks.sort();
+        for (_, key, index, length) in blocks {
+            state.tick += 1;
+            let last_use = state.tick;
+            match state.files.get_mut(&key) {
+                Some(file) => {
+                    file.blocks.insert(index, CachedBlock { length, last_use });
+                    state.size += length;
+                }
+                None => fs::remove_file(self.block_path(&key, index))?,
+            }
+        }
+        let evicted = self.evict(&mut state);
+        drop(state);
+        remove_files(evicted)
+    }
+
+    /// Returns the key of the cache entry for the given version of `path`, creating
+    /// the entry if needed
+    fn register(&self, path: &Path, file_size: u64, version: Option<&str>) -> Result<String> {
+        let path_str = path.to_string_lossy();
+        let identity = format!("{}\0{}\0{}", path_str, file_size, version.unwrap_or(""));
+        let key = to_hex(&sha256(identity.as_bytes()))[..32].to_string();
+        if self.state.lock().unwrap().files.contains_key(&key) {
+            return Ok(key);
+        }
+
+        let version_line = version.map_or("none".to_string(), |version| format!("version {}", version));
+        let contents = format!("{}\n{}\n{}\n", path_str, file_size, version_line);
+        self.write_atomically(&self.meta_path(&key), contents.as_bytes())?;
+        self.state.lock().unwrap().files.entry(key.clone()).or_insert_with(|| CachedFile {
+            path: path.to_path_buf(),
+            file_size,
+            version: version.map(str::to_string),
+            blocks: HashMap::new(),
+        });
+        Ok(key)
+    }
+
+    /// Reads a cached block into `buffer`, which has the length of the block; returns
+    /// whether the block was cached
+    fn read_block(&self, key: &str, index: u64, buffer: &mut [u8]) -> bool {
+        {
+            let mut state = self.state.lock().unwrap();
+            state.tick += 1;
+            let tick = state.tick;
+            match state.files.get_mut(key).and_then(|file| file.blocks.get_mut(&index)) {
+                Some(block) if block.length == buffer.len() as u64 => block.last_use = tick,
+                _ => {
+                    self.misses.fetch_add(1, Ordering::Relaxed);
+                    return false;
+                }
+            }
+        }
+
+        // the block may be evicted meanwhile, which makes this a miss
+        match fs::read(self.block_path(key, index)) {
+            Ok(data) if data.len() == buffer.len() => {
+                buffer.copy_from_slice(&data);
+                self.hits.fetch_add(1, Ordering::Relaxed);
+                true
+            }
+            _ => {
+                self.misses.fetch_add(1, Ordering::Relaxed);
+                self.forget_missing_block(key, index);
+                false
+            }
+        }
+    }
+
+    /// Drops a block whose file could not be read from the index, so that it is
+    /// fetched and cached again
+    fn forget_missing_block(&self, key: &str, index: u64) {
+        let mut state = self.state.lock().unwrap();
+        let Some(file) = state.files.get_mut(key) else { return };
+        if let Some(block) = file.blocks.remove(&index) {
+            state.size -= block.length;
+        }
+    }
+
+    fn insert_block(&self, key: &str, index: u64, data: &[u8]) -> Result<()> {
+        if data.len() as u64 > self.config.max_size {
+            return Ok(());
+        }
+        let block_path = self.block_path(key, index);
+        self.write_atomically(&block_path, data)?;
+
+        let mut state = self.state.lock().unwrap();
+        if !state.files.contains_key(key) {
+            // cleared while the block was written
+            drop(state);
+            return remove_files(vec![block_path]);
+        }
+        state.tick += 1;
+        let block = CachedBlock { length: data.len() as u64, last_use: state.tick };
+        let file = state.files.get_mut(key).expect("checked above");
+        let replaced = file.blocks.insert(index, block).map_or(0, |block| block.length);
+        state.size = state.size - replaced + data.len() as u64;
+        let evicted = self.evict(&mut state);
+        drop(state);
+        remove_files(evicted)
+    }
+
+    /// Writes `data` to a temporary file renamed to `path`, so that readers never see
+    /// a partial file
+    fn write_atomically(&self, path: &Path, data: &[u8]) -> Result<()> {
+        let counter = self.temporary_files.fetch_add(1, Ordering::Relaxed);
+        let mut temporary = path.as_os_str().to_owned();
+        temporary.push(format!(".tmp{}", counter));
+        fs::write(&temporary, data)?;
+        fs::rename(&temporary, path)
+    }
+
+    /// Drops the least recently used blocks from the index until the cache fits in
+    /// `max_size`; returns their files, for the caller to remove once it released the
+    /// lock
+    fn evict(&self, state: &mut CacheState) -> Vec<PathBuf> {
+        let mut evicted = Vec::new();
+        while state.size > self.config.max_size {
+            let oldest = state.files.iter()
+                .flat_map(|(key, file)| file.blocks.iter().map(move |(index, block)| (block.last_use, key, *index)))
+                .min()
+                .map(|(_, key, index)| (key.clone(), index));
+            let Some((key, index)) = oldest else { break };
+
+            let file = state.files.get_mut(&key).expect("block of a cached file");
+            let block = file.blocks.remove(&index).expect("cached block");
+            state.size -= block.length;
+            evicted.push(self.block_path(&key, index));
+        }
+        evicted
+    }
+
+    /// Drops a file from the index; returns its cache files, for the caller to remove
+    /// once it released the lock
+    fn forget_file(&self, state: &mut CacheState, key: &str) -> Vec<PathBuf> {
+        let Some(file) = state.files.remove(key) else { return Vec::new() };
+        let mut paths = Vec::new();
+        for (index, block) in file.blocks {
+            state.size -= block.length;
+            paths.push(self.block_path(key, index));
+        }
+        paths.push(self.meta_path(key));
+        paths
+    }
+}
+
+/// Reads a `.meta` file; fails with `InvalidData` if it is corrupt
+fn read_meta(meta_path: &Path) -> Result<CachedFile> {
+    let contents = fs::read(meta_path)?;
+    let contents = String::from_utf8(contents).map_err(|_| corrupt_entry(meta_path))?;
+    let mut lines = contents.lines();
+    let (Some(path), Some(file_size), Some(version)) = (lines.next(), lines.next(), lines.next()) else {
+        return Err(corrupt_entry(meta_path));
+    };
+    Ok(CachedFile {
+        path: PathBuf::from(path),
+        file_size: file_size.parse().map_err(|_| corrupt_entry(meta_path))?,
+        version: version.strip_prefix("version ").map(str::to_string),
+        blocks: HashMap::new(),
+    })
+}
+
+/// Removes cache files, ignoring those that are already gone
+fn remove_files(paths: Vec<PathBuf>) -> Result<()> {
+    for path in paths {
+        match fs::remove_file(&path) {
+            Err(e) if e.kind() != ErrorKind::NotFound => return Err(e),
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
+impl CachingFileSystem {
+    /// Caches the files of `inner`, recording them under paths with `scheme`
+    pub fn new(inner: BoxedFileSystem, cache: Arc<DiskCache>, scheme: &str) -> Self {
+        CachingFileSystem { inner, cache, scheme: scheme.to_ascii_lowercase() }
+    }
+
+    pub fn cache(&self) -> &Arc<DiskCache> {
+        &self.cache
+    }
+
+    /// The path the cache records `path` under, including the scheme
+    fn cache_path(&self, path: &Path) -> PathBuf {
+        match split_scheme(path) {
+            (Some(_), _) => path.to_path_buf(),
+            (None, _) => PathBuf::from(format!("{}://{}", self.scheme, path.display())),
+        }
+    }
+
+    fn read_cached_file(&self, handle: &CachingFileHandle<'_>, buffer: &mut [u8], nr_bytes: i64, location: u64) -> Result<()> {
+        let Some((key, file_size)) = &handle.cached else {
+            return self.inner.read_at(handle.inner.as_ref(), buffer, nr_bytes, location);
+        };
+        let len = transfer_size(buffer.len(), nr_bytes)?;
+        let end = location + len as u64;
+        if end > *file_size {
+            return Err(Error::new(
+                ErrorKind::UnexpectedEof,
+                format!("read of {} bytes at {} is beyond the end of '{}'", len, location, handle.path.display())
+            ));
+        }
+
+        let block_size = self.cache.config.block_size;
+        let mut block = Vec::new();
+        let mut position = location;
+        while position < end {
+            let index = position / block_size;
+            let block_start = index * block_size;
+            let block_len = block_size.min(file_size - block_start) as usize;
+            block.resize(block_len, 0);
+            if !self.cache.read_block(key, index, &mut block) {
+                self.inner.read_at(handle.inner.as_ref(), &mut block, block_len as i64, block_start)?;
+                // a failure to cache the block does not fail the read
+                let _ = self.cache.insert_block(key, index, &block);
+            }
+
+            let offset = (position - block_start) as usize;
+            let count = (block_len - offset).min((end - position) as usize);
+            let copied = (position - location) as usize;
+            buffer[copied..copied + count].copy_from_slice(&block[offset..offset + count]);
+            position += count as u64;
+        }
+        Ok(())
+    }
+}
+
+impl<'fs> DynFileHandle<'fs> for CachingFileHandle<'fs> {
+    fn file_system(&self) -> &dyn DynFileSystem<'fs> {
+        self.fs
+    }
+
+    fn path(&self) -> &Path {
+        &self.path
+    }
+
+    fn close(&mut self) -> Result<()> {
+        self.inner.close()
+    }
+
+    fn handle_type_id(&self, _token: Token) -> Option<TypeId> {
+        Some(TypeId::of::<CachingFileHandle<'static>>())
+    }
+}
+
+/// Recovers the `CachingFileHandle` behind a handle passed to the `DynFileSystem` interface.
+fn caching_handle<'h, 'fs>(handle: &'h dyn DynFileHandle<'fs>) -> Result<&'h CachingFileHandle<'fs>> {
+    downcast_handle(handle, TypeId::of::<CachingFileHandle<'static>>())
+}
+
+impl<'fs> DynFileSystem<'fs> for CachingFileSystem {
+    fn read_at(&self, handle: &dyn DynFileHandle<'fs>, buffer: &mut [u8], nr_bytes: i64, location: u64) -> Result<()> {
+        self.read_cached_file(caching_handle(handle)?, buffer, nr_bytes, location)
+    }
+
+    fn write_at(&self, handle: &dyn DynFileHandle<'fs>, buffer: &[u8], nr_bytes: i64, location: u64) -> Result<()> {
+        self.inner.write_at(caching_handle(handle)?.inner.as_ref(), buffer, nr_bytes, location)
+    }
+
+    fn open_file(&'fs self, path: &Path, flags: FileFlags, lock: Option<FileLockType>) -> Result<Box<dyn DynFileHandle<'fs> + 'fs>> {
+        let inner = self.inner.open_file(path, flags, lock)?;
+        let cached = if flags.contains(FileFlags::WRITE) {
+            self.cache.invalidate(&self.cache_path(path))?;
+            None
+        } else {
+            let file_size = self.inner.file_size(inner.as_ref())?;
+            let version = self.inner.file_version(inner.as_ref())?;
+            let key = self.cache.register(&self.cache_path(path), file_size, version.as_deref())?;
+            Some((key, file_size))
+        };
+        Ok(Box::new(CachingFileHandle {
+            fs: self,
+            inner,
+            path: path.to_path_buf(),
+            cached,
+            position: Mutex::new(0),
+        }))
+    }
+
+    fn set_file_pointer(&self, handle: &dyn DynFileHandle<'fs>, location: u64) -> Result<()> {
+        let handle = caching_handle(handle)?;
+        if handle.cached.is_none() {
+            return self.inner.set_file_pointer(handle.inner.as_ref(), location);
+        }
+        *handle.position.lock().unwrap() = location;
+        Ok(())
+    }
+
+    /// Reads exactly `nr_bytes` from the current file pointer.
+    fn read(&self, handle: &dyn DynFileHandle<'fs>, buffer: &mut [u8], nr_bytes: i64) -> Result<()> {
+        let handle = caching_handle(handle)?;
+        if handle.cached.is_none() {
+            return self.inner.read(handle.inner.as_ref(), buffer, nr_bytes);
+        }
+        let mut position = handle.position.lock().unwrap();
+        self.read_cached_file(handle, buffer, nr_bytes, *position)?;
+        *position += nr_bytes as u64;
+        Ok(())
+    }
+
+    fn write(&self, handle: &dyn DynFileHandle<'fs>, buffer: &[u8], nr_bytes: i64) -> Result<()> {
+        self.inner.write(caching_handle(handle)?.inner.as_ref(), buffer, nr_bytes)
+    }
+
+    fn file_size(&self, handle: &dyn DynFileHandle<'fs>) -> Result<u64> {
+        let handle = caching_handle(handle)?;
+        match &handle.cached {
+            Some((_, file_size)) => Ok(*file_size),
+            None => self.inner.file_size(handle.inner.as_ref()),
+        }
+    }
+
+    fn directory_exists(&self, path: &Path) -> Result<bool> {
+        self.inner.directory_exists(path)
+    }
+
+    fn file_exists(&self, file_name: &Path) -> Result<bool> {
+        self.inner.file_exists(file_name)
+    }
+
+    fn create_directory(&self, path: &Path) -> Result<()> {
+        self.inner.create_directory(path)
+    }
+
+    fn remove_directory(&self, path: &Path) -> Result<()> {
+        self.inner.remove_directory(path)
+    }
+
+    fn remove_file(&self, file_name: &Path) -> Result<()> {
+        self.cache.invalidate(&self.cache_path(file_name))?;
+        self.inner.remove_file(file_name)
+    }
+
+    fn list_files(&self, directory: &Path, callback: &mut dyn FnMut(String)) -> Result<bool> {
+        self.inner.list_files(directory, callback)
+    }
+
+    fn path_separator(&self) -> &'static str {
+        self.inner.path_separator()
+    }
+
+    fn fsync(&self, handle: &dyn DynFileHandle<'fs>) -> Result<()> {
+        self.inner.fsync(caching_handle(handle)?.inner.as_ref())
+    }
+
+    fn truncate(&self, handle: &dyn DynFileHandle<'fs>, new_size: u64) -> Result<()> {
+        self.inner.truncate(caching_handle(handle)?.inner.as_ref(), new_size)
+    }
+
+    fn allocate(&self, handle: &dyn DynFileHandle<'fs>, offset: u64, length: u64) -> Result<()> {
+        self.inner.allocate(caching_handle(handle)?.inner.as_ref(), offset, length)
+    }
+
+    fn punch_hole(&self, handle: &dyn DynFileHandle<'fs>, offset: u64, length: u64) -> Result<()> {
+        self.inner.punch_hole(caching_handle(handle)?.inner.as_ref(), offset, length)
+    }
+
+    fn sync_directory(&self, directory: &Path) -> Result<()> {
+        self.inner.sync_directory(directory)
+    }
+
+    fn move_file(&self, src: &Path, dst: &Path) -> Result<()> {
+        self.cache.invalidate(&self.cache_path(src))?;
+        self.cache.invalidate(&self.cache_path(dst))?;
+        self.inner.move_file(src, dst)
+    }
+
+    fn file_version(&self, handle: &dyn DynFileHandle<'fs>) -> Result<Option<String>> {
+        self.inner.file_version(caching_handle(handle)?.inner.as_ref())
+    }
+
+    fn join_path(&self, l: &Path, r: &Path) -> Result<PathBuf> {
+        self.inner.join_path(l, r)
+    }
+
+    fn glob(&self, pattern: &str) -> Result<Vec<PathBuf>> {
+        self.inner.glob(pattern)
+    }
+}
@@ -0,0 +1,518 @@
+use std::any::TypeId;
+use std::fmt::{self, Debug};
+use std::io::{self, Error, ErrorKind, Read, Result, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+use flate2::read::MultiGzDecoder;
+use flate2::write::GzEncoder;
+
+use super::dynamic_fs::{downcast_handle, BoxedFileSystem, DynFileHandle, DynFileSystem, Token};
+use super::{transfer_size, FileFlags, FileLockType};
+
+/// The zstd compression level used for writing, zstd's default
+const ZSTD_COMPRESSION_LEVEL: i32 = 3;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FileCompression {
+    /// Detect the compression from the file extension (`.gz`, `.gzip`, `.zst`, `.zstd`),
+    /// files with other extensions are passed through uncompressed
+    Auto,
+    Uncompressed,
+    Gzip,
+    Zstd,
+}
+
+impl FileCompression {
+    pub fn from_path(path: &Path) -> FileCompression {
+        let extension = path.extension()
+            .and_then(|extension| extension.to_str())
+            .map(|extension| extension.to_ascii_lowercase());
+        match extension.as_deref() {
+            Some("gz") | Some("gzip") => FileCompression::Gzip,
+            Some("zst") | Some("zstd") => FileCompression::Zstd,
+            _ => FileCompression::Uncompressed,
+        }
+    }
+
+    fn resolve(self, path: &Path) -> FileCompression {
+        match self {
+            FileCompression::Auto => FileCompression::from_path(path),
+            compression => compression,
+        }
+    }
+}
+
+/// A file system that transparently (de)compresses the files of the file system it wraps.
+///
+/// Files opened for reading are decompressed as a sequential stream: reads continue where
+/// the previous one ended, seeking forward skips data and seeking backward restarts the
+/// decompression. Files opened for writing are truncated and compressed as they are
+/// written, which must happen sequentially; the stream is finished when the handle is
+/// closed.
+#[derive(Debug)]
+pub struct CompressedFileSystem {
+    inner: BoxedFileSystem,
+    compression: FileCompression,
+}
+
+/// Reads the compressed bytes of the inner file for a decoder
+struct InnerReader<'fs> {
+    fs: &'fs dyn DynFileSystem<'fs>,
+    handle: Arc<Box<dyn DynFileHandle<'fs> + 'fs>>,
+    position: u64,
+    size: u64,
+}
+
+/// Writes the compressed bytes produced by an encoder to the inner file
+struct InnerWriter<'fs> {
+    fs: &'fs dyn DynFileSystem<'fs>,
+    handle: Arc<Box<dyn DynFileHandle<'fs> + 'fs>>,
+    position: u64,
+}
+
+trait CompressedWriter: Write + Send {
+    /// Writes the end of the compressed stream
+    fn finish(&mut self) -> Result<()>;
+}
+
+enum CompressedStream<'fs> {
+    Reader(Box<dyn Read + Send + 'fs>),
+    Writer(Box<dyn CompressedWriter + 'fs>),
+    Finished,
+}
+
+struct StreamState<'fs> {
+    stream: CompressedStream<'fs>,
+    /// The position in the uncompressed data
+    position: u64,
+    /// The uncompressed size, once known
+    size: Option<u64>,
+}
+
+pub struct CompressedFileHandle<'fs> {
+    fs: &'fs CompressedFileSystem,
+    inner_fs: &'fs dyn DynFileSystem<'fs>,
+    inner: Arc<Box<dyn DynFileHandle<'fs> + 'fs>>,
+    path: PathBuf,
+    compression: FileCompression,
+    /// `None` for uncompressed files, which are passed through
+    state: Option<Mutex<StreamState<'fs>>>,
+}
+
+impl<'fs> Read for InnerReader<'fs> {
+    fn read(&mut self, buffer: &mut [u8]) -> io::Result<usize> {
+        let count = (self.size - self.position).min(buffer.len() as u64) as usize;
+        if count == 0 {
+            return Ok(0);
+        }
+        self.fs.read_at(self.handle.as_ref().as_ref(), &mut buffer[..count], count as i64, self.position)?;
+        self.position += count as u64;
+        Ok(count)
+    }
+}
+
+impl<'fs> Write for InnerWriter<'fs> {
+    fn write(&mut self, buffer: &[u8]) -> io::Result<usize> {
+        self.fs.write_at(self.handle.as_ref().as_ref(), buffer, buffer.len() as i64, self.position)?;
+        self.position += buffer.len() as u64;
+        Ok(buffer.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl<W: Write + Send> CompressedWriter for GzEncoder<W> {
+    fn finish(&mut self) -> Result<()> {
+        self.try_finish()
+    }
+}
+
+impl<W: Write + Send> CompressedWriter for zstd::stream::write::Encoder<'static, W> {
+    fn finish(&mut self) -> Result<()> {
+        self.do_finish()
+    }
+}
+
+impl<'fs> Debug for CompressedFileHandle<'fs> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("CompressedFileHandle")
+            .field("path", &self.path)
+            .field("compression", &self.compression)
+            .finish()
+    }
+}
+
+fn not_sequential(path: &Path, location: u64, position: u64) -> Error {
+    Error::new(
+        ErrorKind::Unsupported,
+        format!("compressed file '{}' must be written sequentially: cannot write at {} (position is {})",
+            path.display(), location, position)
+    )
+}
+
+impl CompressedFileSystem {
+    pub fn new(inner: BoxedFileSystem, compression: FileCompression) -> Self {
+        CompressedFileSystem { inner, compression }
+    }
+}
+
+impl<'fs> CompressedFileHandle<'fs> {
+    fn open_reader(&self) -> Result<Box<dyn Read + Send + 'fs>> {
+        let reader = InnerReader {
+            fs: self.inner_fs,
+            handle: self.inner.clone(),
+            position: 0,
+            size: self.inner_fs.file_size(self.inner.as_ref().as_ref())?,
+        };
+        Ok(match self.compression {
+            FileCompression::Gzip => Box::new(MultiGzDecoder::new(reader)),
+            FileCompression::Zstd => Box::new(zstd::stream::read::Decoder::new(reader)?),
+            _ => unreachable!("uncompressed files have no stream"),
+        })
+    }
+
+    fn open_writer(&self) -> Result<Box<dyn CompressedWriter + 'fs>> {
+        let writer = InnerWriter {
+            fs: self.inner_fs,
+            handle: self.inner.clone(),
+            position: 0,
+        };
+        Ok(match self.compression {
+            FileCompression::Gzip => Box::new(GzEncoder::new(writer, flate2::Compression::default())),
+            FileCompression::Zstd => Box::new(zstd::stream::write::Encoder::new(writer, ZSTD_COMPRESSION_LEVEL)?),
+            _ => unreachable!("uncompressed files have no stream"),
+        })
+    }
+
+    /// Reads up to `buffer.len()` bytes at `location` of the uncompressed data
+    fn read_stream(&self, state: &mut StreamState<'fs>, buffer: &mut [u8], location: u64) -> Result<usize> {
+        self.seek_stream(state, location)?;
+        let CompressedStream::Reader(reader) = &mut state.stream else {
+            return Err(Error::new(
+                ErrorKind::Unsupported,
+                format!("compressed file '{}' was not opened for reading", self.path.display())
+            ));
+        };
+
+        let mut bytes_read = 0;
+        while bytes_read < buffer.len() {
+            match reader.read(&mut buffer[bytes_read..]) {
+                Ok(0) => {
+                    state.size = Some(state.position + bytes_read as u64);
+                    break;
+                }
+                Ok(n) => bytes_read += n,
+                Err(e) if e.kind() == ErrorKind::Interrupted => {}
+                Err(e) => return Err(e),
+            }
+        }
+        state.position += bytes_read as u64;
+        Ok(bytes_read)
+    }
+
+    /// Moves the read position of the stream to `location`
+    fn seek_stream(&self, state: &mut StreamState<'fs>, location: u64) -> Result<()> {
+        if !matches!(state.stream, CompressedStream::Reader(_)) {
+            return Ok(());
+        }
+        if location < state.position {
+            state.stream = CompressedStream::Reader(self.open_reader()?);
+            state.position = 0;
+        }
+
+        let mut skip_buffer = [0u8; 4096];
+        while state.position < location {
+            let count = (location - state.position).min(skip_buffer.len() as u64) as usize;
+            let CompressedStream::Reader(reader) = &mut state.stream else { unreachable!() };
+            let n = reader.read(&mut skip_buffer[..count])?;
+            if n == 0 {
+                return Err(Error::new(
+                    ErrorKind::UnexpectedEof,
+                    format!("cannot seek to {} in compressed file '{}': file is shorter", location, self.path.display())
+                ));
+            }
+            state.position += n as u64;
+        }
+        Ok(())
+    }
+
+    fn write_stream(&self, state: &mut StreamState<'fs>, buffer: &[u8], location: u64) -> Result<()> {
+        if location != state.position {
+            return Err(not_sequential(&self.path, location, state.position));
+        }
+        let CompressedStream::Writer(writer) = &mut state.stream else {
+            return Err(Error::new(
+                ErrorKind::Unsupported,
+                format!("compressed file '{}' is not open for writing", self.path.display())
+            ));
+        };
+        writer.write_all(buffer)?;
+        state.position += buffer.len() as u64;
+        state.size = Some(state.position);
+        Ok(())
+    }
+
+    /// Returns the uncompressed size, decompressing the whole file the first time
+    fn uncompressed_size(&self, state: &mut StreamState<'fs>) -> Result<u64> {
+        if let Some(size) = state.size {
+            return Ok(size);
+        }
+        let mut reader = self.open_reader()?;
+        let size = io::copy(&mut reader, &mut io::sink())?;
+        state.size = Some(size);
+        Ok(size)
+    }
+
+    /// Finishes the compressed stream of a writer
+    fn finish(&mut self) -> Result<()> {
+        let Some(state) = &self.state else {
+            return Ok(());
+        };
+        let mut state = state.lock().unwrap();
+        if let CompressedStream::Writer(writer) = &mut state.stream {
+            writer.finish()?;
+        }
+        state.stream = CompressedStream::Finished;
+        Ok(())
+    }
+
+    fn close_handle(&mut self) -> Result<()> {
+        let finished = self.finish();
+        let closed = match Arc::get_mut(&mut self.inner) {
+            Some(inner) => inner.close(),
+            None => Ok(()),
+        };
+        finished.and(closed)
+    }
+}
+
+impl<'fs> DynFileHandle<'fs> for CompressedFileHandle<'fs> {
+    fn file_system(&self) -> &dyn DynFileSystem<'fs> {
+        self.fs
+    }
+
+    fn path(&self) -> &Path {
+        &self.path
+    }
+
+    fn close(&mut self) -> Result<()> {
+        self.close_handle()
+    }
+
+    fn handle_type_id(&self, _token: Token) -> Option<TypeId> {
+        Some(TypeId::of::<CompressedFileHandle<'static>>())
+    }
+}
+
+impl<'fs> Drop for CompressedFileHandle<'fs> {
+    fn drop(&mut self) {
+        if let Err(e) = self.finish() {
+            eprintln!("failed to finish compressed file '{}': {}", self.path.display(), e);
+        }
+    }
+}
+
+/// Recovers the `CompressedFileHandle` behind a handle passed to the `DynFileSystem` interface.
+fn compressed_handle<'h, 'fs>(handle: &'h dyn DynFileHandle<'fs>) -> Result<&'h CompressedFileHandle<'fs>> {
+    downcast_handle(handle, TypeId::of::<CompressedFileHandle<'static>>())
+}
+
+impl CompressedFileSystem {
+    /// Returns the inner file system and handle of an uncompressed file, for operations
+    /// that cannot be applied to a compressed stream
+    fn uncompressed_file<'h, 'fs>(&self, handle: &'h dyn DynFileHandle<'fs>) -> Result<(&'h dyn DynFileSystem<'fs>, &'h dyn DynFileHandle<'fs>)> {
+        let handle = compressed_handle(handle)?;
+        if handle.state.is_some() {
+            return Err(Error::new(
+                ErrorKind::Unsupported,
+                format!("operation is not supported on compressed file '{}'", handle.path.display())
+            ));
+        }
+        Ok((handle.inner_fs, handle.inner.as_ref().as_ref()))
+    }
+}
+
+impl<'fs> DynFileSystem<'fs> for CompressedFileSystem {
+    fn read_at(&self, handle: &dyn DynFileHandle<'fs>, buffer: &mut [u8], nr_bytes: i64, location: u64) -> Result<()> {
+        let handle = compressed_handle(handle)?;
+        let Some(state) = &handle.state else {
+            return handle.inner_fs.read_at(handle.inner.as_ref().as_ref(), buffer, nr_bytes, location);
+        };
+        let count = transfer_size(buffer.len(), nr_bytes)?;
+        let bytes_read = handle.read_stream(&mut state.lock().unwrap(), &mut buffer[..count], location)?;
+        if bytes_read != count {
+            return Err(Error::new(
+                ErrorKind::UnexpectedEof,
+                format!("read_at failed: expected {} bytes, but read {}", nr_bytes, bytes_read)
+            ));
+        }
+        Ok(())
+    }
+
+    fn write_at(&self, handle: &dyn DynFileHandle<'fs>, buffer: &[u8], nr_bytes: i64, location: u64) -> Result<()> {
+        let handle = compressed_handle(handle)?;
+        let Some(state) = &handle.state else {
+            return handle.inner_fs.write_at(handle.inner.as_ref().as_ref(), buffer, nr_bytes, location);
+        };
+        let count = transfer_size(buffer.len(), nr_bytes)?;
+        handle.write_stream(&mut state.lock().unwrap(), &buffer[..count], location)
+    }
+
+    fn open_file(&'fs self, path: &Path, flags: FileFlags, lock: Option<FileLockType>) -> Result<Box<dyn DynFileHandle<'fs> + 'fs>> {
+        let inner_fs: &'fs dyn DynFileSystem<'fs> = self.inner.as_ref();
+        let inner = Arc::new(inner_fs.open_file(path, flags, lock)?);
+        let compression = self.compression.resolve(path);
+
+        let mut handle = CompressedFileHandle {
+            fs: self,
+            inner_fs,
+            inner,
+            path: path.to_path_buf(),
+            compression,
+            state: None,
+        };
+        if compression != FileCompression::Uncompressed {
+            let (stream, size) = if flags.contains(FileFlags::READ) {
+                (CompressedStream::Reader(handle.open_reader()?), None)
+            } else {
+                // a compressed stream cannot be appended to, so it is rewritten
+                if inner_fs.file_size(handle.inner.as_ref().as_ref())? != 0 {
+                    inner_fs.truncate(handle.inner.as_ref().as_ref(), 0)?;
+                }
+                (CompressedStream::Writer(handle.open_writer()?), Some(0))
+            };
+            handle.state = Some(Mutex::new(StreamState { stream, position: 0, size }));
+        }
+        Ok(Box::new(handle))
+    }
+
+    fn set_file_pointer(&self, handle: &dyn DynFileHandle<'fs>, location: u64) -> Result<()> {
+        let handle = compressed_handle(handle)?;
+        let Some(state) = &handle.state else {
+            return handle.inner_fs.set_file_pointer(handle.inner.as_ref().as_ref(), location);
+        };
+        let mut state = state.lock().unwrap();
+        if matches!(state.stream, CompressedStream::Writer(_)) && location != state.position {
+            return Err(not_sequential(&handle.path, location, state.position));
+        }
+        handle.seek_stream(&mut state, location)
+    }
+
+    /// Reads exactly `nr_bytes` from the current position.
+    fn read(&self, handle: &dyn DynFileHandle<'fs>, buffer: &mut [u8], nr_bytes: i64) -> Result<()> {
+        let handle = compressed_handle(handle)?;
+        let Some(state) = &handle.state else {
+            return handle.inner_fs.read(handle.inner.as_ref().as_ref(), buffer, nr_bytes);
+        };
+        let count = transfer_size(buffer.len(), nr_bytes)?;
+        let mut state = state.lock().unwrap();
+        let location = state.position;
+        let bytes_read = handle.read_stream(&mut state, &mut buffer[..count], location)?;
+        if bytes_read != count {
+            return Err(Error::new(
+                ErrorKind::UnexpectedEof,
+                format!("read failed: expected {} bytes, but read {}", nr_bytes, bytes_read)
+            ));
+        }
+        Ok(())
+    }
+
+    fn write(&self, handle: &dyn DynFileHandle<'fs>, buffer: &[u8], nr_bytes: i64) -> Result<()> {
+        let handle = compressed_handle(handle)?;
+        let Some(state) = &handle.state else {
+            return handle.inner_fs.write(handle.inner.as_ref().as_ref(), buffer, nr_bytes);
+        };
+        let count = transfer_size(buffer.len(), nr_bytes)?;
+        let mut state = state.lock().unwrap();
+        let location = state.position;
+        handle.write_stream(&mut state, &buffer[..count], location)
+    }
+
+    /// Returns the uncompressed size of the file. For files opened for reading, this
+    /// decompresses the whole file the first time it is called.
+    fn file_size(&self, handle: &dyn DynFileHandle<'fs>) -> Result<u64> {
+        let handle = compressed_handle(handle)?;
+        match &handle.state {
+            Some(state) => handle.uncompressed_size(&mut state.lock().unwrap()),
+            None => handle.inner_fs.file_size(handle.inner.as_ref().as_ref()),
+        }
+    }
+
+    fn directory_exists(&self, path: &Path) -> Result<bool> {
+        self.inner.directory_exists(path)
+    }
+
+    fn file_exists(&self, file_name: &Path) -> Result<bool> {
+        self.inner.file_exists(file_name)
+    }
+
+    fn create_directory(&self, path: &Path) -> Result<()> {
+        self.inner.create_directory(path)
+    }
+
+    fn remove_directory(&self, path: &Path) -> Result<()> {
+        self.inner.remove_directory(path)
+    }
+
+    fn remove_file(&self, file_name: &Path) -> Result<()> {
+        self.inner.remove_file(file_name)
+    }
+
+    fn list_files(&self, directory: &Path, callback: &mut dyn FnMut(String)) -> Result<bool> {
+        self.inner.list_files(directory, callback)
+    }
+
+    fn path_separator(&self) -> &'static str {
+        self.inner.path_separator()
+    }
+
+    /// Flushes the data compressed so far to the inner file and syncs it
+    fn fsync(&self, handle: &dyn DynFileHandle<'fs>) -> Result<()> {
+        let handle = compressed_handle(handle)?;
+        if let Some(state) = &handle.state
+            && let CompressedStream::Writer(writer) = &mut state.lock().unwrap().stream {
+            writer.flush()?;
+        }
+        handle.inner_fs.fsync(handle.inner.as_ref().as_ref())
+    }
+
+    fn truncate(&self, handle: &dyn DynFileHandle<'fs>, new_size: u64) -> Result<()> {
+        let (inner_fs, inner) = self.uncompressed_file(handle)?;
+        inner_fs.truncate(inner, new_size)
+    }
+
+    fn allocate(&self, handle: &dyn DynFileHandle<'fs>, offset: u64, length: u64) -> Result<()> {
+        let (inner_fs, inner) = self.uncompressed_file(handle)?;
+        inner_fs.allocate(inner, offset, length)
+    }
+
+    fn punch_hole(&self, handle: &dyn DynFileHandle<'fs>, offset: u64, length: u64) -> Result<()> {
+        let (inner_fs, inner) = self.uncompressed_file(handle)?;
+        inner_fs.punch_hole(inner, offset, length)
+    }
+
+    fn sync_directory(&self, directory: &Path) -> Result<()> {
+        self.inner.sync_directory(directory)
+    }
+
+    fn move_file(&self, src: &Path, dst: &Path) -> Result<()> {
+        self.inner.move_file(src, dst)
+    }
+
+    /// The version of the compressed file, which changes along with its contents
+    fn file_version(&self, handle: &dyn DynFileHandle<'fs>) -> Result<Option<String>> {
+        let handle = compressed_handle(handle)?;
+        handle.inner_fs.file_version(handle.inner.as_ref().as_ref())
+    }
+
+    fn join_path(&self, l: &Path, r: &Path) -> Result<PathBuf> {
+        self.inner.join_path(l, r)
+    }
+
+    fn glob(&self, pattern: &str) -> Result<Vec<PathBuf>> {
+        self.inner.glob(pattern)
+    }
+}
@@ -1,16 +1,45 @@
 use super::FileFlags;
 use super::FileLockType;
+use super::{replace_file, unsupported_operation, ReplaceFileSteps};
+use super::glob::{expand_glob, GlobSource};
+use std::any::TypeId;
 use std::fmt::Debug;
-use std::io::{Result};
+use std::io::{Error, ErrorKind, Result};
 use std::path::{Path, PathBuf};
 
 
+mod sealed {
+    /// Taken by `DynFileHandle::handle_type_id`, so that only this crate can override it
+    pub struct Token;
+}
+
+pub(crate) use sealed::Token;
+
 pub trait DynFileHandle<'a>: Debug + Send + Sync {
     fn file_system(&self) -> &dyn DynFileSystem<'a>;
     fn path(&self) -> &Path;
     fn close(&mut self) -> Result<()>;
+
+    /// The `TypeId` of the concrete handle type, instantiated with `'static`, which
+    /// the file systems of this crate use to recover the handles they handed out, since
+    /// handles borrowing their file system cannot go through `Any`. The private token
+    /// keeps other crates from overriding it, so their handles can never be mistaken
+    /// for a handle of this crate.
+    #[doc(hidden)]
+    fn handle_type_id(&self, _token: Token) -> Option<TypeId> {
+        None
+    }
+
+    /// Returns the `length` bytes at `location` without copying them, if the file was
+    /// opened with `FileFlags::MEMORY_MAP` and the range lies within the mapping
+    fn mapped_slice(&self, _location: u64, _length: usize) -> Option<&[u8]> {
+        None
+    }
 }
 
+/// An owned plugin file system, which can open handles for any borrow of itself
+pub type BoxedFileSystem = Box<dyn for<'fs> DynFileSystem<'fs>>;
+
 pub trait DynFileSystem<'fs>: Debug + Send + Sync {
 
     fn read_at(&self, handle: &dyn DynFileHandle<'fs>, buffer: &mut [u8], nr_bytes: i64, location: u64) -> Result<()>;
@@ -43,7 +72,108 @@ pub trait DynFileSystem<'fs>: Debug + Send + Sync {
 
     fn fsync(&self, handle: &dyn DynFileHandle<'fs>) -> Result<()>;
 
+    /// Sets the size of the file to `new_size`, discarding the data beyond it or
+    /// extending it with zeros. Fails with `ErrorKind::Unsupported` by default.
+    fn truncate(&self, handle: &dyn DynFileHandle<'fs>, _new_size: u64) -> Result<()> {
+        Err(unsupported_operation("truncate", handle.path()))
+    }
+
+    /// Reserves disk space for `length` bytes at `offset`, so that writing them cannot
+    /// fail for lack of space. Extends the file if the range ends beyond it. Fails with
+    /// `ErrorKind::Unsupported` by default.
+    fn allocate(&self, handle: &dyn DynFileHandle<'fs>, _offset: u64, _length: u64) -> Result<()> {
+        Err(unsupported_operation("allocate space in", handle.path()))
+    }
+
+    /// Gives the disk space of `length` bytes at `offset` back to the file system; the
+    /// range reads as zeros afterwards and the file size does not change. Fails with
+    /// `ErrorKind::Unsupported` by default.
+    fn punch_hole(&self, handle: &dyn DynFileHandle<'fs>, _offset: u64, _length: u64) -> Result<()> {
+        Err(unsupported_operation("punch hole in", handle.path()))
+    }
+
+    /// Flushes the entries of `directory` to disk, making the creation, removal and
+    /// renaming of the files in it durable. Fails with `ErrorKind::Unsupported` by default.
+    fn sync_directory(&self, directory: &Path) -> Result<()> {
+        Err(unsupported_operation("sync directory", directory))
+    }
+
     fn move_file(&self, src: &Path, dst: &Path) -> Result<()>;
+
+    /// An identifier of the version of the file, such as an ETag, that changes whenever
+    /// its contents change; `None` if the file system cannot tell
+    fn file_version(&self, _handle: &dyn DynFileHandle<'fs>) -> Result<Option<String>> {
+        Ok(None)
+    }
     
     fn join_path(&self, l: &Path, r:&Path) -> Result<PathBuf>;
+
+    /// Expands a glob pattern (`*`, `?`, `[...]` and recursive `**`) to the sorted
+    /// list of files it matches. File systems with symbolic links override it so that
+    /// `**` does not follow them.
+    fn glob(&self, pattern: &str) -> Result<Vec<PathBuf>> {
+        let source = GlobSource {
+            list_files: &|directory, callback| self.list_files(directory, callback),
+            directory_exists: &|path| self.directory_exists(path),
+            file_exists: &|path| self.file_exists(path),
+            is_symlink: &|_| Ok(false),
+        };
+        expand_glob(&source, pattern)
+    }
+
+    /// Durably replaces the file at `path` with the contents written by `write`, see
+    /// `replace_file`
+    fn replace_file(&'fs self, path: &Path, write: &mut dyn FnMut(&dyn DynFileHandle<'fs>) -> Result<()>) -> Result<()> {
+        replace_file(&DynReplaceSteps(self), path, |handle| write(handle.as_ref()))
+    }
+}
+
+/// The steps of `replace_file` for a `DynFileSystem`
+struct DynReplaceSteps<'fs, FS: ?Sized>(&'fs FS);
+
+impl<'fs, FS: DynFileSystem<'fs> + ?Sized> ReplaceFileSteps for DynReplaceSteps<'fs, FS> {
+    type Handle = Box<dyn DynFileHandle<'fs> + 'fs>;
+
+    fn file_exists(&self, path: &Path) -> Result<bool> {
+        self.0.file_exists(path)
+    }
+
+    fn remove_file(&self, path: &Path) -> Result<()> {
+        self.0.remove_file(path)
+    }
+
+    fn create_file(&self, path: &Path) -> Result<Self::Handle> {
+        self.0.open_file(path, FileFlags::WRITE | FileFlags::CREATE, Some(FileLockType::WriteLock))
+    }
+
+    fn fsync(&self, handle: &Self::Handle) -> Result<()> {
+        self.0.fsync(handle.as_ref())
+    }
+
+    fn close(&self, handle: &mut Self::Handle) -> Result<()> {
+        handle.close()
+    }
+
+    fn move_file(&self, src: &Path, dst: &Path) -> Result<()> {
+        self.0.move_file(src, dst)
+    }
+
+    fn sync_directory(&self, directory: &Path) -> Result<()> {
+        self.0.sync_directory(directory)
+    }
+}
+
+/// Recovers the concrete handle type `H` behind a handle passed to a `DynFileSystem`,
+/// where `expected` is the `TypeId` of `H` instantiated with `'static`.
+pub(crate) fn downcast_handle<'h, 'fs, H: DynFileHandle<'fs>>(handle: &'h dyn DynFileHandle<'fs>, expected: TypeId) -> Result<&'h H> {
+    if handle.handle_type_id(Token) != Some(expected) {
+        return Err(Error::new(
+            ErrorKind::InvalidInput,
+            format!("handle for '{}' was not opened by this file system", handle.path().display())
+        ));
+    }
+    // SAFETY: only the handle types of this crate can override `handle_type_id`, and
+    // each returns its own `TypeId` and implements `DynFileHandle<'a>` only for its own
+    // lifetime `'a`, so the handle is an `H` borrowing its file system for `'fs`
+    Ok(unsafe { &*(handle as *const dyn DynFileHandle<'fs> as *const H) })
 }
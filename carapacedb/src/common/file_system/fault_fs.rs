@@ -0,0 +1,599 @@
+use std::collections::{HashMap, VecDeque};
+use std::fmt::{self, Debug};
+use std::io::{Error, ErrorKind, Result};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+use super::adapter_fs::SFileSystemAdapter;
+use super::static_fs::{SFileHandle, SFileSystem};
+use super::{normalize_path, transfer_size, FileFlags, FileLockType, UnifiedFileSystem};
+
+/// The probabilities with which `FaultInjectionFileSystem` injects faults. With the
+/// same seed and the same sequence of operations, the same faults are injected.
+#[derive(Debug, Clone, Copy)]
+pub struct FaultInjectionConfig {
+    pub seed: u64,
+    /// Probability that an fsync fails; the unsynced writes stay unsynced
+    pub fsync_failure_probability: f64,
+    /// Probability that a read transfers fewer bytes than requested
+    pub short_read_probability: f64,
+    /// Probability that a write transfers fewer bytes than requested
+    pub short_write_probability: f64,
+    /// Probability that an unsynced write is torn instead of dropped on power loss.
+    /// Each sector of a torn write independently survives with probability 1/2.
+    pub torn_write_probability: f64,
+    /// The granularity at which writes are torn
+    pub sector_size: usize,
+}
+
+impl Default for FaultInjectionConfig {
+    fn default() -> Self {
+        FaultInjectionConfig {
+            seed: 0,
+            fsync_failure_probability: 0.0,
+            short_read_probability: 0.0,
+            short_write_probability: 0.0,
+            torn_write_probability: 0.0,
+            sector_size: 512,
+        }
+    }
+}
+
+/// A fault scripted through `FaultInjectionFileSystem::inject`, which is triggered by
+/// the next operation of the matching kind.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InjectedFault {
+    FsyncFailure,
+    ShortRead,
+    ShortWrite,
+}
+
+/// A deterministic splitmix64 generator, so that fault schedules are reproducible
+#[derive(Debug)]
+struct FaultRng(u64);
+
+impl FaultRng {
+    fn next_u64(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9e3779b97f4a7c15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+        z ^ (z >> 31)
+    }
+
+    fn chance(&mut self, probability: f64) -> bool {
+        if probability <= 0.0 {
+            return false;
+        }
+        ((self.next_u64() >> 11) as f64 / (1u64 << 53) as f64) < probability
+    }
+
+    /// Returns a value in `1..n`, for `n > 1`
+    fn short_count(&mut self, n: usize) -> usize {
+        1 + (self.next_u64() % (n as u64 - 1)) as usize
+    }
+}
+
+#[derive(Debug)]
+struct PendingWrite {
+    location: u64,
+    data: Vec<u8>,
+}
+
+/// The unsynced writes of a file, in the order they were issued
+#[derive(Debug)]
+struct PendingFile {
+    /// The path the file was opened with, which power loss reopens it by
+    path: PathBuf,
+    writes: Vec<PendingWrite>,
+}
+
+#[derive(Debug)]
+struct FaultState {
+    rng: FaultRng,
+    scripted: VecDeque<InjectedFault>,
+    /// Writes that were not yet fsynced, keyed by the normalized path of the file
+    pending: HashMap<PathBuf, PendingFile>,
+}
+
+/// A wrapper over any `SFileSystem` that injects I/O faults for crash-consistency testing.
+/// A `UnifiedFileSystem` is wrapped with `FaultInjectionFileSystem::wrap_unified`.
+/// Clones share the wrapped file system and the fault state, so that a test can register
+/// one clone as a plugin, through `DynFileSystemAdapter`, and simulate power loss through
+/// another.
+///
+/// Writes are kept in a volatile cache until the file is fsynced; only then do they reach
+/// the wrapped file system, which therefore always holds the durable state of every file.
+/// `simulate_power_loss` drops (or tears) everything that was not synced. Metadata
+/// operations (create, move, remove) are passed through and are durable immediately.
+pub struct FaultInjectionFileSystem<FS: SFileSystem> {
+    inner: Arc<FS>,
+    config: FaultInjectionConfig,
+    state: Arc<Mutex<FaultState>>,
+}
+
+pub struct FaultInjectionFileHandle<'a, FS: SFileSystem + 'a> {
+    fs: &'a FaultInjectionFileSystem<FS>,
+    inner: FS::Handle<'a>,
+    pub path: PathBuf,
+    /// `path`, normalized, under which the unsynced writes of the file are kept
+    key: PathBuf,
+    flags: FileFlags,
+    position: Mutex<u64>,
+}
+
+impl<FS: SFileSystem> Clone for FaultInjectionFileSystem<FS> {
+    fn clone(&self) -> Self {
+        FaultInjectionFileSystem {
+            inner: self.inner.clone(),
+            config: self.config,
+            state: self.state.clone(),
+        }
+    }
+}
+
+impl<FS: SFileSystem + Debug> Debug for FaultInjectionFileSystem<FS> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("FaultInjectionFileSystem")
+            .field("inner", &self.inner)
+            .field("config", &self.config)
+            .finish()
+    }
+}
+
+impl<'a, FS: SFileSystem + 'a> Debug for FaultInjectionFileHandle<'a, FS> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("FaultInjectionFileHandle")
+            .field("path", &self.path)
+            .finish()
+    }
+}
+
+fn injected_error(operation: &str) -> Error {
+    Error::other(format!("injected fault: {} failed", operation))
+}
+
+/// The end of the range of `length` bytes at `offset`, which must fit in a `u64`
+fn range_end(offset: u64, length: u64) -> Result<u64> {
+    offset.checked_add(length).ok_or_else(|| Error::new(
+        ErrorKind::InvalidInput,
+        format!("range of {} bytes at offset {} is out of bounds", length, offset)
+    ))
+}
+
+impl FaultInjectionFileSystem<SFileSystemAdapter> {
+    /// Wraps a `UnifiedFileSystem`, such as the local file system of a database
+    pub fn wrap_unified(inner: UnifiedFileSystem, config: FaultInjectionConfig) -> Self {
+        Self::new(SFileSystemAdapter::new(Box::new(inner)), config)
+    }
+}
+
+impl<FS: SFileSystem> FaultInjectionFileSystem<FS> {
+    pub fn new(inner: FS, config: FaultInjectionConfig) -> Self {
+        assert!(config.sector_size > 0, "sector size must be positive");
+        FaultInjectionFileSystem {
+            inner: Arc::new(inner),
+            config,
+            state: Arc::new(Mutex::new(FaultState {
+                rng: FaultRng(config.seed),
+                scripted: VecDeque::new(),
+                pending: HashMap::new(),
+            })),
+        }
+    }
+
+    /// The wrapped file system, which holds the durable state of all files
+    pub fn inner(&self) -> &FS {
+        &self.inner
+    }
+
+    /// Schedules `fault` for the next operation of the matching kind
+    pub fn inject(&self, fault: InjectedFault) {
+        self.state.lock().unwrap().scripted.push_back(fault);
+    }
+
+    /// Returns whether any write has not been fsynced yet
+    pub fn has_unsynced_writes(&self) -> bool {
+        self.state.lock().unwrap().pending.values().any(|file| !file.writes.is_empty())
+    }
+
+    /// Simulates a power loss: every write that was not fsynced is dropped or, with
+    /// `torn_write_probability`, partially persisted at sector granularity.
+    /// Handles opened before the power loss should not be used afterwards.
+    pub fn simulate_power_loss(&self) -> Result<()> {
+        let mut state = self.state.lock().unwrap();
+        let pending = std::mem::take(&mut state.pending);
+        let sector_size = self.config.sector_size;
+
+        let mut keys = pending.keys().collect::<Vec<_>>();
+        keys.sort();
+        for key in keys {
+            let file = &pending[key];
+            let mut survivors = Vec::new();
+            for write in &file.writes {
+                if !state.rng.chance(self.config.torn_write_probability) {
+                    continue;
+                }
+                // sectors are aligned to the file, not to the start of the write
+                let end = write.location + write.data.len() as u64;
+                let mut sector_start = write.location;
+                while sector_start < end {
+                    let sector_end = ((sector_start / sector_size as u64 + 1) * sector_size as u64).min(end);
+                    if state.rng.next_u64() & 1 == 1 {
+                        let offset = (sector_start - write.location) as usize;
+                        let length = (sector_end - sector_start) as usize;
+                        survivors.push((sector_start, &write.data[offset..offset + length]));
+                    }
+                    sector_start = sector_end;
+                }
+            }
+            if survivors.is_empty() || !self.inner.file_exists(&file.path)? {
+                continue;
+            }
+
+            let handle = self.inner.open_file(&file.path, FileFlags::WRITE, FileLockType::NoLock)?;
+            for (location, data) in survivors {
+                self.inner.write_at(&handle, data, data.len() as i64, location)?;
+            }
+            self.inner.fsync(&handle)?;
+        }
+        Ok(())
+    }
+
+    fn should_inject(&self, fault: InjectedFault, probability: f64) -> bool {
+        let mut state = self.state.lock().unwrap();
+        if let Some(index) = state.scripted.iter().position(|f| *f == fault) {
+            state.scripted.remove(index);
+            return true;
+        }
+        state.rng.chance(probability)
+    }
+
+    fn short_count(&self, n: usize) -> usize {
+        self.state.lock().unwrap().rng.short_count(n)
+    }
+
+    /// Returns the size of the file including writes that are not yet synced
+    fn logical_size(&self, handle: &FaultInjectionFileHandle<'_, FS>) -> Result<u64> {
+        let durable_size = self.inner.file_size(&handle.inner)?;
+        let state = self.state.lock().unwrap();
+        let pending_end = state.pending.get(&handle.key)
+            .and_then(|file| file.writes.iter().map(|w| w.location + w.data.len() as u64).max())
+            .unwrap_or(0);
+        Ok(durable_size.max(pending_end))
+    }
+
+    /// Reads `buffer.len()` bytes at `location` as seen by the application: the durable
+    /// contents overlaid with the unsynced writes. The range must be within the file.
+    fn read_overlay(&self, handle: &FaultInjectionFileHandle<'_, FS>, buffer: &mut [u8], location: u64) -> Result<()> {
+        let durable_size = self.inner.file_size(&handle.inner)?;
+        let durable = durable_size.saturating_sub(location).min(buffer.len() as u64) as usize;
+        if durable > 0 {
+            self.inner.read_at(&handle.inner, &mut buffer[..durable], durable as i64, location)?;
+        }
+        buffer[durable..].fill(0);
+
+        let state = self.state.lock().unwrap();
+        let end = location + buffer.len() as u64;
+        for write in state.pending.get(&handle.key).into_iter().flat_map(|file| &file.writes) {
+            let write_end = write.location + write.data.len() as u64;
+            if write_end <= location || write.location >= end {
+                continue;
+            }
+            let start = write.location.max(location);
+            let stop = write_end.min(end);
+            let src = (start - write.location) as usize;
+            let dst = (start - location) as usize;
+            let length = (stop - start) as usize;
+            buffer[dst..dst + length].copy_from_slice(&write.data[src..src + length]);
+        }
+        Ok(())
+    }
+
+    fn open_fault_file<'a>(&'a self, path: &Path, flags: FileFlags, lock: FileLockType) -> Result<FaultInjectionFileHandle<'a, FS>> {
+        let inner = self.inner.open_file(path, flags, lock)?;
+        Ok(FaultInjectionFileHandle {
+            fs: self,
+            inner,
+            path: path.to_path_buf(),
+            key: normalize_path(path),
+            flags,
+            position: Mutex::new(0),
+        })
+    }
+
+    fn read_fault_file_at(&self, handle: &FaultInjectionFileHandle<'_, FS>, buffer: &mut [u8], nr_bytes: i64, location: u64) -> Result<()> {
+        let count = transfer_size(buffer.len(), nr_bytes)?;
+        let size = self.logical_size(handle)?;
+        let available = size.saturating_sub(location).min(count as u64) as usize;
+        let mut bytes_read = available;
+        if available > 1 && self.should_inject(InjectedFault::ShortRead, self.config.short_read_probability) {
+            bytes_read = self.short_count(available);
+        }
+        self.read_overlay(handle, &mut buffer[..bytes_read], location)?;
+
+        if bytes_read != count {
+            return Err(Error::new(
+                ErrorKind::UnexpectedEof,
+                format!("read_at failed: expected {} bytes, but read {}", nr_bytes, bytes_read)
+            ));
+        }
+        Ok(())
+    }
+
+    fn write_fault_file_at(&self, handle: &FaultInjectionFileHandle<'_, FS>, buffer: &[u8], nr_bytes: i64, location: u64) -> Result<()> {
+        let count = transfer_size(buffer.len(), nr_bytes)?;
+        let bytes_written = self.buffer_write(handle, &buffer[..count], location)?;
+        if bytes_written != count {
+            return Err(Error::new(
+                ErrorKind::WriteZero,
+                format!("write_at failed: expected {} bytes, but wrote {}", nr_bytes, bytes_written)
+            ));
+        }
+        Ok(())
+    }
+
+    fn read_fault_file(&self, handle: &FaultInjectionFileHandle<'_, FS>, buffer: &mut [u8], nr_bytes: i64) -> Result<u64> {
+        let count = transfer_size(buffer.len(), nr_bytes)?;
+        let mut position = handle.position.lock().unwrap();
+        let size = self.logical_size(handle)?;
+        let mut bytes_read = size.saturating_sub(*position).min(count as u64) as usize;
+        if bytes_read > 1 && self.should_inject(InjectedFault::ShortRead, self.config.short_read_probability) {
+            bytes_read = self.short_count(bytes_read);
+        }
+        self.read_overlay(handle, &mut buffer[..bytes_read], *position)?;
+        *position += bytes_read as u64;
+        Ok(bytes_read as u64)
+    }
+
+    fn write_fault_file(&self, handle: &FaultInjectionFileHandle<'_, FS>, buffer: &[u8], nr_bytes: i64) -> Result<u64> {
+        let count = transfer_size(buffer.len(), nr_bytes)?;
+        let mut position = handle.position.lock().unwrap();
+        let bytes_written = self.buffer_write(handle, &buffer[..count], *position)?;
+        *position += bytes_written as u64;
+        Ok(bytes_written as u64)
+    }
+
+    /// Adds a write to the volatile cache, possibly cut short; returns the bytes written
+    fn buffer_write(&self, handle: &FaultInjectionFileHandle<'_, FS>, buffer: &[u8], location: u64) -> Result<usize> {
+        if !handle.flags.contains(FileFlags::WRITE) {
+            return Err(Error::new(
+                ErrorKind::PermissionDenied,
+                format!("file '{}' was not opened for writing", handle.path.display())
+            ));
+        }
+        range_end(location, buffer.len() as u64)?;
+        let mut count = buffer.len();
+        if count > 1 && self.should_inject(InjectedFault::ShortWrite, self.config.short_write_probability) {
+            count = self.short_count(count);
+        }
+        if count > 0 {
+            let mut state = self.state.lock().unwrap();
+            let file = state.pending.entry(handle.key.clone()).or_insert_with(|| PendingFile {
+                path: handle.path.clone(),
+                writes: Vec::new(),
+            });
+            file.writes.push(PendingWrite {
+                location,
+                data: buffer[..count].to_vec(),
+            });
+        }
+        Ok(count)
+    }
+
+    fn fsync_fault_file(&self, handle: &FaultInjectionFileHandle<'_, FS>) -> Result<()> {
+        if self.should_inject(InjectedFault::FsyncFailure, self.config.fsync_failure_probability) {
+            return Err(injected_error("fsync"));
+        }
+
+        let Some(file) = self.state.lock().unwrap().pending.remove(&handle.key) else {
+            return self.inner.fsync(&handle.inner);
+        };
+        for (i, write) in file.writes.iter().enumerate() {
+            if let Err(e) = self.inner.write_at(&handle.inner, &write.data, write.data.len() as i64, write.location) {
+                // keep whatever did not reach the inner file system pending
+                let mut state = self.state.lock().unwrap();
+                let remaining = state.pending.entry(handle.key.clone()).or_insert_with(|| PendingFile {
+                    path: file.path.clone(),
+                    writes: Vec::new(),
+                });
+                remaining.writes.splice(0..0, file.writes.into_iter().skip(i));
+                return Err(e);
+            }
+        }
+        self.inner.fsync(&handle.inner)
+    }
+
+    /// Removes the range `start..end` from the unsynced writes of the file under `key`
+    fn discard_pending(&self, key: &Path, start: u64, end: u64) {
+        let mut state = self.state.lock().unwrap();
+        let Some(PendingFile { writes, .. }) = state.pending.get_mut(key) else {
+            return;
+        };
+        let mut kept = Vec::with_capacity(writes.len());
+        for write in writes.drain(..) {
+            let write_end = write.location + write.data.len() as u64;
+            if write_end <= start || write.location >= end {
+                kept.push(write);
+                continue;
+            }
+            if write.location < start {
+                kept.push(PendingWrite {
+                    location: write.location,
+                    data: write.data[..(start - write.location) as usize].to_vec(),
+                });
+            }
+            if write_end > end {
+                kept.push(PendingWrite {
+                    location: end,
+                    data: write.data[(end - write.location) as usize..].to_vec(),
+                });
+            }
+        }
+        *writes = kept;
+    }
+
+    /// Truncation is a metadata operation, which is durable immediately
+    fn truncate_fault_file(&self, handle: &FaultInjectionFileHandle<'_, FS>, new_size: u64) -> Result<()> {
+        self.inner.truncate(&handle.inner, new_size)?;
+        self.discard_pending(&handle.key, new_size, u64::MAX);
+        Ok(())
+    }
+
+    fn punch_fault_hole(&self, handle: &FaultInjectionFileHandle<'_, FS>, offset: u64, length: u64) -> Result<()> {
+        let end = range_end(offset, length)?;
+        self.inner.punch_hole(&handle.inner, offset, length)?;
+        self.discard_pending(&handle.key, offset, end);
+        Ok(())
+    }
+
+    fn remove_fault_file(&self, file_name: &Path) -> Result<()> {
+        self.inner.remove_file(file_name)?;
+        self.state.lock().unwrap().pending.remove(&normalize_path(file_name));
+        Ok(())
+    }
+
+    fn remove_fault_directory(&self, path: &Path) -> Result<()> {
+        self.inner.remove_directory(path)?;
+        let directory = normalize_path(path);
+        self.state.lock().unwrap().pending.retain(|key, _| !key.starts_with(&directory));
+        Ok(())
+    }
+
+    /// Directory syncs fail like file syncs, with `fsync_failure_probability`
+    fn sync_fault_directory(&self, directory: &Path) -> Result<()> {
+        if self.should_inject(InjectedFault::FsyncFailure, self.config.fsync_failure_probability) {
+            return Err(injected_error("directory fsync"));
+        }
+        self.inner.sync_directory(directory)
+    }
+
+    fn move_fault_file(&self, src: &Path, dst: &Path) -> Result<()> {
+        self.inner.move_file(src, dst)?;
+        let (src_key, dst_key) = (normalize_path(src), normalize_path(dst));
+        let mut state = self.state.lock().unwrap();
+        let moved = state.pending.keys()
+            .filter(|key| key.starts_with(&src_key))
+            .cloned()
+            .collect::<Vec<_>>();
+        state.pending.remove(&dst_key);
+        for key in moved {
+            let mut file = state.pending.remove(&key).unwrap();
+            let relative = key.strip_prefix(&src_key).unwrap();
+            if relative.as_os_str().is_empty() {
+                file.path = dst.to_path_buf();
+                state.pending.insert(dst_key.clone(), file);
+            } else {
+                file.path = dst.join(relative);
+                state.pending.insert(dst_key.join(relative), file);
+            }
+        }
+        Ok(())
+    }
+}
+
+impl<'a, FS: SFileSystem + 'a> SFileHandle<FaultInjectionFileSystem<FS>> for FaultInjectionFileHandle<'a, FS> {
+    fn file_system(&self) -> &FaultInjectionFileSystem<FS> {
+        self.fs
+    }
+
+    fn path(&self) -> &Path {
+        &self.path
+    }
+
+    fn close(&mut self) -> Result<()> {
+        self.inner.close()
+    }
+}
+
+impl<FS: SFileSystem> SFileSystem for FaultInjectionFileSystem<FS> {
+    type Handle<'a> = FaultInjectionFileHandle<'a, FS>
+    where
+        Self: 'a;
+
+    fn open_file<'a>(&'a self, path: &Path, flags: FileFlags, lock: FileLockType) -> Result<Self::Handle<'a>> {
+        self.open_fault_file(path, flags, lock)
+    }
+
+    fn read_at(&self, handle: &Self::Handle<'_>, buffer: &mut [u8], nr_bytes: i64, location: u64) -> Result<()> {
+        self.read_fault_file_at(handle, buffer, nr_bytes, location)
+    }
+
+    fn write_at(&self, handle: &Self::Handle<'_>, buffer: &[u8], nr_bytes: i64, location: u64) -> Result<()> {
+        self.write_fault_file_at(handle, buffer, nr_bytes, location)
+    }
+
+    fn set_file_pointer(&self, handle: &Self::Handle<'_>, location: u64) -> Result<()> {
+        *handle.position.lock().unwrap() = location;
+        Ok(())
+    }
+
+    fn read(&self, handle: &Self::Handle<'_>, buffer: &mut [u8], nr_bytes: i64) -> Result<u64> {
+        self.read_fault_file(handle, buffer, nr_bytes)
+    }
+
+    fn write(&self, handle: &Self::Handle<'_>, buffer: &[u8], nr_bytes: i64) -> Result<u64> {
+        self.write_fault_file(handle, buffer, nr_bytes)
+    }
+
+    fn file_size(&self, handle: &Self::Handle<'_>) -> Result<u64> {
+        self.logical_size(handle)
+    }
+
+    fn directory_exists(&self, path: &Path) -> Result<bool> {
+        self.inner.directory_exists(path)
+    }
+
+    fn file_exists(&self, file_name: &Path) -> Result<bool> {
+        self.inner.file_exists(file_name)
+    }
+
+    fn create_directory(&self, path: &Path) -> Result<()> {
+        self.inner.create_directory(path)
+    }
+
+    fn remove_directory(&self, path: &Path) -> Result<()> {
+        self.remove_fault_directory(path)
+    }
+
+    fn remove_file(&self, file_name: &Path) -> Result<()> {
+        self.remove_fault_file(file_name)
+    }
+
+    fn list_files<F>(&self, directory: &Path, callback: F) -> Result<bool>
+    where F: FnMut(String) {
+        self.inner.list_files(directory, callback)
+    }
+
+    fn path_separator(&self) -> &'static str {
+        self.inner.path_separator()
+    }
+
+    fn fsync(&self, handle: &Self::Handle<'_>) -> Result<()> {
+        self.fsync_fault_file(handle)
+    }
+
+    fn truncate(&self, handle: &Self::Handle<'_>, new_size: u64) -> Result<()> {
+        self.truncate_fault_file(handle, new_size)
+    }
+
+    fn allocate(&self, handle: &Self::Handle<'_>, offset: u64, length: u64) -> Result<()> {
+        self.inner.allocate(&handle.inner, offset, length)
+    }
+
+    fn punch_hole(&self, handle: &Self::Handle<'_>, offset: u64, length: u64) -> Result<()> {
+        self.punch_fault_hole(handle, offset, length)
+    }
+
+    fn sync_directory(&self, directory: &Path) -> Result<()> {
+        self.sync_fault_directory(directory)
+    }
+
+    fn move_file(&self, src: &Path, dst: &Path) -> Result<()> {
+        self.move_fault_file(src, dst)
+    }
+
+    fn join_path(&self, l: &Path, r: &Path) -> Result<PathBuf> {
+        self.inner.join_path(l, r)
+    }
+}
@@ -0,0 +1,169 @@
+use std::io::Result;
+use std::path::{Path, PathBuf};
+
+/// Returns whether `pattern` contains any glob wildcard
+pub fn has_glob(pattern: &str) -> bool {
+    pattern.contains(['*', '?', '['])
+}
+
+/// Matches a single path component against a glob pattern supporting `*` (any run of
+/// characters), `?` (any single character) and `[...]` (character classes with ranges,
+/// negated by a leading `!` or `^`).
+pub fn glob_match(pattern: &str, name: &str) -> bool {
+    let pattern = pattern.chars().collect::<Vec<_>>();
+    let name = name.chars().collect::<Vec<_>>();
+
+    let (mut p, mut n) = (0, 0);
+    // the position after the last `*` in the pattern, and the name position it matched up to
+    let mut backtrack: Option<(usize, usize)> = None;
+    while n < name.len() {
+        let step = match pattern.get(p) {
+            Some('*') => {
+                backtrack = Some((p + 1, n));
+                p += 1;
+                continue;
+            }
+            Some('?') => Some(p + 1),
+            Some('[') => match_class(&pattern, p, name[n]),
+            Some(c) if *c == name[n] => Some(p + 1),
+            _ => None,
+        };
+        match (step, backtrack) {
+            (Some(next), _) => {
+                p = next;
+                n += 1;
+            }
+            (None, Some((star, matched))) => {
+                // let the last `*` swallow one more character
+                p = star;
+                n = matched + 1;
+                backtrack = Some((star, matched + 1));
+            }
+            (None, None) => return false,
+        }
+    }
+    pattern[p..].iter().all(|c| *c == '*')
+}
+
+/// Matches `c` against the character class starting at `pattern[start] == '['`.
+/// Returns the position after the class if it matches. An unterminated class is
+/// treated as a literal `[`.
+fn match_class(pattern: &[char], start: usize, c: char) -> Option<usize> {
+    let mut i = start + 1;
+    let negated = matches!(pattern.get(i), Some('!') | Some('^'));
+    if negated {
+        i += 1;
+    }
+
+    let mut matched = false;
+    let mut first = true;
+    loop {
+        match pattern.get(i) {
+            None => return if c == '[' { Some(start + 1) } else { None },
+            Some(']') if !first => break,
+            Some(&low) => {
+                if pattern.get(i + 1) == Some(&'-') && pattern.get(i + 2).is_some_and(|high| *high != ']') {
+                    matched |= low <= c && c <= pattern[i + 2];
+                    i += 3;
+                } else {
+                    matched |= low == c;
+                    i += 1;
+                }
+            }
+        }
+        first = false;
+    }
+
+    if matched != negated { Some(i + 1) } else { None }
+}
+
+/// Calls the callback with the name of each entry of the directory, see `list_files`
+pub(crate) type ListFiles<'a> = &'a dyn Fn(&Path, &mut dyn FnMut(String)) -> Result<bool>;
+
+/// The file system operations that glob expansion is built on
+pub(crate) struct GlobSource<'a> {
+    pub list_files: ListFiles<'a>,
+    pub directory_exists: &'a dyn Fn(&Path) -> Result<bool>,
+    pub file_exists: &'a dyn Fn(&Path) -> Result<bool>,
+    /// Whether a path is a symbolic link, which `**` does not descend into, so that a
+    /// link to a parent directory cannot make the expansion recurse forever
+    pub is_symlink: &'a dyn Fn(&Path) -> Result<bool>,
+}
+
+/// Expands `pattern` to the sorted list of files it matches. Wildcards match within a
+/// single path component, except for a `**` component, which matches any number of
+/// directories (including none); a trailing `**` matches every file below the
+/// directory. Symbolic links to directories are not followed by `**`.
+pub(crate) fn expand_glob(source: &GlobSource<'_>, pattern: &str) -> Result<Vec<PathBuf>> {
+    if !has_glob(pattern) {
+        let path = PathBuf::from(pattern);
+        return Ok(if (source.file_exists)(&path)? { vec![path] } else { Vec::new() });
+    }
+
+    let (base, rest) = match pattern.strip_prefix('/') {
+        Some(rest) => (Some(PathBuf::from("/")), rest),
+        None => (None, pattern),
+    };
+    let components = rest.split('/')
+        .filter(|component| !component.is_empty() && *component != ".")
+        .collect::<Vec<_>>();
+
+    let mut result = Vec::new();
+    expand_components(source, base, &components, &mut result)?;
+    result.sort();
+    result.dedup();
+    Ok(result)
+}
+
+/// `base` is `None` for the current directory of relative patterns
+fn expand_components(source: &GlobSource<'_>, base: Option<PathBuf>, components: &[&str], result: &mut Vec<PathBuf>) -> Result<()> {
+    let Some((component, rest)) = components.split_first() else {
+        if let Some(path) = base
+            && (source.file_exists)(&path)?
+        {
+            result.push(path);
+        }
+        return Ok(());
+    };
+
+    let join = |name: &str| match &base {
+        Some(base) => base.join(name),
+        None => PathBuf::from(name),
+    };
+
+    if !has_glob(component) {
+        let path = join(component);
+        if rest.is_empty() || (source.directory_exists)(&path)? {
+            expand_components(source, Some(path), rest, result)?;
+        }
+        return Ok(());
+    }
+
+    let directory = base.clone().unwrap_or_else(|| PathBuf::from("."));
+    let mut names = Vec::new();
+    (source.list_files)(&directory, &mut |name| names.push(name))?;
+    names.sort();
+
+    if *component == "**" {
+        expand_components(source, base.clone(), rest, result)?;
+        for name in names {
+            let path = join(&name);
+            if (source.directory_exists)(&path)? {
+                if !(source.is_symlink)(&path)? {
+                    expand_components(source, Some(path), components, result)?;
+                }
+            } else if rest.is_empty() && (source.file_exists)(&path)? {
+                result.push(path);
+            }
+        }
+        return Ok(());
+    }
+
+    for name in names.iter().filter(|name| glob_match(component, name)) {
+        let path = join(name);
+        if rest.is_empty() || (source.directory_exists)(&path)? {
+            expand_components(source, Some(path), rest, result)?;
+        }
+    }
+    Ok(())
+}
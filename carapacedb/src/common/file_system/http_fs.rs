@@ -0,0 +1,637 @@
+use std::any::TypeId;
+use std::collections::HashMap;
+use std::fmt::Debug;
+use std::io::{BufRead, BufReader, Error, ErrorKind, Read, Result, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, OnceLock};
+use std::time::Duration;
+
+use rustls::pki_types::ServerName;
+use rustls::{ClientConfig, ClientConnection, RootCertStore, StreamOwned};
+
+use super::dynamic_fs::{downcast_handle, DynFileHandle, DynFileSystem, Token};
+use super::virtual_fs::split_scheme;
+use super::{transfer_size, FileFlags, FileLockType};
+
+#[derive(Debug, Clone)]
+pub struct HttpConfig {
+    /// Reads fetch and cache whole blocks of this many bytes; must not be 0
+    pub block_size: u64,
+    /// The number of blocks kept in the cache; 0 disables caching
+    pub cache_blocks: usize,
+    /// How often a request that failed with a transient error is retried
+    pub retries: u32,
+    /// The delay before the first retry, doubled for every further one
+    pub retry_backoff: Duration,
+    pub timeout: Duration,
+}
+
+impl Default for HttpConfig {
+    fn default() -> Self {
+        HttpConfig {
+            block_size: 1 << 20,
+            cache_blocks: 64,
+            retries: 3,
+            retry_backoff: Duration::from_millis(100),
+            timeout: Duration::from_secs(30),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HttpResponse {
+    pub status: u16,
+    /// Header names are lowercase
+    pub headers: Vec<(String, String)>,
+    pub body: Vec<u8>,
+}
+
+impl HttpResponse {
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers.iter()
+            .find(|(key, _)| key.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+}
+
+/// Sends HTTP requests for an `HttpFileSystem`, see `TcpTransport`
+pub trait HttpTransport: Debug + Send + Sync {
+    fn request(&self, method: &str, url: &str, headers: &[(&str, String)], body: &[u8]) -> Result<HttpResponse>;
+}
+
+/// An HTTP/1.1 client opening one connection per request. `https` URLs are sent over
+/// TLS, verifying the server against the Mozilla root certificates.
+#[derive(Debug, Clone)]
+pub struct TcpTransport {
+    timeout: Duration,
+}
+
+/// A read-only file system for files served over HTTP. The server must support HEAD
+/// requests and range requests; reads fetch whole blocks, which are kept in an LRU
+/// cache shared by all handles.
+#[derive(Debug)]
+pub struct HttpFileSystem {
+    scheme: String,
+    config: HttpConfig,
+    transport: Box<dyn HttpTransport>,
+    cache: Mutex<BlockCache>,
+}
+
+#[derive(Debug)]
+pub struct HttpFileHandle<'fs> {
+    fs: &'fs HttpFileSystem,
+    path: PathBuf,
+    url: String,
+    size: u64,
+    /// The ETag of the file, or else its modification time
+    version: Option<String>,
+    position: Mutex<u64>,
+}
+
+#[derive(Debug)]
+struct CachedBlock {
+    last_use: u64,
+    data: Arc<Vec<u8>>,
+}
+
+#[derive(Debug)]
+struct BlockCache {
+    capacity: usize,
+    tick: u64,
+    blocks: HashMap<BlockKey, CachedBlock>,
+}
+
+/// A block of a file by URL, version and index, so that the blocks of a file that
+/// changed on the server are never served for its new version
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+struct BlockKey {
+    url: String,
+    version: Option<String>,
+    index: u64,
+}
+
+impl BlockKey {
+    fn new(handle: &HttpFileHandle<'_>, index: u64) -> Self {
+        BlockKey { url: handle.url.clone(), version: handle.version.clone(), index }
+    }
+}
+
+impl BlockCache {
+    fn get(&mut self, key: &BlockKey) -> Option<Arc<Vec<u8>>> {
+        self.tick += 1;
+        let tick = self.tick;
+        let block = self.blocks.get_mut(key)?;
+        block.last_use = tick;
+        Some(block.data.clone())
+    }
+
+    fn insert(&mut self, key: BlockKey, data: Arc<Vec<u8>>) {
+        if self.capacity == 0 {
+            return;
+        }
+        if self.blocks.len() >= self.capacity {
+            let oldest = self.blocks.iter()
+                .min_by_key(|(_, block)| block.last_use)
+                .map(|(key, _)| key.clone());
+            if let Some(oldest) = oldest {
+                self.blocks.remove(&oldest);
+            }
+        }
+        self.tick += 1;
+        self.blocks.insert(key, CachedBlock { last_use: self.tick, data });
+    }
+}
+
+impl TcpTransport {
+    pub fn new(timeout: Duration) -> Self {
+        TcpTransport { timeout }
+    }
+}
+
+/// The parts of an `http[s]://host[:port]/path` URL that a request needs
+#[derive(Debug, PartialEq, Eq)]
+struct HttpUrl<'u> {
+    https: bool,
+    /// The host without the brackets of an IPv6 address
+    host: &'u str,
+    port: u16,
+    /// The value of the host header
+    authority: &'u str,
+    target: &'u str,
+}
+
+fn parse_http_url(url: &str) -> Result<HttpUrl<'_>> {
+    let (https, rest) = match url.split_once("://") {
+        Some((scheme, rest)) if scheme.eq_ignore_ascii_case("http") => (false, rest),
+        Some((scheme, rest)) if scheme.eq_ignore_ascii_case("https") => (true, rest),
+        _ => return Err(Error::new(
+            ErrorKind::Unsupported,
+            format!("cannot fetch '{}': only http and https are supported", url)
+        )),
+    };
+    let (authority, target) = match rest.find('/') {
+        Some(index) => (&rest[..index], &rest[index..]),
+        None => (rest, "/"),
+    };
+    let invalid = || Error::new(ErrorKind::InvalidInput, format!("invalid host in url '{}'", url));
+    // the port follows the last colon, unless that colon is part of an IPv6 address
+    let (host, port) = match authority.rsplit_once(':') {
+        Some((host, port)) if !port.contains(']') => (host, Some(port.parse::<u16>().map_err(|_| invalid())?)),
+        _ => (authority, None),
+    };
+    let host = match host.strip_prefix('[') {
+        Some(bracketed) => bracketed.strip_suffix(']').ok_or_else(invalid)?,
+        None if host.contains(':') => return Err(invalid()),
+        None => host,
+    };
+    if host.is_empty() {
+        return Err(invalid());
+    }
+    let port = port.unwrap_or(if https { 443 } else { 80 });
+    Ok(HttpUrl { https, host, port, authority, target })
+}
+
+/// Connects to the first address of `url` that accepts within `timeout`
+fn connect(url: &HttpUrl<'_>, timeout: Duration, full_url: &str) -> Result<TcpStream> {
+    let mut last_error = None;
+    for address in (url.host, url.port).to_socket_addrs()? {
+        match TcpStream::connect_timeout(&address, timeout) {
+            Ok(stream) => return Ok(stream),
+            Err(e) => last_error = Some(e),
+        }
+    }
+    Err(last_error.unwrap_or_else(|| Error::new(
+        ErrorKind::NotFound,
+        format!("cannot fetch '{}': '{}' has no address", full_url, url.host)
+    )))
+}
+
+fn tls_config() -> Arc<ClientConfig> {
+    static CONFIG: OnceLock<Arc<ClientConfig>> = OnceLock::new();
+    CONFIG.get_or_init(|| {
+        let roots = RootCertStore { roots: webpki_roots::TLS_SERVER_ROOTS.to_vec() };
+        let config = ClientConfig::builder_with_provider(Arc::new(rustls::crypto::ring::default_provider()))
+            .with_safe_default_protocol_versions()
+            .expect("the ring provider supports the default protocol versions")
+            .with_root_certificates(roots)
+            .with_no_client_auth();
+        Arc::new(config)
+    }).clone()
+}
+
+pub(crate) fn invalid_response(url: &str, message: &str) -> Error {
+    Error::new(ErrorKind::InvalidData, format!("invalid response from '{}': {}", url, message))
+}
+
+/// Reads the status line and headers of a response, skipping interim 1xx responses
+fn read_response_head(reader: &mut impl BufRead, url: &str) -> Result<HttpResponse> {
+    loop {
+        let mut line = String::new();
+        reader.read_line(&mut line)?;
+        let status = line.split_whitespace().nth(1)
+            .and_then(|status| status.parse::<u16>().ok())
+            .ok_or_else(|| invalid_response(url, "bad status line"))?;
+
+        let mut headers = Vec::new();
+        loop {
+            line.clear();
+            if reader.read_line(&mut line)? == 0 {
+                return Err(Error::new(ErrorKind::UnexpectedEof, format!("connection to '{}' closed in headers", url)));
+            }
+            let header = line.trim_end();
+            if header.is_empty() {
+                break;
+            }
+            let (name, value) = header.split_once(':').ok_or_else(|| invalid_response(url, "bad header"))?;
+            headers.push((name.trim().to_ascii_lowercase(), value.trim().to_string()));
+        }
+        if !(100..200).contains(&status) {
+            return Ok(HttpResponse { status, headers, body: Vec::new() });
+        }
+    }
+}
+
+fn read_chunked_body(reader: &mut impl BufRead, url: &str) -> Result<Vec<u8>> {
+    let mut body = Vec::new();
+    loop {
+        let mut line = String::new();
+        reader.read_line(&mut line)?;
+        let size_field = line.trim().split(';').next().unwrap_or("");
+        let size = usize::from_str_radix(size_field, 16)
+            .map_err(|_| invalid_response(url, "bad chunk size"))?;
+        if size == 0 {
+            // skip the trailer
+            loop {
+                line.clear();
+                if reader.read_line(&mut line)? == 0 || line.trim().is_empty() {
+                    return Ok(body);
+                }
+            }
+        }
+        let start = body.len();
+        body.resize(start + size, 0);
+        reader.read_exact(&mut body[start..])?;
+        line.clear();
+        reader.read_line(&mut line)?;
+    }
+}
+
+/// Sends a request over `stream`, which is closed after the response
+fn exchange(mut stream: impl Read + Write, method: &str, url: &HttpUrl<'_>, full_url: &str, headers: &[(&str, String)], body: &[u8]) -> Result<HttpResponse> {
+    let mut request = format!("{} {} HTTP/1.1\r\nHost: {}\r\nConnection: close\r\n", method, url.target, url.authority);
+    for (name, value) in headers {
+        request.push_str(&format!("{}: {}\r\n", name, value));
+    }
+    if !body.is_empty() || method == "PUT" || method == "POST" {
+        request.push_str(&format!("Content-Length: {}\r\n", body.len()));
+    }
+    request.push_str("\r\n");
+    stream.write_all(request.as_bytes())?;
+    stream.write_all(body)?;
+    stream.flush()?;
+
+    let mut reader = BufReader::new(stream);
+    let mut response = read_response_head(&mut reader, full_url)?;
+    if method == "HEAD" || response.status == 204 || response.status == 304 {
+        return Ok(response);
+    }
+    if response.header("transfer-encoding").is_some_and(|encoding| encoding.eq_ignore_ascii_case("chunked")) {
+        response.body = read_chunked_body(&mut reader, full_url)?;
+    } else if let Some(length) = response.header("content-length") {
+        let length = length.parse::<usize>().map_err(|_| invalid_response(full_url, "bad content length"))?;
+        response.body.resize(length, 0);
+        reader.read_exact(&mut response.body)?;
+    } else {
+        reader.read_to_end(&mut response.body)?;
+    }
+    Ok(response)
+}
+
+impl HttpTransport for TcpTransport {
+    fn request(&self, method: &str, url: &str, headers: &[(&str, String)], body: &[u8]) -> Result<HttpResponse> {
+        let parsed = parse_http_url(url)?;
+        let stream = connect(&parsed, self.timeout, url)?;
+        stream.set_read_timeout(Some(self.timeout))?;
+        stream.set_write_timeout(Some(self.timeout))?;
+        if !parsed.https {
+            return exchange(stream, method, &parsed, url, headers, body);
+        }
+
+        let server_name = ServerName::try_from(parsed.host.to_string())
+            .map_err(|e| Error::new(ErrorKind::InvalidInput, format!("invalid host in url '{}': {}", url, e)))?;
+        let connection = ClientConnection::new(tls_config(), server_name).map_err(Error::other)?;
+        exchange(StreamOwned::new(connection, stream), method, &parsed, url, headers, body)
+    }
+}
+
+/// Whether a request that failed with `error` may succeed when retried
+fn is_transient(error: &Error) -> bool {
+    matches!(
+        error.kind(),
+        ErrorKind::ConnectionRefused | ErrorKind::ConnectionReset | ErrorKind::ConnectionAborted
+            | ErrorKind::BrokenPipe | ErrorKind::TimedOut | ErrorKind::WouldBlock
+            | ErrorKind::Interrupted | ErrorKind::UnexpectedEof
+    )
+}
+
+pub(crate) fn status_error(status: u16, url: &str) -> Error {
+    let kind = match status {
+        404 | 410 => ErrorKind::NotFound,
+        401 | 403 => ErrorKind::PermissionDenied,
+        416 => ErrorKind::UnexpectedEof,
+        _ => ErrorKind::Other,
+    };
+    Error::new(kind, format!("request for '{}' failed with status {}", url, status))
+}
+
+fn read_only(operation: &str, path: &Path) -> Error {
+    Error::new(
+        ErrorKind::PermissionDenied,
+        format!("cannot {} '{}': http file system is read-only", operation, path.display())
+    )
+}
+
+impl HttpFileSystem {
+    /// Creates a file system for URLs with `scheme`, which sends its requests through
+    /// a `TcpTransport`
+    pub fn new(scheme: &str, config: HttpConfig) -> Self {
+        let transport = TcpTransport::new(config.timeout);
+        Self::with_transport(scheme, config, Box::new(transport))
+    }
+
+    pub fn with_transport(scheme: &str, config: HttpConfig, transport: Box<dyn HttpTransport>) -> Self {
+        let cache = BlockCache { capacity: config.cache_blocks, tick: 0, blocks: HashMap::new() };
+        HttpFileSystem {
+            scheme: scheme.to_ascii_lowercase(),
+            config,
+            transport,
+            cache: Mutex::new(cache),
+        }
+    }
+
+    pub fn config(&self) -> &HttpConfig {
+        &self.config
+    }
+
+    pub fn clear_cache(&self) {
+        self.cache.lock().unwrap().blocks.clear();
+    }
+
+    /// The URL of `path`, which is either a full URL or, as passed on by a
+    /// `VirtualFileSystem`, a URL with the scheme stripped
+    fn url(&self, path: &Path) -> Result<String> {
+        let Some(path_str) = path.to_str() else {
+            return Err(Error::new(ErrorKind::InvalidInput, format!("invalid url '{}'", path.display())));
+        };
+        match split_scheme(path) {
+            (Some(_), _) => Ok(path_str.to_string()),
+            (None, _) => Ok(format!("{}://{}", self.scheme, path_str)),
+        }
+    }
+
+    /// Sends a request once, without retrying it
+    pub(crate) fn request_once(&self, method: &str, url: &str, headers: &[(&str, String)], body: &[u8]) -> Result<HttpResponse> {
+        self.transport.request(method, url, headers, body)
+    }
+
+    /// Sends a request, retrying transient failures and server errors
+    pub(crate) fn request(&self, method: &str, url: &str, headers: &[(&str, String)], body: &[u8]) -> Result<HttpResponse> {
+        let mut backoff = self.config.retry_backoff;
+        let mut attempt = 0;
+        loop {
+            let error = match self.transport.request(method, url, headers, body) {
+                Ok(response) if response.status >= 500 || response.status == 429 => status_error(response.status, url),
+                Ok(response) => return Ok(response),
+                Err(e) if is_transient(&e) => e,
+                Err(e) => return Err(e),
+            };
+            if attempt >= self.config.retries {
+                return Err(error);
+            }
+            std::thread::sleep(backoff);
+            backoff *= 2;
+            attempt += 1;
+        }
+    }
+
+    /// The size and version of the file at `url`, or `None` if it does not exist
+    fn remote_metadata(&self, url: &str) -> Result<Option<(u64, Option<String>)>> {
+        let response = self.request("HEAD", url, &[], &[])?;
+        match response.status {
+            200..=299 => {
+                let length = response.header("content-length")
+                    .and_then(|length| length.parse::<u64>().ok())
+                    .ok_or_else(|| invalid_response(url, "missing content length"))?;
+                let version = response.header("etag").or(response.header("last-modified"));
+                Ok(Some((length, version.map(str::to_string))))
+            }
+            404 | 410 => Ok(None),
+            status => Err(status_error(status, url)),
+        }
+    }
+
+    fn fetch_block(&self, handle: &HttpFileHandle<'_>, index: u64) -> Result<Arc<Vec<u8>>> {
+        let key = BlockKey::new(handle, index);
+        if let Some(block) = self.cache.lock().unwrap().get(&key) {
+            return Ok(block);
+        }
+
+        let start = index * self.config.block_size;
+        let end = (start + self.config.block_size).min(handle.size);
+        let range = format!("bytes={}-{}", start, end - 1);
+        let response = self.request("GET", &handle.url, &[("Range", range)], &[])?;
+        let body = match response.status {
+            206 => response.body,
+            // the server ignored the range and sent the whole file
+            200 if response.body.len() as u64 >= end => response.body[start as usize..end as usize].to_vec(),
+            200 => return Err(invalid_response(&handle.url, "file is shorter than its content length")),
+            status => return Err(status_error(status, &handle.url)),
+        };
+        if body.len() as u64 != end - start {
+            return Err(invalid_response(&handle.url, "range response has the wrong length"));
+        }
+
+        let block = Arc::new(body);
+        self.cache.lock().unwrap().insert(key, block.clone());
+        Ok(block)
+    }
+
+    fn read_http_file(&self, handle: &HttpFileHandle<'_>, buffer: &mut [u8], nr_bytes: i64, location: u64) -> Result<()> {
+        let len = transfer_size(buffer.len(), nr_bytes)?;
+        let end = location + len as u64;
+        if end > handle.size {
+            return Err(Error::new(
+                ErrorKind::UnexpectedEof,
+                format!("read of {} bytes at {} is beyond the end of '{}'", len, location, handle.url)
+            ));
+        }
+
+        let block_size = self.config.block_size;
+        let mut position = location;
+        while position < end {
+            let block = self.fetch_block(handle, position / block_size)?;
+            let offset = (position % block_size) as usize;
+            let count = (block.len() - offset).min((end - position) as usize);
+            let copied = (position - location) as usize;
+            buffer[copied..copied + count].copy_from_slice(&block[offset..offset + count]);
+            position += count as u64;
+        }
+        Ok(())
+    }
+}
+
+impl<'fs> DynFileHandle<'fs> for HttpFileHandle<'fs> {
+    fn file_system(&self) -> &dyn DynFileSystem<'fs> {
+        self.fs
+    }
+
+    fn path(&self) -> &Path {
+        &self.path
+    }
+
+    fn close(&mut self) -> Result<()> {
+        Ok(())
+    }
+
+    fn handle_type_id(&self, _token: Token) -> Option<TypeId> {
+        Some(TypeId::of::<HttpFileHandle<'static>>())
+    }
+}
+
+/// Recovers the `HttpFileHandle` behind a handle passed to the `DynFileSystem` interface.
+fn http_handle<'h, 'fs>(handle: &'h dyn DynFileHandle<'fs>) -> Result<&'h HttpFileHandle<'fs>> {
+    downcast_handle(handle, TypeId::of::<HttpFileHandle<'static>>())
+}
+
+impl<'fs> DynFileSystem<'fs> for HttpFileSystem {
+    fn read_at(&self, handle: &dyn DynFileHandle<'fs>, buffer: &mut [u8], nr_bytes: i64, location: u64) -> Result<()> {
+        self.read_http_file(http_handle(handle)?, buffer, nr_bytes, location)
+    }
+
+    fn write_at(&self, handle: &dyn DynFileHandle<'fs>, _buffer: &[u8], _nr_bytes: i64, _location: u64) -> Result<()> {
+        Err(read_only("write", handle.path()))
+    }
+
+    fn open_file(&'fs self, path: &Path, flags: FileFlags, _lock: Option<FileLockType>) -> Result<Box<dyn DynFileHandle<'fs> + 'fs>> {
+        if flags.intersects(FileFlags::WRITE | FileFlags::CREATE) {
+            return Err(read_only("open for writing", path));
+        }
+        // reads are split into blocks of this size
+        if self.config.block_size == 0 {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                format!("cannot open '{}': the block size of the http file system is 0", path.display())
+            ));
+        }
+        let url = self.url(path)?;
+        let Some((size, version)) = self.remote_metadata(&url)? else {
+            return Err(Error::new(ErrorKind::NotFound, format!("file '{}' does not exist", url)));
+        };
+        Ok(Box::new(HttpFileHandle {
+            fs: self,
+            path: path.to_path_buf(),
+            url,
+            size,
+            version,
+            position: Mutex::new(0),
+        }))
+    }
+
+    fn set_file_pointer(&self, handle: &dyn DynFileHandle<'fs>, location: u64) -> Result<()> {
+        *http_handle(handle)?.position.lock().unwrap() = location;
+        Ok(())
+    }
+
+    /// Reads exactly `nr_bytes` from the current file pointer.
+    fn read(&self, handle: &dyn DynFileHandle<'fs>, buffer: &mut [u8], nr_bytes: i64) -> Result<()> {
+        let handle = http_handle(handle)?;
+        let mut position = handle.position.lock().unwrap();
+        self.read_http_file(handle, buffer, nr_bytes, *position)?;
+        *position += nr_bytes as u64;
+        Ok(())
+    }
+
+    fn write(&self, handle: &dyn DynFileHandle<'fs>, _buffer: &[u8], _nr_bytes: i64) -> Result<()> {
+        Err(read_only("write", handle.path()))
+    }
+
+    fn file_size(&self, handle: &dyn DynFileHandle<'fs>) -> Result<u64> {
+        Ok(http_handle(handle)?.size)
+    }
+
+    /// HTTP has no directories
+    fn directory_exists(&self, _path: &Path) -> Result<bool> {
+        Ok(false)
+    }
+
+    fn file_exists(&self, file_name: &Path) -> Result<bool> {
+        Ok(self.remote_metadata(&self.url(file_name)?)?.is_some())
+    }
+
+    fn create_directory(&self, path: &Path) -> Result<()> {
+        Err(read_only("create directory", path))
+    }
+
+    fn remove_directory(&self, path: &Path) -> Result<()> {
+        Err(read_only("remove directory", path))
+    }
+
+    fn remove_file(&self, file_name: &Path) -> Result<()> {
+        Err(read_only("remove", file_name))
+    }
+
+    fn list_files(&self, directory: &Path, _callback: &mut dyn FnMut(String)) -> Result<bool> {
+        Err(Error::new(
+            ErrorKind::Unsupported,
+            format!("cannot list '{}': http file system has no directories", directory.display())
+        ))
+    }
+
+    fn path_separator(&self) -> &'static str {
+        "/"
+    }
+
+    /// There is nothing to sync on a read-only file system
+    fn fsync(&self, _handle: &dyn DynFileHandle<'fs>) -> Result<()> {
+        Ok(())
+    }
+
+    fn truncate(&self, handle: &dyn DynFileHandle<'fs>, _new_size: u64) -> Result<()> {
+        Err(read_only("truncate", handle.path()))
+    }
+
+    fn allocate(&self, handle: &dyn DynFileHandle<'fs>, _offset: u64, _length: u64) -> Result<()> {
+        Err(read_only("allocate space in", handle.path()))
+    }
+
+    fn punch_hole(&self, handle: &dyn DynFileHandle<'fs>, _offset: u64, _length: u64) -> Result<()> {
+        Err(read_only("punch hole in", handle.path()))
+    }
+
+    fn sync_directory(&self, _directory: &Path) -> Result<()> {
+        Ok(())
+    }
+
+    fn move_file(&self, src: &Path, _dst: &Path) -> Result<()> {
+        Err(read_only("move", src))
+    }
+
+    fn file_version(&self, handle: &dyn DynFileHandle<'fs>) -> Result<Option<String>> {
+        Ok(http_handle(handle)?.version.clone())
+    }
+
+    fn join_path(&self, l: &Path, r: &Path) -> Result<PathBuf> {
+        Ok(l.join(r))
+    }
+
+    /// Patterns are not expanded, as there is no way to list files over HTTP
+    fn glob(&self, pattern: &str) -> Result<Vec<PathBuf>> {
+        let path = Path::new(pattern);
+        if self.file_exists(path)? {
+            Ok(vec![path.to_path_buf()])
+        } else {
+            Ok(Vec::new())
+        }
+    }
+}
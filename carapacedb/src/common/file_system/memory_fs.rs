@@ -0,0 +1,447 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::io::{Error, ErrorKind, Result};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, RwLock};
+
+use super::static_fs::{SFileHandle, SFileSystem};
+use super::{normalize_path, transfer_size, FileFlags, FileLockType};
+
+/// A file system that keeps all files and directories in memory.
+///
+/// It behaves like `LocalFileSystem`, which makes it suitable for running the storage
+/// layer without touching disk, e.g. for `:memory:` databases. Clones share the same
+/// underlying namespace. It implements `SFileSystem` only; wrap it in a
+/// `DynFileSystemAdapter` to use it as a plugin or in a `VirtualFileSystem`.
+///
+/// File locks follow the `fcntl` locks of `LocalFileSystem`: they are held by the
+/// process, so handles of the same process never conflict. Since the files of a memory
+/// file system are private to the process, locking a file never fails; only a write
+/// lock on a file opened for reading is rejected.
+#[derive(Debug, Clone, Default)]
+pub struct MemoryFileSystem {
+    state: Arc<RwLock<MemoryFsState>>,
+}
+
+#[derive(Debug, Default)]
+struct MemoryFsState {
+    files: BTreeMap<PathBuf, Arc<MemoryFile>>,
+    directories: BTreeSet<PathBuf>,
+}
+
+#[derive(Debug, Default)]
+struct MemoryFile {
+    data: RwLock<Vec<u8>>,
+}
+
+#[derive(Debug)]
+pub struct MemoryFileHandle<'a> {
+    fs: &'a MemoryFileSystem,
+    pub path: PathBuf,
+    file: Arc<MemoryFile>,
+    flags: FileFlags,
+    position: Mutex<u64>,
+}
+
+/// The end of the range of `length` bytes at `offset`, as an index into a file
+fn range_end(offset: u64, length: u64) -> Result<usize> {
+    offset.checked_add(length)
+        .and_then(|end| usize::try_from(end).ok())
+        .ok_or_else(|| Error::new(
+            ErrorKind::InvalidInput,
+            format!("range of {} bytes at offset {} is out of bounds", length, offset)
+        ))
+}
+
+/// Resizes `data` to `size` bytes, failing instead of aborting if memory runs out
+fn resize(data: &mut Vec<u8>, size: usize) -> Result<()> {
+    if size > data.len() {
+        data.try_reserve(size - data.len()).map_err(|e| Error::new(
+            ErrorKind::OutOfMemory,
+            format!("cannot grow file to {} bytes: {}", size, e)
+        ))?;
+    }
+    data.resize(size, 0);
+    Ok(())
+}
+
+impl MemoryFsState {
+    fn is_directory(&self, path: &Path) -> bool {
+        path.as_os_str().is_empty() || path == Path::new("/") || self.directories.contains(path)
+    }
+
+    fn parent_exists(&self, path: &Path) -> bool {
+        match path.parent() {
+            Some(parent) => self.is_directory(parent),
+            None => true,
+        }
+    }
+}
+
+impl MemoryFile {
+    fn read_at(&self, buffer: &mut [u8], location: u64) -> usize {
+        let data = self.data.read().unwrap();
+        let start = usize::try_from(location).unwrap_or(usize::MAX).min(data.len());
+        let count = buffer.len().min(data.len() - start);
+        buffer[..count].copy_from_slice(&data[start..start + count]);
+        count
+    }
+
+    fn write_at(&self, buffer: &[u8], location: u64) -> Result<()> {
+        let end = range_end(location, buffer.len() as u64)?;
+        let mut data = self.data.write().unwrap();
+        if data.len() < end {
+            resize(&mut data, end)?;
+        }
+        data[end - buffer.len()..end].copy_from_slice(buffer);
+        Ok(())
+    }
+}
+
+impl MemoryFileSystem {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn check_writable(handle: &MemoryFileHandle<'_>) -> Result<()> {
+        if handle.flags.contains(FileFlags::READ) {
+            return Err(Error::new(
+                ErrorKind::PermissionDenied,
+                format!("file '{}' was opened read-only", handle.path.display())
+            ));
+        }
+        Ok(())
+    }
+}
+
+impl<'a> SFileHandle<MemoryFileSystem> for MemoryFileHandle<'a> {
+    fn file_system(&self) -> &MemoryFileSystem {
+        self.fs
+    }
+
+    fn path(&self) -> &Path {
+        &self.path
+    }
+
+    fn close(&mut self) -> Result<()> {
+        Ok(())
+    }
+}
+
+impl SFileSystem for MemoryFileSystem {
+    type Handle<'a> = MemoryFileHandle<'a>;
+
+    fn open_file<'a>(&'a self, path: &Path, flags: FileFlags, lock: FileLockType) -> Result<Self::Handle<'a>> {
+        debug_assert!(
+            !flags.contains(FileFlags::READ | FileFlags::WRITE),
+            "cannot combine READ and WRITE flags"
+        );
+        debug_assert!(
+            !flags.contains(FileFlags::READ | FileFlags::CREATE),
+            "cannot combine READ and CREATE flags"
+        );
+
+        let key = normalize_path(path);
+        let file = {
+            let mut state = self.state.write().unwrap();
+            if state.is_directory(&key) {
+                return Err(Error::new(
+                    ErrorKind::IsADirectory,
+                    format!("cannot open '{}': is a directory", path.display())
+                ));
+            }
+            match state.files.get(&key) {
+                Some(file) => file.clone(),
+                None if flags.contains(FileFlags::CREATE) => {
+                    if !state.parent_exists(&key) {
+                        return Err(Error::new(
+                            ErrorKind::NotFound,
+                            format!("cannot create '{}': parent directory does not exist", path.display())
+                        ));
+                    }
+                    let file = Arc::new(MemoryFile::default());
+                    state.files.insert(key, file.clone());
+                    file
+                }
+                None => {
+                    return Err(Error::new(
+                        ErrorKind::NotFound,
+                        format!("cannot open '{}': no such file", path.display())
+                    ));
+                }
+            }
+        };
+
+        if lock == FileLockType::WriteLock && flags.contains(FileFlags::READ) {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                "cannot take a write lock on a file opened for reading"
+            ));
+        }
+
+        Ok(MemoryFileHandle {
+            fs: self,
+            path: path.to_path_buf(),
+            file,
+            flags,
+            position: Mutex::new(0),
+        })
+    }
+
+    fn read_at(&self, handle: &Self::Handle<'_>, buffer: &mut [u8], nr_bytes: i64, location: u64) -> Result<()> {
+        let count = transfer_size(buffer.len(), nr_bytes)?;
+        let bytes_read = handle.file.read_at(&mut buffer[..count], location);
+        if bytes_read != count {
+            return Err(Error::new(
+                ErrorKind::UnexpectedEof,
+                format!("read_at failed: expected {} bytes, but read {}", nr_bytes, bytes_read)
+            ));
+        }
+        Ok(())
+    }
+
+    fn write_at(&self, handle: &Self::Handle<'_>, buffer: &[u8], nr_bytes: i64, location: u64) -> Result<()> {
+        Self::check_writable(handle)?;
+        let count = transfer_size(buffer.len(), nr_bytes)?;
+        handle.file.write_at(&buffer[..count], location)
+    }
+
+    fn set_file_pointer(&self, handle: &Self::Handle<'_>, location: u64) -> Result<()> {
+        *handle.position.lock().unwrap() = location;
+        Ok(())
+    }
+
+    fn read(&self, handle: &Self::Handle<'_>, buffer: &mut [u8], nr_bytes: i64) -> Result<u64> {
+        let count = transfer_size(buffer.len(), nr_bytes)?;
+        let mut position = handle.position.lock().unwrap();
+        let bytes_read = handle.file.read_at(&mut buffer[..count], *position);
+        *position += bytes_read as u64;
+        Ok(bytes_read as u64)
+    }
+
+    fn write(&self, handle: &Self::Handle<'_>, buffer: &[u8], nr_bytes: i64) -> Result<u64> {
+        Self::check_writable(handle)?;
+        let count = transfer_size(buffer.len(), nr_bytes)?;
+        let mut position = handle.position.lock().unwrap();
+        handle.file.write_at(&buffer[..count], *position)?;
+        *position += count as u64;
+        Ok(count as u64)
+    }
+
+    fn file_size(&self, handle: &Self::Handle<'_>) -> Result<u64> {
+        Ok(handle.file.data.read().unwrap().len() as u64)
+    }
+
+    fn directory_exists(&self, path: &Path) -> Result<bool> {
+        if path.as_os_str().is_empty() {
+            return Ok(false);
+        }
+        Ok(self.state.read().unwrap().is_directory(&normalize_path(path)))
+    }
+
+    fn file_exists(&self, file_name: &Path) -> Result<bool> {
+        if file_name.as_os_str().is_empty() {
+            return Ok(false);
+        }
+        Ok(self.state.read().unwrap().files.contains_key(&normalize_path(file_name)))
+    }
+
+    fn create_directory(&self, path: &Path) -> Result<()> {
+        let key = normalize_path(path);
+        let mut state = self.state.write().unwrap();
+        if state.files.contains_key(&key) {
+            return Err(Error::other("Could not create directory: path exists but is not a directory"));
+        }
+        if state.is_directory(&key) {
+            return Ok(());
+        }
+        if !state.parent_exists(&key) {
+            return Err(Error::new(
+                ErrorKind::NotFound,
+                format!("Failed to create directory: parent of '{}' does not exist", path.display())
+            ));
+        }
+        state.directories.insert(key);
+        Ok(())
+    }
+
+    fn remove_directory(&self, path: &Path) -> Result<()> {
+        let key = normalize_path(path);
+        let mut state = self.state.write().unwrap();
+        if state.files.contains_key(&key) {
+            return Err(Error::new(
+                ErrorKind::NotADirectory,
+                format!("failed to remove directory '{}': not a directory", path.display())
+            ));
+        }
+        if !state.directories.remove(&key) {
+            return Err(Error::new(
+                ErrorKind::NotFound,
+                format!("failed to remove directory '{}': no such directory", path.display())
+            ));
+        }
+        state.files.retain(|file, _| !file.starts_with(&key));
+        state.directories.retain(|dir| !dir.starts_with(&key));
+        Ok(())
+    }
+
+    fn remove_file(&self, file_name: &Path) -> Result<()> {
+        let key = normalize_path(file_name);
+        let mut state = self.state.write().unwrap();
+        if state.files.remove(&key).is_none() {
+            let kind = if state.is_directory(&key) { ErrorKind::IsADirectory } else { ErrorKind::NotFound };
+            return Err(Error::new(
+                kind,
+                format!("failed to remove file '{}': no such file", file_name.display())
+            ));
+        }
+        Ok(())
+    }
+
+    fn list_files<F>(&self, directory: &Path, mut callback: F) -> Result<bool>
+    where F: FnMut(String) {
+        let key = normalize_path(directory);
+        let state = self.state.read().unwrap();
+        if directory.as_os_str().is_empty() || !state.is_directory(&key) {
+            return Ok(false);
+        }
+
+        let children = state.files.keys().chain(state.directories.iter())
+            .filter(|entry| entry.parent() == Some(key.as_path()))
+            .filter_map(|entry| entry.file_name())
+            .map(|name| name.to_string_lossy().into_owned())
+            .filter(|name| !name.is_empty() && !name.starts_with('.'))
+            .collect::<BTreeSet<_>>();
+        drop(state);
+
+        for name in children {
+            callback(name);
+        }
+        Ok(true)
+    }
+
+    fn path_separator(&self) -> &'static str {
+        "/"
+    }
+
+    fn fsync(&self, _handle: &Self::Handle<'_>) -> Result<()> {
+        Ok(())
+    }
+
+    fn truncate(&self, handle: &Self::Handle<'_>, new_size: u64) -> Result<()> {
+        Self::check_writable(handle)?;
+        let new_size = range_end(new_size, 0)?;
+        resize(&mut handle.file.data.write().unwrap(), new_size)
+    }
+
+    /// Memory is not reserved ahead of time; only the file size is extended
+    fn allocate(&self, handle: &Self::Handle<'_>, offset: u64, length: u64) -> Result<()> {
+        Self::check_writable(handle)?;
+        let end = range_end(offset, length)?;
+        let mut data = handle.file.data.write().unwrap();
+        if data.len() < end {
+            resize(&mut data, end)?;
+        }
+        Ok(())
+    }
+
+    fn punch_hole(&self, handle: &Self::Handle<'_>, offset: u64, length: u64) -> Result<()> {
+        Self::check_writable(handle)?;
+        let end = range_end(offset, length)?;
+        let mut data = handle.file.data.write().unwrap();
+        let start = (offset as usize).min(data.len());
+        let end = end.min(data.len());
+        data[start..end].fill(0);
+        Ok(())
+    }
+
+    /// Memory file systems have nothing to flush, but a missing directory is an error
+    /// like on disk
+    fn sync_directory(&self, directory: &Path) -> Result<()> {
+        if !self.directory_exists(directory)? {
+            return Err(Error::new(
+                ErrorKind::NotFound,
+                format!("cannot sync directory '{}': directory does not exist", directory.display())
+            ));
+        }
+        Ok(())
+    }
+
+    fn move_file(&self, src: &Path, dst: &Path) -> Result<()> {
+        let src_key = normalize_path(src);
+        let dst_key = normalize_path(dst);
+        if src_key == dst_key {
+            return Ok(());
+        }
+
+        let mut state = self.state.write().unwrap();
+        if !state.parent_exists(&dst_key) {
+            return Err(Error::new(
+                ErrorKind::NotFound,
+                format!("cannot move to '{}': parent directory does not exist", dst.display())
+            ));
+        }
+
+        if let Some(file) = state.files.get(&src_key).cloned() {
+            if state.is_directory(&dst_key) {
+                return Err(Error::new(
+                    ErrorKind::IsADirectory,
+                    format!("cannot move '{}' over directory '{}'", src.display(), dst.display())
+                ));
+            }
+            state.files.remove(&src_key);
+            state.files.insert(dst_key, file);
+            return Ok(());
+        }
+
+        if !state.directories.contains(&src_key) {
+            return Err(Error::new(
+                ErrorKind::NotFound,
+                format!("cannot move '{}': no such file or directory", src.display())
+            ));
+        }
+        if dst_key.starts_with(&src_key) {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                format!("cannot move directory '{}' into itself", src.display())
+            ));
+        }
+        if state.files.contains_key(&dst_key) {
+            return Err(Error::new(
+                ErrorKind::NotADirectory,
+                format!("cannot move directory '{}' over file '{}'", src.display(), dst.display())
+            ));
+        }
+        let dst_not_empty = state.files.keys().chain(state.directories.iter())
+            .any(|entry| entry != &dst_key && entry.starts_with(&dst_key));
+        if dst_not_empty {
+            return Err(Error::new(
+                ErrorKind::DirectoryNotEmpty,
+                format!("cannot move '{}': directory '{}' is not empty", src.display(), dst.display())
+            ));
+        }
+
+        let rebase = |entry: &Path| dst_key.join(entry.strip_prefix(&src_key).unwrap());
+        let moved_files = state.files.keys()
+            .filter(|file| file.starts_with(&src_key))
+            .cloned()
+            .collect::<Vec<_>>();
+        for file in moved_files {
+            let contents = state.files.remove(&file).unwrap();
+            state.files.insert(rebase(&file), contents);
+        }
+        let moved_directories = state.directories.iter()
+            .filter(|dir| dir.starts_with(&src_key))
+            .cloned()
+            .collect::<Vec<_>>();
+        for dir in moved_directories {
+            state.directories.remove(&dir);
+            state.directories.insert(rebase(&dir));
+        }
+        Ok(())
+    }
+
+    fn join_path(&self, l: &Path, r: &Path) -> Result<PathBuf> {
+        let full_path = l.to_string_lossy().to_string() + "/" + &r.to_string_lossy();
+        Ok(PathBuf::from(full_path))
+    }
+}
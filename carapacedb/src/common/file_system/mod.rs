@@ -1,9 +1,33 @@
 pub mod dynamic_fs;
+pub mod adapter_fs;
 pub mod static_fs;
+pub mod memory_fs;
+pub mod fault_fs;
+#[cfg(target_os = "linux")]
+pub mod uring_fs;
+#[cfg(unix)]
+pub mod plugin_fs;
+pub mod virtual_fs;
+pub mod compressed_fs;
+pub mod http_fs;
+pub mod cache_fs;
+pub mod s3_fs;
+pub mod stats_fs;
+pub mod throttle_fs;
+pub mod glob;
+
+use std::io::{Error, ErrorKind, Result};
+use std::ops::Deref;
+use std::path::{Component, Path, PathBuf};
+use std::sync::Arc;
 
 use bitflags::bitflags;
-use static_fs::{LocalFileSystem, LocalFileHandle};
-use dynamic_fs::{DynFileSystem, DynFileHandle};
+use adapter_fs::{DynAdapterHandle, DynFileSystemAdapter};
+use static_fs::LocalFileSystem;
+use dynamic_fs::{BoxedFileSystem, DynFileSystem, DynFileHandle};
+use virtual_fs::{VirtualFileSystem, VirtualFileHandle};
+use stats_fs::{IoStatistics, StatisticsFileSystem};
+use throttle_fs::{IoRateLimit, RateLimiter, ThrottledFileSystem};
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum FileLockType {
@@ -13,21 +37,513 @@ pub enum FileLockType {
 }
 
 bitflags! {
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
     pub struct FileFlags: u16 {
         const READ = 1 << 0;
         const WRITE = 1 << 1;
         const DIRECT_IO = 1 << 2;
         const CREATE = 1 << 3;
+        /// Writes through the handle are background I/O, such as checkpoints and spill
+        /// files, and may be throttled by a `ThrottledFileSystem`
+        const BACKGROUND = 1 << 4;
+        /// Maps a file opened with `READ` into memory, so that reads come from the page
+        /// cache without a syscall, see `UnifiedFileHandle::mapped_slice`. Only for files
+        /// that nobody writes or truncates while they are open, such as the database
+        /// file under `AccessMode::ReadOnly`.
+        const MEMORY_MAP = 1 << 5;
     }
 }
 
 #[derive(Debug)]
 pub enum UnifiedFileSystem {
     Local(LocalFileSystem),
-    Plugin(Box<dyn DynFileSystem<'static> + 'static>),
+    Plugin(BoxedFileSystem),
+    Virtual(VirtualFileSystem),
+    /// Records I/O statistics for the file system it wraps
+    Statistics(StatisticsFileSystem),
+    /// Throttles background writes to the file system it wraps
+    Throttled(ThrottledFileSystem),
 }
 
+#[derive(Debug)]
 pub enum UnifiedFileHandle<'a> {
-    Local(LocalFileHandle<'a>),
+    Local(DynAdapterHandle<'a, LocalFileSystem>),
     Plugin(Box<dyn DynFileHandle<'a> + 'a>),
+    Virtual(VirtualFileHandle<'a>),
+}
+
+/// A handle that keeps the file system it was opened on alive, so that it can be
+/// stored without borrowing the file system, e.g. by the block manager and buffered
+/// readers and writers. Derefs to the handle for I/O.
+#[derive(Debug)]
+pub struct OwnedFileHandle {
+    // declared before `fs`, so that the handle is dropped before the file system it borrows
+    handle: UnifiedFileHandle<'static>,
+    fs: Arc<UnifiedFileSystem>,
+}
+
+/// `LocalFileSystem` is stateless, so every `UnifiedFileSystem::Local` dispatches to
+/// this one adapter
+static LOCAL_FILE_SYSTEM: DynFileSystemAdapter<LocalFileSystem> = DynFileSystemAdapter::new(LocalFileSystem);
+
+impl UnifiedFileSystem {
+    /// The file system behind the variant, as a `DynFileSystem`
+    pub fn as_dyn(&self) -> &dyn for<'fs> DynFileSystem<'fs> {
+        match self {
+            UnifiedFileSystem::Local(_) => &LOCAL_FILE_SYSTEM,
+            UnifiedFileSystem::Plugin(fs) => fs.as_ref(),
+            UnifiedFileSystem::Virtual(fs) => fs,
+            UnifiedFileSystem::Statistics(fs) => fs,
+            UnifiedFileSystem::Throttled(fs) => fs,
+        }
+    }
+
+    /// Wraps the file system so that it records I/O statistics
+    pub fn with_statistics(self) -> UnifiedFileSystem {
+        match self {
+            fs if fs.io_statistics().is_some() => fs,
+            fs => UnifiedFileSystem::Statistics(StatisticsFileSystem::new(fs)),
+        }
+    }
+
+    /// Wraps the file system so that writes through handles opened with
+    /// `FileFlags::BACKGROUND` stay within `limit`
+    pub fn with_write_limit(self, limit: IoRateLimit) -> Result<UnifiedFileSystem> {
+        Ok(UnifiedFileSystem::Throttled(ThrottledFileSystem::new(self, limit)?))
+    }
+
+    /// The I/O statistics, if the file system records them
+    pub fn io_statistics(&self) -> Option<&IoStatistics> {
+        match self {
+            UnifiedFileSystem::Statistics(fs) => Some(fs.statistics()),
+            UnifiedFileSystem::Throttled(fs) => fs.inner().io_statistics(),
+            _ => None,
+        }
+    }
+
+    /// The limiter of background writes, if the file system throttles them
+    pub fn write_limiter(&self) -> Option<&RateLimiter> {
+        match self {
+            UnifiedFileSystem::Throttled(fs) => Some(fs.limiter()),
+            UnifiedFileSystem::Statistics(fs) => fs.inner().write_limiter(),
+            _ => None,
+        }
+    }
+
+    /// Opens `path`; a missing lock type opens the file without a lock
+    pub fn open_file(&self, path: &Path, flags: FileFlags, lock: Option<FileLockType>) -> Result<UnifiedFileHandle<'_>> {
+        match self {
+            UnifiedFileSystem::Local(_) => Ok(UnifiedFileHandle::Local(LOCAL_FILE_SYSTEM.open_handle(path, flags, lock)?)),
+            UnifiedFileSystem::Plugin(fs) => Ok(UnifiedFileHandle::Plugin(fs.open_file(path, flags, lock)?)),
+            UnifiedFileSystem::Virtual(fs) => Ok(UnifiedFileHandle::Virtual(fs.open_virtual_file(path, flags, lock)?)),
+            UnifiedFileSystem::Statistics(fs) => Ok(UnifiedFileHandle::Plugin(fs.open_file(path, flags, lock)?)),
+            UnifiedFileSystem::Throttled(fs) => Ok(UnifiedFileHandle::Plugin(fs.open_file(path, flags, lock)?)),
+        }
+    }
+
+    pub fn directory_exists(&self, path: &Path) -> Result<bool> {
+        self.as_dyn().directory_exists(path)
+    }
+
+    pub fn file_exists(&self, file_name: &Path) -> Result<bool> {
+        self.as_dyn().file_exists(file_name)
+    }
+
+    pub fn create_directory(&self, path: &Path) -> Result<()> {
+        self.as_dyn().create_directory(path)
+    }
+
+    pub fn remove_directory(&self, path: &Path) -> Result<()> {
+        self.as_dyn().remove_directory(path)
+    }
+
+    pub fn remove_file(&self, file_name: &Path) -> Result<()> {
+        self.as_dyn().remove_file(file_name)
+    }
+
+    pub fn list_files<F>(&self, directory: &Path, mut callback: F) -> Result<bool>
+    where F: FnMut(String) {
+        self.as_dyn().list_files(directory, &mut callback)
+    }
+
+    pub fn path_separator(&self) -> &'static str {
+        self.as_dyn().path_separator()
+    }
+
+    pub fn sync_directory(&self, directory: &Path) -> Result<()> {
+        self.as_dyn().sync_directory(directory)
+    }
+
+    pub fn move_file(&self, src: &Path, dst: &Path) -> Result<()> {
+        self.as_dyn().move_file(src, dst)
+    }
+
+    pub fn join_path(&self, l: &Path, r: &Path) -> Result<PathBuf> {
+        self.as_dyn().join_path(l, r)
+    }
+
+    pub fn glob(&self, pattern: &str) -> Result<Vec<PathBuf>> {
+        self.as_dyn().glob(pattern)
+    }
+
+    /// Durably replaces the file at `path` with the contents written by `write`, see
+    /// `replace_file`
+    pub fn replace_file<F>(&self, path: &Path, write: F) -> Result<()>
+    where F: FnOnce(&UnifiedFileHandle<'_>) -> Result<()> {
+        replace_file(&self, path, write)
+    }
+
+    /// Loads the file system plugin at `library_path`, see `PluginFileSystem::load`, and
+    /// registers it for paths with the URI scheme `scheme`
+    #[cfg(unix)]
+    pub fn register_plugin(&self, scheme: &str, library_path: &Path) -> Result<()> {
+        let plugin = plugin_fs::PluginFileSystem::load(library_path)?;
+        self.register_file_system(scheme, Box::new(plugin))
+    }
+
+    /// Registers `fs` for paths with the URI scheme `scheme`, see `VirtualFileSystem`.
+    /// Only a virtual file system can dispatch to several file systems.
+    pub fn register_file_system(&self, scheme: &str, fs: BoxedFileSystem) -> Result<()> {
+        match self {
+            UnifiedFileSystem::Virtual(vfs) => vfs.register(scheme, fs),
+            UnifiedFileSystem::Statistics(stats) => stats.inner().register_file_system(scheme, fs),
+            UnifiedFileSystem::Throttled(throttled) => throttled.inner().register_file_system(scheme, fs),
+            _ => Err(Error::new(
+                ErrorKind::Unsupported,
+                format!("cannot register file system for '{}': not a virtual file system", scheme)
+            )),
+        }
+    }
+}
+
+/// Lets a `UnifiedFileSystem` be wrapped by the `SFileSystem` wrappers through
+/// `SFileSystemAdapter`, e.g. by `FaultInjectionFileSystem::wrap_unified`
+impl<'fs> DynFileSystem<'fs> for UnifiedFileSystem {
+    fn read_at(&self, handle: &dyn DynFileHandle<'fs>, buffer: &mut [u8], nr_bytes: i64, location: u64) -> Result<()> {
+        self.as_dyn().read_at(handle, buffer, nr_bytes, location)
+    }
+
+    fn write_at(&self, handle: &dyn DynFileHandle<'fs>, buffer: &[u8], nr_bytes: i64, location: u64) -> Result<()> {
+        self.as_dyn().write_at(handle, buffer, nr_bytes, location)
+    }
+
+    fn open_file(&'fs self, path: &Path, flags: FileFlags, lock: Option<FileLockType>) -> Result<Box<dyn DynFileHandle<'fs> + 'fs>> {
+        self.as_dyn().open_file(path, flags, lock)
+    }
+
+    fn set_file_pointer(&self, handle: &dyn DynFileHandle<'fs>, location: u64) -> Result<()> {
+        self.as_dyn().set_file_pointer(handle, location)
+    }
+
+    fn read(&self, handle: &dyn DynFileHandle<'fs>, buffer: &mut [u8], nr_bytes: i64) -> Result<()> {
+        self.as_dyn().read(handle, buffer, nr_bytes)
+    }
+
+    fn write(&self, handle: &dyn DynFileHandle<'fs>, buffer: &[u8], nr_bytes: i64) -> Result<()> {
+        self.as_dyn().write(handle, buffer, nr_bytes)
+    }
+
+    fn file_size(&self, handle: &dyn DynFileHandle<'fs>) -> Result<u64> {
+        self.as_dyn().file_size(handle)
+    }
+
+    fn directory_exists(&self, path: &Path) -> Result<bool> {
+        self.as_dyn().directory_exists(path)
+    }
+
+    fn file_exists(&self, file_name: &Path) -> Result<bool> {
+        self.as_dyn().file_exists(file_name)
+    }
+
+    fn create_directory(&self, path: &Path) -> Result<()> {
+        self.as_dyn().create_directory(path)
+    }
+
+    fn remove_directory(&self, path: &Path) -> Result<()> {
+        self.as_dyn().remove_directory(path)
+    }
+
+    fn remove_file(&self, file_name: &Path) -> Result<()> {
+        self.as_dyn().remove_file(file_name)
+    }
+
+    fn list_files(&self, directory: &Path, callback: &mut dyn FnMut(String)) -> Result<bool> {
+        self.as_dyn().list_files(directory, callback)
+    }
+
+    fn path_separator(&self) -> &'static str {
+        self.as_dyn().path_separator()
+    }
+
+    fn fsync(&self, handle: &dyn DynFileHandle<'fs>) -> Result<()> {
+        self.as_dyn().fsync(handle)
+    }
+
+    fn truncate(&self, handle: &dyn DynFileHandle<'fs>, new_size: u64) -> Result<()> {
+        self.as_dyn().truncate(handle, new_size)
+    }
+
+    fn allocate(&self, handle: &dyn DynFileHandle<'fs>, offset: u64, length: u64) -> Result<()> {
+        self.as_dyn().allocate(handle, offset, length)
+    }
+
+    fn punch_hole(&self, handle: &dyn DynFileHandle<'fs>, offset: u64, length: u64) -> Result<()> {
+        self.as_dyn().punch_hole(handle, offset, length)
+    }
+
+    fn sync_directory(&self, directory: &Path) -> Result<()> {
+        self.as_dyn().sync_directory(directory)
+    }
+
+    fn move_file(&self, src: &Path, dst: &Path) -> Result<()> {
+        self.as_dyn().move_file(src, dst)
+    }
+
+    fn file_version(&self, handle: &dyn DynFileHandle<'fs>) -> Result<Option<String>> {
+        self.as_dyn().file_version(handle)
+    }
+
+    fn join_path(&self, l: &Path, r: &Path) -> Result<PathBuf> {
+        self.as_dyn().join_path(l, r)
+    }
+
+    fn glob(&self, pattern: &str) -> Result<Vec<PathBuf>> {
+        self.as_dyn().glob(pattern)
+    }
+
+    fn replace_file(&'fs self, path: &Path, write: &mut dyn FnMut(&dyn DynFileHandle<'fs>) -> Result<()>) -> Result<()> {
+        self.as_dyn().replace_file(path, write)
+    }
+}
+
+/// The I/O methods of a handle dispatch to the file system that opened it and follow
+/// the `DynFileSystem` semantics: `read` and `write` transfer exactly `nr_bytes`.
+impl<'a> UnifiedFileHandle<'a> {
+    /// The handle behind the variant, as a `DynFileHandle`
+    pub fn as_dyn(&self) -> &dyn DynFileHandle<'a> {
+        match self {
+            UnifiedFileHandle::Local(handle) => handle,
+            UnifiedFileHandle::Plugin(handle) => handle.as_ref(),
+            UnifiedFileHandle::Virtual(handle) => handle,
+        }
+    }
+
+    /// Returns the `length` bytes at `location` without copying them, if the file was
+    /// opened with `FileFlags::MEMORY_MAP` and the range lies within the mapping
+    pub fn mapped_slice(&self, location: u64, length: usize) -> Option<&[u8]> {
+        self.as_dyn().mapped_slice(location, length)
+    }
+
+    pub fn file_system(&self) -> &dyn DynFileSystem<'a> {
+        self.as_dyn().file_system()
+    }
+
+    pub fn path(&self) -> &Path {
+        self.as_dyn().path()
+    }
+
+    pub fn close(&mut self) -> Result<()> {
+        match self {
+            UnifiedFileHandle::Local(handle) => DynFileHandle::close(handle),
+            UnifiedFileHandle::Plugin(handle) => handle.close(),
+            UnifiedFileHandle::Virtual(handle) => handle.close(),
+        }
+    }
+
+    pub fn read_at(&self, buffer: &mut [u8], nr_bytes: i64, location: u64) -> Result<()> {
+        self.file_system().read_at(self.as_dyn(), buffer, nr_bytes, location)
+    }
+
+    pub fn write_at(&self, buffer: &[u8], nr_bytes: i64, location: u64) -> Result<()> {
+        self.file_system().write_at(self.as_dyn(), buffer, nr_bytes, location)
+    }
+
+    pub fn set_file_pointer(&self, location: u64) -> Result<()> {
+        self.file_system().set_file_pointer(self.as_dyn(), location)
+    }
+
+    pub fn read(&self, buffer: &mut [u8], nr_bytes: i64) -> Result<()> {
+        self.file_system().read(self.as_dyn(), buffer, nr_bytes)
+    }
+
+    pub fn write(&self, buffer: &[u8], nr_bytes: i64) -> Result<()> {
+        self.file_system().write(self.as_dyn(), buffer, nr_bytes)
+    }
+
+    pub fn file_size(&self) -> Result<u64> {
+        self.file_system().file_size(self.as_dyn())
+    }
+
+    pub fn fsync(&self) -> Result<()> {
+        self.file_system().fsync(self.as_dyn())
+    }
+
+    pub fn truncate(&self, new_size: u64) -> Result<()> {
+        self.file_system().truncate(self.as_dyn(), new_size)
+    }
+
+    pub fn allocate(&self, offset: u64, length: u64) -> Result<()> {
+        self.file_system().allocate(self.as_dyn(), offset, length)
+    }
+
+    pub fn punch_hole(&self, offset: u64, length: u64) -> Result<()> {
+        self.file_system().punch_hole(self.as_dyn(), offset, length)
+    }
+
+    pub fn file_version(&self) -> Result<Option<String>> {
+        self.file_system().file_version(self.as_dyn())
+    }
+}
+
+impl OwnedFileHandle {
+    pub fn open(fs: Arc<UnifiedFileSystem>, path: &Path, flags: FileFlags, lock: Option<FileLockType>) -> Result<Self> {
+        let handle = fs.open_file(path, flags, lock)?;
+        // SAFETY: the handle borrows the file system behind the `Arc`, which does not
+        // move and is kept alive by `fs` until the handle has been dropped. The handle
+        // is never handed out by value or by mutable reference, so it cannot escape.
+        let handle = unsafe { std::mem::transmute::<UnifiedFileHandle<'_>, UnifiedFileHandle<'static>>(handle) };
+        Ok(OwnedFileHandle { handle, fs })
+    }
+
+    pub fn file_system(&self) -> &Arc<UnifiedFileSystem> {
+        &self.fs
+    }
+
+    pub fn close(&mut self) -> Result<()> {
+        self.handle.close()
+    }
+}
+
+impl Deref for OwnedFileHandle {
+    type Target = UnifiedFileHandle<'static>;
+
+    fn deref(&self) -> &Self::Target {
+        &self.handle
+    }
+}
+
+/// The operations `replace_file` needs from a file system, so that the static, dynamic
+/// and unified file systems share its implementation with their own handle types
+pub(crate) trait ReplaceFileSteps {
+    type Handle;
+
+    fn file_exists(&self, path: &Path) -> Result<bool>;
+    fn remove_file(&self, path: &Path) -> Result<()>;
+    /// Creates the file at `path` for writing, holding a write lock on it
+    fn create_file(&self, path: &Path) -> Result<Self::Handle>;
+    fn fsync(&self, handle: &Self::Handle) -> Result<()>;
+    fn close(&self, handle: &mut Self::Handle) -> Result<()>;
+    fn move_file(&self, src: &Path, dst: &Path) -> Result<()>;
+    fn sync_directory(&self, directory: &Path) -> Result<()>;
+}
+
+impl<'a> ReplaceFileSteps for &'a UnifiedFileSystem {
+    type Handle = UnifiedFileHandle<'a>;
+
+    fn file_exists(&self, path: &Path) -> Result<bool> {
+        UnifiedFileSystem::file_exists(self, path)
+    }
+
+    fn remove_file(&self, path: &Path) -> Result<()> {
+        UnifiedFileSystem::remove_file(self, path)
+    }
+
+    fn create_file(&self, path: &Path) -> Result<Self::Handle> {
+        self.open_file(path, FileFlags::WRITE | FileFlags::CREATE, Some(FileLockType::WriteLock))
+    }
+
+    fn fsync(&self, handle: &Self::Handle) -> Result<()> {
+        handle.fsync()
+    }
+
+    fn close(&self, handle: &mut Self::Handle) -> Result<()> {
+        handle.close()
+    }
+
+    fn move_file(&self, src: &Path, dst: &Path) -> Result<()> {
+        UnifiedFileSystem::move_file(self, src, dst)
+    }
+
+    fn sync_directory(&self, directory: &Path) -> Result<()> {
+        UnifiedFileSystem::sync_directory(self, directory)
+    }
+}
+
+/// Durably replaces the file at `path` with the contents written by `write`. The
+/// contents go to a temporary file next to `path`, replacing one left behind by an
+/// earlier attempt. It is synced and renamed over `path` before the parent directory
+/// is synced, so that a crash leaves either the old or the new file in place. If
+/// writing fails, the temporary file is removed and `path` is left untouched.
+pub(crate) fn replace_file<S: ReplaceFileSteps>(fs: &S, path: &Path, write: impl FnOnce(&S::Handle) -> Result<()>) -> Result<()> {
+    let temp_path = temporary_path(path);
+    if fs.file_exists(&temp_path)? {
+        fs.remove_file(&temp_path)?;
+    }
+
+    let mut handle = fs.create_file(&temp_path)?;
+    let written = write(&handle).and_then(|_| fs.fsync(&handle));
+    let closed = fs.close(&mut handle);
+    if let Err(e) = written.and(closed) {
+        let _ = fs.remove_file(&temp_path);
+        return Err(e);
+    }
+
+    fs.move_file(&temp_path, path)?;
+    fs.sync_directory(&parent_directory(path))
+}
+
+/// The path a new version of `path` is written to before it replaces `path`
+pub(crate) fn temporary_path(path: &Path) -> PathBuf {
+    let mut temp_path = path.as_os_str().to_owned();
+    temp_path.push(".tmp");
+    PathBuf::from(temp_path)
+}
+
+/// The directory containing `path`, keeping the URI scheme of `path` if it has one
+pub(crate) fn parent_directory(path: &Path) -> PathBuf {
+    let (scheme, stripped) = virtual_fs::split_scheme(path);
+    let parent = match stripped.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent,
+        _ => Path::new("."),
+    };
+    match scheme {
+        Some(scheme) => PathBuf::from(format!("{}://{}", scheme, parent.display())),
+        None => parent.to_path_buf(),
+    }
+}
+
+/// Normalizes a path lexically, so that `a/./b` and `a/c/../b` refer to the same entry.
+pub(crate) fn normalize_path(path: &Path) -> PathBuf {
+    let mut normalized = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir => {
+                normalized.pop();
+            }
+            other => normalized.push(other.as_os_str()),
+        }
+    }
+    normalized
+}
+
+/// Validates the `nr_bytes` argument of a read or write against the size of its
+/// buffer, and returns it as the number of bytes to transfer.
+pub(crate) fn transfer_size(buffer_len: usize, nr_bytes: i64) -> Result<usize> {
+    if nr_bytes < 0 || nr_bytes as usize > buffer_len {
+        return Err(Error::new(
+            ErrorKind::InvalidInput,
+            format!("invalid transfer size {} for buffer of {} bytes", nr_bytes, buffer_len)
+        ));
+    }
+    Ok(nr_bytes as usize)
+}
+
+/// The error of the default implementation of a file system operation that the file
+/// system does not override
+pub(crate) fn unsupported_operation(operation: &str, path: &Path) -> Error {
+    Error::new(
+        ErrorKind::Unsupported,
+        format!("cannot {} '{}': not supported by the file system", operation, path.display())
+    )
 }
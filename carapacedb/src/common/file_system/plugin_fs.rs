@@ -0,0 +1,461 @@
+//! A stable C ABI for file system plugins, and the loader for plugins built as shared
+//! libraries.
+//!
+//! A plugin exports two functions: `carapacedb_file_system_plugin_abi_version` of type
+//! `FileSystemPluginAbiVersion`, and `carapacedb_file_system_plugin` of type
+//! `FileSystemPluginEntry`. The loader first asks for the ABI version, and only if it
+//! matches passes a zeroed `FileSystemPlugin` to the entry, which the plugin fills in
+//! with an opaque context and its functions. Every
+//! function returns 0 on success or an `errno` value on failure; a function left null
+//! makes the corresponding operation fail with `ErrorKind::Unsupported`. The plugin
+//! must allow its functions to be called from several threads at once.
+
+use std::any::TypeId;
+use std::ffi::{CStr, CString};
+use std::io::{Error, ErrorKind, Result};
+use std::os::raw::{c_char, c_int, c_void};
+use std::os::unix::ffi::OsStrExt;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use super::dynamic_fs::{downcast_handle, DynFileHandle, DynFileSystem, Token};
+use super::{transfer_size, FileFlags, FileLockType};
+
+/// The version of `FileSystemPlugin` this build understands. Plugins built against a
+/// different version are rejected before their entry is called.
+pub const FILE_SYSTEM_PLUGIN_ABI_VERSION: u32 = 1;
+
+/// The symbol a plugin library exports its `FileSystemPluginAbiVersion` under
+pub const FILE_SYSTEM_PLUGIN_ABI_VERSION_SYMBOL: &str = "carapacedb_file_system_plugin_abi_version";
+
+/// The symbol a plugin library exports its `FileSystemPluginEntry` under
+pub const FILE_SYSTEM_PLUGIN_ENTRY: &str = "carapacedb_file_system_plugin";
+
+/// Returns the version of `FileSystemPlugin` the plugin was built against
+pub type FileSystemPluginAbiVersion = unsafe extern "C" fn() -> u32;
+
+/// Fills in `plugin`; returns 0 on success or an `errno` value on failure
+pub type FileSystemPluginEntry = unsafe extern "C" fn(plugin: *mut FileSystemPlugin) -> c_int;
+
+/// Called by `list_files` with the `user_data` it was given and each entry name
+pub type ListFilesCallback = unsafe extern "C" fn(user_data: *mut c_void, name: *const c_char);
+
+/// The function table of a plugin. Paths are NUL-terminated, `flags` holds the bits of
+/// `FileFlags` and `lock` is 0 for no lock, 1 for a read lock and 2 for a write lock.
+/// `read_at` and `write_at` transfer exactly `nr_bytes` or fail.
+#[repr(C)]
+#[derive(Debug)]
+pub struct FileSystemPlugin {
+    pub context: *mut c_void,
+    /// Releases `context`; called once when the file system is dropped
+    pub destroy: Option<unsafe extern "C" fn(context: *mut c_void)>,
+    pub open_file: Option<unsafe extern "C" fn(context: *mut c_void, path: *const c_char, flags: u16, lock: c_int, handle: *mut *mut c_void) -> c_int>,
+    pub close_file: Option<unsafe extern "C" fn(context: *mut c_void, handle: *mut c_void) -> c_int>,
+    pub read_at: Option<unsafe extern "C" fn(context: *mut c_void, handle: *mut c_void, buffer: *mut u8, nr_bytes: u64, location: u64) -> c_int>,
+    pub write_at: Option<unsafe extern "C" fn(context: *mut c_void, handle: *mut c_void, buffer: *const u8, nr_bytes: u64, location: u64) -> c_int>,
+    pub file_size: Option<unsafe extern "C" fn(context: *mut c_void, handle: *mut c_void, size: *mut u64) -> c_int>,
+    pub fsync: Option<unsafe extern "C" fn(context: *mut c_void, handle: *mut c_void) -> c_int>,
+    pub truncate: Option<unsafe extern "C" fn(context: *mut c_void, handle: *mut c_void, new_size: u64) -> c_int>,
+    pub allocate: Option<unsafe extern "C" fn(context: *mut c_void, handle: *mut c_void, offset: u64, length: u64) -> c_int>,
+    pub punch_hole: Option<unsafe extern "C" fn(context: *mut c_void, handle: *mut c_void, offset: u64, length: u64) -> c_int>,
+    pub directory_exists: Option<unsafe extern "C" fn(context: *mut c_void, path: *const c_char, exists: *mut bool) -> c_int>,
+    pub file_exists: Option<unsafe extern "C" fn(context: *mut c_void, path: *const c_char, exists: *mut bool) -> c_int>,
+    pub create_directory: Option<unsafe extern "C" fn(context: *mut c_void, path: *const c_char) -> c_int>,
+    pub remove_directory: Option<unsafe extern "C" fn(context: *mut c_void, path: *const c_char) -> c_int>,
+    pub remove_file: Option<unsafe extern "C" fn(context: *mut c_void, path: *const c_char) -> c_int>,
+    /// Calls `callback` for each entry of `directory`; `found` is set to whether the
+    /// directory exists
+    pub list_files: Option<unsafe extern "C" fn(context: *mut c_void, directory: *const c_char, callback: ListFilesCallback, user_data: *mut c_void, found: *mut bool) -> c_int>,
+    pub sync_directory: Option<unsafe extern "C" fn(context: *mut c_void, directory: *const c_char) -> c_int>,
+    pub move_file: Option<unsafe extern "C" fn(context: *mut c_void, src: *const c_char, dst: *const c_char) -> c_int>,
+    /// Sets `modified` to the last modification time of the file in nanoseconds since
+    /// the Unix epoch, which is used as its version (see `DynFileSystem::file_version`)
+    pub last_modified: Option<unsafe extern "C" fn(context: *mut c_void, handle: *mut c_void, modified: *mut i64) -> c_int>,
+}
+
+/// A shared library opened with `dlopen`, closed when dropped
+#[derive(Debug)]
+struct PluginLibrary {
+    handle: *mut c_void,
+}
+
+/// A file system implemented by a plugin library
+#[derive(Debug)]
+pub struct PluginFileSystem {
+    library_path: PathBuf,
+    plugin: FileSystemPlugin,
+    // dropped after `plugin` has been destroyed, see `Drop for PluginFileSystem`
+    _library: PluginLibrary,
+}
+
+#[derive(Debug)]
+pub struct PluginFileHandle<'fs> {
+    fs: &'fs PluginFileSystem,
+    path: PathBuf,
+    raw: Option<*mut c_void>,
+    position: Mutex<u64>,
+}
+
+// SAFETY: plugins must be thread-safe, see the module documentation
+unsafe impl Send for PluginFileSystem {}
+unsafe impl Sync for PluginFileSystem {}
+unsafe impl Send for PluginFileHandle<'_> {}
+unsafe impl Sync for PluginFileHandle<'_> {}
+
+impl PluginLibrary {
+    /// The address of the symbol `name` exported by the library
+    fn symbol(&self, name: &str, library_path: &Path) -> Result<*mut c_void> {
+        let c_name = CString::new(name).unwrap();
+        let symbol = unsafe { libc::dlsym(self.handle, c_name.as_ptr()) };
+        if symbol.is_null() {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                format!("plugin '{}' does not export '{}': {}", library_path.display(), name, dl_error())
+            ));
+        }
+        Ok(symbol)
+    }
+}
+
+impl Drop for PluginLibrary {
+    fn drop(&mut self) {
+        unsafe { libc::dlclose(self.handle) };
+    }
+}
+
+impl Drop for PluginFileSystem {
+    fn drop(&mut self) {
+        if let Some(destroy) = self.plugin.destroy {
+            unsafe { destroy(self.plugin.context) };
+        }
+    }
+}
+
+impl Drop for PluginFileHandle<'_> {
+    fn drop(&mut self) {
+        if let Err(e) = self.close_plugin_file() {
+            eprintln!("failed to close handle: {}", e);
+        }
+    }
+}
+
+/// The message of the last `dlopen`/`dlsym` failure
+fn dl_error() -> String {
+    let message = unsafe { libc::dlerror() };
+    if message.is_null() {
+        return "unknown error".to_string();
+    }
+    unsafe { CStr::from_ptr(message) }.to_string_lossy().into_owned()
+}
+
+fn c_path(path: &Path) -> Result<CString> {
+    CString::new(path.as_os_str().as_bytes()).map_err(|_| Error::new(
+        ErrorKind::InvalidInput,
+        format!("path '{}' contains a NUL byte", path.display())
+    ))
+}
+
+fn lock_code(lock: Option<FileLockType>) -> c_int {
+    match lock {
+        None | Some(FileLockType::NoLock) => 0,
+        Some(FileLockType::ReadLock) => 1,
+        Some(FileLockType::WriteLock) => 2,
+    }
+}
+
+/// Converts the return code of a plugin function into a `Result`
+fn check(code: c_int, operation: &str, path: &Path) -> Result<()> {
+    if code == 0 {
+        return Ok(());
+    }
+    let error = Error::from_raw_os_error(code);
+    Err(Error::new(error.kind(), format!("{} '{}' failed: {}", operation, path.display(), error)))
+}
+
+fn unsupported<T>(operation: &str, path: &Path) -> Result<T> {
+    Err(Error::new(
+        ErrorKind::Unsupported,
+        format!("{} '{}' is not supported by the plugin", operation, path.display())
+    ))
+}
+
+unsafe extern "C" fn list_files_callback(user_data: *mut c_void, name: *const c_char) {
+    let callback = unsafe { &mut *(user_data as *mut &mut dyn FnMut(String)) };
+    let name = unsafe { CStr::from_ptr(name) }.to_string_lossy().into_owned();
+    callback(name);
+}
+
+impl PluginFileSystem {
+    /// Loads the plugin library at `library_path` and initializes its file system
+    pub fn load(library_path: &Path) -> Result<Self> {
+        let c_library_path = c_path(library_path)?;
+        let handle = unsafe { libc::dlopen(c_library_path.as_ptr(), libc::RTLD_NOW | libc::RTLD_LOCAL) };
+        if handle.is_null() {
+            return Err(Error::new(
+                ErrorKind::NotFound,
+                format!("cannot load plugin '{}': {}", library_path.display(), dl_error())
+            ));
+        }
+        let library = PluginLibrary { handle };
+
+        // SAFETY: the exported symbols have the types documented for them by contract
+        let abi_version: FileSystemPluginAbiVersion = unsafe { std::mem::transmute(library.symbol(FILE_SYSTEM_PLUGIN_ABI_VERSION_SYMBOL, library_path)?) };
+        let entry: FileSystemPluginEntry = unsafe { std::mem::transmute(library.symbol(FILE_SYSTEM_PLUGIN_ENTRY, library_path)?) };
+
+        // the table must not be handed to a plugin that expects a different layout,
+        // which could write past its end
+        let version = unsafe { abi_version() };
+        if version != FILE_SYSTEM_PLUGIN_ABI_VERSION {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                format!(
+                    "plugin '{}' has ABI version {}, expected {}",
+                    library_path.display(), version, FILE_SYSTEM_PLUGIN_ABI_VERSION
+                )
+            ));
+        }
+
+        // SAFETY: `FileSystemPlugin` consists of pointers and optional function
+        // pointers, for all of which zero is a valid value
+        let mut plugin: FileSystemPlugin = unsafe { std::mem::zeroed() };
+        check(unsafe { entry(&mut plugin) }, "initializing plugin", library_path)?;
+        let fs = PluginFileSystem {
+            library_path: library_path.to_path_buf(),
+            plugin,
+            _library: library,
+        };
+
+        if fs.plugin.open_file.is_none() || fs.plugin.close_file.is_none() || fs.plugin.file_size.is_none() {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                format!("plugin '{}' does not implement open_file, close_file and file_size", library_path.display())
+            ));
+        }
+        Ok(fs)
+    }
+
+    /// The path the plugin library was loaded from
+    pub fn library_path(&self) -> &Path {
+        &self.library_path
+    }
+
+    fn path_exists(
+        &self,
+        function: Option<unsafe extern "C" fn(*mut c_void, *const c_char, *mut bool) -> c_int>,
+        operation: &str,
+        path: &Path,
+    ) -> Result<bool> {
+        let Some(function) = function else { return unsupported(operation, path) };
+        let c_path = c_path(path)?;
+        let mut exists = false;
+        check(unsafe { function(self.plugin.context, c_path.as_ptr(), &mut exists) }, operation, path)?;
+        Ok(exists)
+    }
+
+    fn path_operation(
+        &self,
+        function: Option<unsafe extern "C" fn(*mut c_void, *const c_char) -> c_int>,
+        operation: &str,
+        path: &Path,
+    ) -> Result<()> {
+        let Some(function) = function else { return unsupported(operation, path) };
+        let c_path = c_path(path)?;
+        check(unsafe { function(self.plugin.context, c_path.as_ptr()) }, operation, path)
+    }
+
+    fn range_operation(
+        &self,
+        function: Option<unsafe extern "C" fn(*mut c_void, *mut c_void, u64, u64) -> c_int>,
+        operation: &str,
+        handle: &PluginFileHandle<'_>,
+        offset: u64,
+        length: u64,
+    ) -> Result<()> {
+        let Some(function) = function else { return unsupported(operation, &handle.path) };
+        check(unsafe { function(self.plugin.context, handle.raw()?, offset, length) }, operation, &handle.path)
+    }
+
+    fn read_plugin_file(&self, handle: &PluginFileHandle<'_>, buffer: &mut [u8], nr_bytes: i64, location: u64) -> Result<()> {
+        let len = transfer_size(buffer.len(), nr_bytes)?;
+        let Some(read_at) = self.plugin.read_at else { return unsupported("read", &handle.path) };
+        let code = unsafe { read_at(self.plugin.context, handle.raw()?, buffer.as_mut_ptr(), len as u64, location) };
+        check(code, "read", &handle.path)
+    }
+
+    fn write_plugin_file(&self, handle: &PluginFileHandle<'_>, buffer: &[u8], nr_bytes: i64, location: u64) -> Result<()> {
+        let len = transfer_size(buffer.len(), nr_bytes)?;
+        let Some(write_at) = self.plugin.write_at else { return unsupported("write", &handle.path) };
+        let code = unsafe { write_at(self.plugin.context, handle.raw()?, buffer.as_ptr(), len as u64, location) };
+        check(code, "write", &handle.path)
+    }
+}
+
+impl PluginFileHandle<'_> {
+    fn raw(&self) -> Result<*mut c_void> {
+        self.raw.ok_or_else(|| Error::new(
+            ErrorKind::InvalidInput,
+            format!("file '{}' is closed", self.path.display())
+        ))
+    }
+
+    fn close_plugin_file(&mut self) -> Result<()> {
+        match (self.raw.take(), self.fs.plugin.close_file) {
+            (Some(raw), Some(close_file)) => check(unsafe { close_file(self.fs.plugin.context, raw) }, "close", &self.path),
+            _ => Ok(()),
+        }
+    }
+}
+
+impl<'fs> DynFileHandle<'fs> for PluginFileHandle<'fs> {
+    fn file_system(&self) -> &dyn DynFileSystem<'fs> {
+        self.fs
+    }
+
+    fn path(&self) -> &Path {
+        &self.path
+    }
+
+    fn close(&mut self) -> Result<()> {
+        self.close_plugin_file()
+    }
+
+    fn handle_type_id(&self, _token: Token) -> Option<TypeId> {
+        Some(TypeId::of::<PluginFileHandle<'static>>())
+    }
+}
+
+/// Recovers the `PluginFileHandle` behind a handle passed to the `DynFileSystem` interface.
+fn plugin_handle<'h, 'fs>(handle: &'h dyn DynFileHandle<'fs>) -> Result<&'h PluginFileHandle<'fs>> {
+    downcast_handle(handle, TypeId::of::<PluginFileHandle<'static>>())
+}
+
+impl<'fs> DynFileSystem<'fs> for PluginFileSystem {
+    fn read_at(&self, handle: &dyn DynFileHandle<'fs>, buffer: &mut [u8], nr_bytes: i64, location: u64) -> Result<()> {
+        self.read_plugin_file(plugin_handle(handle)?, buffer, nr_bytes, location)
+    }
+
+    fn write_at(&self, handle: &dyn DynFileHandle<'fs>, buffer: &[u8], nr_bytes: i64, location: u64) -> Result<()> {
+        self.write_plugin_file(plugin_handle(handle)?, buffer, nr_bytes, location)
+    }
+
+    fn open_file(&'fs self, path: &Path, flags: FileFlags, lock: Option<FileLockType>) -> Result<Box<dyn DynFileHandle<'fs> + 'fs>> {
+        let Some(open_file) = self.plugin.open_file else { return unsupported("open", path) };
+        let c_path = c_path(path)?;
+        let mut raw = std::ptr::null_mut();
+        let code = unsafe { open_file(self.plugin.context, c_path.as_ptr(), flags.bits(), lock_code(lock), &mut raw) };
+        check(code, "open", path)?;
+        Ok(Box::new(PluginFileHandle {
+            fs: self,
+            path: path.to_path_buf(),
+            raw: Some(raw),
+            position: Mutex::new(0),
+        }))
+    }
+
+    fn set_file_pointer(&self, handle: &dyn DynFileHandle<'fs>, location: u64) -> Result<()> {
+        *plugin_handle(handle)?.position.lock().unwrap() = location;
+        Ok(())
+    }
+
+    /// Reads exactly `nr_bytes` from the current file pointer.
+    fn read(&self, handle: &dyn DynFileHandle<'fs>, buffer: &mut [u8], nr_bytes: i64) -> Result<()> {
+        let handle = plugin_handle(handle)?;
+        let mut position = handle.position.lock().unwrap();
+        self.read_plugin_file(handle, buffer, nr_bytes, *position)?;
+        *position += nr_bytes as u64;
+        Ok(())
+    }
+
+    fn write(&self, handle: &dyn DynFileHandle<'fs>, buffer: &[u8], nr_bytes: i64) -> Result<()> {
+        let handle = plugin_handle(handle)?;
+        let mut position = handle.position.lock().unwrap();
+        self.write_plugin_file(handle, buffer, nr_bytes, *position)?;
+        *position += nr_bytes as u64;
+        Ok(())
+    }
+
+    fn file_size(&self, handle: &dyn DynFileHandle<'fs>) -> Result<u64> {
+        let handle = plugin_handle(handle)?;
+        let Some(file_size) = self.plugin.file_size else { return unsupported("file size of", &handle.path) };
+        let mut size = 0;
+        check(unsafe { file_size(self.plugin.context, handle.raw()?, &mut size) }, "file size of", &handle.path)?;
+        Ok(size)
+    }
+
+    fn directory_exists(&self, path: &Path) -> Result<bool> {
+        self.path_exists(self.plugin.directory_exists, "directory exists", path)
+    }
+
+    fn file_exists(&self, file_name: &Path) -> Result<bool> {
+        self.path_exists(self.plugin.file_exists, "file exists", file_name)
+    }
+
+    fn create_directory(&self, path: &Path) -> Result<()> {
+        self.path_operation(self.plugin.create_directory, "create directory", path)
+    }
+
+    fn remove_directory(&self, path: &Path) -> Result<()> {
+        self.path_operation(self.plugin.remove_directory, "remove directory", path)
+    }
+
+    fn remove_file(&self, file_name: &Path) -> Result<()> {
+        self.path_operation(self.plugin.remove_file, "remove file", file_name)
+    }
+
+    fn list_files(&self, directory: &Path, mut callback: &mut dyn FnMut(String)) -> Result<bool> {
+        let Some(list_files) = self.plugin.list_files else { return unsupported("list files", directory) };
+        let c_directory = c_path(directory)?;
+        let user_data = &mut callback as *mut &mut dyn FnMut(String) as *mut c_void;
+        let mut found = false;
+        let code = unsafe { list_files(self.plugin.context, c_directory.as_ptr(), list_files_callback, user_data, &mut found) };
+        check(code, "list files", directory)?;
+        Ok(found)
+    }
+
+    fn path_separator(&self) -> &'static str {
+        "/"
+    }
+
+    fn fsync(&self, handle: &dyn DynFileHandle<'fs>) -> Result<()> {
+        let handle = plugin_handle(handle)?;
+        let Some(fsync) = self.plugin.fsync else { return unsupported("fsync", &handle.path) };
+        check(unsafe { fsync(self.plugin.context, handle.raw()?) }, "fsync", &handle.path)
+    }
+
+    fn truncate(&self, handle: &dyn DynFileHandle<'fs>, new_size: u64) -> Result<()> {
+        let handle = plugin_handle(handle)?;
+        let Some(truncate) = self.plugin.truncate else { return unsupported("truncate", &handle.path) };
+        check(unsafe { truncate(self.plugin.context, handle.raw()?, new_size) }, "truncate", &handle.path)
+    }
+
+    fn allocate(&self, handle: &dyn DynFileHandle<'fs>, offset: u64, length: u64) -> Result<()> {
+        self.range_operation(self.plugin.allocate, "allocate", plugin_handle(handle)?, offset, length)
+    }
+
+    fn punch_hole(&self, handle: &dyn DynFileHandle<'fs>, offset: u64, length: u64) -> Result<()> {
+        self.range_operation(self.plugin.punch_hole, "punch hole in", plugin_handle(handle)?, offset, length)
+    }
+
+    fn sync_directory(&self, directory: &Path) -> Result<()> {
+        self.path_operation(self.plugin.sync_directory, "sync directory", directory)
+    }
+
+    fn move_file(&self, src: &Path, dst: &Path) -> Result<()> {
+        let Some(move_file) = self.plugin.move_file else { return unsupported("move file", src) };
+        let (c_src, c_dst) = (c_path(src)?, c_path(dst)?);
+        check(unsafe { move_file(self.plugin.context, c_src.as_ptr(), c_dst.as_ptr()) }, "move file", src)
+    }
+
+    /// The modification time reported by the plugin, if it implements `last_modified`
+    fn file_version(&self, handle: &dyn DynFileHandle<'fs>) -> Result<Option<String>> {
+        let handle = plugin_handle(handle)?;
+        let Some(last_modified) = self.plugin.last_modified else { return Ok(None) };
+        let mut modified = 0;
+        check(unsafe { last_modified(self.plugin.context, handle.raw()?, &mut modified) }, "modification time of", &handle.path)?;
+        Ok(Some(modified.to_string()))
+    }
+
+    fn join_path(&self, l: &Path, r: &Path) -> Result<PathBuf> {
+        Ok(l.join(r))
+    }
+}
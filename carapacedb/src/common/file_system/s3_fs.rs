@@ -0,0 +1,708 @@
+use std::any::TypeId;
+use std::io::{Error, ErrorKind, Result};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::common::sha256::{hmac_sha256, sha256, to_hex};
+use super::dynamic_fs::{downcast_handle, DynFileHandle, DynFileSystem, Token};
+use super::http_fs::{invalid_response, status_error, HttpConfig, HttpFileSystem, HttpResponse, HttpTransport, TcpTransport};
+use super::virtual_fs::split_scheme;
+use super::{transfer_size, FileFlags, FileLockType};
+
+#[derive(Debug, Clone)]
+pub struct S3Config {
+    /// The URL of the object store, e.g. `http://127.0.0.1:9000`; buckets are addressed
+    /// path-style
+    pub endpoint: String,
+    pub region: String,
+    pub access_key_id: String,
+    pub secret_access_key: String,
+    pub session_token: Option<String>,
+    /// Writes are uploaded in parts of this many bytes; objects smaller than one part
+    /// are uploaded with a single PUT
+    pub part_size: usize,
+    /// Block size, caching and retries of requests
+    pub http: HttpConfig,
+}
+
+impl Default for S3Config {
+    fn default() -> Self {
+        S3Config {
+            endpoint: "http://127.0.0.1:9000".to_string(),
+            region: "us-east-1".to_string(),
+            access_key_id: String::new(),
+            secret_access_key: String::new(),
+            session_token: None,
+            part_size: 8 << 20,
+            http: HttpConfig::default(),
+        }
+    }
+}
+
+/// Signs requests for `s3://bucket/key?query` URLs with AWS Signature Version 4 and
+/// sends them to the endpoint. The key is passed unencoded and the query encoded.
+#[derive(Debug)]
+struct S3Transport {
+    config: S3Config,
+    inner: Box<dyn HttpTransport>,
+}
+
+/// A file system for an S3-compatible object store. Paths are `bucket/key`; there are
+/// no directories, a directory exists as long as an object has a key under it.
+/// Objects are written sequentially by one handle and become visible once the handle
+/// is synced or closed. A handle dropped without either discards what was written, and
+/// an upload that failed cannot be continued, so that an incomplete object is never
+/// published.
+#[derive(Debug)]
+pub struct S3FileSystem {
+    part_size: usize,
+    /// Reads objects through ranged GETs and caches their blocks
+    http: HttpFileSystem,
+}
+
+/// A handle to an object being read or written. Callers must `close` a handle they
+/// wrote to: dropping it instead discards what was written and makes one attempt to
+/// abort the multipart upload, without retrying and without reporting a failure.
+#[derive(Debug)]
+pub struct S3FileHandle<'fs> {
+    fs: &'fs S3FileSystem,
+    path: PathBuf,
+    object: S3Object<'fs>,
+}
+
+#[derive(Debug)]
+enum S3Object<'fs> {
+    Read(Box<dyn DynFileHandle<'fs> + 'fs>),
+    Write(Mutex<S3Upload>),
+}
+
+#[derive(Debug)]
+struct S3Upload {
+    url: String,
+    buffer: Vec<u8>,
+    size: u64,
+    upload_id: Option<String>,
+    etags: Vec<String>,
+    finished: bool,
+    /// Set once uploading a part failed or the upload was discarded
+    aborted: bool,
+}
+
+/// Percent-encodes `value` as required for signing: everything but unreserved
+/// characters, and `/` if `encode_slash` is false
+fn uri_encode(value: &str, encode_slash: bool) -> String {
+    let mut encoded = String::with_capacity(value.len());
+    for byte in value.bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => encoded.push(byte as char),
+            b'/' if !encode_slash => encoded.push('/'),
+            _ => encoded.push_str(&format!("%{:02X}", byte)),
+        }
+    }
+    encoded
+}
+
+/// The `YYYYMMDD` date and `YYYYMMDDTHHMMSSZ` timestamp of `time`, in UTC
+fn amz_timestamp(time: SystemTime) -> (String, String) {
+    let seconds = time.duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0);
+    let (days, time_of_day) = ((seconds / 86400) as i64, seconds % 86400);
+    // civil date from days since 1970-01-01, see http://howardhinnant.github.io/date_algorithms.html
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let day_of_era = z.rem_euclid(146097);
+    let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let mp = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+
+    let date = format!("{:04}{:02}{:02}", year, month, day);
+    let timestamp = format!(
+        "{}T{:02}{:02}{:02}Z",
+        date, time_of_day / 3600, time_of_day / 60 % 60, time_of_day % 60
+    );
+    (date, timestamp)
+}
+
+/// The text content of every `<tag>` element in `xml`
+fn xml_elements<'x>(xml: &'x str, tag: &str) -> Vec<&'x str> {
+    let (open, close) = (format!("<{}>", tag), format!("</{}>", tag));
+    let mut elements = Vec::new();
+    let mut rest = xml;
+    while let Some(start) = rest.find(&open) {
+        rest = &rest[start + open.len()..];
+        let Some(end) = rest.find(&close) else { break };
+        elements.push(&rest[..end]);
+        rest = &rest[end + close.len()..];
+    }
+    elements
+}
+
+fn xml_unescape(text: &str) -> String {
+    text.replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&apos;", "'")
+        .replace("&amp;", "&")
+}
+
+fn xml_escape(text: &str) -> String {
+    text.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;")
+}
+
+/// Splits `bucket/key` into the bucket and the key, which may be empty
+fn split_bucket(path: &Path) -> Result<(&str, &str)> {
+    let (_, stripped) = split_scheme(path);
+    let Some(path_str) = stripped.to_str() else {
+        return Err(Error::new(ErrorKind::InvalidInput, format!("invalid s3 path '{}'", path.display())));
+    };
+    let path_str = path_str.trim_matches('/');
+    let (bucket, key) = path_str.split_once('/').unwrap_or((path_str, ""));
+    if bucket.is_empty() {
+        return Err(Error::new(ErrorKind::InvalidInput, format!("missing bucket in s3 path '{}'", path.display())));
+    }
+    Ok((bucket, key.trim_end_matches('/')))
+}
+
+fn object_url(bucket: &str, key: &str) -> String {
+    format!("s3://{}/{}", bucket, key)
+}
+
+fn unsupported(operation: &str, path: &Path) -> Error {
+    Error::new(
+        ErrorKind::Unsupported,
+        format!("cannot {} '{}': not supported by the s3 file system", operation, path.display())
+    )
+}
+
+/// Fails unless `response` has a 2xx status and no error document; S3 can report errors
+/// with status 200 once it has started sending a response
+fn check_response(response: HttpResponse, url: &str) -> Result<HttpResponse> {
+    if !(200..300).contains(&response.status) {
+        return Err(status_error(response.status, url));
+    }
+    if response.body.starts_with(b"<?xml") && String::from_utf8_lossy(&response.body).contains("<Error>") {
+        return Err(Error::other(format!("request for '{}' failed: {}", url, String::from_utf8_lossy(&response.body))));
+    }
+    Ok(response)
+}
+
+impl HttpTransport for S3Transport {
+    fn request(&self, method: &str, url: &str, headers: &[(&str, String)], body: &[u8]) -> Result<HttpResponse> {
+        let Some(rest) = url.strip_prefix("s3://") else {
+            return Err(Error::new(ErrorKind::InvalidInput, format!("invalid s3 url '{}'", url)));
+        };
+        let (path, query) = rest.split_once('?').unwrap_or((rest, ""));
+        let canonical_uri = format!("/{}", uri_encode(path, false));
+        let mut parameters: Vec<String> = query.split('&')
+            .filter(|parameter| !parameter.is_empty())
+            .map(|parameter| if parameter.contains('=') { parameter.to_string() } else { format!("{}=", parameter) })
+            .collect();
+        parameters.sort();
+        let canonical_query = parameters.join("&");
+
+        let endpoint = self.config.endpoint.trim_end_matches('/');
+        let host = endpoint.split_once("://").map_or(endpoint, |(_, host)| host);
+        let (date, timestamp) = amz_timestamp(SystemTime::now());
+        let payload_hash = to_hex(&sha256(body));
+
+        let mut signed_headers = vec![
+            ("host".to_string(), host.to_string()),
+            ("x-amz-content-sha256".to_string(), payload_hash.clone()),
+            ("x-amz-date".to_string(), timestamp.clone()),
+        ];
+        if let Some(token) = &self.config.session_token {
+            signed_headers.push(("x-amz-security-token".to_string(), token.clone()));
+        }
+        for (name, value) in headers {
+            let name = name.to_ascii_lowercase();
+            if name.starts_with("x-amz-") {
+                signed_headers.push((name, value.trim().to_string()));
+            }
+        }
+        signed_headers.sort();
+        let canonical_headers: String = signed_headers.iter()
+            .map(|(name, value)| format!("{}:{}\n", name, value))
+            .collect();
+        let signed_header_names = signed_headers.iter()
+            .map(|(name, _)| name.as_str())
+            .collect::<Vec<_>>()
+            .join(";");
+
+        let canonical_request = format!(
+            "{}\n{}\n{}\n{}\n{}\n{}",
+            method, canonical_uri, canonical_query, canonical_headers, signed_header_names, payload_hash
+        );
+        let scope = format!("{}/{}/s3/aws4_request", date, self.config.region);
+        let string_to_sign = format!(
+            "AWS4-HMAC-SHA256\n{}\n{}\n{}",
+            timestamp, scope, to_hex(&sha256(canonical_request.as_bytes()))
+        );
+        let mut key = hmac_sha256(format!("AWS4{}", self.config.secret_access_key).as_bytes(), date.as_bytes());
+        for part in [self.config.region.as_str(), "s3", "aws4_request"] {
+            key = hmac_sha256(&key, part.as_bytes());
+        }
+        let signature = to_hex(&hmac_sha256(&key, string_to_sign.as_bytes()));
+        let authorization = format!(
+            "AWS4-HMAC-SHA256 Credential={}/{}, SignedHeaders={}, Signature={}",
+            self.config.access_key_id, scope, signed_header_names, signature
+        );
+
+        let mut request_headers: Vec<(&str, String)> = headers.to_vec();
+        request_headers.push(("x-amz-content-sha256", payload_hash));
+        request_headers.push(("x-amz-date", timestamp));
+        if let Some(token) = &self.config.session_token {
+            request_headers.push(("x-amz-security-token", token.clone()));
+        }
+        request_headers.push(("Authorization", authorization));
+
+        let mut endpoint_url = format!("{}{}", endpoint, canonical_uri);
+        if !canonical_query.is_empty() {
+            endpoint_url.push('?');
+            endpoint_url.push_str(&canonical_query);
+        }
+        self.inner.request(method, &endpoint_url, &request_headers, body)
+    }
+}
+
+impl S3FileSystem {
+    /// Creates a file system sending its requests to `config.endpoint` through a
+    /// `TcpTransport`
+    pub fn new(config: S3Config) -> Self {
+        let transport = TcpTransport::new(config.http.timeout);
+        Self::with_transport(config, Box::new(transport))
+    }
+
+    /// Creates a file system sending its signed requests through `transport`
+    pub fn with_transport(config: S3Config, transport: Box<dyn HttpTransport>) -> Self {
+        let part_size = config.part_size.max(1);
+        let http_config = config.http.clone();
+        let transport = S3Transport { config, inner: transport };
+        S3FileSystem {
+            part_size,
+            http: HttpFileSystem::with_transport("s3", http_config, Box::new(transport)),
+        }
+    }
+
+    fn request(&self, method: &str, url: &str, headers: &[(&str, String)], body: &[u8]) -> Result<HttpResponse> {
+        check_response(self.http.request(method, url, headers, body)?, url)
+    }
+
+    /// Calls `callback` with each key under `prefix` and, if `delimited`, each common
+    /// prefix up to the next `/`, following continuation tokens
+    fn list_objects(&self, bucket: &str, prefix: &str, delimited: bool, callback: &mut dyn FnMut(&str)) -> Result<()> {
+        let mut continuation: Option<String> = None;
+        loop {
+            let mut url = format!("s3://{}?list-type=2&prefix={}", bucket, uri_encode(prefix, true));
+            if delimited {
+                url.push_str("&delimiter=%2F");
+            }
+            if let Some(token) = &continuation {
+                url.push_str(&format!("&continuation-token={}", uri_encode(token, true)));
+            }
+            let response = self.request("GET", &url, &[], &[])?;
+            let body = String::from_utf8_lossy(&response.body);
+
+            for contents in xml_elements(&body, "Contents") {
+                if let Some(key) = xml_elements(contents, "Key").first() {
+                    callback(&xml_unescape(key));
+                }
+            }
+            for common_prefix in xml_elements(&body, "CommonPrefixes") {
+                if let Some(prefix) = xml_elements(common_prefix, "Prefix").first() {
+                    callback(&xml_unescape(prefix));
+                }
+            }
+
+            let truncated = xml_elements(&body, "IsTruncated").first().is_some_and(|value| *value == "true");
+            continuation = xml_elements(&body, "NextContinuationToken").first().map(|token| xml_unescape(token));
+            if !truncated || continuation.is_none() {
+                return Ok(());
+            }
+        }
+    }
+
+    /// Uploads the first `part_len` bytes of the buffer as the next part and removes them
+    /// from the buffer
+    fn upload_part(&self, upload: &mut S3Upload, part_len: usize) -> Result<()> {
+        let upload_id = match &upload.upload_id {
+            Some(upload_id) => upload_id.clone(),
+            None => {
+                let response = self.request("POST", &format!("{}?uploads", upload.url), &[], &[])?;
+                let body = String::from_utf8_lossy(&response.body);
+                let Some(upload_id) = xml_elements(&body, "UploadId").first().map(|id| xml_unescape(id)) else {
+                    return Err(invalid_response(&upload.url, "missing upload id"));
+                };
+                upload.upload_id = Some(upload_id.clone());
+                upload_id
+            }
+        };
+
+        let url = format!(
+            "{}?partNumber={}&uploadId={}",
+            upload.url, upload.etags.len() + 1, uri_encode(&upload_id, true)
+        );
+        let response = self.request("PUT", &url, &[], &upload.buffer[..part_len])?;
+        let Some(etag) = response.header("etag") else {
+            return Err(invalid_response(&upload.url, "missing part etag"));
+        };
+        upload.etags.push(etag.to_string());
+        upload.buffer.drain(..part_len);
+        Ok(())
+    }
+
+    /// Discards the upload: a multipart upload is aborted, so that the store drops its
+    /// parts, and the object is left as it was
+    /// Marks `upload` as aborted and returns the URL that deletes its uploaded parts,
+    /// if there are any
+    fn discard_upload(upload: &mut S3Upload) -> Option<String> {
+        upload.aborted = true;
+        upload.buffer = Vec::new();
+        let upload_id = upload.upload_id.take()?;
+        Some(format!("{}?uploadId={}", upload.url, uri_encode(&upload_id, true)))
+    }
+
+    fn abort_upload(&self, upload: &mut S3Upload) {
+        if let Some(url) = Self::discard_upload(upload) {
+            let _ = self.http.request("DELETE", &url, &[], &[]);
+        }
+    }
+
+    fn write_upload(&self, handle: &S3FileHandle<'_>, buffer: &[u8], nr_bytes: i64, location: Option<u64>) -> Result<()> {
+        let len = transfer_size(buffer.len(), nr_bytes)?;
+        let S3Object::Write(upload) = &handle.object else {
+            return Err(Error::new(
+                ErrorKind::PermissionDenied,
+                format!("file '{}' was not opened for writing", handle.path.display())
+            ));
+        };
+        let mut upload = upload.lock().unwrap();
+        upload.check_writable(&handle.path)?;
+        if location.is_some_and(|location| location != upload.size) {
+            return Err(unsupported("write out of order to", &handle.path));
+        }
+
+        upload.buffer.extend_from_slice(&buffer[..len]);
+        upload.size += len as u64;
+        while upload.buffer.len() >= self.part_size {
+            if let Err(e) = self.upload_part(&mut upload, self.part_size) {
+                self.abort_upload(&mut upload);
+                return Err(e);
+            }
+        }
+        Ok(())
+    }
+
+    /// Uploads the rest of the object and makes it visible. A failed single PUT can be
+    /// retried by finishing the upload again; a failed multipart upload is aborted.
+    fn finish_upload(&self, upload: &mut S3Upload, path: &Path) -> Result<()> {
+        if upload.finished {
+            return Ok(());
+        }
+        upload.check_writable(path)?;
+
+        match upload.upload_id.clone() {
+            None => {
+                self.request("PUT", &upload.url, &[], &upload.buffer)?;
+            }
+            Some(upload_id) => {
+                if let Err(e) = self.complete_upload(upload, &upload_id) {
+                    self.abort_upload(upload);
+                    return Err(e);
+                }
+            }
+        }
+        upload.finished = true;
+        upload.buffer = Vec::new();
+        self.http.clear_cache();
+        Ok(())
+    }
+
+    fn complete_upload(&self, upload: &mut S3Upload, upload_id: &str) -> Result<()> {
+        if !upload.buffer.is_empty() {
+            self.upload_part(upload, upload.buffer.len())?;
+        }
+        let mut complete = String::from("<CompleteMultipartUpload>");
+        for (index, etag) in upload.etags.iter().enumerate() {
+            complete.push_str(&format!(
+                "<Part><PartNumber>{}</PartNumber><ETag>{}</ETag></Part>",
+                index + 1, xml_escape(etag)
+            ));
+        }
+        complete.push_str("</CompleteMultipartUpload>");
+        let url = format!("{}?uploadId={}", upload.url, uri_encode(upload_id, true));
+        self.request("POST", &url, &[], complete.as_bytes())?;
+        Ok(())
+    }
+
+    fn delete_object(&self, bucket: &str, key: &str) -> Result<()> {
+        self.request("DELETE", &object_url(bucket, key), &[], &[]).map(|_| ())
+    }
+}
+
+impl S3Upload {
+    fn check_writable(&self, path: &Path) -> Result<()> {
+        if self.finished {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                format!("cannot write to '{}': the object has already been uploaded", path.display())
+            ));
+        }
+        if self.aborted {
+            return Err(Error::other(format!(
+                "cannot write to '{}': the upload failed and was aborted", path.display()
+            )));
+        }
+        Ok(())
+    }
+}
+
+impl<'fs> S3FileHandle<'fs> {
+    fn close_object(&mut self) -> Result<()> {
+        match &self.object {
+            S3Object::Read(_) => Ok(()),
+            S3Object::Write(upload) => self.fs.finish_upload(&mut upload.lock().unwrap(), &self.path),
+        }
+    }
+
+    fn reader(&self) -> Result<&(dyn DynFileHandle<'fs> + 'fs)> {
+        match &self.object {
+            S3Object::Read(handle) => Ok(handle.as_ref()),
+            S3Object::Write(_) => Err(Error::new(
+                ErrorKind::PermissionDenied,
+                format!("file '{}' was not opened for reading", self.path.display())
+            )),
+        }
+    }
+}
+
+/// Discards an object that was neither synced nor closed, which is most likely the
+/// result of an error, instead of publishing what was written so far
+impl Drop for S3FileHandle<'_> {
+    fn drop(&mut self) {
+        if let S3Object::Write(upload) = &self.object {
+            let mut upload = upload.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+            // a single attempt, so that dropping a handle cannot stall on retries; parts
+            // left behind when it fails are cleaned up by the store's lifecycle rules
+            if upload.finished || upload.aborted {
+                return;
+            }
+            if let Some(url) = S3FileSystem::discard_upload(&mut upload) {
+                let _ = self.fs.http.request_once("DELETE", &url, &[], &[]);
+            }
+        }
+    }
+}
+
+impl<'fs> DynFileHandle<'fs> for S3FileHandle<'fs> {
+    fn file_system(&self) -> &dyn DynFileSystem<'fs> {
+        self.fs
+    }
+
+    fn path(&self) -> &Path {
+        &self.path
+    }
+
+    fn close(&mut self) -> Result<()> {
+        self.close_object()
+    }
+
+    fn handle_type_id(&self, _token: Token) -> Option<TypeId> {
+        Some(TypeId::of::<S3FileHandle<'static>>())
+    }
+}
+
+/// Recovers the `S3FileHandle` behind a handle passed to the `DynFileSystem` interface.
+fn s3_handle<'h, 'fs>(handle: &'h dyn DynFileHandle<'fs>) -> Result<&'h S3FileHandle<'fs>> {
+    downcast_handle(handle, TypeId::of::<S3FileHandle<'static>>())
+}
+
+impl<'fs> DynFileSystem<'fs> for S3FileSystem {
+    fn read_at(&self, handle: &dyn DynFileHandle<'fs>, buffer: &mut [u8], nr_bytes: i64, location: u64) -> Result<()> {
+        let reader = s3_handle(handle)?.reader()?;
+        reader.file_system().read_at(reader, buffer, nr_bytes, location)
+    }
+
+    fn write_at(&self, handle: &dyn DynFileHandle<'fs>, buffer: &[u8], nr_bytes: i64, location: u64) -> Result<()> {
+        self.write_upload(s3_handle(handle)?, buffer, nr_bytes, Some(location))
+    }
+
+    /// Opens an object for reading, or for writing a new version of it; objects cannot
+    /// be opened for both
+    fn open_file(&'fs self, path: &Path, flags: FileFlags, lock: Option<FileLockType>) -> Result<Box<dyn DynFileHandle<'fs> + 'fs>> {
+        let (bucket, key) = split_bucket(path)?;
+        if key.is_empty() {
+            return Err(Error::new(ErrorKind::InvalidInput, format!("missing key in s3 path '{}'", path.display())));
+        }
+        let url = object_url(bucket, key);
+
+        let object = if flags.contains(FileFlags::WRITE) {
+            if flags.contains(FileFlags::READ) {
+                return Err(unsupported("open for reading and writing", path));
+            }
+            if !flags.contains(FileFlags::CREATE) && !self.file_exists(path)? {
+                return Err(Error::new(ErrorKind::NotFound, format!("file '{}' does not exist", path.display())));
+            }
+            S3Object::Write(Mutex::new(S3Upload {
+                url,
+                buffer: Vec::new(),
+                size: 0,
+                upload_id: None,
+                etags: Vec::new(),
+                finished: false,
+                aborted: false,
+            }))
+        } else {
+            S3Object::Read(self.http.open_file(Path::new(&url), flags, lock)?)
+        };
+        Ok(Box::new(S3FileHandle { fs: self, path: path.to_path_buf(), object }))
+    }
+
+    fn set_file_pointer(&self, handle: &dyn DynFileHandle<'fs>, location: u64) -> Result<()> {
+        let handle = s3_handle(handle)?;
+        match &handle.object {
+            S3Object::Read(reader) => reader.file_system().set_file_pointer(reader.as_ref(), location),
+            S3Object::Write(upload) if upload.lock().unwrap().size == location => Ok(()),
+            S3Object::Write(_) => Err(unsupported("seek in object being written", &handle.path)),
+        }
+    }
+
+    fn read(&self, handle: &dyn DynFileHandle<'fs>, buffer: &mut [u8], nr_bytes: i64) -> Result<()> {
+        let reader = s3_handle(handle)?.reader()?;
+        reader.file_system().read(reader, buffer, nr_bytes)
+    }
+
+    fn write(&self, handle: &dyn DynFileHandle<'fs>, buffer: &[u8], nr_bytes: i64) -> Result<()> {
+        self.write_upload(s3_handle(handle)?, buffer, nr_bytes, None)
+    }
+
+    fn file_size(&self, handle: &dyn DynFileHandle<'fs>) -> Result<u64> {
+        match &s3_handle(handle)?.object {
+            S3Object::Read(reader) => reader.file_system().file_size(reader.as_ref()),
+            S3Object::Write(upload) => Ok(upload.lock().unwrap().size),
+        }
+    }
+
+    /// Whether any object has a key under `path`, or `path` is an existing bucket
+    fn directory_exists(&self, path: &Path) -> Result<bool> {
+        let (bucket, key) = split_bucket(path)?;
+        if key.is_empty() {
+            return match self.http.request("GET", &format!("s3://{}?list-type=2&max-keys=0", bucket), &[], &[])? {
+                response if (200..300).contains(&response.status) => Ok(true),
+                response if response.status == 404 => Ok(false),
+                response => Err(status_error(response.status, bucket)),
+            };
+        }
+        let url = format!("s3://{}?list-type=2&max-keys=1&prefix={}", bucket, uri_encode(&format!("{}/", key), true));
+        let response = self.request("GET", &url, &[], &[])?;
+        Ok(!xml_elements(&String::from_utf8_lossy(&response.body), "Contents").is_empty())
+    }
+
+    fn file_exists(&self, file_name: &Path) -> Result<bool> {
+        let (bucket, key) = split_bucket(file_name)?;
+        if key.is_empty() {
+            return Ok(false);
+        }
+        self.http.file_exists(Path::new(&object_url(bucket, key)))
+    }
+
+    /// Directories exist implicitly, so there is nothing to create
+    fn create_directory(&self, _path: &Path) -> Result<()> {
+        Ok(())
+    }
+
+    /// Deletes every object under `path`
+    fn remove_directory(&self, path: &Path) -> Result<()> {
+        let (bucket, key) = split_bucket(path)?;
+        let prefix = if key.is_empty() { String::new() } else { format!("{}/", key) };
+        let mut keys = Vec::new();
+        self.list_objects(bucket, &prefix, false, &mut |key| keys.push(key.to_string()))?;
+        if keys.is_empty() {
+            return Err(Error::new(ErrorKind::NotFound, format!("directory '{}' does not exist", path.display())));
+        }
+        for key in keys {
+            self.delete_object(bucket, &key)?;
+        }
+        self.http.clear_cache();
+        Ok(())
+    }
+
+    fn remove_file(&self, file_name: &Path) -> Result<()> {
+        if !self.file_exists(file_name)? {
+            return Err(Error::new(ErrorKind::NotFound, format!("file '{}' does not exist", file_name.display())));
+        }
+        let (bucket, key) = split_bucket(file_name)?;
+        self.delete_object(bucket, key)?;
+        self.http.clear_cache();
+        Ok(())
+    }
+
+    /// Lists the objects and common prefixes directly under `directory`
+    fn list_files(&self, directory: &Path, callback: &mut dyn FnMut(String)) -> Result<bool> {
+        let (bucket, key) = split_bucket(directory)?;
+        let prefix = if key.is_empty() { String::new() } else { format!("{}/", key) };
+        let mut found = false;
+        self.list_objects(bucket, &prefix, true, &mut |entry| {
+            let name = entry[prefix.len()..].trim_end_matches('/');
+            found = true;
+            // keys ending in `/` mark directories and have no name of their own
+            if !name.is_empty() {
+                callback(name.to_string());
+            }
+        })?;
+        Ok(found)
+    }
+
+    fn path_separator(&self) -> &'static str {
+        "/"
+    }
+
+    /// Completes the upload of an object being written; it cannot be written to after
+    fn fsync(&self, handle: &dyn DynFileHandle<'fs>) -> Result<()> {
+        let handle = s3_handle(handle)?;
+        match &handle.object {
+            S3Object::Read(_) => Ok(()),
+            S3Object::Write(upload) => self.finish_upload(&mut upload.lock().unwrap(), &handle.path),
+        }
+    }
+
+    fn truncate(&self, handle: &dyn DynFileHandle<'fs>, _new_size: u64) -> Result<()> {
+        Err(unsupported("truncate", handle.path()))
+    }
+
+    fn allocate(&self, handle: &dyn DynFileHandle<'fs>, _offset: u64, _length: u64) -> Result<()> {
+        Err(unsupported("allocate space in", handle.path()))
+    }
+
+    fn punch_hole(&self, handle: &dyn DynFileHandle<'fs>, _offset: u64, _length: u64) -> Result<()> {
+        Err(unsupported("punch hole in", handle.path()))
+    }
+
+    fn sync_directory(&self, _directory: &Path) -> Result<()> {
+        Ok(())
+    }
+
+    /// Copies the object and deletes the source; unlike a rename this is not atomic
+    fn move_file(&self, src: &Path, dst: &Path) -> Result<()> {
+        let (src_bucket, src_key) = split_bucket(src)?;
+        let (dst_bucket, dst_key) = split_bucket(dst)?;
+        let copy_source = format!("/{}/{}", src_bucket, uri_encode(src_key, false));
+        self.request("PUT", &object_url(dst_bucket, dst_key), &[("x-amz-copy-source", copy_source)], &[])?;
+        self.delete_object(src_bucket, src_key)?;
+        self.http.clear_cache();
+        Ok(())
+    }
+
+    fn file_version(&self, handle: &dyn DynFileHandle<'fs>) -> Result<Option<String>> {
+        match &s3_handle(handle)?.object {
+            S3Object::Read(reader) => reader.file_system().file_version(reader.as_ref()),
+            S3Object::Write(_) => Ok(None),
+        }
+    }
+
+    fn join_path(&self, l: &Path, r: &Path) -> Result<PathBuf> {
+        Ok(l.join(r))
+    }
+}
@@ -4,6 +4,8 @@ use std::os::unix::io::RawFd;
 
 use super::FileFlags;
 use super::FileLockType;
+use super::{replace_file, transfer_size, unsupported_operation, ReplaceFileSteps};
+use super::glob::{expand_glob, GlobSource};
 use std::ffi::{CString, CStr, OsStr};
 use std::os::unix::ffi::OsStrExt;
 
@@ -12,6 +14,12 @@ pub trait SFileHandle<FS: SFileSystem>: Send + Sync {
 
     fn path(&self) -> &Path;
     fn close(&mut self) -> Result<()>;
+
+    /// Returns the `length` bytes at `location` without copying them, if the file is
+    /// memory-mapped and the range lies within the mapping
+    fn mapped_slice(&self, _location: u64, _length: usize) -> Option<&[u8]> {
+        None
+    }
 }
 
 pub trait SFileSystem: Send + Sync + Sized {
@@ -49,14 +57,105 @@ pub trait SFileSystem: Send + Sync + Sized {
 
     fn fsync(&self, handle: &Self::Handle<'_>) -> Result<()>;
 
+    /// Sets the size of the file to `new_size`, discarding the data beyond it or
+    /// extending it with zeros. Fails with `ErrorKind::Unsupported` by default.
+    fn truncate(&self, handle: &Self::Handle<'_>, _new_size: u64) -> Result<()> {
+        Err(unsupported_operation("truncate", handle.path()))
+    }
+
+    /// Reserves disk space for `length` bytes at `offset`, so that writing them cannot
+    /// fail for lack of space. Extends the file if the range ends beyond it. Fails with
+    /// `ErrorKind::Unsupported` by default.
+    fn allocate(&self, handle: &Self::Handle<'_>, _offset: u64, _length: u64) -> Result<()> {
+        Err(unsupported_operation("allocate space in", handle.path()))
+    }
+
+    /// Gives the disk space of `length` bytes at `offset` back to the file system; the
+    /// range reads as zeros afterwards and the file size does not change. Fails with
+    /// `ErrorKind::Unsupported` by default.
+    fn punch_hole(&self, handle: &Self::Handle<'_>, _offset: u64, _length: u64) -> Result<()> {
+        Err(unsupported_operation("punch hole in", handle.path()))
+    }
+
+    /// Flushes the entries of `directory` to disk, making the creation, removal and
+    /// renaming of the files in it durable. Fails with `ErrorKind::Unsupported` by default.
+    fn sync_directory(&self, directory: &Path) -> Result<()> {
+        Err(unsupported_operation("sync directory", directory))
+    }
+
     fn move_file(&self, src: &Path, dst: &Path) -> Result<()>;
+
+    /// An identifier of the version of the file, such as an ETag, that changes whenever
+    /// its contents change; `None` if the file system cannot tell
+    fn file_version(&self, _handle: &Self::Handle<'_>) -> Result<Option<String>> {
+        Ok(None)
+    }
     
     fn join_path(&self, l: &Path, r:&Path) -> Result<PathBuf>;
+
+    /// Expands a glob pattern (`*`, `?`, `[...]` and recursive `**`) to the sorted
+    /// list of files it matches. File systems with symbolic links override it so that
+    /// `**` does not follow them.
+    fn glob(&self, pattern: &str) -> Result<Vec<PathBuf>> {
+        let list_files = |directory: &Path, callback: &mut dyn FnMut(String)| {
+            self.list_files(directory, callback)
+        };
+        let source = GlobSource {
+            list_files: &list_files,
+            directory_exists: &|path| self.directory_exists(path),
+            file_exists: &|path| self.file_exists(path),
+            is_symlink: &|_| Ok(false),
+        };
+        expand_glob(&source, pattern)
+    }
+
+    /// Durably replaces the file at `path` with the contents written by `write`, see
+    /// `replace_file`
+    fn replace_file<'a, F>(&'a self, path: &Path, write: F) -> Result<()>
+    where F: FnOnce(&Self::Handle<'a>) -> Result<()> {
+        replace_file(&StaticReplaceSteps(self), path, write)
+    }
+}
+
+/// The steps of `replace_file` for an `SFileSystem`
+struct StaticReplaceSteps<'a, FS>(&'a FS);
+
+impl<'a, FS: SFileSystem> ReplaceFileSteps for StaticReplaceSteps<'a, FS> {
+    type Handle = FS::Handle<'a>;
+
+    fn file_exists(&self, path: &Path) -> Result<bool> {
+        self.0.file_exists(path)
+    }
+
+    fn remove_file(&self, path: &Path) -> Result<()> {
+        self.0.remove_file(path)
+    }
+
+    fn create_file(&self, path: &Path) -> Result<Self::Handle> {
+        self.0.open_file(path, FileFlags::WRITE | FileFlags::CREATE, FileLockType::WriteLock)
+    }
+
+    fn fsync(&self, handle: &Self::Handle) -> Result<()> {
+        self.0.fsync(handle)
+    }
+
+    fn close(&self, handle: &mut Self::Handle) -> Result<()> {
+        handle.close()
+    }
+
+    fn move_file(&self, src: &Path, dst: &Path) -> Result<()> {
+        self.0.move_file(src, dst)
+    }
+
+    fn sync_directory(&self, directory: &Path) -> Result<()> {
+        self.0.sync_directory(directory)
+    }
 }
 
 #[derive(Debug, Clone, Copy)]
 pub struct LocalFileSystem;
 
+#[derive(Debug)]
 pub struct LocalFileHandle<'a> {
     fs: &'a LocalFileSystem,
     pub path: PathBuf,
@@ -65,6 +164,73 @@ pub struct LocalFileHandle<'a> {
 
     #[cfg(windows)]
     pub fd: std::os::windows::io::RawHandle,
+
+    /// A read-only mapping of the file, for files opened with `FileFlags::MEMORY_MAP`
+    #[cfg(unix)]
+    mapping: Option<FileMapping>,
+}
+
+/// A read-only shared mapping of a whole file, as large as the file was when mapped
+#[cfg(unix)]
+#[derive(Debug)]
+struct FileMapping {
+    ptr: *mut libc::c_void,
+    len: usize,
+}
+
+// SAFETY: the mapping is read-only and owned by a single handle
+#[cfg(unix)]
+unsafe impl Send for FileMapping {}
+#[cfg(unix)]
+unsafe impl Sync for FileMapping {}
+
+#[cfg(unix)]
+impl FileMapping {
+    /// Maps the file behind `fd`; returns `None` if it is empty or cannot be mapped
+    fn map(fd: RawFd) -> Option<FileMapping> {
+        let mut status = std::mem::MaybeUninit::<libc::stat>::uninit();
+        if unsafe { libc::fstat(fd, status.as_mut_ptr()) } != 0 {
+            return None;
+        }
+        let len = unsafe { status.assume_init() }.st_size as usize;
+        if len == 0 {
+            return None;
+        }
+
+        let ptr = unsafe { libc::mmap(std::ptr::null_mut(), len, libc::PROT_READ, libc::MAP_SHARED, fd, 0) };
+        if ptr == libc::MAP_FAILED {
+            return None;
+        }
+        Some(FileMapping { ptr, len })
+    }
+
+    fn as_slice(&self) -> &[u8] {
+        // SAFETY: the mapping is readable and `len` bytes long until it is unmapped
+        unsafe { std::slice::from_raw_parts(self.ptr as *const u8, self.len) }
+    }
+}
+
+#[cfg(unix)]
+impl Drop for FileMapping {
+    fn drop(&mut self) {
+        unsafe { libc::munmap(self.ptr, self.len) };
+    }
+}
+
+#[cfg(unix)]
+impl<'a> LocalFileHandle<'a> {
+    /// Returns whether reads go through a memory mapping of the file
+    pub fn is_mapped(&self) -> bool {
+        self.mapping.is_some()
+    }
+
+    /// Returns the `length` bytes at `location` without copying them, if the file is
+    /// mapped and the range lies within the mapping
+    pub fn mapped_slice(&self, location: u64, length: usize) -> Option<&[u8]> {
+        let data = self.mapping.as_ref()?.as_slice();
+        let start = usize::try_from(location).ok()?;
+        data.get(start..start.checked_add(length)?)
+    }
 }
 
 impl<'a> SFileHandle<LocalFileSystem> for LocalFileHandle<'a> {
@@ -77,10 +243,16 @@ impl<'a> SFileHandle<LocalFileSystem> for LocalFileHandle<'a> {
         &self.path
     }
     
+    /// Closes the file descriptor; closing an already closed handle does nothing, so
+    /// that dropping it afterwards cannot close a descriptor reused by another file
     fn close(&mut self) -> Result<()> {
         #[cfg(unix)]
         {
-            let ret = unsafe { libc::close(self.fd) };
+            if self.fd == -1 {
+                return Ok(());
+            }
+            let fd = std::mem::replace(&mut self.fd, -1);
+            let ret = unsafe { libc::close(fd) };
             if ret < 0 {
                 return Err(Error::last_os_error());
             }
@@ -95,11 +267,16 @@ impl<'a> SFileHandle<LocalFileSystem> for LocalFileHandle<'a> {
         
         Ok(())
     }
+
+    #[cfg(unix)]
+    fn mapped_slice(&self, location: u64, length: usize) -> Option<&[u8]> {
+        LocalFileHandle::mapped_slice(self, location, length)
+    }
 }
 
 impl<'a> Drop for LocalFileHandle<'a> {
     fn drop(&mut self) {
-        if let Err(e) = self.close() {
+        if let Err(e) = SFileHandle::close(self) {
             eprintln!("failed to close handle: {}", e);
         }
     }
@@ -268,10 +445,22 @@ impl SFileSystem for LocalFileSystem {
             }
         }
 
+        // reads of mapped files come from the page cache without a syscall; if the file
+        // cannot be mapped, they fall back to pread. Only files that are neither written
+        // nor truncated while open are mapped, since a shrinking file raises SIGBUS on
+        // access and writes would change the slices returned by `mapped_slice`.
+        let memory_map = FileFlags::READ | FileFlags::MEMORY_MAP;
+        let mapping = if flags.contains(memory_map) && !flags.contains(FileFlags::DIRECT_IO) {
+            FileMapping::map(fd)
+        } else {
+            None
+        };
+
         Ok(LocalFileHandle {
             fs: self,  
             path: path.to_path_buf(),       
             fd,                            
+            mapping,
         })
     }
 
@@ -294,34 +483,46 @@ impl SFileSystem for LocalFileSystem {
 
 
     fn read(&self, handle: &Self::Handle<'_>, buffer: &mut [u8], nr_bytes: i64) -> Result<u64> {
-        let result = unsafe {
-            libc::read(
-                handle.fd,
-                buffer.as_mut_ptr() as *mut libc::c_void,
-                buffer.len(),
-            )
-        };
+        let count = transfer_size(buffer.len(), nr_bytes)?;
+        loop {
+            let result = unsafe {
+                libc::read(
+                    handle.fd,
+                    buffer.as_mut_ptr() as *mut libc::c_void,
+                    count,
+                )
+            };
 
-        if result == -1 {
-            Err(Error::last_os_error())
-        } else {
-            Ok(result as u64)
+            if result == -1 {
+                let err = Error::last_os_error();
+                if err.kind() == ErrorKind::Interrupted {
+                    continue;
+                }
+                return Err(err);
+            }
+            return Ok(result as u64);
         }
     }
 
     fn write(&self, handle: &Self::Handle<'_>, buffer: &[u8], nr_bytes: i64) -> Result<u64> {
-        let result = unsafe {
-            libc::write(
-                handle.fd,
-                buffer.as_ptr() as *const libc::c_void,
-                buffer.len(),
-            )
-        };
+        let count = transfer_size(buffer.len(), nr_bytes)?;
+        loop {
+            let result = unsafe {
+                libc::write(
+                    handle.fd,
+                    buffer.as_ptr() as *const libc::c_void,
+                    count,
+                )
+            };
 
-        if result == -1 {
-            Err(Error::last_os_error())
-        } else {
-            Ok(result as u64)
+            if result == -1 {
+                let err = Error::last_os_error();
+                if err.kind() == ErrorKind::Interrupted {
+                    continue;
+                }
+                return Err(err);
+            }
+            return Ok(result as u64);
         }
     }
 
@@ -336,6 +537,17 @@ impl SFileSystem for LocalFileSystem {
         Ok(stat.st_size as u64)
     }
 
+    /// The modification time and inode number of the file, so that files rewritten in
+    /// place or replaced by a rename get a new version
+    fn file_version(&self, handle: &Self::Handle<'_>) -> Result<Option<String>> {
+        let mut stat = std::mem::MaybeUninit::<libc::stat>::uninit();
+        if unsafe { libc::fstat(handle.fd, stat.as_mut_ptr()) } == -1 {
+            return Err(Error::last_os_error());
+        }
+        let stat = unsafe { stat.assume_init() };
+        Ok(Some(format!("{}.{:09}-{}", stat.st_mtime, stat.st_mtime_nsec, stat.st_ino)))
+    }
+
     fn directory_exists(&self, path: &Path) -> Result<bool> {
         if path.as_os_str().is_empty() {
             return Ok(false);
@@ -424,7 +636,7 @@ impl SFileSystem for LocalFileSystem {
 
     fn list_files<F>(&self, directory: &Path, callback: F) -> Result<bool>
      where F: FnMut(String) {
-        if !self.directory_exists(directory)? {
+        if !SFileSystem::directory_exists(self, directory)? {
             return Ok(false);
         }
 
@@ -470,8 +682,85 @@ impl SFileSystem for LocalFileSystem {
         }
     }
 
+    fn truncate(&self, handle: &Self::Handle<'_>, new_size: u64) -> Result<()> {
+        loop {
+            let result = unsafe { libc::ftruncate(handle.fd, new_size as libc::off_t) };
+            if result == 0 {
+                return Ok(());
+            }
+            let err = Error::last_os_error();
+            if err.kind() != ErrorKind::Interrupted {
+                return Err(Error::new(
+                    err.kind(),
+                    format!("failed to truncate '{}' to {} bytes: {}", handle.path.display(), new_size, err)
+                ));
+            }
+        }
+    }
+
+    #[cfg(target_os = "linux")]
+    fn allocate(&self, handle: &Self::Handle<'_>, offset: u64, length: u64) -> Result<()> {
+        fallocate(handle, 0, offset, length)
+    }
+
+    /// Without fallocate the space is not reserved, only the file size is extended
+    #[cfg(not(target_os = "linux"))]
+    fn allocate(&self, handle: &Self::Handle<'_>, offset: u64, length: u64) -> Result<()> {
+        let Some(end) = offset.checked_add(length) else {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                format!("cannot allocate {} bytes at offset {} in '{}': out of bounds", length, offset, handle.path.display())
+            ));
+        };
+        if SFileSystem::file_size(self, handle)? < end {
+            SFileSystem::truncate(self, handle, end)?;
+        }
+        Ok(())
+    }
+
+    /// Fails with `ErrorKind::Unsupported` if the underlying file system cannot punch holes
+    #[cfg(target_os = "linux")]
+    fn punch_hole(&self, handle: &Self::Handle<'_>, offset: u64, length: u64) -> Result<()> {
+        fallocate(handle, libc::FALLOC_FL_PUNCH_HOLE | libc::FALLOC_FL_KEEP_SIZE, offset, length)
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    fn punch_hole(&self, handle: &Self::Handle<'_>, _offset: u64, _length: u64) -> Result<()> {
+        Err(Error::new(
+            ErrorKind::Unsupported,
+            format!("cannot punch hole in '{}': not supported on this platform", handle.path.display())
+        ))
+    }
+
+    fn sync_directory(&self, directory: &Path) -> Result<()> {
+        let c_path = CString::new(directory.as_os_str().as_bytes())?;
+
+        let fd = unsafe { libc::open(c_path.as_ptr(), libc::O_RDONLY | libc::O_DIRECTORY | libc::O_CLOEXEC) };
+        if fd == -1 {
+            let err = Error::last_os_error();
+            return Err(Error::new(
+                err.kind(),
+                format!("cannot open directory '{}' for syncing: {}", directory.display(), err)
+            ));
+        }
+
+        let result = unsafe { libc::fsync(fd) };
+        let err = Error::last_os_error();
+        unsafe { libc::close(fd) };
+
+        if result == -1 {
+            Err(Error::new(
+                err.kind(),
+                format!("failed to sync directory '{}': {}", directory.display(), err)
+            ))
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Atomically replaces `dst` with `src` (both must be on the same device). The rename
+    /// is only durable once the parent directory has been synced, see `replace_file`.
     fn move_file(&self, src: &Path, dst: &Path) -> Result<()> {
-        //! FIXME: rename does not guarantee atomicity or overwriting target file if it exists
         let c_source = CString::new(src.as_os_str().as_bytes()).map_err(|e| {
             Error::new(ErrorKind::InvalidInput, format!("invalid source file path: {}", e))
         })?;
@@ -494,36 +783,121 @@ impl SFileSystem for LocalFileSystem {
     fn join_path(&self, l: &Path, r: &Path) -> Result<PathBuf> {
         let l_str = l.to_string_lossy();
         let r_str = r.to_string_lossy();
-        let sep = self.path_separator();
+        let sep = SFileSystem::path_separator(self);
     
         let full_path = l_str.to_string() + sep + &r_str;
         Ok(PathBuf::from(full_path))
     }
 
+    fn glob(&self, pattern: &str) -> Result<Vec<PathBuf>> {
+        let list_files = |directory: &Path, callback: &mut dyn FnMut(String)| {
+            SFileSystem::list_files(self, directory, callback)
+        };
+        let is_symlink = |path: &Path| match std::fs::symlink_metadata(path) {
+            Ok(metadata) => Ok(metadata.file_type().is_symlink()),
+            Err(e) if e.kind() == ErrorKind::NotFound => Ok(false),
+            Err(e) => Err(e),
+        };
+        let source = GlobSource {
+            list_files: &list_files,
+            directory_exists: &|path| SFileSystem::directory_exists(self, path),
+            file_exists: &|path| SFileSystem::file_exists(self, path),
+            is_symlink: &is_symlink,
+        };
+        expand_glob(&source, pattern)
+    }
+
+    /// Reads exactly `nr_bytes` at `location` from the mapping of the file, or with pread
+    /// if the range is not mapped, so that the file pointer is not touched and several
+    /// threads can read through the same handle concurrently.
     fn read_at(&self, handle: &Self::Handle<'_>, buffer: &mut [u8], nr_bytes: i64, location: u64) -> Result<()> {
-        self.set_file_pointer(handle, location)?;
-        let bytes_read = self.read(handle, buffer, nr_bytes)?;
+        let count = transfer_size(buffer.len(), nr_bytes)?;
+        if let Some(data) = handle.mapped_slice(location, count) {
+            buffer[..count].copy_from_slice(data);
+            return Ok(());
+        }
 
-        if bytes_read as i64 != nr_bytes {
-            return Err(Error::new(
-                ErrorKind::UnexpectedEof, 
-                format!("read_at failed: expected {} bytes, but read {}", nr_bytes, bytes_read)
-            ));
+        let mut bytes_read = 0;
+        while bytes_read < count {
+            let result = unsafe {
+                libc::pread(
+                    handle.fd,
+                    buffer[bytes_read..].as_mut_ptr() as *mut libc::c_void,
+                    count - bytes_read,
+                    (location + bytes_read as u64) as libc::off_t,
+                )
+            };
+
+            if result == -1 {
+                let err = Error::last_os_error();
+                if err.kind() == ErrorKind::Interrupted {
+                    continue;
+                }
+                return Err(err);
+            }
+            if result == 0 {
+                return Err(Error::new(
+                    ErrorKind::UnexpectedEof,
+                    format!("read_at failed: expected {} bytes, but read {}", nr_bytes, bytes_read)
+                ));
+            }
+            bytes_read += result as usize;
         }
-        
+
         Ok(())
     }
 
+    /// Writes exactly `nr_bytes` at `location` with pwrite, see `read_at`.
     fn write_at(&self, handle: &Self::Handle<'_>, buffer: &[u8], nr_bytes: i64, location: u64) -> Result<()> {
-        self.set_file_pointer(handle, location)?;
-        let bytes_written = self.write(handle, buffer, nr_bytes)?;
-        if bytes_written as i64 != nr_bytes {
-            return Err(Error::new(
-                ErrorKind::UnexpectedEof,
-                format!("write_at failed: expected {} bytes, but wrote {}", nr_bytes, bytes_written)
-            ));
+        let count = transfer_size(buffer.len(), nr_bytes)?;
+        let mut bytes_written = 0;
+        while bytes_written < count {
+            let result = unsafe {
+                libc::pwrite(
+                    handle.fd,
+                    buffer[bytes_written..].as_ptr() as *const libc::c_void,
+                    count - bytes_written,
+                    (location + bytes_written as u64) as libc::off_t,
+                )
+            };
+
+            if result == -1 {
+                let err = Error::last_os_error();
+                if err.kind() == ErrorKind::Interrupted {
+                    continue;
+                }
+                return Err(err);
+            }
+            if result == 0 {
+                return Err(Error::new(
+                    ErrorKind::WriteZero,
+                    format!("write_at failed: expected {} bytes, but wrote {}", nr_bytes, bytes_written)
+                ));
+            }
+            bytes_written += result as usize;
         }
+
         Ok(())
     }
 }
-  
+#[cfg(target_os = "linux")]
+fn fallocate(handle: &LocalFileHandle<'_>, mode: libc::c_int, offset: u64, length: u64) -> Result<()> {
+    loop {
+        let result = unsafe { libc::fallocate(handle.fd, mode, offset as libc::off_t, length as libc::off_t) };
+        if result == 0 {
+            return Ok(());
+        }
+        let err = Error::last_os_error();
+        match err.raw_os_error() {
+            Some(libc::EINTR) => continue,
+            Some(libc::EOPNOTSUPP) => return Err(Error::new(
+                ErrorKind::Unsupported,
+                format!("fallocate on '{}' is not supported by the file system", handle.path.display())
+            )),
+            _ => return Err(Error::new(
+                err.kind(),
+                format!("fallocate on '{}' failed: {}", handle.path.display(), err)
+            )),
+        }
+    }
+}
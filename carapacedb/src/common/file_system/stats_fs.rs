@@ -0,0 +1,396 @@
+use std::any::TypeId;
+use std::collections::BTreeMap;
+use std::fmt::{self, Debug};
+use std::io::Result;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::{Duration, Instant};
+
+use super::dynamic_fs::{downcast_handle, DynFileHandle, DynFileSystem, Token};
+use super::{FileFlags, FileLockType, UnifiedFileHandle, UnifiedFileSystem};
+
+/// The number of buckets of a `LatencyHistogram`; the last one also counts everything
+/// slower than its lower bound (about 18 minutes)
+const LATENCY_BUCKETS: usize = 32;
+
+/// A histogram of operation latencies with power-of-two buckets: bucket `i` counts the
+/// operations that took less than `2^i` microseconds (and at least `2^(i-1)`).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LatencyHistogram {
+    buckets: [u64; LATENCY_BUCKETS],
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct FileIoStatistics {
+    pub bytes_read: u64,
+    pub bytes_written: u64,
+    /// The number of read calls, including failed ones
+    pub reads: u64,
+    /// The number of write calls, including failed ones
+    pub writes: u64,
+    /// The number of fsync calls, including failed ones
+    pub fsyncs: u64,
+    pub read_latency: LatencyHistogram,
+    pub write_latency: LatencyHistogram,
+    pub fsync_latency: LatencyHistogram,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IoOperation {
+    Read,
+    Write,
+    Fsync,
+}
+
+/// A single I/O operation, as passed to the trace hook
+#[derive(Debug, Clone, Copy)]
+pub struct IoEvent<'a> {
+    pub path: &'a Path,
+    pub operation: IoOperation,
+    /// The file offset, for positional reads and writes
+    pub location: Option<u64>,
+    pub bytes: u64,
+    pub latency: Duration,
+    pub success: bool,
+}
+
+pub type IoTraceHook = Box<dyn Fn(&IoEvent<'_>) + Send + Sync>;
+
+/// An installed trace hook, which is cloned out of the lock before it is called, so
+/// that the hook can replace itself
+type SharedTraceHook = Arc<dyn Fn(&IoEvent<'_>) + Send + Sync>;
+
+/// The I/O statistics of a file system, per file
+#[derive(Default)]
+pub struct IoStatistics {
+    /// Entries are never removed, so that open handles can keep updating theirs
+    files: Mutex<BTreeMap<PathBuf, Arc<Mutex<FileIoStatistics>>>>,
+    trace_hook: RwLock<Option<SharedTraceHook>>,
+}
+
+/// A file system that records I/O statistics for every file of the file system it
+/// wraps, and optionally reports every read, write and fsync to a trace hook.
+///
+/// Reads served from memory-mapped files through `mapped_slice` are not recorded.
+#[derive(Debug)]
+pub struct StatisticsFileSystem {
+    inner: Box<UnifiedFileSystem>,
+    statistics: IoStatistics,
+}
+
+#[derive(Debug)]
+pub struct StatisticsFileHandle<'fs> {
+    fs: &'fs StatisticsFileSystem,
+    inner: UnifiedFileHandle<'fs>,
+    statistics: Arc<Mutex<FileIoStatistics>>,
+}
+
+impl Default for LatencyHistogram {
+    fn default() -> Self {
+        LatencyHistogram { buckets: [0; LATENCY_BUCKETS] }
+    }
+}
+
+impl LatencyHistogram {
+    pub fn record(&mut self, latency: Duration) {
+        let micros = latency.as_micros().min(u64::MAX as u128) as u64;
+        let bucket = (u64::BITS - micros.leading_zeros()) as usize;
+        self.buckets[bucket.min(LATENCY_BUCKETS - 1)] += 1;
+    }
+
+    pub fn buckets(&self) -> &[u64] {
+        &self.buckets
+    }
+
+    /// The number of recorded operations
+    pub fn count(&self) -> u64 {
+        self.buckets.iter().sum()
+    }
+
+    /// An upper bound of the latency below which a `fraction` (between 0 and 1) of the
+    /// operations completed, or `None` if nothing was recorded
+    pub fn percentile(&self, fraction: f64) -> Option<Duration> {
+        let count = self.count();
+        if count == 0 {
+            return None;
+        }
+        let target = ((count as f64 * fraction).ceil() as u64).clamp(1, count);
+        let mut seen = 0;
+        for (i, bucket) in self.buckets.iter().enumerate() {
+            seen += bucket;
+            if seen >= target {
+                return Some(Duration::from_micros(1 << i));
+            }
+        }
+        unreachable!("the buckets add up to count")
+    }
+
+    fn merge(&mut self, other: &LatencyHistogram) {
+        for (bucket, other) in self.buckets.iter_mut().zip(other.buckets) {
+            *bucket += other;
+        }
+    }
+}
+
+impl FileIoStatistics {
+    fn record(&mut self, event: &IoEvent<'_>) {
+        let transferred = if event.success { event.bytes } else { 0 };
+        match event.operation {
+            IoOperation::Read => {
+                self.reads += 1;
+                self.bytes_read += transferred;
+                self.read_latency.record(event.latency);
+            }
+            IoOperation::Write => {
+                self.writes += 1;
+                self.bytes_written += transferred;
+                self.write_latency.record(event.latency);
+            }
+            IoOperation::Fsync => {
+                self.fsyncs += 1;
+                self.fsync_latency.record(event.latency);
+            }
+        }
+    }
+
+    fn merge(&mut self, other: &FileIoStatistics) {
+        self.bytes_read += other.bytes_read;
+        self.bytes_written += other.bytes_written;
+        self.reads += other.reads;
+        self.writes += other.writes;
+        self.fsyncs += other.fsyncs;
+        self.read_latency.merge(&other.read_latency);
+        self.write_latency.merge(&other.write_latency);
+        self.fsync_latency.merge(&other.fsync_latency);
+    }
+}
+
+impl Debug for IoStatistics {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("IoStatistics")
+            .field("files", &self.files.lock().unwrap().len())
+            .field("trace_hook", &self.trace_hook.read().unwrap().is_some())
+            .finish()
+    }
+}
+
+impl IoStatistics {
+    /// The statistics of every file that was opened, by path
+    pub fn files(&self) -> BTreeMap<PathBuf, FileIoStatistics> {
+        self.files.lock().unwrap().iter()
+            .map(|(path, statistics)| (path.clone(), *statistics.lock().unwrap()))
+            .collect()
+    }
+
+    pub fn file(&self, path: &Path) -> Option<FileIoStatistics> {
+        self.files.lock().unwrap().get(path).map(|statistics| *statistics.lock().unwrap())
+    }
+
+    /// The statistics of all files added up
+    pub fn total(&self) -> FileIoStatistics {
+        let mut total = FileIoStatistics::default();
+        for statistics in self.files.lock().unwrap().values() {
+            total.merge(&statistics.lock().unwrap());
+        }
+        total
+    }
+
+    /// Sets all counters back to zero
+    pub fn reset(&self) {
+        for statistics in self.files.lock().unwrap().values() {
+            *statistics.lock().unwrap() = FileIoStatistics::default();
+        }
+    }
+
+    /// Installs a hook that is called after every read, write and fsync, or removes it
+    pub fn set_trace_hook(&self, hook: Option<IoTraceHook>) {
+        *self.trace_hook.write().unwrap() = hook.map(Arc::from);
+    }
+
+    fn file_entry(&self, path: &Path) -> Arc<Mutex<FileIoStatistics>> {
+        self.files.lock().unwrap().entry(path.to_path_buf()).or_default().clone()
+    }
+}
+
+impl StatisticsFileSystem {
+    pub fn new(inner: UnifiedFileSystem) -> Self {
+        StatisticsFileSystem {
+            inner: Box::new(inner),
+            statistics: IoStatistics::default(),
+        }
+    }
+
+    pub fn inner(&self) -> &UnifiedFileSystem {
+        &self.inner
+    }
+
+    pub fn statistics(&self) -> &IoStatistics {
+        &self.statistics
+    }
+
+    /// Runs `operation` on `handle` and records it
+    fn record<T>(
+        &self,
+        handle: &StatisticsFileHandle<'_>,
+        operation: IoOperation,
+        location: Option<u64>,
+        bytes: u64,
+        run: impl FnOnce(&UnifiedFileHandle<'_>) -> Result<T>,
+    ) -> Result<T> {
+        let start = Instant::now();
+        let result = run(&handle.inner);
+        let event = IoEvent {
+            path: handle.inner.path(),
+            operation,
+            location,
+            bytes,
+            latency: start.elapsed(),
+            success: result.is_ok(),
+        };
+        handle.statistics.lock().unwrap().record(&event);
+        let hook = self.statistics.trace_hook.read().unwrap().clone();
+        if let Some(hook) = hook {
+            hook(&event);
+        }
+        result
+    }
+}
+
+impl<'fs> DynFileHandle<'fs> for StatisticsFileHandle<'fs> {
+    fn file_system(&self) -> &dyn DynFileSystem<'fs> {
+        self.fs
+    }
+
+    fn path(&self) -> &Path {
+        self.inner.path()
+    }
+
+    fn close(&mut self) -> Result<()> {
+        self.inner.close()
+    }
+
+    fn handle_type_id(&self, _token: Token) -> Option<TypeId> {
+        Some(TypeId::of::<StatisticsFileHandle<'static>>())
+    }
+
+    fn mapped_slice(&self, location: u64, length: usize) -> Option<&[u8]> {
+        self.inner.mapped_slice(location, length)
+    }
+}
+
+/// Recovers the `StatisticsFileHandle` behind a handle passed to the `DynFileSystem` interface.
+fn statistics_handle<'h, 'fs>(handle: &'h dyn DynFileHandle<'fs>) -> Result<&'h StatisticsFileHandle<'fs>> {
+    downcast_handle(handle, TypeId::of::<StatisticsFileHandle<'static>>())
+}
+
+impl<'fs> DynFileSystem<'fs> for StatisticsFileSystem {
+    fn read_at(&self, handle: &dyn DynFileHandle<'fs>, buffer: &mut [u8], nr_bytes: i64, location: u64) -> Result<()> {
+        let handle = statistics_handle(handle)?;
+        self.record(handle, IoOperation::Read, Some(location), nr_bytes.max(0) as u64, |inner| {
+            inner.read_at(buffer, nr_bytes, location)
+        })
+    }
+
+    fn write_at(&self, handle: &dyn DynFileHandle<'fs>, buffer: &[u8], nr_bytes: i64, location: u64) -> Result<()> {
+        let handle = statistics_handle(handle)?;
+        self.record(handle, IoOperation::Write, Some(location), nr_bytes.max(0) as u64, |inner| {
+            inner.write_at(buffer, nr_bytes, location)
+        })
+    }
+
+    fn open_file(&'fs self, path: &Path, flags: FileFlags, lock: Option<FileLockType>) -> Result<Box<dyn DynFileHandle<'fs> + 'fs>> {
+        let inner = self.inner.open_file(path, flags, lock)?;
+        Ok(Box::new(StatisticsFileHandle {
+            fs: self,
+            inner,
+            statistics: self.statistics.file_entry(path),
+        }))
+    }
+
+    fn set_file_pointer(&self, handle: &dyn DynFileHandle<'fs>, location: u64) -> Result<()> {
+        statistics_handle(handle)?.inner.set_file_pointer(location)
+    }
+
+    fn read(&self, handle: &dyn DynFileHandle<'fs>, buffer: &mut [u8], nr_bytes: i64) -> Result<()> {
+        let handle = statistics_handle(handle)?;
+        self.record(handle, IoOperation::Read, None, nr_bytes.max(0) as u64, |inner| {
+            inner.read(buffer, nr_bytes)
+        })
+    }
+
+    fn write(&self, handle: &dyn DynFileHandle<'fs>, buffer: &[u8], nr_bytes: i64) -> Result<()> {
+        let handle = statistics_handle(handle)?;
+        self.record(handle, IoOperation::Write, None, nr_bytes.max(0) as u64, |inner| {
+            inner.write(buffer, nr_bytes)
+        })
+    }
+
+    fn file_size(&self, handle: &dyn DynFileHandle<'fs>) -> Result<u64> {
+        statistics_handle(handle)?.inner.file_size()
+    }
+
+    fn directory_exists(&self, path: &Path) -> Result<bool> {
+        self.inner.directory_exists(path)
+    }
+
+    fn file_exists(&self, file_name: &Path) -> Result<bool> {
+        self.inner.file_exists(file_name)
+    }
+
+    fn create_directory(&self, path: &Path) -> Result<()> {
+        self.inner.create_directory(path)
+    }
+
+    fn remove_directory(&self, path: &Path) -> Result<()> {
+        self.inner.remove_directory(path)
+    }
+
+    fn remove_file(&self, file_name: &Path) -> Result<()> {
+        self.inner.remove_file(file_name)
+    }
+
+    fn list_files(&self, directory: &Path, callback: &mut dyn FnMut(String)) -> Result<bool> {
+        self.inner.list_files(directory, callback)
+    }
+
+    fn path_separator(&self) -> &'static str {
+        self.inner.path_separator()
+    }
+
+    fn fsync(&self, handle: &dyn DynFileHandle<'fs>) -> Result<()> {
+        let handle = statistics_handle(handle)?;
+        self.record(handle, IoOperation::Fsync, None, 0, |inner| inner.fsync())
+    }
+
+    fn truncate(&self, handle: &dyn DynFileHandle<'fs>, new_size: u64) -> Result<()> {
+        statistics_handle(handle)?.inner.truncate(new_size)
+    }
+
+    fn allocate(&self, handle: &dyn DynFileHandle<'fs>, offset: u64, length: u64) -> Result<()> {
+        statistics_handle(handle)?.inner.allocate(offset, length)
+    }
+
+    fn punch_hole(&self, handle: &dyn DynFileHandle<'fs>, offset: u64, length: u64) -> Result<()> {
+        statistics_handle(handle)?.inner.punch_hole(offset, length)
+    }
+
+    fn sync_directory(&self, directory: &Path) -> Result<()> {
+        self.inner.sync_directory(directory)
+    }
+
+    fn move_file(&self, src: &Path, dst: &Path) -> Result<()> {
+        self.inner.move_file(src, dst)
+    }
+
+    fn file_version(&self, handle: &dyn DynFileHandle<'fs>) -> Result<Option<String>> {
+        statistics_handle(handle)?.inner.file_version()
+    }
+
+    fn join_path(&self, l: &Path, r: &Path) -> Result<PathBuf> {
+        self.inner.join_path(l, r)
+    }
+
+    fn glob(&self, pattern: &str) -> Result<Vec<PathBuf>> {
+        self.inner.glob(pattern)
+    }
+}
@@ -0,0 +1,290 @@
+use std::any::TypeId;
+use std::io::{Error, ErrorKind, Result};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use super::dynamic_fs::{downcast_handle, DynFileHandle, DynFileSystem, Token};
+use super::{transfer_size, FileFlags, FileLockType, UnifiedFileHandle, UnifiedFileSystem};
+
+/// A cap on write bandwidth and write operations per second; `None` is unlimited.
+/// A rate of zero would block every write forever and is rejected with `InvalidInput`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct IoRateLimit {
+    pub bytes_per_second: Option<u64>,
+    pub operations_per_second: Option<u64>,
+}
+
+impl IoRateLimit {
+    fn validate(&self) -> Result<()> {
+        if self.bytes_per_second == Some(0) || self.operations_per_second == Some(0) {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                format!("invalid rate limit {:?}: rates must be positive, or None for unlimited", self)
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// A token bucket holding up to one second worth of bytes and operations. Callers
+/// take what they need right away and then wait until the bucket has refilled, so a
+/// transfer larger than the bucket is admitted but delays the ones after it.
+#[derive(Debug)]
+pub struct RateLimiter {
+    state: Mutex<BucketState>,
+}
+
+#[derive(Debug)]
+struct BucketState {
+    limit: IoRateLimit,
+    bytes: f64,
+    operations: f64,
+    last_refill: Instant,
+}
+
+/// A file system that throttles the writes through handles opened with
+/// `FileFlags::BACKGROUND` (checkpoints, spill files) to an `IoRateLimit`. All other
+/// I/O, including every read, passes through unthrottled.
+#[derive(Debug)]
+pub struct ThrottledFileSystem {
+    inner: Box<UnifiedFileSystem>,
+    limiter: RateLimiter,
+}
+
+#[derive(Debug)]
+pub struct ThrottledFileHandle<'fs> {
+    fs: &'fs ThrottledFileSystem,
+    inner: UnifiedFileHandle<'fs>,
+    background: bool,
+}
+
+impl BucketState {
+    fn refill(&mut self) {
+        let now = Instant::now();
+        let elapsed = now.duration_since(self.last_refill).as_secs_f64();
+        self.last_refill = now;
+        if let Some(rate) = self.limit.bytes_per_second {
+            self.bytes = (self.bytes + elapsed * rate as f64).min(rate as f64);
+        }
+        if let Some(rate) = self.limit.operations_per_second {
+            self.operations = (self.operations + elapsed * rate as f64).min(rate as f64);
+        }
+    }
+}
+
+/// The time it takes to pay back a negative token balance at `rate` tokens per second
+fn debt_duration(balance: f64, rate: Option<u64>) -> Duration {
+    match rate {
+        Some(rate) if balance < 0.0 => Duration::from_secs_f64(-balance / rate as f64),
+        _ => Duration::ZERO,
+    }
+}
+
+impl RateLimiter {
+    /// Creates a limiter with a full bucket
+    pub fn new(limit: IoRateLimit) -> Result<Self> {
+        limit.validate()?;
+        Ok(RateLimiter {
+            state: Mutex::new(BucketState {
+                limit,
+                bytes: limit.bytes_per_second.unwrap_or(0) as f64,
+                operations: limit.operations_per_second.unwrap_or(0) as f64,
+                last_refill: Instant::now(),
+            }),
+        })
+    }
+
+    pub fn limit(&self) -> IoRateLimit {
+        self.state.lock().unwrap().limit
+    }
+
+    /// Changes the limit; the bucket is refilled to the new capacity
+    pub fn set_limit(&self, limit: IoRateLimit) -> Result<()> {
+        limit.validate()?;
+        *self.state.lock().unwrap() = BucketState {
+            limit,
+            bytes: limit.bytes_per_second.unwrap_or(0) as f64,
+            operations: limit.operations_per_second.unwrap_or(0) as f64,
+            last_refill: Instant::now(),
+        };
+        Ok(())
+    }
+
+    /// Takes the tokens for one operation of `bytes` bytes, sleeping as long as needed
+    /// to stay within the limit
+    pub fn acquire(&self, bytes: u64) {
+        let wait = {
+            let mut state = self.state.lock().unwrap();
+            state.refill();
+            if state.limit.bytes_per_second.is_some() {
+                state.bytes -= bytes as f64;
+            }
+            if state.limit.operations_per_second.is_some() {
+                state.operations -= 1.0;
+            }
+            debt_duration(state.bytes, state.limit.bytes_per_second)
+                .max(debt_duration(state.operations, state.limit.operations_per_second))
+        };
+        if !wait.is_zero() {
+            std::thread::sleep(wait);
+        }
+    }
+}
+
+impl ThrottledFileSystem {
+    pub fn new(inner: UnifiedFileSystem, limit: IoRateLimit) -> Result<Self> {
+        Ok(ThrottledFileSystem {
+            inner: Box::new(inner),
+            limiter: RateLimiter::new(limit)?,
+        })
+    }
+
+    pub fn inner(&self) -> &UnifiedFileSystem {
+        &self.inner
+    }
+
+    pub fn limiter(&self) -> &RateLimiter {
+        &self.limiter
+    }
+
+    /// Takes the tokens for a write of `nr_bytes` from `buffer`, once the size is known
+    /// to be valid
+    fn throttle(&self, handle: &ThrottledFileHandle<'_>, buffer: &[u8], nr_bytes: i64) -> Result<()> {
+        let count = transfer_size(buffer.len(), nr_bytes)?;
+        if handle.background {
+            self.limiter.acquire(count as u64);
+        }
+        Ok(())
+    }
+}
+
+impl<'fs> DynFileHandle<'fs> for ThrottledFileHandle<'fs> {
+    fn file_system(&self) -> &dyn DynFileSystem<'fs> {
+        self.fs
+    }
+
+    fn path(&self) -> &Path {
+        self.inner.path()
+    }
+
+    fn close(&mut self) -> Result<()> {
+        self.inner.close()
+    }
+
+    fn handle_type_id(&self, _token: Token) -> Option<TypeId> {
+        Some(TypeId::of::<ThrottledFileHandle<'static>>())
+    }
+
+    fn mapped_slice(&self, location: u64, length: usize) -> Option<&[u8]> {
+        self.inner.mapped_slice(location, length)
+    }
+}
+
+/// Recovers the `ThrottledFileHandle` behind a handle passed to the `DynFileSystem` interface.
+fn throttled_handle<'h, 'fs>(handle: &'h dyn DynFileHandle<'fs>) -> Result<&'h ThrottledFileHandle<'fs>> {
+    downcast_handle(handle, TypeId::of::<ThrottledFileHandle<'static>>())
+}
+
+impl<'fs> DynFileSystem<'fs> for ThrottledFileSystem {
+    fn read_at(&self, handle: &dyn DynFileHandle<'fs>, buffer: &mut [u8], nr_bytes: i64, location: u64) -> Result<()> {
+        throttled_handle(handle)?.inner.read_at(buffer, nr_bytes, location)
+    }
+
+    fn write_at(&self, handle: &dyn DynFileHandle<'fs>, buffer: &[u8], nr_bytes: i64, location: u64) -> Result<()> {
+        let handle = throttled_handle(handle)?;
+        self.throttle(handle, buffer, nr_bytes)?;
+        handle.inner.write_at(buffer, nr_bytes, location)
+    }
+
+    fn open_file(&'fs self, path: &Path, flags: FileFlags, lock: Option<FileLockType>) -> Result<Box<dyn DynFileHandle<'fs> + 'fs>> {
+        let inner = self.inner.open_file(path, flags, lock)?;
+        Ok(Box::new(ThrottledFileHandle {
+            fs: self,
+            inner,
+            background: flags.contains(FileFlags::BACKGROUND),
+        }))
+    }
+
+    fn set_file_pointer(&self, handle: &dyn DynFileHandle<'fs>, location: u64) -> Result<()> {
+        throttled_handle(handle)?.inner.set_file_pointer(location)
+    }
+
+    fn read(&self, handle: &dyn DynFileHandle<'fs>, buffer: &mut [u8], nr_bytes: i64) -> Result<()> {
+        throttled_handle(handle)?.inner.read(buffer, nr_bytes)
+    }
+
+    fn write(&self, handle: &dyn DynFileHandle<'fs>, buffer: &[u8], nr_bytes: i64) -> Result<()> {
+        let handle = throttled_handle(handle)?;
+        self.throttle(handle, buffer, nr_bytes)?;
+        handle.inner.write(buffer, nr_bytes)
+    }
+
+    fn file_size(&self, handle: &dyn DynFileHandle<'fs>) -> Result<u64> {
+        throttled_handle(handle)?.inner.file_size()
+    }
+
+    fn directory_exists(&self, path: &Path) -> Result<bool> {
+        self.inner.directory_exists(path)
+    }
+
+    fn file_exists(&self, file_name: &Path) -> Result<bool> {
+        self.inner.file_exists(file_name)
+    }
+
+    fn create_directory(&self, path: &Path) -> Result<()> {
+        self.inner.create_directory(path)
+    }
+
+    fn remove_directory(&self, path: &Path) -> Result<()> {
+        self.inner.remove_directory(path)
+    }
+
+    fn remove_file(&self, file_name: &Path) -> Result<()> {
+        self.inner.remove_file(file_name)
+    }
+
+    fn list_files(&self, directory: &Path, callback: &mut dyn FnMut(String)) -> Result<bool> {
+        self.inner.list_files(directory, callback)
+    }
+
+    fn path_separator(&self) -> &'static str {
+        self.inner.path_separator()
+    }
+
+    fn fsync(&self, handle: &dyn DynFileHandle<'fs>) -> Result<()> {
+        throttled_handle(handle)?.inner.fsync()
+    }
+
+    fn truncate(&self, handle: &dyn DynFileHandle<'fs>, new_size: u64) -> Result<()> {
+        throttled_handle(handle)?.inner.truncate(new_size)
+    }
+
+    fn allocate(&self, handle: &dyn DynFileHandle<'fs>, offset: u64, length: u64) -> Result<()> {
+        throttled_handle(handle)?.inner.allocate(offset, length)
+    }
+
+    fn punch_hole(&self, handle: &dyn DynFileHandle<'fs>, offset: u64, length: u64) -> Result<()> {
+        throttled_handle(handle)?.inner.punch_hole(offset, length)
+    }
+
+    fn sync_directory(&self, directory: &Path) -> Result<()> {
+        self.inner.sync_directory(directory)
+    }
+
+    fn move_file(&self, src: &Path, dst: &Path) -> Result<()> {
+        self.inner.move_file(src, dst)
+    }
+
+    fn file_version(&self, handle: &dyn DynFileHandle<'fs>) -> Result<Option<String>> {
+        throttled_handle(handle)?.inner.file_version()
+    }
+
+    fn join_path(&self, l: &Path, r: &Path) -> Result<PathBuf> {
+        self.inner.join_path(l, r)
+    }
+
+    fn glob(&self, pattern: &str) -> Result<Vec<PathBuf>> {
+        self.inner.glob(pattern)
+    }
+}
@@ -0,0 +1,375 @@
+use std::fmt::{self, Debug};
+use std::io::{Error, ErrorKind, Result};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use io_uring::{opcode, types, EnterFlags, IoUring};
+
+use super::static_fs::{LocalFileHandle, LocalFileSystem, SFileHandle, SFileSystem};
+use super::{transfer_size, FileFlags, FileLockType};
+
+/// The number of submission queue entries of the ring, i.e. the maximum number of
+/// requests that are in flight at the same time
+const URING_QUEUE_DEPTH: u32 = 128;
+
+/// A file system that performs positional reads and writes through io_uring, and
+/// everything else through `LocalFileSystem`.
+///
+/// Besides the synchronous `SFileSystem` interface it offers `IoBatch`, which submits
+/// many reads and writes at once so that the device sees a deep queue. When io_uring
+/// is not available (old kernel, seccomp), all I/O falls back to `LocalFileSystem`.
+///
+/// Each batch runs on a ring of its own, taken from a pool of idle rings and set up
+/// anew when all of them are in use, so that threads never wait for the I/O of others.
+/// The pool grows to the largest number of batches that ran at the same time.
+pub struct UringFileSystem {
+    local: LocalFileSystem,
+    uring_available: bool,
+    rings: Mutex<Vec<IoUring>>,
+}
+
+pub struct UringFileHandle<'a> {
+    fs: &'a UringFileSystem,
+    inner: LocalFileHandle<'a>,
+}
+
+enum IoOp<'b> {
+    Read(&'b mut [u8]),
+    Write(&'b [u8]),
+}
+
+struct IoRequest<'fs, 'b> {
+    handle: &'b UringFileHandle<'fs>,
+    op: IoOp<'b>,
+    location: u64,
+}
+
+/// A set of reads and writes that is submitted at once with `submit_and_wait`.
+/// The buffers and handles stay borrowed until all requests have completed.
+pub struct IoBatch<'fs, 'b> {
+    fs: &'fs UringFileSystem,
+    requests: Vec<IoRequest<'fs, 'b>>,
+}
+
+impl Debug for UringFileSystem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("UringFileSystem")
+            .field("uring_available", &self.uring_available)
+            .finish()
+    }
+}
+
+impl Default for UringFileSystem {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl UringFileSystem {
+    /// Sets up the ring, falling back to synchronous I/O if that fails
+    pub fn new() -> Self {
+        let rings: Vec<IoUring> = IoUring::new(URING_QUEUE_DEPTH).into_iter().collect();
+        UringFileSystem {
+            local: LocalFileSystem,
+            uring_available: !rings.is_empty(),
+            rings: Mutex::new(rings),
+        }
+    }
+
+    /// A file system that never uses io_uring
+    pub fn fallback() -> Self {
+        UringFileSystem {
+            local: LocalFileSystem,
+            uring_available: false,
+            rings: Mutex::new(Vec::new()),
+        }
+    }
+
+    /// Returns whether I/O goes through io_uring rather than the synchronous fallback
+    pub fn uring_available(&self) -> bool {
+        self.uring_available
+    }
+
+    /// An idle ring, or a new one if all are in use; `None` if none can be set up
+    fn take_ring(&self) -> Option<IoUring> {
+        if !self.uring_available {
+            return None;
+        }
+        let idle = self.rings.lock().unwrap().pop();
+        idle.or_else(|| IoUring::new(URING_QUEUE_DEPTH).ok())
+    }
+
+    fn return_ring(&self, ring: IoUring) {
+        self.rings.lock().unwrap().push(ring);
+    }
+
+    pub fn batch<'b>(&self) -> IoBatch<'_, 'b> {
+        IoBatch {
+            fs: self,
+            requests: Vec::new(),
+        }
+    }
+
+    fn single(&self, request: IoRequest<'_, '_>) -> Result<()> {
+        let mut batch = self.batch();
+        batch.requests.push(request);
+        batch.submit_and_wait().pop().unwrap()
+    }
+}
+
+impl<'fs, 'b> IoBatch<'fs, 'b> {
+    /// Adds a read of `buffer.len()` bytes at `location`; returns the index of its result
+    pub fn read_at(&mut self, handle: &'b UringFileHandle<'fs>, buffer: &'b mut [u8], location: u64) -> usize {
+        self.requests.push(IoRequest { handle, op: IoOp::Read(buffer), location });
+        self.requests.len() - 1
+    }
+
+    /// Adds a write of `buffer` at `location`; returns the index of its result
+    pub fn write_at(&mut self, handle: &'b UringFileHandle<'fs>, buffer: &'b [u8], location: u64) -> usize {
+        self.requests.push(IoRequest { handle, op: IoOp::Write(buffer), location });
+        self.requests.len() - 1
+    }
+
+    pub fn len(&self) -> usize {
+        self.requests.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.requests.is_empty()
+    }
+
+    /// Submits all requests and waits until every one of them has completed. Each
+    /// request transfers its whole buffer or fails; the results are in request order.
+    pub fn submit_and_wait(mut self) -> Vec<Result<()>> {
+        let mut transferred = vec![None; self.requests.len()];
+        if let Some(mut ring) = self.fs.take_ring() {
+            let mut usable = true;
+            for start in (0..self.requests.len()).step_by(URING_QUEUE_DEPTH as usize) {
+                let end = (start + URING_QUEUE_DEPTH as usize).min(self.requests.len());
+                usable = Self::run_on_ring(&mut ring, &mut self.requests[start..end], &mut transferred[start..end], start);
+                if !usable {
+                    break;
+                }
+            }
+            if usable {
+                self.fs.return_ring(ring);
+            }
+        }
+
+        // requests that did not go through the ring are done synchronously
+        let fs = self.fs;
+        self.requests.iter_mut()
+            .zip(transferred)
+            .map(|(request, result)| fs.complete(request, result))
+            .collect()
+    }
+
+    /// Pushes `requests` to the ring and reaps all of their completions. Leaves the
+    /// number of bytes transferred (or the negated errno) in `results`. Returns false
+    /// if submitting failed, leaving the requests the kernel did not take without a
+    /// result and the ring unfit for further use.
+    fn run_on_ring(ring: &mut IoUring, requests: &mut [IoRequest<'_, '_>], results: &mut [Option<i32>], first_id: usize) -> bool {
+        for (i, request) in requests.iter_mut().enumerate() {
+            let fd = types::Fd(request.handle.inner.fd);
+            let entry = match &mut request.op {
+                IoOp::Read(buffer) => opcode::Read::new(fd, buffer.as_mut_ptr(), buffer.len() as u32)
+                    .offset(request.location)
+                    .build(),
+                IoOp::Write(buffer) => opcode::Write::new(fd, buffer.as_ptr(), buffer.len() as u32)
+                    .offset(request.location)
+                    .build(),
+            };
+            // SAFETY: the buffers stay borrowed until the completion is reaped below
+            unsafe {
+                ring.submission()
+                    .push(&entry.user_data((first_id + i) as u64))
+                    .expect("submission queue is sized for a full chunk");
+            }
+        }
+
+        let mut remaining = requests.len();
+        while remaining > 0 {
+            if let Err(e) = ring.submit_and_wait(remaining)
+                && !matches!(e.raw_os_error(), Some(libc::EINTR) | Some(libc::EAGAIN) | Some(libc::EBUSY))
+            {
+                let in_flight = remaining - ring.submission().len();
+                Self::drain(ring, in_flight, results, first_id);
+                return false;
+            }
+            for cqe in ring.completion() {
+                results[cqe.user_data() as usize - first_id] = Some(cqe.result());
+                remaining -= 1;
+            }
+        }
+        true
+    }
+
+    /// Waits for the `in_flight` requests the kernel has taken to complete, without
+    /// submitting any more. Waiting is retried until all of them have completed, since
+    /// they write into buffers that are only borrowed until this batch returns.
+    fn drain(ring: &mut IoUring, mut in_flight: usize, results: &mut [Option<i32>], first_id: usize) {
+        while in_flight > 0 {
+            // SAFETY: entering with nothing to submit only waits for completions
+            let waited = unsafe {
+                ring.submitter().enter::<libc::sigset_t>(0, in_flight as u32, EnterFlags::GETEVENTS.bits(), None)
+            };
+            if waited.is_err() {
+                std::thread::yield_now();
+            }
+            for cqe in ring.completion() {
+                results[cqe.user_data() as usize - first_id] = Some(cqe.result());
+                in_flight -= 1;
+            }
+        }
+    }
+}
+
+impl UringFileSystem {
+    /// Finishes a request given the result of its io_uring operation, if it was submitted
+    /// to the ring: transfers whatever the ring did not (short transfers, interrupted or
+    /// unsupported operations) through `LocalFileSystem`.
+    fn complete(&self, request: &mut IoRequest<'_, '_>, result: Option<i32>) -> Result<()> {
+        let done = match result {
+            Some(result) if result < 0 => match -result {
+                libc::EINTR | libc::EAGAIN | libc::EOPNOTSUPP | libc::EINVAL => 0,
+                errno => return Err(Error::from_raw_os_error(errno)),
+            },
+            Some(result) => result as usize,
+            None => 0,
+        };
+
+        let location = request.location + done as u64;
+        match &mut request.op {
+            IoOp::Read(buffer) => {
+                if result == Some(0) && !buffer.is_empty() {
+                    return Err(Error::new(
+                        ErrorKind::UnexpectedEof,
+                        format!("read_at failed: expected {} bytes, but read 0", buffer.len())
+                    ));
+                }
+                let rest = &mut buffer[done..];
+                if rest.is_empty() {
+                    return Ok(());
+                }
+                self.local.read_at(&request.handle.inner, rest, rest.len() as i64, location)
+            }
+            IoOp::Write(buffer) => {
+                let rest = &buffer[done..];
+                if rest.is_empty() {
+                    return Ok(());
+                }
+                self.local.write_at(&request.handle.inner, rest, rest.len() as i64, location)
+            }
+        }
+    }
+}
+
+impl<'a> SFileHandle<UringFileSystem> for UringFileHandle<'a> {
+    fn file_system(&self) -> &UringFileSystem {
+        self.fs
+    }
+
+    fn path(&self) -> &Path {
+        self.inner.path()
+    }
+
+    fn close(&mut self) -> Result<()> {
+        self.inner.close()
+    }
+}
+
+impl SFileSystem for UringFileSystem {
+    type Handle<'a> = UringFileHandle<'a>;
+
+    fn open_file<'a>(&'a self, path: &Path, flags: FileFlags, lock: FileLockType) -> Result<Self::Handle<'a>> {
+        let inner = self.local.open_file(path, flags, lock)?;
+        Ok(UringFileHandle { fs: self, inner })
+    }
+
+    fn read_at(&self, handle: &Self::Handle<'_>, buffer: &mut [u8], nr_bytes: i64, location: u64) -> Result<()> {
+        let count = transfer_size(buffer.len(), nr_bytes)?;
+        self.single(IoRequest { handle, op: IoOp::Read(&mut buffer[..count]), location })
+    }
+
+    fn write_at(&self, handle: &Self::Handle<'_>, buffer: &[u8], nr_bytes: i64, location: u64) -> Result<()> {
+        let count = transfer_size(buffer.len(), nr_bytes)?;
+        self.single(IoRequest { handle, op: IoOp::Write(&buffer[..count]), location })
+    }
+
+    fn set_file_pointer(&self, handle: &Self::Handle<'_>, location: u64) -> Result<()> {
+        self.local.set_file_pointer(&handle.inner, location)
+    }
+
+    fn read(&self, handle: &Self::Handle<'_>, buffer: &mut [u8], nr_bytes: i64) -> Result<u64> {
+        self.local.read(&handle.inner, buffer, nr_bytes)
+    }
+
+    fn write(&self, handle: &Self::Handle<'_>, buffer: &[u8], nr_bytes: i64) -> Result<u64> {
+        self.local.write(&handle.inner, buffer, nr_bytes)
+    }
+
+    fn file_size(&self, handle: &Self::Handle<'_>) -> Result<u64> {
+        self.local.file_size(&handle.inner)
+    }
+
+    fn file_version(&self, handle: &Self::Handle<'_>) -> Result<Option<String>> {
+        self.local.file_version(&handle.inner)
+    }
+
+    fn directory_exists(&self, path: &Path) -> Result<bool> {
+        self.local.directory_exists(path)
+    }
+
+    fn file_exists(&self, file_name: &Path) -> Result<bool> {
+        self.local.file_exists(file_name)
+    }
+
+    fn create_directory(&self, path: &Path) -> Result<()> {
+        self.local.create_directory(path)
+    }
+
+    fn remove_directory(&self, path: &Path) -> Result<()> {
+        self.local.remove_directory(path)
+    }
+
+    fn remove_file(&self, file_name: &Path) -> Result<()> {
+        self.local.remove_file(file_name)
+    }
+
+    fn list_files<F>(&self, directory: &Path, callback: F) -> Result<bool>
+    where F: FnMut(String) {
+        self.local.list_files(directory, callback)
+    }
+
+    fn path_separator(&self) -> &'static str {
+        self.local.path_separator()
+    }
+
+    fn fsync(&self, handle: &Self::Handle<'_>) -> Result<()> {
+        self.local.fsync(&handle.inner)
+    }
+
+    fn truncate(&self, handle: &Self::Handle<'_>, new_size: u64) -> Result<()> {
+        self.local.truncate(&handle.inner, new_size)
+    }
+
+    fn allocate(&self, handle: &Self::Handle<'_>, offset: u64, length: u64) -> Result<()> {
+        self.local.allocate(&handle.inner, offset, length)
+    }
+
+    fn punch_hole(&self, handle: &Self::Handle<'_>, offset: u64, length: u64) -> Result<()> {
+        self.local.punch_hole(&handle.inner, offset, length)
+    }
+
+    fn sync_directory(&self, directory: &Path) -> Result<()> {
+        self.local.sync_directory(directory)
+    }
+
+    fn move_file(&self, src: &Path, dst: &Path) -> Result<()> {
+        self.local.move_file(src, dst)
+    }
+
+    fn join_path(&self, l: &Path, r: &Path) -> Result<PathBuf> {
+        self.local.join_path(l, r)
+    }
+}
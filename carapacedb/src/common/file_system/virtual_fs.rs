@@ -0,0 +1,339 @@
+use std::any::TypeId;
+use std::io::{Error, ErrorKind, Result};
+use std::path::{Path, PathBuf};
+use std::sync::RwLock;
+
+use super::adapter_fs::DynFileSystemAdapter;
+use super::compressed_fs::{CompressedFileSystem, FileCompression};
+use super::dynamic_fs::{downcast_handle, BoxedFileSystem, DynFileHandle, DynFileSystem, Token};
+use super::memory_fs::MemoryFileSystem;
+use super::static_fs::LocalFileSystem;
+use super::{FileFlags, FileLockType};
+
+/// The scheme of paths that do not carry one, e.g. `/data/db.bin`
+pub const DEFAULT_SCHEME: &str = "file";
+
+/// The scheme of the in-memory file system registered by `VirtualFileSystem::new`
+pub const MEMORY_SCHEME: &str = "memory";
+
+/// The scheme of the gzip-compressed local files registered by `VirtualFileSystem::new`
+pub const GZIP_SCHEME: &str = "gzip";
+
+const SCHEME_SEPARATOR: &str = "://";
+
+/// The maximum number of registrations, including those replaced by registering a scheme
+/// again, which are kept alive for the lifetime of the virtual file system
+pub const MAX_REGISTRATIONS: usize = 256;
+
+/// The file system a path resolves to, the scheme of the path and the path that file
+/// system sees
+type ResolvedPath<'fs, 'p> = (&'fs dyn for<'a> DynFileSystem<'a>, Option<&'p str>, &'p Path);
+
+#[derive(Debug)]
+struct RegisteredFileSystem {
+    scheme: String,
+    fs: BoxedFileSystem,
+}
+
+/// A file system that dispatches every path to the file system registered for its
+/// URI scheme: `memory://db/x` goes to the file system registered as `memory` (which
+/// sees `db/x`), while paths without a scheme go to the one registered as `file`.
+#[derive(Debug)]
+pub struct VirtualFileSystem {
+    /// Registrations are only ever appended: registering a scheme again shadows the
+    /// earlier entry but keeps it alive, so that open handles can keep borrowing it.
+    /// Replaced entries are only freed with the virtual file system, so their number is
+    /// bounded by `MAX_REGISTRATIONS`.
+    file_systems: RwLock<Vec<RegisteredFileSystem>>,
+}
+
+#[derive(Debug)]
+pub struct VirtualFileHandle<'fs> {
+    fs: &'fs VirtualFileSystem,
+    target: &'fs dyn for<'a> DynFileSystem<'a>,
+    inner: Box<dyn DynFileHandle<'fs> + 'fs>,
+    path: PathBuf,
+}
+
+/// Splits `scheme://rest` into its scheme and the rest; paths without a valid scheme
+/// are returned unchanged.
+pub fn split_scheme(path: &Path) -> (Option<&str>, &Path) {
+    let Some(path_str) = path.to_str() else {
+        return (None, path);
+    };
+    let Some(index) = path_str.find(SCHEME_SEPARATOR) else {
+        return (None, path);
+    };
+    let scheme = &path_str[..index];
+    if !is_valid_scheme(scheme) {
+        return (None, path);
+    }
+    (Some(scheme), Path::new(&path_str[index + SCHEME_SEPARATOR.len()..]))
+}
+
+/// RFC 3986: a letter followed by letters, digits, `+`, `-` or `.`
+fn is_valid_scheme(scheme: &str) -> bool {
+    let mut chars = scheme.chars();
+    matches!(chars.next(), Some(c) if c.is_ascii_alphabetic())
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '+' || c == '-' || c == '.')
+}
+
+impl Default for VirtualFileSystem {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl VirtualFileSystem {
+    /// Creates a virtual file system with the built-in file systems registered:
+    /// `LocalFileSystem` as `file`, a `MemoryFileSystem` as `memory` and gzip-compressed
+    /// local files as `gzip`
+    pub fn new() -> Self {
+        let vfs = VirtualFileSystem {
+            file_systems: RwLock::new(Vec::new()),
+        };
+        let gzip = CompressedFileSystem::new(DynFileSystemAdapter::boxed(LocalFileSystem), FileCompression::Gzip);
+        let built_ins: [(&str, BoxedFileSystem); 3] = [
+            (DEFAULT_SCHEME, DynFileSystemAdapter::boxed(LocalFileSystem)),
+            (MEMORY_SCHEME, DynFileSystemAdapter::boxed(MemoryFileSystem::new())),
+            (GZIP_SCHEME, Box::new(gzip)),
+        ];
+        for (scheme, fs) in built_ins {
+            vfs.register(scheme, fs).expect("built-in schemes are valid");
+        }
+        vfs
+    }
+
+    /// Registers `fs` for paths starting with `scheme://`, replacing any file system that
+    /// was registered for the scheme before. Registering `file` replaces the file system
+    /// used for paths without a scheme.
+    ///
+    /// A replaced file system stays alive until the virtual file system is dropped, so
+    /// schemes are meant to be registered once rather than swapped repeatedly; after
+    /// `MAX_REGISTRATIONS` registrations this fails with `ErrorKind::QuotaExceeded`.
+    pub fn register(&self, scheme: &str, fs: BoxedFileSystem) -> Result<()> {
+        if !is_valid_scheme(scheme) {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                format!("invalid file system scheme '{}'", scheme)
+            ));
+        }
+        let mut file_systems = self.file_systems.write().unwrap();
+        if file_systems.len() >= MAX_REGISTRATIONS {
+            return Err(Error::new(
+                ErrorKind::QuotaExceeded,
+                format!("cannot register file system for '{}': limit of {} registrations reached", scheme, MAX_REGISTRATIONS)
+            ));
+        }
+        file_systems.push(RegisteredFileSystem {
+            scheme: scheme.to_ascii_lowercase(),
+            fs,
+        });
+        Ok(())
+    }
+
+    /// Returns the registered schemes, in order of registration
+    pub fn schemes(&self) -> Vec<String> {
+        let mut schemes = Vec::new();
+        for entry in self.file_systems.read().unwrap().iter() {
+            if !schemes.contains(&entry.scheme) {
+                schemes.push(entry.scheme.clone());
+            }
+        }
+        schemes
+    }
+
+    /// Opens `path` on the file system registered for its scheme
+    pub fn open_virtual_file(&self, path: &Path, flags: FileFlags, lock: Option<FileLockType>) -> Result<VirtualFileHandle<'_>> {
+        let (target, _, stripped) = self.resolve(path)?;
+        let inner = target.open_file(stripped, flags, lock)?;
+        Ok(VirtualFileHandle {
+            fs: self,
+            target,
+            inner,
+            path: path.to_path_buf(),
+        })
+    }
+
+    /// Returns the file system for `path` together with the path it sees
+    fn resolve<'p>(&self, path: &'p Path) -> Result<ResolvedPath<'_, 'p>> {
+        let (scheme, stripped) = split_scheme(path);
+        let lookup = scheme.unwrap_or(DEFAULT_SCHEME).to_ascii_lowercase();
+
+        let file_systems = self.file_systems.read().unwrap();
+        let Some(entry) = file_systems.iter().rev().find(|entry| entry.scheme == lookup) else {
+            return Err(Error::new(
+                ErrorKind::Unsupported,
+                format!("no file system registered for scheme '{}' of '{}'", lookup, path.display())
+            ));
+        };
+        let fs: *const dyn for<'a> DynFileSystem<'a> = entry.fs.as_ref();
+        drop(file_systems);
+
+        // SAFETY: entries are never removed while `self` is alive, and the boxed file
+        // system does not move when the vector grows.
+        Ok((unsafe { &*fs }, scheme, stripped))
+    }
+}
+
+impl<'fs> DynFileHandle<'fs> for VirtualFileHandle<'fs> {
+    fn file_system(&self) -> &dyn DynFileSystem<'fs> {
+        self.fs
+    }
+
+    fn path(&self) -> &Path {
+        &self.path
+    }
+
+    fn close(&mut self) -> Result<()> {
+        self.inner.close()
+    }
+
+    fn handle_type_id(&self, _token: Token) -> Option<TypeId> {
+        Some(TypeId::of::<VirtualFileHandle<'static>>())
+    }
+
+    fn mapped_slice(&self, location: u64, length: usize) -> Option<&[u8]> {
+        self.inner.mapped_slice(location, length)
+    }
+}
+
+/// Recovers the `VirtualFileHandle` behind a handle passed to the `DynFileSystem` interface.
+fn virtual_handle<'h, 'fs>(handle: &'h dyn DynFileHandle<'fs>) -> Result<&'h VirtualFileHandle<'fs>> {
+    downcast_handle(handle, TypeId::of::<VirtualFileHandle<'static>>())
+}
+
+impl<'fs> DynFileSystem<'fs> for VirtualFileSystem {
+    fn read_at(&self, handle: &dyn DynFileHandle<'fs>, buffer: &mut [u8], nr_bytes: i64, location: u64) -> Result<()> {
+        let handle = virtual_handle(handle)?;
+        handle.target.read_at(handle.inner.as_ref(), buffer, nr_bytes, location)
+    }
+
+    fn write_at(&self, handle: &dyn DynFileHandle<'fs>, buffer: &[u8], nr_bytes: i64, location: u64) -> Result<()> {
+        let handle = virtual_handle(handle)?;
+        handle.target.write_at(handle.inner.as_ref(), buffer, nr_bytes, location)
+    }
+
+    fn open_file(&'fs self, path: &Path, flags: FileFlags, lock: Option<FileLockType>) -> Result<Box<dyn DynFileHandle<'fs> + 'fs>> {
+        Ok(Box::new(self.open_virtual_file(path, flags, lock)?))
+    }
+
+    fn set_file_pointer(&self, handle: &dyn DynFileHandle<'fs>, location: u64) -> Result<()> {
+        let handle = virtual_handle(handle)?;
+        handle.target.set_file_pointer(handle.inner.as_ref(), location)
+    }
+
+    fn read(&self, handle: &dyn DynFileHandle<'fs>, buffer: &mut [u8], nr_bytes: i64) -> Result<()> {
+        let handle = virtual_handle(handle)?;
+        handle.target.read(handle.inner.as_ref(), buffer, nr_bytes)
+    }
+
+    fn write(&self, handle: &dyn DynFileHandle<'fs>, buffer: &[u8], nr_bytes: i64) -> Result<()> {
+        let handle = virtual_handle(handle)?;
+        handle.target.write(handle.inner.as_ref(), buffer, nr_bytes)
+    }
+
+    fn file_size(&self, handle: &dyn DynFileHandle<'fs>) -> Result<u64> {
+        let handle = virtual_handle(handle)?;
+        handle.target.file_size(handle.inner.as_ref())
+    }
+
+    fn directory_exists(&self, path: &Path) -> Result<bool> {
+        let (target, _, stripped) = self.resolve(path)?;
+        target.directory_exists(stripped)
+    }
+
+    fn file_exists(&self, file_name: &Path) -> Result<bool> {
+        let (target, _, stripped) = self.resolve(file_name)?;
+        target.file_exists(stripped)
+    }
+
+    fn create_directory(&self, path: &Path) -> Result<()> {
+        let (target, _, stripped) = self.resolve(path)?;
+        target.create_directory(stripped)
+    }
+
+    fn remove_directory(&self, path: &Path) -> Result<()> {
+        let (target, _, stripped) = self.resolve(path)?;
+        target.remove_directory(stripped)
+    }
+
+    fn remove_file(&self, file_name: &Path) -> Result<()> {
+        let (target, _, stripped) = self.resolve(file_name)?;
+        target.remove_file(stripped)
+    }
+
+    fn list_files(&self, directory: &Path, callback: &mut dyn FnMut(String)) -> Result<bool> {
+        let (target, _, stripped) = self.resolve(directory)?;
+        target.list_files(stripped, callback)
+    }
+
+    fn path_separator(&self) -> &'static str {
+        "/"
+    }
+
+    fn fsync(&self, handle: &dyn DynFileHandle<'fs>) -> Result<()> {
+        let handle = virtual_handle(handle)?;
+        handle.target.fsync(handle.inner.as_ref())
+    }
+
+    fn truncate(&self, handle: &dyn DynFileHandle<'fs>, new_size: u64) -> Result<()> {
+        let handle = virtual_handle(handle)?;
+        handle.target.truncate(handle.inner.as_ref(), new_size)
+    }
+
+    fn allocate(&self, handle: &dyn DynFileHandle<'fs>, offset: u64, length: u64) -> Result<()> {
+        let handle = virtual_handle(handle)?;
+        handle.target.allocate(handle.inner.as_ref(), offset, length)
+    }
+
+    fn punch_hole(&self, handle: &dyn DynFileHandle<'fs>, offset: u64, length: u64) -> Result<()> {
+        let handle = virtual_handle(handle)?;
+        handle.target.punch_hole(handle.inner.as_ref(), offset, length)
+    }
+
+    fn sync_directory(&self, directory: &Path) -> Result<()> {
+        let (target, _, stripped) = self.resolve(directory)?;
+        target.sync_directory(stripped)
+    }
+
+    fn move_file(&self, src: &Path, dst: &Path) -> Result<()> {
+        let (src_fs, _, src_stripped) = self.resolve(src)?;
+        let (dst_fs, _, dst_stripped) = self.resolve(dst)?;
+        if !std::ptr::addr_eq(src_fs, dst_fs) {
+            return Err(Error::new(
+                ErrorKind::Unsupported,
+                format!("cannot move '{}' to '{}': different file systems", src.display(), dst.display())
+            ));
+        }
+        src_fs.move_file(src_stripped, dst_stripped)
+    }
+
+    fn file_version(&self, handle: &dyn DynFileHandle<'fs>) -> Result<Option<String>> {
+        let handle = virtual_handle(handle)?;
+        handle.target.file_version(handle.inner.as_ref())
+    }
+
+    fn join_path(&self, l: &Path, r: &Path) -> Result<PathBuf> {
+        let (target, scheme, stripped) = self.resolve(l)?;
+        let joined = target.join_path(stripped, r)?;
+        match scheme {
+            Some(scheme) => Ok(PathBuf::from(format!("{}{}{}", scheme, SCHEME_SEPARATOR, joined.display()))),
+            None => Ok(joined),
+        }
+    }
+
+    fn glob(&self, pattern: &str) -> Result<Vec<PathBuf>> {
+        let (target, scheme, stripped) = self.resolve(Path::new(pattern))?;
+        let Some(stripped) = stripped.to_str() else {
+            return Err(Error::new(ErrorKind::InvalidInput, "glob pattern is not valid UTF-8"));
+        };
+        let matches = target.glob(stripped)?;
+        match scheme {
+            Some(scheme) => Ok(matches.into_iter()
+                .map(|path| PathBuf::from(format!("{}{}{}", scheme, SCHEME_SEPARATOR, path.display())))
+                .collect()),
+            None => Ok(matches),
+        }
+    }
+}
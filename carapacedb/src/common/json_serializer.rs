@@ -0,0 +1,636 @@
+//! A human-readable JSON form of any `Serializable`, for inspecting catalog entries,
+//! WAL records and plans, and for golden-file tests. Tagged objects become JSON objects
+//! keyed by field name, lists become arrays, missing optionals `null`, and raw data a
+//! hex string. A value that serializes to other than exactly one JSON value, such as a
+//! positional record, is wrapped as `{"$sequence": [...]}`, and a present optional whose
+//! value would read back as missing, such as `Some(None)`, as `{"$some": ...}`.
+
+use std::io::{Error, ErrorKind, Result};
+use std::vec;
+use crate::common::serializer::{Deserializable, Deserializer, ObjectReader, Primitive, Serializable, Serializer, FieldId};
+use crate::common::sha256::to_hex;
+
+/// The key of the object wrapping a sequence of values
+const SEQUENCE_KEY: &str = "$sequence";
+
+/// The key of the object wrapping the value of a present optional
+const SOME_KEY: &str = "$some";
+
+/// The maximum nesting of arrays and objects accepted by the parser
+const MAX_DEPTH: usize = 512;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum JsonValue {
+    Null,
+    Bool(bool),
+    /// The number as written, so that no precision is lost
+    Number(String),
+    String(String),
+    Array(Vec<JsonValue>),
+    /// Fields in the order they were written
+    Object(Vec<(String, JsonValue)>),
+}
+
+impl JsonValue {
+    /// Parses a JSON document
+    pub fn parse(text: &str) -> Result<JsonValue> {
+        let mut parser = Parser { bytes: text.as_bytes(), position: 0 };
+        let value = parser.parse_value(0)?;
+        parser.skip_whitespace();
+        if parser.position != parser.bytes.len() {
+            return Err(parser.error("trailing characters"));
+        }
+        Ok(value)
+    }
+
+    /// The JSON text of the value, indented by two spaces per level
+    pub fn to_pretty_string(&self) -> String {
+        let mut out = String::new();
+        write_value(&mut out, self, 0);
+        out
+    }
+
+    fn kind(&self) -> &'static str {
+        match self {
+            JsonValue::Null => "null",
+            JsonValue::Bool(_) => "bool",
+            JsonValue::Number(_) => "number",
+            JsonValue::String(_) => "string",
+            JsonValue::Array(_) => "array",
+            JsonValue::Object(_) => "object",
+        }
+    }
+}
+
+/// Serializes `value` as pretty-printed JSON
+pub fn to_json<T: Serializable>(value: &T) -> Result<String> {
+    let mut serializer = JsonSerializer::new();
+    value.serialize(&mut serializer)?;
+    serializer.finish()
+}
+
+/// Deserializes a `T` from JSON written by `to_json`
+pub fn from_json<T: Deserializable>(text: &str) -> Result<T> {
+    JsonDeserializer::new(text)?.deserialize()
+}
+
+enum Frame {
+    /// The values written at the top level or as a field, list element or optional
+    Sequence(Vec<JsonValue>),
+    /// The fields of an object between `begin_object` and `end_object`
+    Object(Vec<(String, JsonValue)>),
+}
+
+/// Builds the JSON form of the serialized values
+pub struct JsonSerializer {
+    frames: Vec<Frame>,
+}
+
+impl Default for JsonSerializer {
+    fn default() -> Self {
+        JsonSerializer::new()
+    }
+}
+
+impl JsonSerializer {
+    pub fn new() -> Self {
+        JsonSerializer { frames: vec![Frame::Sequence(Vec::new())] }
+    }
+
+    /// The serialized values as a JSON value
+    pub fn into_value(mut self) -> Result<JsonValue> {
+        match (self.frames.pop(), self.frames.is_empty()) {
+            (Some(Frame::Sequence(values)), true) => Ok(sequence_value(values)),
+            _ => Err(Error::new(ErrorKind::InvalidInput, "object started with `begin_object` was not ended")),
+        }
+    }
+
+    /// The serialized values as pretty-printed JSON text
+    pub fn finish(self) -> Result<String> {
+        Ok(self.into_value()?.to_pretty_string())
+    }
+
+    fn push_value(&mut self, value: JsonValue) -> Result<()> {
+        match self.frames.last_mut() {
+            Some(Frame::Sequence(values)) => {
+                values.push(value);
+                Ok(())
+            }
+            _ => Err(Error::new(ErrorKind::InvalidInput, "value written inside an object but outside of a field")),
+        }
+    }
+
+    /// Runs `serialize` and returns what it wrote as a single JSON value
+    fn collect(&mut self, serialize: impl FnOnce(&mut Self) -> Result<()>) -> Result<JsonValue> {
+        self.frames.push(Frame::Sequence(Vec::new()));
+        let result = serialize(self);
+        let frame = self.frames.pop();
+        result?;
+        match frame {
+            Some(Frame::Sequence(values)) => Ok(sequence_value(values)),
+            _ => Err(Error::new(ErrorKind::InvalidInput, "object started with `begin_object` was not ended")),
+        }
+    }
+}
+
+fn sequence_value(mut values: Vec<JsonValue>) -> JsonValue {
+    if values.len() == 1 {
+        values.pop().unwrap()
+    } else {
+        JsonValue::Object(vec![(SEQUENCE_KEY.to_string(), JsonValue::Array(values))])
+    }
+}
+
+/// The values of a sequence written as `value` by `sequence_value`
+fn sequence_values(value: JsonValue) -> Vec<JsonValue> {
+    match value {
+        JsonValue::Object(mut fields)
+            if fields.len() == 1 && fields[0].0 == SEQUENCE_KEY && matches!(fields[0].1, JsonValue::Array(_)) =>
+        {
+            let Some((_, JsonValue::Array(values))) = fields.pop() else {
+                unreachable!();
+            };
+            values
+        }
+        value => vec![value],
+    }
+}
+
+/// Whether `value` is an object wrapping a present optional
+fn is_some_wrapper(value: &JsonValue) -> bool {
+    matches!(value, JsonValue::Object(fields) if fields.len() == 1 && fields[0].0 == SOME_KEY)
+}
+
+/// The JSON form of a primitive: its textual form as a literal if that is valid JSON,
+/// and as a string otherwise (e.g. `NaN`)
+fn primitive_value<T: Primitive>(element: T) -> JsonValue {
+    let text = element.format();
+    match text.as_str() {
+        "true" => JsonValue::Bool(true),
+        "false" => JsonValue::Bool(false),
+        _ if is_json_number(&text) => JsonValue::Number(text),
+        _ => JsonValue::String(text),
+    }
+}
+
+impl Serializer for JsonSerializer {
+    fn write_data(&mut self, buffer: &[u8]) -> Result<()> {
+        self.push_value(JsonValue::String(to_hex(buffer)))
+    }
+
+    fn write<T: Primitive>(&mut self, element: T) -> Result<()> {
+        self.push_value(primitive_value(element))
+    }
+
+    fn write_varint(&mut self, value: u64) -> Result<()> {
+        self.push_value(JsonValue::Number(value.to_string()))
+    }
+
+    fn write_string(&mut self, val: &str) -> Result<()> {
+        self.push_value(JsonValue::String(val.to_string()))
+    }
+
+    fn write_list<T: Serializable>(&mut self, list: &[T]) -> Result<()> {
+        let items = list.iter()
+            .map(|item| self.collect(|serializer| item.serialize(serializer)))
+            .collect::<Result<Vec<_>>>()?;
+        self.push_value(JsonValue::Array(items))
+    }
+
+    fn write_optional<T: Serializable>(&mut self, element: &Option<T>) -> Result<()> {
+        let value = match element {
+            Some(item) => {
+                let value = self.collect(|serializer| item.serialize(serializer))?;
+                // `null` and a wrapper would read back as a missing or an unwrapped optional
+                if value == JsonValue::Null || is_some_wrapper(&value) {
+                    JsonValue::Object(vec![(SOME_KEY.to_string(), value)])
+                } else {
+                    value
+                }
+            }
+            None => JsonValue::Null,
+        };
+        self.push_value(value)
+    }
+
+    fn begin_object(&mut self) -> Result<()> {
+        self.frames.push(Frame::Object(Vec::new()));
+        Ok(())
+    }
+
+    fn end_object(&mut self) -> Result<()> {
+        let Some(Frame::Object(fields)) = self.frames.pop() else {
+            return Err(Error::new(ErrorKind::InvalidInput, "`end_object` without `begin_object`"));
+        };
+        self.push_value(JsonValue::Object(fields))
+    }
+
+    fn write_field<T: Serializable>(&mut self, _field_id: FieldId, name: &str, value: &T) -> Result<()> {
+        let value = self.collect(|serializer| value.serialize(serializer))?;
+        match self.frames.last_mut() {
+            Some(Frame::Object(fields)) => {
+                fields.push((name.to_string(), value));
+                Ok(())
+            }
+            _ => Err(Error::new(ErrorKind::InvalidInput, format!("field '{}' written outside of an object", name))),
+        }
+    }
+}
+
+/// Reads values back from the JSON form written by `JsonSerializer`
+pub struct JsonDeserializer {
+    /// The values left in the sequences being read, innermost last
+    cursors: Vec<vec::IntoIter<JsonValue>>,
+}
+
+impl JsonDeserializer {
+    pub fn new(text: &str) -> Result<Self> {
+        Ok(JsonDeserializer::from_value(JsonValue::parse(text)?))
+    }
+
+    pub fn from_value(value: JsonValue) -> Self {
+        JsonDeserializer { cursors: vec![sequence_values(value).into_iter()] }
+    }
+
+    /// Deserializes a `T` from all of the values, failing if any are left over
+    pub fn deserialize<T: Deserializable>(mut self) -> Result<T> {
+        let value = T::deserialize(&mut self)?;
+        if self.cursors.last().is_some_and(|cursor| cursor.len() > 0) {
+            return Err(Error::new(ErrorKind::InvalidData, "unexpected trailing JSON values"));
+        }
+        Ok(value)
+    }
+
+    fn next_value(&mut self) -> Result<JsonValue> {
+        self.cursors.last_mut()
+            .and_then(Iterator::next)
+            .ok_or_else(|| Error::new(ErrorKind::UnexpectedEof, "read past the last JSON value"))
+    }
+
+    /// Runs `deserialize` on the sequence of values written as `value`
+    fn nested<T>(&mut self, value: JsonValue, deserialize: impl FnOnce(&mut Self) -> Result<T>) -> Result<T> {
+        self.cursors.push(sequence_values(value).into_iter());
+        let result = deserialize(self);
+        let cursor = self.cursors.pop();
+        let result = result?;
+        if cursor.is_some_and(|cursor| cursor.len() > 0) {
+            return Err(Error::new(ErrorKind::InvalidData, "unexpected trailing JSON values"));
+        }
+        Ok(result)
+    }
+}
+
+fn type_error(expected: &str, value: &JsonValue) -> Error {
+    Error::new(ErrorKind::InvalidData, format!("expected a JSON {}, found {}", expected, value.kind()))
+}
+
+/// The fields of a JSON object by name
+pub struct JsonObjectReader {
+    fields: Vec<(String, JsonValue)>,
+}
+
+impl ObjectReader for JsonObjectReader {
+    fn read_field<T: Deserializable>(&mut self, _field_id: FieldId, name: &str) -> Result<Option<T>> {
+        let Some(index) = self.fields.iter().position(|(field_name, _)| field_name == name) else {
+            return Ok(None);
+        };
+        let (_, value) = self.fields.swap_remove(index);
+        JsonDeserializer::from_value(value).deserialize().map(Some)
+    }
+}
+
+impl Deserializer for JsonDeserializer {
+    type Object = JsonObjectReader;
+
+    fn read_data(&mut self, buffer: &mut [u8]) -> Result<()> {
+        let value = self.next_value()?;
+        let JsonValue::String(hex) = &value else {
+            return Err(type_error("hex string", &value));
+        };
+        let bytes = from_hex(hex)?;
+        if bytes.len() != buffer.len() {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                format!("expected {} bytes of data, found {}", buffer.len(), bytes.len())
+            ));
+        }
+        buffer.copy_from_slice(&bytes);
+        Ok(())
+    }
+
+    fn read<T: Primitive>(&mut self) -> Result<T> {
+        match self.next_value()? {
+            JsonValue::Bool(value) => T::parse(if value { "true" } else { "false" }),
+            JsonValue::Number(text) | JsonValue::String(text) => T::parse(&text),
+            value => Err(type_error("number", &value)),
+        }
+    }
+
+    fn read_varint(&mut self) -> Result<u64> {
+        match self.next_value()? {
+            JsonValue::Number(text) => u64::parse(&text),
+            value => Err(type_error("number", &value)),
+        }
+    }
+
+    fn read_string(&mut self) -> Result<String> {
+        match self.next_value()? {
+            JsonValue::String(text) => Ok(text),
+            value => Err(type_error("string", &value)),
+        }
+    }
+
+    fn read_list<T: Deserializable>(&mut self) -> Result<Vec<T>> {
+        match self.next_value()? {
+            JsonValue::Array(items) => items.into_iter()
+                .map(|item| self.nested(item, |deserializer| T::deserialize(deserializer)))
+                .collect(),
+            value => Err(type_error("array", &value)),
+        }
+    }
+
+    fn read_optional<T: Deserializable>(&mut self) -> Result<Option<T>> {
+        match self.next_value()? {
+            JsonValue::Null => Ok(None),
+            JsonValue::Object(mut fields) if fields.len() == 1 && fields[0].0 == SOME_KEY => {
+                let (_, value) = fields.pop().unwrap();
+                self.nested(value, |deserializer| T::deserialize(deserializer)).map(Some)
+            }
+            value => self.nested(value, |deserializer| T::deserialize(deserializer)).map(Some),
+        }
+    }
+
+    fn read_object(&mut self) -> Result<JsonObjectReader> {
+        match self.next_value()? {
+            JsonValue::Object(fields) => Ok(JsonObjectReader { fields }),
+            value => Err(type_error("object", &value)),
+        }
+    }
+}
+
+fn from_hex(hex: &str) -> Result<Vec<u8>> {
+    let invalid = || Error::new(ErrorKind::InvalidData, format!("invalid hex string '{}'", hex));
+    if !hex.len().is_multiple_of(2) {
+        return Err(invalid());
+    }
+    hex.as_bytes().chunks_exact(2)
+        .map(|pair| {
+            let pair = std::str::from_utf8(pair).map_err(|_| invalid())?;
+            u8::from_str_radix(pair, 16).map_err(|_| invalid())
+        })
+        .collect()
+}
+
+/// Whether `text` is a number in JSON syntax
+fn is_json_number(text: &str) -> bool {
+    let bytes = text.as_bytes();
+    let mut position = usize::from(bytes.first() == Some(&b'-'));
+    let digits = |position: &mut usize| {
+        let start = *position;
+        while bytes.get(*position).is_some_and(u8::is_ascii_digit) {
+            *position += 1;
+        }
+        *position - start
+    };
+
+    let integer_digits = digits(&mut position);
+    if integer_digits == 0 || (integer_digits > 1 && bytes[position - integer_digits] == b'0') {
+        return false;
+    }
+    if bytes.get(position) == Some(&b'.') {
+        position += 1;
+        if digits(&mut position) == 0 {
+            return false;
+        }
+    }
+    if matches!(bytes.get(position), Some(b'e' | b'E')) {
+        position += 1;
+        if matches!(bytes.get(position), Some(b'+' | b'-')) {
+            position += 1;
+        }
+        if digits(&mut position) == 0 {
+            return false;
+        }
+    }
+    position == bytes.len()
+}
+
+struct Parser<'a> {
+    bytes: &'a [u8],
+    position: usize,
+}
+
+impl Parser<'_> {
+    fn error(&self, message: &str) -> Error {
+        Error::new(ErrorKind::InvalidData, format!("invalid JSON at offset {}: {}", self.position, message))
+    }
+
+    fn skip_whitespace(&mut self) {
+        while self.bytes.get(self.position).is_some_and(|byte| matches!(byte, b' ' | b'\t' | b'\n' | b'\r')) {
+            self.position += 1;
+        }
+    }
+
+    fn expect(&mut self, byte: u8) -> Result<()> {
+        self.skip_whitespace();
+        if self.bytes.get(self.position) != Some(&byte) {
+            return Err(self.error(&format!("expected '{}'", byte as char)));
+        }
+        self.position += 1;
+        Ok(())
+    }
+
+    /// Consumes `byte` if it is next
+    fn consume(&mut self, byte: u8) -> bool {
+        self.skip_whitespace();
+        let found = self.bytes.get(self.position) == Some(&byte);
+        if found {
+            self.position += 1;
+        }
+        found
+    }
+
+    fn parse_value(&mut self, depth: usize) -> Result<JsonValue> {
+        if depth > MAX_DEPTH {
+            return Err(self.error("nested too deeply"));
+        }
+        self.skip_whitespace();
+        match self.bytes.get(self.position) {
+            Some(b'n') => self.parse_keyword("null", JsonValue::Null),
+            Some(b't') => self.parse_keyword("true", JsonValue::Bool(true)),
+            Some(b'f') => self.parse_keyword("false", JsonValue::Bool(false)),
+            Some(b'"') => Ok(JsonValue::String(self.parse_string()?)),
+            Some(b'[') => {
+                self.position += 1;
+                let mut items = Vec::new();
+                if !self.consume(b']') {
+                    loop {
+                        items.push(self.parse_value(depth + 1)?);
+                        if self.consume(b']') {
+                            break;
+                        }
+                        self.expect(b',')?;
+                    }
+                }
+                Ok(JsonValue::Array(items))
+            }
+            Some(b'{') => {
+                self.position += 1;
+                let mut fields = Vec::new();
+                if !self.consume(b'}') {
+                    loop {
+                        self.skip_whitespace();
+                        let name = self.parse_string()?;
+                        self.expect(b':')?;
+                        fields.push((name, self.parse_value(depth + 1)?));
+                        if self.consume(b'}') {
+                            break;
+                        }
+                        self.expect(b',')?;
+                    }
+                }
+                Ok(JsonValue::Object(fields))
+            }
+            Some(b'-' | b'0'..=b'9') => {
+                let start = self.position;
+                while self.bytes.get(self.position).is_some_and(|byte| matches!(byte, b'-' | b'+' | b'.' | b'e' | b'E' | b'0'..=b'9')) {
+                    self.position += 1;
+                }
+                let text = std::str::from_utf8(&self.bytes[start..self.position]).unwrap();
+                if !is_json_number(text) {
+                    return Err(self.error("invalid number"));
+                }
+                Ok(JsonValue::Number(text.to_string()))
+            }
+            Some(_) => Err(self.error("expected a value")),
+            None => Err(self.error("unexpected end of input")),
+        }
+    }
+
+    fn parse_keyword(&mut self, keyword: &str, value: JsonValue) -> Result<JsonValue> {
+        if !self.bytes[self.position..].starts_with(keyword.as_bytes()) {
+            return Err(self.error("expected a value"));
+        }
+        self.position += keyword.len();
+        Ok(value)
+    }
+
+    fn parse_hex4(&mut self) -> Result<u32> {
+        let digits = self.bytes.get(self.position..self.position + 4)
+            .and_then(|digits| std::str::from_utf8(digits).ok())
+            .and_then(|digits| u32::from_str_radix(digits, 16).ok())
+            .ok_or_else(|| self.error("invalid unicode escape"))?;
+        self.position += 4;
+        Ok(digits)
+    }
+
+    fn parse_string(&mut self) -> Result<String> {
+        if self.bytes.get(self.position) != Some(&b'"') {
+            return Err(self.error("expected a string"));
+        }
+        self.position += 1;
+        let mut bytes = Vec::new();
+        loop {
+            let Some(&byte) = self.bytes.get(self.position) else {
+                return Err(self.error("unterminated string"));
+            };
+            self.position += 1;
+            match byte {
+                b'"' => break,
+                b'\\' => {
+                    let Some(&escape) = self.bytes.get(self.position) else {
+                        return Err(self.error("unterminated string"));
+                    };
+                    self.position += 1;
+                    let decoded = match escape {
+                        b'"' => '"',
+                        b'\\' => '\\',
+                        b'/' => '/',
+                        b'b' => '\u{8}',
+                        b'f' => '\u{c}',
+                        b'n' => '\n',
+                        b'r' => '\r',
+                        b't' => '\t',
+                        b'u' => {
+                            let mut code = self.parse_hex4()?;
+                            if (0xd800..0xdc00).contains(&code) && self.bytes[self.position..].starts_with(b"\\u") {
+                                self.position += 2;
+                                let low = self.parse_hex4()?;
+                                if !(0xdc00..0xe000).contains(&low) {
+                                    return Err(self.error("invalid surrogate pair"));
+                                }
+                                code = 0x10000 + ((code - 0xd800) << 10) + (low - 0xdc00);
+                            }
+                            char::from_u32(code).ok_or_else(|| self.error("invalid unicode escape"))?
+                        }
+                        _ => return Err(self.error("invalid escape")),
+                    };
+                    let mut encoded = [0u8; 4];
+                    bytes.extend_from_slice(decoded.encode_utf8(&mut encoded).as_bytes());
+                }
+                0x00..=0x1f => return Err(self.error("control character in string")),
+                _ => bytes.push(byte),
+            }
+        }
+        // the input is a `str` and escapes decode to whole characters
+        Ok(String::from_utf8(bytes).unwrap())
+    }
+}
+
+fn write_string(out: &mut String, text: &str) {
+    out.push('"');
+    for c in text.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if (c as u32) < 0x20 => out.push_str(&format!("\\u{:04x}", c as u32)),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+}
+
+fn write_indent(out: &mut String, indent: usize) {
+    out.push('\n');
+    out.extend(std::iter::repeat_n(' ', indent * 2));
+}
+
+fn write_value(out: &mut String, value: &JsonValue, indent: usize) {
+    match value {
+        JsonValue::Null => out.push_str("null"),
+        JsonValue::Bool(value) => out.push_str(if *value { "true" } else { "false" }),
+        JsonValue::Number(text) => out.push_str(text),
+        JsonValue::String(text) => write_string(out, text),
+        JsonValue::Array(items) if items.is_empty() => out.push_str("[]"),
+        JsonValue::Array(items) => {
+            out.push('[');
+            for (index, item) in items.iter().enumerate() {
+                if index > 0 {
+                    out.push(',');
+                }
+                write_indent(out, indent + 1);
+                write_value(out, item, indent + 1);
+            }
+            write_indent(out, indent);
+            out.push(']');
+        }
+        JsonValue::Object(fields) if fields.is_empty() => out.push_str("{}"),
+        JsonValue::Object(fields) => {
+            out.push('{');
+            for (index, (name, field)) in fields.iter().enumerate() {
+                if index > 0 {
+                    out.push(',');
+                }
+                write_indent(out, indent + 1);
+                write_string(out, name);
+                out.push_str(": ");
+                write_value(out, field, indent + 1);
+            }
+            write_indent(out, indent);
+            out.push('}');
+        }
+    }
+}
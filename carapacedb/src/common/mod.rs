@@ -1,5 +1,10 @@
 pub mod file_system;
 pub mod file_buffer;
 pub mod serializer;
+pub mod buffered_serializer;
+pub mod json_serializer;
+pub mod sha256;
+pub mod checksum;
 pub mod catalog_type;
 pub mod buffered_file_writer;
+pub mod buffered_file_reader;
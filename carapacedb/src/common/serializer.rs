@@ -1,5 +1,31 @@
-use std::io::{self, Read, Write};
+//! Binary serialization. Values are written positionally by `Serializer::write` and
+//! friends; objects that are persisted across versions are written as tagged fields
+//! with `Serializer::write_field`, so that fields can be added (under a new field id)
+//! or removed (never reusing its id) without breaking existing database files.
 
+use std::any;
+use std::io;
+
+pub use carapacedb_derive::{Deserializable, Serializable};
+
+use crate::common::buffered_serializer::{BufferedDeserializer, BufferedSerializer};
+
+/// The maximum length of a serialized string in bytes, or of a serialized list in
+/// elements; longer lengths are treated as corruption on read
+pub const MAX_SERIALIZED_LENGTH: u32 = 1 << 30;
+
+/// The maximum size of an LEB128-encoded u64
+const MAX_VARINT_SIZE: usize = 10;
+
+/// The number of list elements to reserve space for up front, so that a corrupt length
+/// cannot trigger a huge allocation before the elements are read
+const MAX_LIST_RESERVATION: usize = 1024;
+
+/// Identifies a field within a tagged object
+pub type FieldId = u16;
+
+/// The field id that marks the end of a tagged object
+pub const OBJECT_END: FieldId = FieldId::MAX;
 
 pub trait Serializable {
    fn serialize<S: Serializer>(&self, serializer: &mut S) -> io::Result<()>;
@@ -8,81 +34,333 @@ pub trait Deserializable {
     fn deserialize<D: Deserializer>(deserializer: &mut D) -> io::Result<Self> where Self: Sized;
 }
 
+/// A fixed-size value with a platform independent, little-endian encoding
+pub trait Primitive: Copy {
+    /// The encoded size in bytes
+    const SIZE: usize;
+
+    fn encode(self, buffer: &mut [u8]);
+
+    /// Decodes a value, failing with `InvalidData` on a bit pattern no value encodes to
+    fn decode(buffer: &[u8]) -> io::Result<Self>;
+
+    /// The textual form of the value, used by text formats; `parse` reads it back exactly
+    fn format(self) -> String;
+
+    fn parse(text: &str) -> io::Result<Self>;
+}
+
+fn parse_primitive<T: std::str::FromStr>(text: &str) -> io::Result<T> {
+    text.parse().map_err(|_| io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!("invalid {} value '{}'", any::type_name::<T>(), text)
+    ))
+}
+
+macro_rules! impl_primitive {
+    ($($ty:ty),*) => {
+        $(
+            impl Primitive for $ty {
+                const SIZE: usize = std::mem::size_of::<$ty>();
+
+                fn encode(self, buffer: &mut [u8]) {
+                    buffer.copy_from_slice(&self.to_le_bytes());
+                }
+
+                fn decode(buffer: &[u8]) -> io::Result<Self> {
+                    Ok(<$ty>::from_le_bytes(buffer.try_into().unwrap()))
+                }
+
+                fn format(self) -> String {
+                    self.to_string()
+                }
+
+                fn parse(text: &str) -> io::Result<Self> {
+                    parse_primitive(text)
+                }
+            }
+        )*
+    };
+}
+
+impl_primitive!(u8, u16, u32, u64, u128, i8, i16, i32, i64, i128, f32, f64);
+
+impl Primitive for bool {
+    const SIZE: usize = 1;
+
+    fn encode(self, buffer: &mut [u8]) {
+        buffer[0] = self as u8;
+    }
+
+    fn decode(buffer: &[u8]) -> io::Result<Self> {
+        match buffer[0] {
+            0 => Ok(false),
+            1 => Ok(true),
+            value => Err(io::Error::new(io::ErrorKind::InvalidData, format!("invalid bool value {}", value))),
+        }
+    }
+
+    fn format(self) -> String {
+        self.to_string()
+    }
+
+    fn parse(text: &str) -> io::Result<Self> {
+        parse_primitive(text)
+    }
+}
+
+/// The largest `Primitive::SIZE`
+const MAX_PRIMITIVE_SIZE: usize = 16;
+
+macro_rules! impl_serializable_primitive {
+    ($($ty:ty),*) => {
+        $(
+            impl Serializable for $ty {
+                fn serialize<S: Serializer>(&self, serializer: &mut S) -> io::Result<()> {
+                    serializer.write::<$ty>(*self)
+                }
+            }
+
+            impl Deserializable for $ty {
+                fn deserialize<D: Deserializer>(deserializer: &mut D) -> io::Result<Self> {
+                    deserializer.read::<$ty>()
+                }
+            }
+        )*
+    };
+}
+
+impl_serializable_primitive!(u8, u16, u32, u64, u128, i8, i16, i32, i64, i128, f32, f64, bool);
+
+impl Serializable for String {
+    fn serialize<S: Serializer>(&self, serializer: &mut S) -> io::Result<()> {
+        serializer.write_string(self)
+    }
+}
+
+impl Deserializable for String {
+    fn deserialize<D: Deserializer>(deserializer: &mut D) -> io::Result<Self> {
+        deserializer.read_string()
+    }
+}
+
+impl<T: Serializable> Serializable for Vec<T> {
+    fn serialize<S: Serializer>(&self, serializer: &mut S) -> io::Result<()> {
+        serializer.write_list(self)
+    }
+}
+
+impl<T: Deserializable> Deserializable for Vec<T> {
+    fn deserialize<D: Deserializer>(deserializer: &mut D) -> io::Result<Self> {
+        deserializer.read_list()
+    }
+}
+
+impl<T: Serializable> Serializable for Option<T> {
+    fn serialize<S: Serializer>(&self, serializer: &mut S) -> io::Result<()> {
+        serializer.write_optional(self)
+    }
+}
+
+impl<T: Deserializable> Deserializable for Option<T> {
+    fn deserialize<D: Deserializer>(deserializer: &mut D) -> io::Result<Self> {
+        deserializer.read_optional()
+    }
+}
+
+fn length_error(kind: &str, len: u64) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!("{} length {} exceeds the maximum of {}", kind, len, MAX_SERIALIZED_LENGTH)
+    )
+}
+
+/// Reads the varint length of a `kind`, rejecting lengths over `MAX_SERIALIZED_LENGTH`
+/// and, for lengths in bytes, over the number of bytes remaining
+fn read_length<D: Deserializer + ?Sized>(deserializer: &mut D, kind: &str, in_bytes: bool) -> io::Result<usize> {
+    let len = deserializer.read_varint()?;
+    if len > MAX_SERIALIZED_LENGTH as u64 {
+        return Err(length_error(kind, len));
+    }
+    if let Some(remaining) = deserializer.remaining().filter(|remaining| in_bytes && len > *remaining) {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("{} length {} exceeds the {} remaining bytes", kind, len, remaining)
+        ));
+    }
+    Ok(len as usize)
+}
+
 pub trait Serializer {
     fn write_data(&mut self, buffer: &[u8]) -> io::Result<()>;
-    
-    fn write<T: Copy>(&mut self, element: T) -> io::Result<()> {
-        let bytes = unsafe {
-            std::slice::from_raw_parts(
-                &element as *const T as *const u8,
-                std::mem::size_of::<T>()
-            )
-        };
-        self.write_data(bytes)
+
+    fn write<T: Primitive>(&mut self, element: T) -> io::Result<()> {
+        let mut bytes = [0u8; MAX_PRIMITIVE_SIZE];
+        element.encode(&mut bytes[..T::SIZE]);
+        self.write_data(&bytes[..T::SIZE])
     }
-    
+
+    /// Writes `value` as an LEB128 varint: 7 bits per byte, least significant first, with
+    /// the high bit set on all but the last byte
+    fn write_varint(&mut self, mut value: u64) -> io::Result<()> {
+        let mut bytes = [0u8; MAX_VARINT_SIZE];
+        let mut len = 0;
+        loop {
+            bytes[len] = (value & 0x7f) as u8;
+            value >>= 7;
+            if value == 0 {
+                break;
+            }
+            bytes[len] |= 0x80;
+            len += 1;
+        }
+        self.write_data(&bytes[..=len])
+    }
+
+    /// Writes a fieldless enum as its `u8` discriminant
+    fn write_enum<E: Into<u8>>(&mut self, element: E) -> io::Result<()> {
+        self.write::<u8>(element.into())
+    }
+
     fn write_string(&mut self, val: &str) -> io::Result<()> {
-        debug_assert!(val.len() <= u32::MAX as usize);
-        self.write::<u32>(val.len() as u32)?;
+        if val.len() > MAX_SERIALIZED_LENGTH as usize {
+            return Err(length_error("string", val.len() as u64));
+        }
+        self.write_varint(val.len() as u64)?;
         if !val.is_empty() {
             self.write_data(val.as_bytes())?;
         }
         Ok(())
     }
-    
-    fn write_list<T: Serializable>(&mut self, list: &[T]) -> io::Result<()> {
-        assert!(list.len() <= u32::MAX as usize);
-        self.write::<u32>(list.len() as u32)?;
+
+    fn write_list<T: Serializable>(&mut self, list: &[T]) -> io::Result<()> where Self: Sized {
+        if list.len() > MAX_SERIALIZED_LENGTH as usize {
+            return Err(length_error("list", list.len() as u64));
+        }
+        self.write_varint(list.len() as u64)?;
         for item in list {
             item.serialize(self)?;
         }
         Ok(())
     }
-    
-    fn write_optional<T: Serializable>(&mut self, element: &Option<T>) -> io::Result<()> {
+
+    fn write_optional<T: Serializable>(&mut self, element: &Option<T>) -> io::Result<()> where Self: Sized {
         self.write::<bool>(element.is_some())?;
         if let Some(item) = element {
             item.serialize(self)?;
         }
         Ok(())
     }
+
+    /// Starts a tagged object, whose fields are written with `write_field`
+    fn begin_object(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+
+    /// Ends a tagged object started with `begin_object`
+    fn end_object(&mut self) -> io::Result<()> {
+        self.write_varint(OBJECT_END as u64)
+    }
+
+    /// Writes `value` as the field `field_id` of the current object, prefixed with its
+    /// id and length so that readers that do not know the field can skip it. `name` is
+    /// for formats that identify fields by name.
+    fn write_field<T: Serializable>(&mut self, field_id: FieldId, name: &str, value: &T) -> io::Result<()> where Self: Sized {
+        let _ = name;
+        debug_assert!(field_id != OBJECT_END);
+        let mut payload = BufferedSerializer::new();
+        value.serialize(&mut payload)?;
+        let payload = payload.data();
+        if payload.len() > MAX_SERIALIZED_LENGTH as usize {
+            return Err(length_error("field", payload.len() as u64));
+        }
+        self.write_varint(field_id as u64)?;
+        self.write_varint(payload.len() as u64)?;
+        self.write_data(payload)
+    }
+
+    /// Writes the field `field_id` if `value` is set; readers see a missing field as `None`
+    fn write_optional_field<T: Serializable>(&mut self, field_id: FieldId, name: &str, value: &Option<T>) -> io::Result<()> where Self: Sized {
+        match value {
+            Some(value) => self.write_field(field_id, name, value),
+            None => Ok(()),
+        }
+    }
+
+    /// Writes the field `field_id` unless `value` is the default, which readers
+    /// substitute for a missing field
+    fn write_field_with_default<T: Serializable + Default + PartialEq>(&mut self, field_id: FieldId, name: &str, value: &T) -> io::Result<()> where Self: Sized {
+        if *value == T::default() {
+            return Ok(());
+        }
+        self.write_field(field_id, name, value)
+    }
 }
 
 
 pub trait Deserializer {
+    /// The reader for the fields of a tagged object
+    type Object: ObjectReader;
+
     fn read_data(&mut self, buffer: &mut [u8]) -> io::Result<()>;
-    
-    fn read<T: Copy + Default>(&mut self) -> io::Result<T> {
-        let mut value = T::default();
-        let bytes = unsafe {
-            std::slice::from_raw_parts_mut(
-                &mut value as *mut T as *mut u8,
-                std::mem::size_of::<T>()
-            )
-        };
-        self.read_data(bytes)?;
-        Ok(value)
+
+    /// The number of bytes left to read, if known; used to reject corrupt lengths
+    /// before allocating for them
+    fn remaining(&self) -> Option<u64> {
+        None
+    }
+
+    fn read<T: Primitive>(&mut self) -> io::Result<T> {
+        let mut bytes = [0u8; MAX_PRIMITIVE_SIZE];
+        self.read_data(&mut bytes[..T::SIZE])?;
+        T::decode(&bytes[..T::SIZE])
+    }
+
+    /// Reads an LEB128 varint written by `Serializer::write_varint`
+    fn read_varint(&mut self) -> io::Result<u64> {
+        let mut value = 0u64;
+        for index in 0..MAX_VARINT_SIZE {
+            let byte = self.read::<u8>()?;
+            let bits = (byte & 0x7f) as u64;
+            if index == MAX_VARINT_SIZE - 1 && bits > 1 {
+                break;
+            }
+            value |= bits << (7 * index);
+            if byte & 0x80 == 0 {
+                return Ok(value);
+            }
+        }
+        Err(io::Error::new(io::ErrorKind::InvalidData, "varint overflows a u64"))
+    }
+
+    /// Reads a fieldless enum written by `Serializer::write_enum`
+    fn read_enum<E: TryFrom<u8>>(&mut self) -> io::Result<E> {
+        let value = self.read::<u8>()?;
+        E::try_from(value).map_err(|_| io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("invalid {} value {}", any::type_name::<E>(), value)
+        ))
     }
-    
+
     fn read_string(&mut self) -> io::Result<String> {
-        let len = self.read::<u32>()? as usize;
+        let len = read_length(self, "string", true)?;
         let mut bytes = vec![0u8; len];
         if len > 0 {
             self.read_data(&mut bytes)?;
         }
         String::from_utf8(bytes).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
     }
-    
-    fn read_list<T: Deserializable>(&mut self) -> io::Result<Vec<T>> {
-        let count = self.read::<u32>()? as usize;
-        let mut list = Vec::with_capacity(count);
+
+    fn read_list<T: Deserializable>(&mut self) -> io::Result<Vec<T>> where Self: Sized {
+        let count = read_length(self, "list", false)?;
+        let mut list = Vec::with_capacity(count.min(MAX_LIST_RESERVATION));
         for _ in 0..count {
             list.push(T::deserialize(self)?);
         }
         Ok(list)
     }
-    
-    fn read_optional<T: Deserializable>(&mut self) -> io::Result<Option<T>> {
+
+    fn read_optional<T: Deserializable>(&mut self) -> io::Result<Option<T>> where Self: Sized {
         let has_entry = self.read::<bool>()?;
         if has_entry {
             Ok(Some(T::deserialize(self)?))
@@ -90,4 +368,74 @@ pub trait Deserializer {
             Ok(None)
         }
     }
+
+    /// Reads a tagged object written by `Serializer::begin_object`, whose fields are then
+    /// looked up through the returned reader. Binary formats use `BinaryObjectReader`.
+    fn read_object(&mut self) -> io::Result<Self::Object>;
+}
+
+/// The fields of a tagged object read by `Deserializer::read_object`. Fields that are
+/// never looked up, such as those written by a newer version, are skipped.
+pub trait ObjectReader {
+    /// Reads the field `field_id`, or returns `None` if the object does not have it
+    fn read_field<T: Deserializable>(&mut self, field_id: FieldId, name: &str) -> io::Result<Option<T>>;
+
+    /// Reads the field `field_id`, failing with `InvalidData` if the object does not have it
+    fn read_required_field<T: Deserializable>(&mut self, field_id: FieldId, name: &str) -> io::Result<T> {
+        self.read_field(field_id, name)?.ok_or_else(|| io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("missing field '{}' ({})", name, field_id)
+        ))
+    }
+
+    /// Reads the field `field_id`, or returns the default if the object does not have it
+    fn read_field_or_default<T: Deserializable + Default>(&mut self, field_id: FieldId, name: &str) -> io::Result<T> {
+        Ok(self.read_field(field_id, name)?.unwrap_or_default())
+    }
+}
+
+/// The fields of a tagged object in the binary format, as payloads by field id
+pub struct BinaryObjectReader {
+    fields: Vec<(FieldId, Vec<u8>)>,
+}
+
+impl BinaryObjectReader {
+    /// Reads the fields of a tagged object up to its end marker
+    pub fn read<D: Deserializer + ?Sized>(deserializer: &mut D) -> io::Result<Self> {
+        let mut fields: Vec<(FieldId, Vec<u8>)> = Vec::new();
+        loop {
+            let field_id = deserializer.read_varint()?;
+            let Ok(field_id) = FieldId::try_from(field_id) else {
+                return Err(io::Error::new(io::ErrorKind::InvalidData, format!("invalid field id {}", field_id)));
+            };
+            if field_id == OBJECT_END {
+                return Ok(BinaryObjectReader { fields });
+            }
+            let len = read_length(deserializer, "field", true)?;
+            if fields.iter().any(|(id, _)| *id == field_id) {
+                return Err(io::Error::new(io::ErrorKind::InvalidData, format!("duplicate field id {}", field_id)));
+            }
+            let mut payload = vec![0u8; len];
+            deserializer.read_data(&mut payload)?;
+            fields.push((field_id, payload));
+        }
+    }
+}
+
+impl ObjectReader for BinaryObjectReader {
+    fn read_field<T: Deserializable>(&mut self, field_id: FieldId, name: &str) -> io::Result<Option<T>> {
+        let Some(index) = self.fields.iter().position(|(id, _)| *id == field_id) else {
+            return Ok(None);
+        };
+        let (_, payload) = self.fields.swap_remove(index);
+        let mut reader = BufferedDeserializer::new(&payload);
+        let value = T::deserialize(&mut reader)?;
+        if !reader.finished() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("{} trailing bytes after field '{}' ({})", payload.len() - reader.position(), name, field_id)
+            ));
+        }
+        Ok(Some(value))
+    }
 }
@@ -1,8 +1,19 @@
+use std::io;
 use std::marker::PhantomPinned;
+use std::path::PathBuf;
 use std::sync::Arc;
 
 use crate::catalog::catalog::Catalog;
 use crate::common::file_system::UnifiedFileSystem;
+use crate::common::file_system::cache_fs::{CachingFileSystem, DiskCache, DiskCacheEntry};
+use crate::common::file_system::dynamic_fs::BoxedFileSystem;
+use crate::common::file_system::http_fs::{HttpConfig, HttpFileSystem};
+#[cfg(unix)]
+use crate::common::file_system::plugin_fs::PluginFileSystem;
+use crate::common::file_system::s3_fs::{S3Config, S3FileSystem};
+use crate::common::file_system::stats_fs::IoStatistics;
+use crate::common::file_system::throttle_fs::IoRateLimit;
+use crate::common::file_system::virtual_fs::VirtualFileSystem;
 use super::connection_manager::ConnectionManager;
 use crate::storage::storage_manager::StorageManager;
 use crate::transaction::transaction_manager::TransactionManager;
@@ -17,6 +28,22 @@ pub enum AccessMode {
 pub struct DBConfig {
     pub access_mode: AccessMode,
     pub file_system: Option<Box<UnifiedFileSystem>>,
+    /// File systems to register under a URI scheme (e.g. `memory`), see `VirtualFileSystem`
+    pub file_systems: Vec<(String, BoxedFileSystem)>,
+    /// File system plugin libraries to load and register under a URI scheme, see
+    /// `PluginFileSystem`. Plugins are only supported on unix; elsewhere a non-empty list
+    /// is an `Unsupported` error.
+    pub file_system_plugins: Vec<(String, PathBuf)>,
+    /// Settings of the read-only file systems registered under `http` and `https`, see
+    /// `HttpFileSystem`; `None`, the default, registers neither
+    pub http: Option<HttpConfig>,
+    /// Endpoint and credentials of an object store to register under `s3`, see
+    /// `S3FileSystem`
+    pub s3: Option<S3Config>,
+    /// The local disk cache for the file systems of remote schemes, see `DiskCache`
+    pub disk_cache: Option<Arc<DiskCache>>,
+    /// Caps the writes of checkpoints and spill files, see `ThrottledFileSystem`
+    pub background_write_limit: Option<IoRateLimit>,
 }
 
 impl Default for DBConfig {
@@ -24,10 +51,69 @@ impl Default for DBConfig {
         DBConfig {
             access_mode: AccessMode::Undefined,
             file_system: None,
+            file_systems: Vec::new(),
+            file_system_plugins: Vec::new(),
+            http: None,
+            s3: None,
+            disk_cache: None,
+            background_write_limit: None,
         }
     }
 }
 
+impl DBConfig {
+    /// Creates the file system of a database: the configured `file_system`, or a
+    /// `VirtualFileSystem`, with the `http` file systems, `file_systems`,
+    /// `file_system_plugins` and the `s3` object store registered in it, where later
+    /// ones replace earlier ones registered under the same scheme. File systems
+    /// registered under one of the `disk_cache` schemes are cached on local disk. The
+    /// file system records I/O statistics, see `DuckDB::io_statistics`, and throttles
+    /// background writes if `background_write_limit` is set. The throttle wraps the
+    /// statistics, so that the recorded latencies do not include the time spent
+    /// waiting for the limit.
+    pub fn create_file_system(&mut self) -> io::Result<UnifiedFileSystem> {
+        let mut fs = match self.file_system.take() {
+            Some(fs) => *fs,
+            None => UnifiedFileSystem::Virtual(VirtualFileSystem::new()),
+        };
+        let mut registered: Vec<(String, BoxedFileSystem)> = Vec::new();
+        if let Some(http) = self.http.take() {
+            for scheme in ["http", "https"] {
+                registered.push((scheme.to_string(), Box::new(HttpFileSystem::new(scheme, http.clone()))));
+            }
+        }
+        registered.append(&mut self.file_systems);
+        #[cfg(unix)]
+        for (scheme, library_path) in self.file_system_plugins.drain(..) {
+            registered.push((scheme, Box::new(PluginFileSystem::load(&library_path)?)));
+        }
+        #[cfg(not(unix))]
+        if let Some((scheme, _)) = self.file_system_plugins.first() {
+            return Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                format!("cannot load the file system plugin for '{}': plugins are only supported on unix", scheme)
+            ));
+        }
+        if let Some(s3) = self.s3.take() {
+            registered.push(("s3".to_string(), Box::new(S3FileSystem::new(s3))));
+        }
+        for (scheme, registered_fs) in registered {
+            let registered_fs: BoxedFileSystem = match &self.disk_cache {
+                Some(cache) if cache.config().schemes.iter().any(|cached| cached.eq_ignore_ascii_case(&scheme)) => {
+                    Box::new(CachingFileSystem::new(registered_fs, cache.clone(), &scheme))
+                }
+                _ => registered_fs,
+            };
+            fs.register_file_system(&scheme, registered_fs)?;
+        }
+        fs = fs.with_statistics();
+        if let Some(limit) = self.background_write_limit {
+            fs = fs.with_write_limit(limit)?;
+        }
+        Ok(fs)
+    }
+}
+
 //
 // currently, we just only arc and weak reference for safe, not use pin 
 //  and raw pointer, maybe optimize later
@@ -39,4 +125,44 @@ pub struct DuckDB {
     pub transaction_manager: Box<TransactionManager>,
     pub connection_manager: Box<ConnectionManager>,
     pub access_mode: AccessMode,
+    pub disk_cache: Option<Arc<DiskCache>>,
+}
+
+impl DuckDB {
+    /// Registers `fs` for paths with the URI scheme `scheme`
+    pub fn register_file_system(&self, scheme: &str, fs: BoxedFileSystem) -> io::Result<()> {
+        self.file_system.register_file_system(scheme, fs)
+    }
+
+    /// The I/O statistics of the database files, if the file system records them
+    pub fn io_statistics(&self) -> Option<&IoStatistics> {
+        self.file_system.io_statistics()
+    }
+
+    pub fn reset_io_statistics(&self) {
+        if let Some(statistics) = self.io_statistics() {
+            statistics.reset();
+        }
+    }
+
+    /// The files in the local disk cache, if there is one
+    pub fn disk_cache_entries(&self) -> Vec<DiskCacheEntry> {
+        self.disk_cache.as_ref().map_or(Vec::new(), |cache| cache.entries())
+    }
+
+    pub fn clear_disk_cache(&self) -> io::Result<()> {
+        match &self.disk_cache {
+            Some(cache) => cache.clear(),
+            None => Ok(()),
+        }
+    }
+
+    /// Changes the limit on background writes; fails if `limit` has a rate of zero or
+    /// the database was opened without `DBConfig::background_write_limit`
+    pub fn set_background_write_limit(&self, limit: IoRateLimit) -> io::Result<()> {
+        match self.file_system.write_limiter() {
+            Some(limiter) => limiter.set_limit(limit),
+            None => Err(io::Error::new(io::ErrorKind::Unsupported, "background writes are not throttled")),
+        }
+    }
 }
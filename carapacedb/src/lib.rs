@@ -1,3 +1,6 @@
+// lets `#[derive(Serializable, Deserializable)]` refer to this crate as `::carapacedb`
+extern crate self as carapacedb;
+
 pub mod core;
 pub mod common;
 pub mod storage;
@@ -1,20 +1,22 @@
+use crate::common::serializer::{Deserializable, Serializable};
 
 #[repr(u8)]
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serializable, Deserializable)]
 pub enum AlterType {
     Invalid = 0,
     AlterTable = 1,
 }
 
 #[repr(u8)]
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serializable, Deserializable)]
 pub enum AlterTableType {
     Invalid = 0,
     RenameColumn = 1,
 }
 
-#[derive(Debug, Clone, PartialEq)]
+#[derive(Debug, Clone, PartialEq, Serializable, Deserializable)]
 pub struct AlterInfo {
+    #[serialize(id = 1)]
     pub alter_type: AlterType,
 }
 
@@ -24,11 +26,15 @@ impl AlterInfo {
     }
 }
 
-#[derive(Debug, Clone, PartialEq)]
+#[derive(Debug, Clone, PartialEq, Serializable, Deserializable)]
 pub struct AlterTableInfo {
+    #[serialize(id = 1)]
     pub base: AlterInfo,
+    #[serialize(id = 2)]
     pub alter_table_type: AlterTableType,
+    #[serialize(id = 3)]
     pub schema: String,
+    #[serialize(id = 4)]
     pub table: String,
 }
 
@@ -47,10 +53,13 @@ impl AlterTableInfo {
     }
 }
 
-#[derive(Debug, Clone, PartialEq)]
+#[derive(Debug, Clone, PartialEq, Serializable, Deserializable)]
 pub struct RenameColumnInfo {
+    #[serialize(id = 1)]
     pub base: AlterTableInfo,
+    #[serialize(id = 2)]
     pub name: String,
+    #[serialize(id = 3)]
     pub new_name: String,
 }
 
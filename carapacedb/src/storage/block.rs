@@ -1,7 +1,48 @@
 use crate::common::file_buffer::FileBuffer;
-use super::storage_info::BlockId;
+use super::storage_info::{BlockId, BLOCK_SIZE};
 
 pub struct Block {
     file_buffer: FileBuffer,
     pub block_id: BlockId,
 }
+
+impl Block {
+    pub fn new(block_id: BlockId) -> Self {
+        Block { file_buffer: FileBuffer::new(BLOCK_SIZE), block_id }
+    }
+
+    pub fn file_buffer(&self) -> &FileBuffer {
+        &self.file_buffer
+    }
+
+    pub fn file_buffer_mut(&mut self) -> &mut FileBuffer {
+        &mut self.file_buffer
+    }
+
+    /// The data of the block, excluding the checksum header
+    pub fn buffer(&self) -> &[u8] {
+        self.file_buffer.buffer()
+    }
+
+    pub fn buffer_mut(&mut self) -> &mut [u8] {
+        self.file_buffer.buffer_mut()
+    }
+}
+
+/// A block of a memory-mapped database file that borrows its data from the mapping
+/// instead of copying it into a `FileBuffer`, see `SingleFileBlockManager::read_mapped`
+pub struct MappedBlock<'a> {
+    data: &'a [u8],
+    pub block_id: BlockId,
+}
+
+impl<'a> MappedBlock<'a> {
+    pub(crate) fn new(block_id: BlockId, data: &'a [u8]) -> Self {
+        MappedBlock { data, block_id }
+    }
+
+    /// The data of the block, excluding the checksum header
+    pub fn buffer(&self) -> &'a [u8] {
+        self.data
+    }
+}
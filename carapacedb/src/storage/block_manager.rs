@@ -1,3 +1,5 @@
+use std::io::Result;
+
 use super::block::Block;
 use super::storage_info::{BlockId, DatabaseHeader};
 
@@ -12,10 +14,11 @@ pub trait BlockManager {
     fn get_meta_block(&self) -> BlockId;
     
     /// Read the content of a block from disk
-    fn read(&self, block: & Block);
+    fn read(&self, block: &mut Block) -> Result<()>;
     
-    fn write(&self, block: & Block);
+    /// Write the content of a block to disk, storing its checksum in the block header
+    fn write(&self, block: &mut Block) -> Result<()>;
     
     /// Write the header; should be the final step of a checkpoint
-    fn write_header(&self, header: &DatabaseHeader);
+    fn write_header(&self, header: &DatabaseHeader) -> Result<()>;
 }
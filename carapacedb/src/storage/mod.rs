@@ -1,5 +1,6 @@
 pub mod storage_manager;
 pub mod block_manager;
+pub mod single_file_block_manager;
 pub mod storage_info;
 pub mod block;
 pub mod wal;
@@ -0,0 +1,419 @@
+use std::collections::BTreeSet;
+use std::io::{Error, ErrorKind, Result};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+use crate::common::buffered_serializer::{BufferedDeserializer, BufferedSerializer};
+use crate::common::file_buffer::{verify_checksum, FileBuffer, FILE_BUFFER_HEADER_SIZE};
+use crate::common::file_system::{FileFlags, FileLockType, OwnedFileHandle, UnifiedFileHandle, UnifiedFileSystem};
+use crate::common::serializer::{Deserializable, Deserializer, Serializable, Serializer};
+use super::block::{Block, MappedBlock};
+use super::block_manager::BlockManager;
+use super::storage_info::{
+    BlockId, DatabaseHeader, MainHeader, BLOCK_SIZE, HEADER_SIZE, INVALID_BLOCK, VERSION_NUMBER
+};
+
+/// The file offset of the first block, after the main header and the two database headers
+const BLOCK_START: u64 = HEADER_SIZE as u64 * 3;
+
+/// The number of block ids stored in a free list block, after the id of the next free
+/// list block and the number of ids in this one
+const FREE_LIST_ENTRIES_PER_BLOCK: usize = (BLOCK_SIZE - FILE_BUFFER_HEADER_SIZE) / size_of::<BlockId>() - 2;
+
+struct BlockManagerState {
+    /// The slot (0 or 1) of the database header that is currently active
+    active_header: usize,
+    iteration_count: u64,
+    meta_block: BlockId,
+    /// The number of blocks in the file; new blocks are allocated from here on
+    max_block: BlockId,
+    /// The number of blocks whose space the file already holds, which can exceed
+    /// `max_block` until the next header has been written
+    allocated_blocks: BlockId,
+    /// Blocks that are not in use by the active header and can be allocated
+    free_list: BTreeSet<BlockId>,
+    /// Blocks holding the free list of the active header, which are free once the next
+    /// header is written
+    free_list_blocks: Vec<BlockId>,
+    /// Blocks that are still in use by the active header but no longer by the checkpoint
+    /// being written, which are free once the next header is written
+    modified_blocks: BTreeSet<BlockId>,
+}
+
+/// Stores the blocks of a database in a single file: a `MainHeader`, two `DatabaseHeader`s,
+/// and the blocks. Checkpoints write the inactive database header, so that a crash while
+/// writing it leaves the previous header intact.
+pub struct SingleFileBlockManager {
+    handle: OwnedFileHandle,
+    path: PathBuf,
+    read_only: bool,
+    state: Mutex<BlockManagerState>,
+}
+
+impl SingleFileBlockManager {
+    /// Opens the database file at `path`, or creates an empty one if `create_new` is set;
+    /// an existing non-empty file is never overwritten. A writable file is locked for
+    /// writing, a read-only file for reading.
+    pub fn new(
+        fs: Arc<UnifiedFileSystem>,
+        path: &Path,
+        read_only: bool,
+        create_new: bool,
+        use_direct_io: bool,
+    ) -> Result<Self> {
+        if create_new {
+            Self::create(&fs, path).map_err(|e| file_error(path, e))?;
+        }
+        let mut flags = if read_only { FileFlags::READ } else { FileFlags::WRITE | FileFlags::BACKGROUND };
+        if use_direct_io {
+            flags |= FileFlags::DIRECT_IO;
+        } else if read_only {
+            // nothing writes to the file of a read-only database, so reads can be
+            // served from a mapping of it
+            flags |= FileFlags::MEMORY_MAP;
+        }
+        let lock = if read_only { FileLockType::ReadLock } else { FileLockType::WriteLock };
+        let handle = OwnedFileHandle::open(fs, path, flags, Some(lock))?;
+
+        let state = Self::load(&handle).map_err(|e| file_error(path, e))?;
+
+        Ok(SingleFileBlockManager {
+            handle,
+            path: path.to_path_buf(),
+            read_only,
+            state: Mutex::new(state),
+        })
+    }
+
+    pub fn file_system(&self) -> &Arc<UnifiedFileSystem> {
+        self.handle.file_system()
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// The number of blocks in the file
+    pub fn block_count(&self) -> u64 {
+        self.state.lock().unwrap().max_block as u64
+    }
+
+    /// The blocks that can be allocated
+    pub fn free_list(&self) -> Vec<BlockId> {
+        self.state.lock().unwrap().free_list.iter().copied().collect()
+    }
+
+    /// Reads `block_id` without copying it, verifying its checksum: the block borrows
+    /// its data from the memory mapping of a read-only database file. Returns `None` if
+    /// the block is not mapped, e.g. because the file could not be mapped or was opened
+    /// for writing or with direct I/O; `BlockManager::read` then reads it with pread.
+    pub fn read_mapped(&self, block_id: BlockId) -> Result<Option<MappedBlock<'_>>> {
+        let location = self.checked_block_location(block_id)?;
+        match self.handle.mapped_slice(location, BLOCK_SIZE) {
+            Some(data) => {
+                let data = verify_checksum(data, location).map_err(|e| file_error(&self.path, e))?;
+                Ok(Some(MappedBlock::new(block_id, data)))
+            }
+            None => Ok(None),
+        }
+    }
+
+    /// Marks a block in use by the last checkpoint as no longer used; it becomes free
+    /// once the next header is written
+    pub fn mark_block_as_modified(&self, block_id: BlockId) {
+        let mut state = self.state.lock().unwrap();
+        if !state.free_list.contains(&block_id) {
+            state.modified_blocks.insert(block_id);
+        }
+    }
+
+    /// Writes the headers of a new, empty database file. They are written to a temporary
+    /// file that replaces `path`, see `UnifiedFileSystem::replace_file`, so that a crash
+    /// never leaves a partially initialized database file behind.
+    fn create(fs: &UnifiedFileSystem, path: &Path) -> Result<()> {
+        if fs.file_exists(path)? {
+            let file_size = fs.open_file(path, FileFlags::READ, None)?.file_size()?;
+            if file_size != 0 {
+                return Err(Error::new(
+                    ErrorKind::AlreadyExists,
+                    format!("cannot create a new database over an existing file of {} bytes", file_size)
+                ));
+            }
+        }
+        fs.replace_file(path, |handle| {
+            let main_header = MainHeader { version_number: VERSION_NUMBER, flags: [0; 4] };
+            write_header_block(handle, &main_header, 0)?;
+            let header = DatabaseHeader { iteration: 0, meta_block: INVALID_BLOCK, free_list: INVALID_BLOCK, block_count: 0 };
+            write_header_block(handle, &header, database_header_location(0))?;
+            write_header_block(handle, &header, database_header_location(1))
+        })
+    }
+
+    /// Reads the headers and free list of an existing database file
+    fn load(handle: &UnifiedFileHandle<'static>) -> Result<BlockManagerState> {
+        let file_size = handle.file_size()?;
+        if file_size < BLOCK_START {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                format!("file of {} bytes is too small to hold the database headers", file_size)
+            ));
+        }
+        let main_header: MainHeader = read_header_block(handle, 0)?;
+        if main_header.version_number != VERSION_NUMBER {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                format!(
+                    "storage version {} is not supported, expected version {}",
+                    main_header.version_number, VERSION_NUMBER
+                )
+            ));
+        }
+
+        // use the header with the highest iteration; if one is corrupt, e.g. because a
+        // crash interrupted writing it, the other one is still intact
+        let headers = [
+            read_header_block::<DatabaseHeader>(handle, database_header_location(0)),
+            read_header_block::<DatabaseHeader>(handle, database_header_location(1)),
+        ];
+        let (active_header, header) = match headers {
+            [Ok(first), Ok(second)] if second.iteration > first.iteration => (1, second),
+            [Ok(first), _] => (0, first),
+            [Err(_), Ok(second)] => (1, second),
+            [Err(e), Err(_)] => return Err(e),
+        };
+
+        let max_block = header.block_count as BlockId;
+        if header.meta_block != INVALID_BLOCK && !(0..max_block).contains(&header.meta_block) {
+            return Err(Error::new(ErrorKind::InvalidData, format!("invalid meta block {}", header.meta_block)));
+        }
+        let mut free_list = BTreeSet::new();
+        let mut free_list_blocks = Vec::new();
+        let mut next_block = header.free_list;
+        while next_block != INVALID_BLOCK {
+            if !(0..max_block).contains(&next_block) || free_list_blocks.contains(&next_block) {
+                return Err(Error::new(ErrorKind::InvalidData, format!("invalid free list block {}", next_block)));
+            }
+            let mut block = Block::new(next_block);
+            block.file_buffer_mut().read(handle, block_location(next_block))?;
+            free_list_blocks.push(next_block);
+
+            let mut deserializer = BufferedDeserializer::new(block.buffer());
+            next_block = deserializer.read::<BlockId>()?;
+            let count = deserializer.read::<u64>()?;
+            if count > FREE_LIST_ENTRIES_PER_BLOCK as u64 {
+                return Err(Error::new(ErrorKind::InvalidData, format!("invalid free list length {}", count)));
+            }
+            for _ in 0..count {
+                let block_id = deserializer.read::<BlockId>()?;
+                if !(0..max_block).contains(&block_id) {
+                    return Err(Error::new(ErrorKind::InvalidData, format!("invalid free block {}", block_id)));
+                }
+                free_list.insert(block_id);
+            }
+        }
+
+        Ok(BlockManagerState {
+            active_header,
+            iteration_count: header.iteration,
+            meta_block: header.meta_block,
+            max_block,
+            allocated_blocks: ((file_size - BLOCK_START) / BLOCK_SIZE as u64) as BlockId,
+            free_list,
+            free_list_blocks,
+            modified_blocks: BTreeSet::new(),
+        })
+    }
+
+    fn check_writable(&self) -> Result<()> {
+        if self.read_only {
+            return Err(Error::new(
+                ErrorKind::PermissionDenied,
+                format!("cannot write to read-only database file '{}'", self.path.display())
+            ));
+        }
+        Ok(())
+    }
+
+    /// The location of `block_id`, which must have been allocated
+    fn checked_block_location(&self, block_id: BlockId) -> Result<u64> {
+        check_block_id(&self.path, self.state.lock().unwrap().max_block, block_id)?;
+        Ok(block_location(block_id))
+    }
+}
+
+fn check_block_id(path: &Path, max_block: BlockId, block_id: BlockId) -> Result<()> {
+    if !(0..max_block).contains(&block_id) {
+        return Err(Error::new(
+            ErrorKind::InvalidInput,
+            format!("block {} is out of range for database file '{}' with {} blocks", block_id, path.display(), max_block)
+        ));
+    }
+    Ok(())
+}
+
+/// Preallocates whole blocks up to and including `block_id` when it lies beyond the
+/// space the file holds, so that growing the file does not fail for lack of space
+/// halfway through a block. File systems that cannot preallocate grow on write.
+fn allocate_blocks(handle: &UnifiedFileHandle<'_>, state: &mut BlockManagerState, block_id: BlockId) -> Result<()> {
+    if block_id < state.allocated_blocks {
+        return Ok(());
+    }
+    let start = block_location(state.allocated_blocks);
+    match handle.allocate(start, block_location(block_id + 1) - start) {
+        Ok(()) => {}
+        Err(e) if e.kind() == ErrorKind::Unsupported => {}
+        Err(e) => return Err(e),
+    }
+    state.allocated_blocks = block_id + 1;
+    Ok(())
+}
+
+/// Adds the path of the database file to `error`
+fn file_error(path: &Path, error: Error) -> Error {
+    Error::new(error.kind(), format!("database file '{}': {}", path.display(), error))
+}
+
+fn block_location(block_id: BlockId) -> u64 {
+    BLOCK_START + block_id as u64 * BLOCK_SIZE as u64
+}
+
+fn database_header_location(slot: usize) -> u64 {
+    HEADER_SIZE as u64 * (1 + slot as u64)
+}
+
+/// Writes `header` into the header-sized block at `location`
+fn write_header_block<T: Serializable>(handle: &UnifiedFileHandle<'_>, header: &T, location: u64) -> Result<()> {
+    let mut serializer = BufferedSerializer::new();
+    header.serialize(&mut serializer)?;
+    let mut buffer = FileBuffer::new(HEADER_SIZE);
+    buffer.buffer_mut()[..serializer.len()].copy_from_slice(serializer.data());
+    buffer.write(handle, location)
+}
+
+/// Reads a header written by `write_header_block`, verifying its checksum
+fn read_header_block<T: Deserializable>(handle: &UnifiedFileHandle<'_>, location: u64) -> Result<T> {
+    let mut buffer = FileBuffer::new(HEADER_SIZE);
+    buffer.read(handle, location)?;
+    T::deserialize(&mut BufferedDeserializer::new(buffer.buffer()))
+}
+
+impl BlockManager for SingleFileBlockManager {
+    fn create_block(&mut self) -> Box<Block> {
+        Box::new(Block::new(self.get_free_block_id()))
+    }
+
+    fn get_free_block_id(&self) -> BlockId {
+        let mut state = self.state.lock().unwrap();
+        match state.free_list.pop_first() {
+            Some(block_id) => block_id,
+            None => {
+                state.max_block += 1;
+                state.max_block - 1
+            }
+        }
+    }
+
+    fn get_meta_block(&self) -> BlockId {
+        self.state.lock().unwrap().meta_block
+    }
+
+    fn read(&self, block: &mut Block) -> Result<()> {
+        let location = self.checked_block_location(block.block_id)?;
+        block.file_buffer_mut().read(&self.handle, location).map_err(|e| file_error(&self.path, e))
+    }
+
+    fn write(&self, block: &mut Block) -> Result<()> {
+        self.check_writable()?;
+        let location = {
+            let mut state = self.state.lock().unwrap();
+            check_block_id(&self.path, state.max_block, block.block_id)?;
+            allocate_blocks(&self.handle, &mut state, block.block_id).map_err(|e| file_error(&self.path, e))?;
+            block_location(block.block_id)
+        };
+        block.file_buffer_mut().write(&self.handle, location).map_err(|e| file_error(&self.path, e))
+    }
+
+    /// Writes the free list and then `header` to the inactive header slot, making it the
+    /// active one. The iteration, free list and block count of `header` are filled in.
+    fn write_header(&self, header: &DatabaseHeader) -> Result<()> {
+        self.check_writable()?;
+        let mut state = self.state.lock().unwrap();
+
+        // the blocks that are free once the new header is written; the blocks the free
+        // list is stored in must not be in use by the active header, so they are taken
+        // from the current free list or appended to the file
+        // free blocks at the end of the file are cut off; only those the active header
+        // does not use, as the blocks of the free list may be written over them
+        let mut max_block = state.max_block;
+        while max_block > 0 && state.free_list.contains(&(max_block - 1)) {
+            max_block -= 1;
+        }
+        let mut free_blocks: BTreeSet<BlockId> = state.free_list.range(..max_block)
+            .chain(&state.modified_blocks)
+            .chain(&state.free_list_blocks)
+            .copied()
+            .collect();
+        let mut available: BTreeSet<BlockId> = state.free_list.range(..max_block).copied().collect();
+        let mut free_list_blocks = Vec::new();
+        while free_list_blocks.len() * FREE_LIST_ENTRIES_PER_BLOCK < free_blocks.len() {
+            let block_id = match available.pop_first() {
+                Some(block_id) => {
+                    free_blocks.remove(&block_id);
+                    block_id
+                }
+                None => {
+                    max_block += 1;
+                    max_block - 1
+                }
+            };
+            free_list_blocks.push(block_id);
+        }
+
+        let free_ids: Vec<BlockId> = free_blocks.iter().copied().collect();
+        for (index, &block_id) in free_list_blocks.iter().enumerate() {
+            let start = (index * FREE_LIST_ENTRIES_PER_BLOCK).min(free_ids.len());
+            let end = ((index + 1) * FREE_LIST_ENTRIES_PER_BLOCK).min(free_ids.len());
+            let mut serializer = BufferedSerializer::with_capacity(BLOCK_SIZE);
+            serializer.write::<BlockId>(free_list_blocks.get(index + 1).copied().unwrap_or(INVALID_BLOCK))?;
+            serializer.write::<u64>((end - start) as u64)?;
+            for &free_id in &free_ids[start..end] {
+                serializer.write::<BlockId>(free_id)?;
+            }
+            let mut block = Block::new(block_id);
+            block.buffer_mut()[..serializer.len()].copy_from_slice(serializer.data());
+            allocate_blocks(&self.handle, &mut state, block_id)?;
+            block.file_buffer_mut().write(&self.handle, block_location(block_id))?;
+        }
+
+        let new_header = DatabaseHeader {
+            iteration: state.iteration_count + 1,
+            meta_block: header.meta_block,
+            free_list: free_list_blocks.first().copied().unwrap_or(INVALID_BLOCK),
+            block_count: max_block as u64,
+        };
+        // the blocks must be durable before the header that references them
+        self.handle.fsync()?;
+        let slot = 1 - state.active_header;
+        write_header_block(&self.handle, &new_header, database_header_location(slot))?;
+        self.handle.fsync()?;
+
+        // give the space of the blocks that became free and of those cut off the end
+        // back to the file system; the header has been written, so failures only cost
+        // disk space
+        for &block_id in state.modified_blocks.iter().chain(&state.free_list_blocks) {
+            let _ = self.handle.punch_hole(block_location(block_id), BLOCK_SIZE as u64);
+        }
+        if max_block < state.allocated_blocks && self.handle.truncate(block_location(max_block)).is_ok() {
+            state.allocated_blocks = max_block;
+        }
+
+        state.active_header = slot;
+        state.iteration_count = new_header.iteration;
+        state.meta_block = new_header.meta_block;
+        state.max_block = max_block;
+        state.free_list = free_blocks;
+        state.free_list_blocks = free_list_blocks;
+        state.modified_blocks.clear();
+        Ok(())
+    }
+}
@@ -1,3 +1,7 @@
+use std::io::{Error, ErrorKind, Result};
+
+use crate::common::serializer::{Deserializable, Deserializer, Serializable, Serializer};
+
 
 /// The version number of the database storage format
 pub static VERSION_NUMBER: u64 = 1; 
@@ -10,6 +14,9 @@ pub const BLOCK_SIZE: usize = 262144;
 /// the page size, which is 4KB. (1 << 12)
 pub const HEADER_SIZE: usize = 4096;
 
+/// The bytes at the start of every database file
+pub const MAGIC_BYTES: &[u8; 4] = b"CRPC";
+
 /// Block ID type alias
 pub type BlockId = i64;
 
@@ -40,3 +47,50 @@ pub struct DatabaseHeader {
     /// block_count any blocks appearing AFTER block_count are implicitly part of the free_list.
     pub block_count: u64,
 }
+
+impl Serializable for MainHeader {
+    fn serialize<S: Serializer>(&self, serializer: &mut S) -> Result<()> {
+        serializer.write_data(MAGIC_BYTES)?;
+        serializer.write::<u64>(self.version_number)?;
+        for flag in self.flags {
+            serializer.write::<u64>(flag)?;
+        }
+        Ok(())
+    }
+}
+
+impl Deserializable for MainHeader {
+    fn deserialize<D: Deserializer>(deserializer: &mut D) -> Result<Self> {
+        let mut magic_bytes = [0u8; MAGIC_BYTES.len()];
+        deserializer.read_data(&mut magic_bytes)?;
+        if &magic_bytes != MAGIC_BYTES {
+            return Err(Error::new(ErrorKind::InvalidData, "not a database file: missing magic bytes"));
+        }
+        let version_number = deserializer.read::<u64>()?;
+        let mut flags = [0u64; 4];
+        for flag in &mut flags {
+            *flag = deserializer.read::<u64>()?;
+        }
+        Ok(MainHeader { version_number, flags })
+    }
+}
+
+impl Serializable for DatabaseHeader {
+    fn serialize<S: Serializer>(&self, serializer: &mut S) -> Result<()> {
+        serializer.write::<u64>(self.iteration)?;
+        serializer.write::<i64>(self.meta_block)?;
+        serializer.write::<i64>(self.free_list)?;
+        serializer.write::<u64>(self.block_count)
+    }
+}
+
+impl Deserializable for DatabaseHeader {
+    fn deserialize<D: Deserializer>(deserializer: &mut D) -> Result<Self> {
+        Ok(DatabaseHeader {
+            iteration: deserializer.read::<u64>()?,
+            meta_block: deserializer.read::<i64>()?,
+            free_list: deserializer.read::<i64>()?,
+            block_count: deserializer.read::<u64>()?,
+        })
+    }
+}
@@ -1,6 +1,8 @@
-use std::{path::PathBuf, sync::Weak };
-use crate::{core::database::DuckDB, storage::wal::WriteAheadLog};
+use std::{io::Result, path::{Path, PathBuf}, sync::{Arc, Weak} };
+use crate::{common::file_system::UnifiedFileSystem, core::database::DuckDB, storage::wal::WriteAheadLog};
 use super::block_manager::BlockManager;
+use super::single_file_block_manager::SingleFileBlockManager;
+use super::storage_info::DatabaseHeader;
 
 ///! StorageManager is responsible for managing the physical storage of the
 ///! database on disk
@@ -9,8 +11,61 @@ pub struct StorageManager {
     path: PathBuf,
     read_only: bool,
     block_manager: Box<dyn BlockManager>,
-    wal: WriteAheadLog,
+    /// The log of a writable database, see `wal_path`
+    wal: Option<WriteAheadLog>,
 }
 
 impl StorageManager {
+    /// Opens the database file at `path`, see `SingleFileBlockManager::new`, and the log
+    /// next to it unless the database is read-only
+    pub fn open(
+        database: Weak<DuckDB>,
+        fs: Arc<UnifiedFileSystem>,
+        path: &Path,
+        read_only: bool,
+        create_new: bool,
+    ) -> Result<Self> {
+        let block_manager = SingleFileBlockManager::new(fs.clone(), path, read_only, create_new, false)?;
+        let wal = if read_only {
+            None
+        } else {
+            Some(WriteAheadLog::open(database.clone(), fs, &wal_path(path))?)
+        };
+        Ok(StorageManager {
+            database,
+            path: path.to_path_buf(),
+            read_only,
+            block_manager: Box::new(block_manager),
+            wal,
+        })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn block_manager(&self) -> &dyn BlockManager {
+        self.block_manager.as_ref()
+    }
+
+    pub fn wal(&mut self) -> Option<&mut WriteAheadLog> {
+        self.wal.as_mut()
+    }
+
+    /// Completes a checkpoint by writing `header`, see `BlockManager::write_header`, after
+    /// which the log is emptied
+    pub fn checkpoint(&mut self, header: &DatabaseHeader) -> Result<()> {
+        self.block_manager.write_header(header)?;
+        match &mut self.wal {
+            Some(wal) => wal.reset(),
+            None => Ok(()),
+        }
+    }
+}
+
+/// The path of the write-ahead log of the database file at `path`
+pub fn wal_path(path: &Path) -> PathBuf {
+    let mut wal_path = path.as_os_str().to_owned();
+    wal_path.push(".wal");
+    PathBuf::from(wal_path)
 }
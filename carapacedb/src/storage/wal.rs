@@ -1,6 +1,10 @@
-use std::{sync::Weak};
+use std::io::Result;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Weak};
 
-use crate::{common::buffered_file_writer::BufferedFileWriter, core::database::DuckDB};
+use crate::common::buffered_file_writer::BufferedFileWriter;
+use crate::common::file_system::{FileFlags, UnifiedFileSystem};
+use crate::core::database::DuckDB;
 
 
 /// The WriteAheadLog (WAL) is a log that is used to provide durability. Prior
@@ -10,5 +14,45 @@ use crate::{common::buffered_file_writer::BufferedFileWriter, core::database::Du
 pub struct WriteAheadLog {
     pub initialized: bool,
     database: Weak<DuckDB>,
+    path: PathBuf,
     writer: Box<BufferedFileWriter>,
 }
+
+impl WriteAheadLog {
+    /// Opens the log at `path` for appending, creating it if it does not exist
+    pub fn open(database: Weak<DuckDB>, fs: Arc<UnifiedFileSystem>, path: &Path) -> Result<Self> {
+        let writer = open_writer(fs, path)?;
+        Ok(WriteAheadLog { initialized: true, database, path: path.to_path_buf(), writer })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn writer(&mut self) -> &mut BufferedFileWriter {
+        &mut self.writer
+    }
+
+    /// Flushes the log and syncs it to disk; called when a transaction commits
+    pub fn sync(&mut self) -> Result<()> {
+        self.writer.sync()
+    }
+
+    /// Empties the log once a checkpoint has written its changes to the database file.
+    /// The log is replaced by an empty file, see `UnifiedFileSystem::replace_file`, so
+    /// that a crash leaves either the complete old log or the empty one.
+    pub fn reset(&mut self) -> Result<()> {
+        let fs = self.writer.file_system().clone();
+        fs.replace_file(&self.path, |_| Ok(()))?;
+        // the old writer refers to the replaced file, whose unwritten entries are
+        // covered by the checkpoint
+        self.writer = open_writer(fs, &self.path)?;
+        Ok(())
+    }
+}
+
+fn open_writer(fs: Arc<UnifiedFileSystem>, path: &Path) -> Result<Box<BufferedFileWriter>> {
+    let writer = BufferedFileWriter::new(fs, path, FileFlags::CREATE)?;
+    writer.handle().set_file_pointer(writer.handle().file_size()?)?;
+    Ok(Box::new(writer))
+}
@@ -0,0 +1,60 @@
+mod common;
+
+use std::io::ErrorKind;
+use std::path::Path;
+
+use carapacedb::common::file_system::adapter_fs::{DynFileSystemAdapter, SFileSystemAdapter};
+use carapacedb::common::file_system::fault_fs::{FaultInjectionConfig, FaultInjectionFileSystem};
+use carapacedb::common::file_system::memory_fs::MemoryFileSystem;
+use carapacedb::common::file_system::static_fs::{LocalFileSystem, SFileSystem};
+use carapacedb::common::file_system::{FileFlags, FileLockType, UnifiedFileSystem};
+
+use common::test_directory;
+
+const CREATE: FileFlags = FileFlags::WRITE.union(FileFlags::CREATE);
+
+#[test]
+fn local_files_go_through_the_adapter() {
+    let directory = test_directory("adapter", "local");
+    let path = directory.join("f");
+    let fs = UnifiedFileSystem::Local(LocalFileSystem);
+    let mut handle = fs.open_file(&path, CREATE, None).unwrap();
+    handle.write(b"hello", 5).unwrap();
+    handle.write_at(b"XY", 2, 1).unwrap();
+    assert_eq!(handle.file_size().unwrap(), 5);
+    handle.close().unwrap();
+
+    let handle = fs.open_file(&path, FileFlags::READ | FileFlags::MEMORY_MAP, None).unwrap();
+    assert_eq!(handle.mapped_slice(1, 4).unwrap(), b"XYlo");
+    assert!(handle.mapped_slice(1, 5).is_none());
+    let mut buffer = [0u8; 5];
+    handle.read(&mut buffer, 5).unwrap();
+    assert_eq!(&buffer, b"hXYlo");
+    assert_eq!(handle.read(&mut buffer, 1).unwrap_err().kind(), ErrorKind::UnexpectedEof);
+    std::fs::remove_dir_all(&directory).unwrap();
+}
+
+#[test]
+fn wrappers_become_plugins() {
+    let memory = MemoryFileSystem::new();
+    let faults = FaultInjectionFileSystem::new(memory.clone(), FaultInjectionConfig::default());
+    let fs = UnifiedFileSystem::Plugin(DynFileSystemAdapter::boxed(faults.clone()));
+    let handle = fs.open_file(Path::new("f"), CREATE, None).unwrap();
+    handle.write_at(b"abcd", 4, 0).unwrap();
+    assert_eq!(handle.file_size().unwrap(), 4);
+    assert!(faults.has_unsynced_writes());
+    handle.fsync().unwrap();
+    assert!(!faults.has_unsynced_writes());
+    assert!(memory.file_exists(Path::new("f")).unwrap());
+}
+
+#[test]
+fn plugins_become_static_file_systems() {
+    let fs = SFileSystemAdapter::new(DynFileSystemAdapter::boxed(MemoryFileSystem::new()));
+    let handle = fs.open_file(Path::new("f"), CREATE, FileLockType::NoLock).unwrap();
+    assert_eq!(fs.write(&handle, b"abc", 3).unwrap(), 3);
+    let mut buffer = [0u8; 3];
+    fs.read_at(&handle, &mut buffer, 3, 0).unwrap();
+    assert_eq!(&buffer, b"abc");
+    assert_eq!(fs.read(&handle, &mut buffer, 3).unwrap_err().kind(), ErrorKind::UnexpectedEof);
+}
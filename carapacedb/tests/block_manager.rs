@@ -0,0 +1,262 @@
+mod common;
+
+use std::io::ErrorKind;
+use std::os::unix::fs::{FileExt, MetadataExt};
+use std::path::Path;
+use std::sync::Weak;
+
+use carapacedb::storage::block::Block;
+use carapacedb::storage::block_manager::BlockManager;
+use carapacedb::storage::single_file_block_manager::SingleFileBlockManager;
+use carapacedb::storage::storage_manager::{wal_path, StorageManager};
+use carapacedb::common::serializer::Serializer;
+use carapacedb::storage::storage_info::{DatabaseHeader, BLOCK_SIZE, HEADER_SIZE, INVALID_BLOCK};
+
+use common::{local_file_system, test_directory};
+
+fn header(meta_block: i64) -> DatabaseHeader {
+    DatabaseHeader { iteration: 0, meta_block, free_list: INVALID_BLOCK, block_count: 0 }
+}
+
+fn open(path: &Path, read_only: bool) -> std::io::Result<SingleFileBlockManager> {
+    SingleFileBlockManager::new(local_file_system(), path, read_only, false, false)
+}
+
+#[test]
+fn blocks_survive_reopening() {
+    let directory = test_directory("block_manager", "reopen");
+    let path = directory.join("db");
+    {
+        let mut manager = SingleFileBlockManager::new(local_file_system(), &path, false, true, false).unwrap();
+        assert_eq!(manager.get_meta_block(), INVALID_BLOCK);
+        for value in 1..=3u8 {
+            let mut block = manager.create_block();
+            block.buffer_mut()[0] = value;
+            manager.write(&mut block).unwrap();
+        }
+        manager.write_header(&header(2)).unwrap();
+    }
+
+    let manager = open(&path, true).unwrap();
+    assert_eq!(manager.get_meta_block(), 2);
+    assert_eq!(manager.block_count(), 3);
+    let mut block = Block::new(2);
+    manager.read(&mut block).unwrap();
+    assert_eq!(block.buffer()[0], 3);
+    assert_eq!(manager.write(&mut block).unwrap_err().kind(), ErrorKind::PermissionDenied);
+    assert!(manager.read(&mut Block::new(9)).is_err());
+    std::fs::remove_dir_all(&directory).unwrap();
+}
+
+#[test]
+fn headers_alternate_and_fall_back_when_corrupt() {
+    let directory = test_directory("block_manager", "alternate");
+    let path = directory.join("db");
+    {
+        let mut manager = SingleFileBlockManager::new(local_file_system(), &path, false, true, false).unwrap();
+        let mut block = manager.create_block();
+        manager.write(&mut block).unwrap();
+        manager.write_header(&header(0)).unwrap();
+        manager.write_header(&header(INVALID_BLOCK)).unwrap();
+    }
+    assert_eq!(open(&path, true).unwrap().get_meta_block(), INVALID_BLOCK);
+
+    // the second header went to the first slot, so corrupting it falls back to the first
+    let file = std::fs::OpenOptions::new().read(true).write(true).open(&path).unwrap();
+    let mut byte = [0u8];
+    file.read_at(&mut byte, HEADER_SIZE as u64 + 20).unwrap();
+    file.write_at(&[byte[0] ^ 0xff], HEADER_SIZE as u64 + 20).unwrap();
+    assert_eq!(open(&path, true).unwrap().get_meta_block(), 0);
+
+    // without a valid main header the file is not a database
+    file.write_at(b"XXXX", 8).unwrap();
+    assert_eq!(open(&path, true).err().unwrap().kind(), ErrorKind::InvalidData);
+    std::fs::remove_dir_all(&directory).unwrap();
+}
+
+#[test]
+fn free_list_round_trips() {
+    let directory = test_directory("block_manager", "free_list");
+    let path = directory.join("db");
+    {
+        let mut manager = SingleFileBlockManager::new(local_file_system(), &path, false, true, false).unwrap();
+        for _ in 0..4 {
+            let mut block = manager.create_block();
+            manager.write(&mut block).unwrap();
+        }
+        manager.write_header(&header(0)).unwrap();
+        manager.mark_block_as_modified(1);
+        manager.mark_block_as_modified(2);
+        manager.write_header(&header(3)).unwrap();
+        assert_eq!(manager.free_list(), vec![1, 2]);
+    }
+
+    let manager = open(&path, false).unwrap();
+    assert_eq!(manager.get_meta_block(), 3);
+    assert_eq!(manager.free_list(), vec![1, 2]);
+    assert_eq!(manager.get_free_block_id(), 1);
+    std::fs::remove_dir_all(&directory).unwrap();
+}
+
+#[test]
+fn create_new_keeps_an_existing_database() {
+    let directory = test_directory("block_manager", "create_new");
+    let path = directory.join("db");
+    {
+        let manager = SingleFileBlockManager::new(local_file_system(), &path, false, true, false).unwrap();
+        manager.write_header(&header(INVALID_BLOCK)).unwrap();
+    }
+    let size = std::fs::metadata(&path).unwrap().len();
+
+    let error = SingleFileBlockManager::new(local_file_system(), &path, false, true, false).err().unwrap();
+    assert_eq!(error.kind(), ErrorKind::AlreadyExists);
+    assert_eq!(std::fs::metadata(&path).unwrap().len(), size);
+    assert!(open(&path, true).is_ok());
+    std::fs::remove_dir_all(&directory).unwrap();
+}
+
+#[test]
+fn invalid_files_are_rejected() {
+    let directory = test_directory("block_manager", "invalid");
+    let path = directory.join("db");
+    {
+        let manager = SingleFileBlockManager::new(local_file_system(), &path, false, true, false).unwrap();
+        manager.write_header(&header(100)).unwrap();
+    }
+    assert_eq!(open(&path, true).err().unwrap().kind(), ErrorKind::InvalidData);
+
+    let small = directory.join("small");
+    std::fs::write(&small, b"abc").unwrap();
+    assert!(open(&small, true).is_err());
+    std::fs::remove_dir_all(&directory).unwrap();
+}
+
+#[test]
+fn new_files_are_created_through_a_temporary_file() {
+    let directory = test_directory("block_manager", "create_temporary");
+    let path = directory.join("db");
+    // an empty file, e.g. left by a crash before the headers were written, is initialized
+    std::fs::write(&path, b"").unwrap();
+    std::fs::write(directory.join("db.tmp"), b"stale").unwrap();
+    SingleFileBlockManager::new(local_file_system(), &path, false, true, false).unwrap();
+    assert!(!directory.join("db.tmp").exists());
+    assert_eq!(open(&path, true).unwrap().get_meta_block(), INVALID_BLOCK);
+    std::fs::remove_dir_all(&directory).unwrap();
+}
+
+#[test]
+fn checkpoints_empty_the_wal() {
+    let directory = test_directory("block_manager", "checkpoint");
+    let path = directory.join("db");
+    let mut storage = StorageManager::open(Weak::new(), local_file_system(), &path, false, true).unwrap();
+    let wal = storage.wal().unwrap();
+    wal.writer().write_data(b"entry").unwrap();
+    wal.sync().unwrap();
+    assert_eq!(std::fs::read(wal_path(&path)).unwrap(), b"entry");
+
+    storage.checkpoint(&header(INVALID_BLOCK)).unwrap();
+    assert_eq!(std::fs::metadata(wal_path(&path)).unwrap().len(), 0);
+    assert!(!directory.join("db.wal.tmp").exists());
+    let wal = storage.wal().unwrap();
+    wal.writer().write_data(b"next").unwrap();
+    wal.sync().unwrap();
+    drop(storage);
+
+    // reopening appends to the log
+    let mut storage = StorageManager::open(Weak::new(), local_file_system(), &path, false, false).unwrap();
+    let wal = storage.wal().unwrap();
+    wal.writer().write_data(b"!").unwrap();
+    wal.sync().unwrap();
+    assert_eq!(std::fs::read(wal_path(&path)).unwrap(), b"next!");
+    drop(storage);
+
+    let mut storage = StorageManager::open(Weak::new(), local_file_system(), &path, true, false).unwrap();
+    assert!(storage.wal().is_none());
+    assert_eq!(storage.block_manager().get_meta_block(), INVALID_BLOCK);
+    std::fs::remove_dir_all(&directory).unwrap();
+}
+
+#[cfg(target_os = "linux")]
+#[test]
+fn growth_is_preallocated_and_free_space_is_given_back() {
+    let directory = test_directory("block_manager", "space");
+    let path = directory.join("db");
+    let file_size = |blocks: u64| 3 * HEADER_SIZE as u64 + blocks * BLOCK_SIZE as u64;
+    let allocated = || std::fs::metadata(&path).unwrap().blocks() * 512;
+    let mut manager = SingleFileBlockManager::new(local_file_system(), &path, false, true, false).unwrap();
+    let mut blocks: Vec<Block> = (0..6).map(|_| *manager.create_block()).collect();
+
+    // writing the last block preallocates the ones before it
+    manager.write(&mut blocks[5]).unwrap();
+    assert_eq!(std::fs::metadata(&path).unwrap().len(), file_size(6));
+    assert!(allocated() >= 6 * BLOCK_SIZE as u64);
+    for block in &mut blocks[..5] {
+        manager.write(block).unwrap();
+    }
+    manager.write_header(&header(0)).unwrap();
+
+    // freed blocks are punched, leaving blocks 0 and 3 and the free list, which is
+    // appended as block 6
+    for block_id in [1, 2, 4, 5] {
+        manager.mark_block_as_modified(block_id);
+    }
+    manager.write_header(&header(0)).unwrap();
+    assert_eq!(manager.block_count(), 7);
+    assert!(allocated() < 4 * BLOCK_SIZE as u64);
+
+    // free blocks at the end are cut off once the active header no longer uses them,
+    // which for block 6 takes one more header
+    manager.write_header(&header(0)).unwrap();
+    assert_eq!(manager.block_count(), 7);
+    manager.write_header(&header(0)).unwrap();
+    assert_eq!(manager.block_count(), 4);
+    assert_eq!(std::fs::metadata(&path).unwrap().len(), file_size(4));
+    drop(manager);
+
+    let manager = open(&path, false).unwrap();
+    assert_eq!(manager.block_count(), 4);
+    assert_eq!(manager.get_free_block_id(), 1);
+    let mut block = Block::new(3);
+    manager.read(&mut block).unwrap();
+    std::fs::remove_dir_all(&directory).unwrap();
+}
+
+#[test]
+fn read_only_blocks_borrow_the_mapping() {
+    let directory = test_directory("block_manager", "mapped");
+    let path = directory.join("db");
+    {
+        let mut manager = SingleFileBlockManager::new(local_file_system(), &path, false, true, false).unwrap();
+        for value in 1..=2u8 {
+            let mut block = manager.create_block();
+            block.buffer_mut().fill(value);
+            manager.write(&mut block).unwrap();
+        }
+        manager.write_header(&header(0)).unwrap();
+        // writable files are not mapped
+        assert!(manager.read_mapped(0).unwrap().is_none());
+    }
+
+    let manager = open(&path, true).unwrap();
+    let block = manager.read_mapped(1).unwrap().unwrap();
+    assert_eq!(block.block_id, 1);
+    assert!(block.buffer().iter().all(|byte| *byte == 2));
+    let mut copied = Block::new(1);
+    manager.read(&mut copied).unwrap();
+    assert_eq!(copied.buffer(), block.buffer());
+    assert_eq!(manager.read_mapped(2).err().unwrap().kind(), ErrorKind::InvalidInput);
+
+    // the checksum of mapped blocks is verified
+    let file = std::fs::OpenOptions::new().write(true).open(&path).unwrap();
+    file.write_at(&[0xff], 3 * HEADER_SIZE as u64 + 100).unwrap();
+    assert_eq!(manager.read_mapped(0).err().unwrap().kind(), ErrorKind::InvalidData);
+    drop(manager);
+
+    // with direct I/O the file is not mapped, and blocks are read with pread
+    let manager = SingleFileBlockManager::new(local_file_system(), &path, true, false, true).unwrap();
+    assert!(manager.read_mapped(1).unwrap().is_none());
+    let mut block = Block::new(1);
+    manager.read(&mut block).unwrap();
+    assert!(block.buffer().iter().all(|byte| *byte == 2));
+    std::fs::remove_dir_all(&directory).unwrap();
+}
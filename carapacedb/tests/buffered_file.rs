@@ -0,0 +1,110 @@
+mod common;
+
+use std::io::ErrorKind;
+
+use carapacedb::common::buffered_file_reader::BufferedFileReader;
+use carapacedb::common::buffered_file_writer::BufferedFileWriter;
+use carapacedb::common::file_system::FileFlags;
+use carapacedb::common::serializer::{Deserializer, Serializer};
+
+use common::{local_file_system, test_directory};
+
+#[test]
+fn written_data_reads_back() {
+    let directory = test_directory("buffered_file", "round_trip");
+    let path = directory.join("f");
+    let fs = local_file_system();
+    let mut writer = BufferedFileWriter::new(fs.clone(), &path, FileFlags::CREATE).unwrap();
+    for i in 0..3000u32 {
+        writer.write::<u32>(i).unwrap();
+    }
+    writer.write_string("hello").unwrap();
+    writer.write_data(&[7u8; 10_000]).unwrap();
+    assert_eq!(writer.total_written(), 12_000 + 6 + 10_000);
+    writer.sync().unwrap();
+    assert_eq!(writer.file_size().unwrap(), 22_006);
+    drop(writer);
+
+    let mut reader = BufferedFileReader::new(fs, &path, FileFlags::empty()).unwrap();
+    for i in 0..3000u32 {
+        assert_eq!(reader.read::<u32>().unwrap(), i);
+    }
+    assert_eq!(reader.read_string().unwrap(), "hello");
+    let mut data = vec![0u8; 10_000];
+    reader.read_data(&mut data).unwrap();
+    assert!(data.iter().all(|&b| b == 7));
+    assert!(reader.finished());
+    std::fs::remove_dir_all(&directory).unwrap();
+}
+
+#[test]
+fn truncate_stays_within_the_file() {
+    let directory = test_directory("buffered_file", "truncate");
+    let path = directory.join("f");
+    let fs = local_file_system();
+    let mut writer = BufferedFileWriter::new(fs.clone(), &path, FileFlags::CREATE).unwrap();
+    writer.write_data(&[1u8; 5000]).unwrap();
+    writer.flush().unwrap();
+    writer.write::<u64>(2).unwrap();
+    assert_eq!(writer.truncate(5009).unwrap_err().kind(), ErrorKind::InvalidInput);
+    assert_eq!(writer.file_size().unwrap(), 5008);
+
+    // into the pending buffer, then into the flushed file
+    writer.truncate(5004).unwrap();
+    assert_eq!(writer.file_size().unwrap(), 5004);
+    writer.truncate(100).unwrap();
+    writer.write::<u32>(99).unwrap();
+    drop(writer);
+
+    let mut reader = BufferedFileReader::new(fs, &path, FileFlags::empty()).unwrap();
+    assert_eq!(reader.file_size(), 104);
+    reader.seek(100).unwrap();
+    assert_eq!(reader.read::<u32>().unwrap(), 99);
+    std::fs::remove_dir_all(&directory).unwrap();
+}
+
+#[test]
+fn reads_past_the_end_fail() {
+    let directory = test_directory("buffered_file", "eof");
+    let path = directory.join("f");
+    std::fs::write(&path, [1u8, 2, 3, 4, 5]).unwrap();
+    let mut reader = BufferedFileReader::new(local_file_system(), &path, FileFlags::empty()).unwrap();
+    assert_eq!(reader.remaining(), Some(5));
+    assert_eq!(reader.read::<u32>().unwrap(), u32::from_le_bytes([1, 2, 3, 4]));
+    assert_eq!(reader.read::<u32>().unwrap_err().kind(), ErrorKind::UnexpectedEof);
+    // the failed read consumed nothing
+    assert_eq!(reader.position(), 4);
+    assert_eq!(reader.read::<u8>().unwrap(), 5);
+    assert!(reader.finished());
+    assert_eq!(reader.read::<u8>().unwrap_err().kind(), ErrorKind::UnexpectedEof);
+    assert_eq!(reader.seek(6).unwrap_err().kind(), ErrorKind::UnexpectedEof);
+    reader.seek(5).unwrap();
+    std::fs::remove_dir_all(&directory).unwrap();
+}
+
+#[test]
+fn seeks_reuse_or_refill_the_buffer() {
+    let directory = test_directory("buffered_file", "seek");
+    let path = directory.join("f");
+    let data: Vec<u8> = (0..10_000u32).map(|i| (i % 251) as u8).collect();
+    std::fs::write(&path, &data).unwrap();
+    let mut reader = BufferedFileReader::new(local_file_system(), &path, FileFlags::empty()).unwrap();
+
+    // a read across the end of the first buffer refills it
+    reader.seek(4090).unwrap();
+    let mut chunk = [0u8; 20];
+    reader.read_data(&mut chunk).unwrap();
+    assert_eq!(&chunk[..], &data[4090..4110]);
+    // within the buffered data, then before and after it
+    for location in [4100, 4096, 10, 9000, 0] {
+        reader.seek(location).unwrap();
+        assert_eq!(reader.position(), location);
+        reader.read_data(&mut chunk).unwrap();
+        assert_eq!(&chunk[..], &data[location as usize..location as usize + 20]);
+    }
+    reader.seek(9990).unwrap();
+    reader.read_data(&mut chunk[..10]).unwrap();
+    assert_eq!(&chunk[..10], &data[9990..]);
+    assert!(reader.finished());
+    std::fs::remove_dir_all(&directory).unwrap();
+}
@@ -0,0 +1,118 @@
+mod common;
+
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+
+use carapacedb::common::file_system::adapter_fs::DynFileSystemAdapter;
+use carapacedb::common::file_system::cache_fs::{DiskCache, DiskCacheConfig};
+use carapacedb::common::file_system::dynamic_fs::BoxedFileSystem;
+use carapacedb::common::file_system::memory_fs::MemoryFileSystem;
+use carapacedb::common::file_system::static_fs::LocalFileSystem;
+use carapacedb::common::file_system::{FileFlags, UnifiedFileSystem};
+use carapacedb::core::database::DBConfig;
+
+use common::test_directory;
+
+fn cache_config(directory: &Path, scheme: &str) -> DiskCacheConfig {
+    DiskCacheConfig {
+        directory: directory.to_path_buf(),
+        block_size: 100,
+        max_size: 1000,
+        schemes: vec![scheme.to_string()],
+    }
+}
+
+fn cached_file_system(cache: &Arc<DiskCache>, scheme: &str, fs: BoxedFileSystem) -> UnifiedFileSystem {
+    let mut config = DBConfig { disk_cache: Some(cache.clone()), ..Default::default() };
+    config.file_systems.push((scheme.to_string(), fs));
+    config.create_file_system().unwrap()
+}
+
+#[test]
+fn blocks_are_cached_evicted_and_reloaded() {
+    let directory = test_directory("cache", "blocks");
+    let config = cache_config(&directory, "mem");
+    let cache = Arc::new(DiskCache::open(config.clone()).unwrap());
+    let fs = cached_file_system(&cache, "mem", DynFileSystemAdapter::boxed(MemoryFileSystem::new()));
+
+    let data: Vec<u8> = (0..550u32).map(|i| i as u8).collect();
+    let path = Path::new("mem://f.bin");
+    fs.open_file(path, FileFlags::WRITE | FileFlags::CREATE, None).unwrap().write_at(&data, 550, 0).unwrap();
+    let handle = fs.open_file(path, FileFlags::READ, None).unwrap();
+    let mut buffer = vec![0u8; 250];
+    handle.read_at(&mut buffer, 250, 120).unwrap();
+    assert_eq!(&buffer[..], &data[120..370]);
+    let statistics = cache.statistics();
+    assert_eq!((statistics.hits, statistics.misses, statistics.size), (0, 3, 300));
+    handle.read_at(&mut buffer, 250, 120).unwrap();
+    assert_eq!(&buffer[..], &data[120..370]);
+    assert_eq!(cache.statistics().hits, 3);
+    drop(handle);
+
+    let entries = cache.entries();
+    assert_eq!(entries.len(), 1);
+    assert_eq!((entries[0].blocks, entries[0].cached_bytes, entries[0].file_size), (3, 300, 550));
+    assert_eq!(DiskCache::open(config).unwrap().entries(), entries);
+
+    let big = vec![9u8; 1000];
+    let big_path = Path::new("mem://g.bin");
+    fs.open_file(big_path, FileFlags::WRITE | FileFlags::CREATE, None).unwrap().write_at(&big, 1000, 0).unwrap();
+    let mut read = vec![0u8; 1000];
+    fs.open_file(big_path, FileFlags::READ, None).unwrap().read_at(&mut read, 1000, 0).unwrap();
+    assert_eq!(read, big);
+    assert_eq!(cache.statistics().size, 1000);
+    assert_eq!(cache.entries().iter().map(|entry| entry.path.clone()).collect::<Vec<_>>(), vec![big_path.to_path_buf()]);
+
+    cache.clear().unwrap();
+    assert!(cache.entries().is_empty());
+    assert_eq!(std::fs::read_dir(&directory).unwrap().count(), 0);
+    std::fs::remove_dir_all(&directory).unwrap();
+}
+
+#[test]
+fn same_size_edit_of_a_local_file_is_not_served_stale() {
+    let directory = test_directory("cache", "local");
+    let cache = Arc::new(DiskCache::open(cache_config(&directory.join("cache"), "disk")).unwrap());
+    let fs = cached_file_system(&cache, "disk", DynFileSystemAdapter::boxed(LocalFileSystem));
+
+    let file = directory.join("data.bin");
+    std::fs::write(&file, b"aaaaaaaa").unwrap();
+    let path = PathBuf::from(format!("disk://{}", file.display()));
+    let mut buffer = [0u8; 8];
+    fs.open_file(&path, FileFlags::READ, None).unwrap().read_at(&mut buffer, 8, 0).unwrap();
+    assert_eq!(&buffer, b"aaaaaaaa");
+
+    // rewritten in place behind the back of the cache, with a new modification time
+    std::fs::write(&file, b"bbbbbbbb").unwrap();
+    let modified = SystemTime::now() + Duration::from_secs(10);
+    std::fs::File::options().write(true).open(&file).unwrap().set_modified(modified).unwrap();
+    fs.open_file(&path, FileFlags::READ, None).unwrap().read_at(&mut buffer, 8, 0).unwrap();
+    assert_eq!(&buffer, b"bbbbbbbb");
+    std::fs::remove_dir_all(&directory).unwrap();
+}
+
+#[test]
+fn corrupt_meta_file_is_discarded_on_open() {
+    let directory = test_directory("cache", "corrupt");
+    std::fs::create_dir_all(&directory).unwrap();
+    std::fs::write(directory.join("0123.meta"), b"mem://f.bin\nnot a size\n").unwrap();
+    std::fs::write(directory.join("0123-0.block"), b"data").unwrap();
+    std::fs::write(directory.join("4567.meta"), [0xff, 0xfe, b'\n']).unwrap();
+
+    let cache = DiskCache::open(cache_config(&directory, "mem")).unwrap();
+    assert!(cache.entries().is_empty());
+    assert_eq!(std::fs::read_dir(&directory).unwrap().count(), 0);
+    std::fs::remove_dir_all(&directory).unwrap();
+}
+
+#[test]
+fn zero_sizes_are_rejected_on_open() {
+    let directory = test_directory("cache", "zero");
+    let config = DiskCacheConfig { block_size: 0, ..cache_config(&directory, "memory") };
+    assert_eq!(DiskCache::open(config).err().unwrap().kind(), ErrorKind::InvalidInput);
+    let config = DiskCacheConfig { max_size: 0, ..cache_config(&directory, "memory") };
+    assert_eq!(DiskCache::open(config).err().unwrap().kind(), ErrorKind::InvalidInput);
+    std::fs::remove_dir_all(&directory).unwrap();
+}
@@ -0,0 +1,127 @@
+//! Helpers shared by the integration tests, including a minimal HTTP/1.1 server
+//! standing in for remote file servers and object stores
+
+#![allow(dead_code)]
+
+use std::collections::BTreeMap;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::TcpListener;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::thread;
+
+use carapacedb::common::file_system::static_fs::LocalFileSystem;
+use carapacedb::common::file_system::UnifiedFileSystem;
+
+/// Returns an empty directory under the system temp directory that is unique to this
+/// process, the test `area` and `name`
+pub fn test_directory(area: &str, name: &str) -> PathBuf {
+    let directory = std::env::temp_dir().join(format!("carapacedb_{}_{}_{}", area, std::process::id(), name));
+    let _ = std::fs::remove_dir_all(&directory);
+    std::fs::create_dir_all(&directory).unwrap();
+    directory
+}
+
+pub fn local_file_system() -> Arc<UnifiedFileSystem> {
+    Arc::new(UnifiedFileSystem::Local(LocalFileSystem))
+}
+
+pub struct Request {
+    pub method: String,
+    /// The percent-decoded path, without the query
+    pub path: String,
+    /// The percent-decoded query parameters
+    pub query: BTreeMap<String, String>,
+    /// Header names are lowercase
+    pub headers: BTreeMap<String, String>,
+    pub body: Vec<u8>,
+}
+
+pub struct Response {
+    pub status: u16,
+    pub headers: Vec<(String, String)>,
+    pub body: Vec<u8>,
+}
+
+impl Response {
+    pub fn new(status: u16) -> Self {
+        Response { status, headers: Vec::new(), body: Vec::new() }
+    }
+
+    pub fn with_body(status: u16, body: impl Into<Vec<u8>>) -> Self {
+        Response { status, headers: Vec::new(), body: body.into() }
+    }
+
+    pub fn header(mut self, name: &str, value: impl ToString) -> Self {
+        self.headers.push((name.to_string(), value.to_string()));
+        self
+    }
+}
+
+pub fn percent_decode(text: &str) -> String {
+    let bytes = text.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'%' && i + 2 < bytes.len() {
+            decoded.push(u8::from_str_radix(&text[i + 1..i + 3], 16).unwrap());
+            i += 3;
+        } else {
+            decoded.push(bytes[i]);
+            i += 1;
+        }
+    }
+    String::from_utf8(decoded).unwrap()
+}
+
+/// Serves requests one at a time with `handler` and returns the base URL of the server
+pub fn serve(mut handler: impl FnMut(Request) -> Response + Send + 'static) -> String {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let address = listener.local_addr().unwrap();
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            let Ok(mut stream) = stream else { continue };
+            let mut reader = BufReader::new(stream.try_clone().unwrap());
+            let mut line = String::new();
+            if reader.read_line(&mut line).unwrap_or(0) == 0 {
+                continue;
+            }
+            let mut parts = line.split_whitespace();
+            let method = parts.next().unwrap().to_string();
+            let target = parts.next().unwrap().to_string();
+
+            let mut headers = BTreeMap::new();
+            loop {
+                let mut header = String::new();
+                reader.read_line(&mut header).unwrap();
+                let Some((name, value)) = header.trim_end().split_once(':') else { break };
+                headers.insert(name.to_ascii_lowercase(), value.trim().to_string());
+            }
+            let length = headers.get("content-length").map_or(0, |length| length.parse().unwrap());
+            let mut body = vec![0; length];
+            reader.read_exact(&mut body).unwrap();
+
+            let (path, query) = target.split_once('?').unwrap_or((&target, ""));
+            let query = query.split('&')
+                .filter(|parameter| !parameter.is_empty())
+                .map(|parameter| {
+                    let (name, value) = parameter.split_once('=').unwrap_or((parameter, ""));
+                    (percent_decode(name), percent_decode(value))
+                })
+                .collect();
+            let request = Request { method: method.clone(), path: percent_decode(path), query, headers, body };
+
+            let response = handler(request);
+            let mut head = format!("HTTP/1.1 {} Status\r\n", response.status);
+            for (name, value) in &response.headers {
+                head.push_str(&format!("{}: {}\r\n", name, value));
+            }
+            if method != "HEAD" {
+                head.push_str(&format!("Content-Length: {}\r\n", response.body.len()));
+            }
+            head.push_str("\r\n");
+            let _ = stream.write_all(head.as_bytes()).and_then(|_| stream.write_all(&response.body));
+        }
+    });
+    format!("http://{}", address)
+}
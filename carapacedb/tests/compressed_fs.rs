@@ -0,0 +1,77 @@
+use std::path::Path;
+
+use carapacedb::common::file_system::adapter_fs::DynFileSystemAdapter;
+use carapacedb::common::file_system::compressed_fs::{CompressedFileSystem, FileCompression};
+use carapacedb::common::file_system::dynamic_fs::DynFileSystem;
+use carapacedb::common::file_system::memory_fs::MemoryFileSystem;
+use carapacedb::common::file_system::static_fs::SFileSystem;
+use carapacedb::common::file_system::{FileFlags, FileLockType};
+
+const CREATE: FileFlags = FileFlags::WRITE.union(FileFlags::CREATE);
+
+fn test_data() -> Vec<u8> {
+    (0..100_000u32).map(|i| (i % 251) as u8).collect()
+}
+
+fn write_file(fs: &CompressedFileSystem, path: &Path, flags: FileFlags, data: &[u8]) {
+    let mut handle = fs.open_file(path, flags, None).unwrap();
+    fs.write(handle.as_ref(), data, data.len() as i64).unwrap();
+    handle.close().unwrap();
+}
+
+fn read_file(fs: &CompressedFileSystem, path: &Path) -> Vec<u8> {
+    let handle = fs.open_file(path, FileFlags::READ, None).unwrap();
+    let size = fs.file_size(handle.as_ref()).unwrap() as usize;
+    let mut buffer = vec![0u8; size];
+    fs.read_at(handle.as_ref(), &mut buffer, size as i64, 0).unwrap();
+    buffer
+}
+
+#[test]
+fn files_round_trip() {
+    let memory = MemoryFileSystem::new();
+    let fs = CompressedFileSystem::new(DynFileSystemAdapter::boxed(memory.clone()), FileCompression::Auto);
+    let data = test_data();
+    for name in ["a.gz", "b.zst", "c.bin"] {
+        let path = Path::new(name);
+        let mut handle = fs.open_file(path, CREATE, None).unwrap();
+        fs.write(handle.as_ref(), &data[..50_000], 50_000).unwrap();
+        fs.write_at(handle.as_ref(), &data[50_000..], 50_000, 50_000).unwrap();
+        if name == "c.bin" {
+            fs.write_at(handle.as_ref(), &data[5..6], 1, 5).unwrap();
+        } else {
+            assert!(fs.write_at(handle.as_ref(), b"x", 1, 5).is_err());
+        }
+        handle.close().unwrap();
+
+        let handle = fs.open_file(path, FileFlags::READ, None).unwrap();
+        assert_eq!(fs.file_size(handle.as_ref()).unwrap(), 100_000);
+        let mut buffer = vec![0u8; 1000];
+        fs.read_at(handle.as_ref(), &mut buffer, 1000, 70_000).unwrap();
+        assert_eq!(&buffer[..], &data[70_000..71_000]);
+        fs.read_at(handle.as_ref(), &mut buffer, 1000, 10).unwrap();
+        assert_eq!(&buffer[..], &data[10..1010]);
+        if name != "c.bin" {
+            fs.read(handle.as_ref(), &mut buffer, 1000).unwrap();
+            assert_eq!(&buffer[..], &data[1010..2010]);
+            assert!(fs.read_at(handle.as_ref(), &mut buffer, 1000, 99_500).is_err());
+        }
+    }
+
+    let handle = memory.open_file(Path::new("a.gz"), FileFlags::READ, FileLockType::NoLock).unwrap();
+    assert!(memory.file_size(&handle).unwrap() < 100_000);
+}
+
+#[test]
+fn existing_files_are_rewritten() {
+    let fs = CompressedFileSystem::new(DynFileSystemAdapter::boxed(MemoryFileSystem::new()), FileCompression::Auto);
+    let data = test_data();
+    for name in ["a.gz", "b.zst"] {
+        let path = Path::new(name);
+        write_file(&fs, path, CREATE, &data);
+        write_file(&fs, path, FileFlags::WRITE, b"short");
+        assert_eq!(read_file(&fs, path), b"short");
+        write_file(&fs, path, CREATE, &data[..10]);
+        assert_eq!(read_file(&fs, path), &data[..10]);
+    }
+}
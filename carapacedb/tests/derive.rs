@@ -0,0 +1,117 @@
+use std::io::{ErrorKind, Result};
+
+use carapacedb::common::buffered_serializer::{BufferedDeserializer, BufferedSerializer};
+use carapacedb::common::catalog_type::CatalogType;
+use carapacedb::common::json_serializer::{from_json, to_json};
+use carapacedb::common::serializer::{Deserializable, Serializable, Serializer};
+use carapacedb::parser::parsed_data::alter_table_info::RenameColumnInfo;
+
+#[derive(Serializable, Deserializable, Debug, PartialEq, Default)]
+struct Evolved {
+    #[serialize(id = 1)]
+    a: u32,
+    #[serialize(id = 5, default)]
+    b: Vec<String>,
+    #[serialize(skip)]
+    c: u8,
+    #[serialize(id = 2)]
+    d: Option<u64>,
+}
+
+#[derive(Serializable, Deserializable, Debug, PartialEq)]
+struct Wrapper<T> {
+    #[serialize(id = 1)]
+    value: T,
+    #[serialize(id = 2)]
+    values: Vec<T>,
+}
+
+#[derive(Serializable, Deserializable, Debug, PartialEq)]
+enum Alter<T> {
+    #[serialize(id = 1)]
+    Rename {
+        #[serialize(id = 1)]
+        old_name: String,
+        #[serialize(id = 2)]
+        new_name: String,
+        #[serialize(skip)]
+        cached: u8,
+    },
+    #[serialize(id = 2)]
+    SetDefault(#[serialize(id = 1)] T, #[serialize(id = 2, default)] bool),
+    #[serialize(id = 7)]
+    Drop,
+}
+
+fn to_binary<T: Serializable>(value: &T) -> Vec<u8> {
+    let mut serializer = BufferedSerializer::new();
+    value.serialize(&mut serializer).unwrap();
+    serializer.into_data()
+}
+
+fn from_binary<T: Deserializable>(data: &[u8]) -> Result<T> {
+    let mut deserializer = BufferedDeserializer::new(data);
+    T::deserialize(&mut deserializer)
+}
+
+#[test]
+fn structs_round_trip() {
+    let evolved = Evolved { a: 1, b: vec![], c: 9, d: Some(3) };
+    assert_eq!(from_binary::<Evolved>(&to_binary(&evolved)).unwrap(), Evolved { c: 0, ..evolved });
+
+    let info = RenameColumnInfo::new("s", "t", "a", "b");
+    assert_eq!(from_binary::<RenameColumnInfo>(&to_binary(&info)).unwrap(), info);
+    assert_eq!(from_json::<RenameColumnInfo>(&to_json(&info).unwrap()).unwrap(), info);
+}
+
+#[test]
+fn generic_types_round_trip() {
+    let wrapper = Wrapper { value: Some(-1i32), values: vec![None, Some(2)] };
+    assert_eq!(from_binary::<Wrapper<Option<i32>>>(&to_binary(&wrapper)).unwrap(), wrapper);
+    assert_eq!(from_json::<Wrapper<Option<i32>>>(&to_json(&wrapper).unwrap()).unwrap(), wrapper);
+}
+
+#[test]
+fn enums_with_fields_round_trip() {
+    let values = [
+        Alter::Rename { old_name: "a".to_string(), new_name: "b".to_string(), cached: 0 },
+        Alter::SetDefault(Wrapper { value: 5u8, values: vec![] }, true),
+        Alter::SetDefault(Wrapper { value: 6u8, values: vec![1] }, false),
+        Alter::Drop,
+    ];
+    for value in &values {
+        let binary = to_binary(value);
+        assert_eq!(&from_binary::<Alter<Wrapper<u8>>>(&binary).unwrap(), value);
+        let back: Alter<Wrapper<u8>> = from_json(&to_json(value).unwrap()).unwrap();
+        assert_eq!(&back, value);
+        assert_eq!(to_binary(&back), binary);
+    }
+    assert_eq!(to_binary(&Alter::<u8>::Drop), [7, 0]);
+
+    let renamed = Alter::<u8>::Rename { old_name: "a".to_string(), new_name: "b".to_string(), cached: 3 };
+    let Alter::Rename { cached, .. } = from_binary::<Alter<u8>>(&to_binary(&renamed)).unwrap() else {
+        panic!("expected a rename");
+    };
+    assert_eq!(cached, 0);
+}
+
+#[test]
+fn invalid_discriminants_are_rejected() {
+    assert_eq!(from_binary::<Alter<u8>>(&[3, 0]).unwrap_err().kind(), ErrorKind::InvalidData);
+    assert_eq!(from_binary::<CatalogType>(&[9]).unwrap_err().kind(), ErrorKind::InvalidData);
+    assert!(matches!(from_binary::<CatalogType>(&to_binary(&CatalogType::Sequence)).unwrap(), CatalogType::Sequence));
+
+    // a rename without its required fields
+    let mut serializer = BufferedSerializer::new();
+    serializer.write::<u16>(1).unwrap();
+    serializer.begin_object().unwrap();
+    serializer.end_object().unwrap();
+    let mut deserializer = BufferedDeserializer::new(serializer.data());
+    assert_eq!(Alter::<u8>::deserialize(&mut deserializer).unwrap_err().kind(), ErrorKind::InvalidData);
+}
+
+#[test]
+fn invalid_derives_fail_to_compile() {
+    let cases = trybuild::TestCases::new();
+    cases.compile_fail("tests/ui/*.rs");
+}
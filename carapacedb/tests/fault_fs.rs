@@ -0,0 +1,145 @@
+mod common;
+
+use std::io::ErrorKind;
+use std::path::Path;
+use std::sync::Arc;
+
+use carapacedb::common::file_system::adapter_fs::DynFileSystemAdapter;
+use carapacedb::common::file_system::fault_fs::{FaultInjectionConfig, FaultInjectionFileSystem, InjectedFault};
+use carapacedb::common::file_system::memory_fs::MemoryFileSystem;
+use carapacedb::common::file_system::static_fs::{LocalFileSystem, SFileSystem};
+use carapacedb::common::file_system::{FileFlags, FileLockType, UnifiedFileSystem};
+use carapacedb::storage::block_manager::BlockManager;
+use carapacedb::storage::single_file_block_manager::SingleFileBlockManager;
+use carapacedb::storage::storage_info::{DatabaseHeader, INVALID_BLOCK};
+
+use common::test_directory;
+
+const CREATE: FileFlags = FileFlags::WRITE.union(FileFlags::CREATE);
+
+fn read_all<FS: SFileSystem>(fs: &FS, path: &Path) -> Vec<u8> {
+    let handle = fs.open_file(path, FileFlags::READ, FileLockType::NoLock).unwrap();
+    let size = fs.file_size(&handle).unwrap() as usize;
+    let mut buffer = vec![0u8; size];
+    fs.read_at(&handle, &mut buffer, size as i64, 0).unwrap();
+    buffer
+}
+
+#[test]
+fn unsynced_writes_are_lost_on_power_loss() {
+    let memory = MemoryFileSystem::new();
+    let fs = FaultInjectionFileSystem::new(memory.clone(), FaultInjectionConfig::default());
+    let handle = fs.open_file(Path::new("f"), CREATE, FileLockType::NoLock).unwrap();
+    fs.write_at(&handle, b"abcd", 4, 0).unwrap();
+    fs.fsync(&handle).unwrap();
+    fs.write_at(&handle, b"XY", 2, 1).unwrap();
+    fs.write_at(&handle, b"ZZ", 2, 6).unwrap();
+    assert_eq!(fs.file_size(&handle).unwrap(), 8);
+    let mut buffer = [9u8; 8];
+    fs.read_at(&handle, &mut buffer, 8, 0).unwrap();
+    assert_eq!(&buffer, b"aXYd\0\0ZZ");
+    assert_eq!(read_all(&memory, Path::new("f")), b"abcd");
+
+    fs.inject(InjectedFault::FsyncFailure);
+    assert!(fs.fsync(&handle).is_err());
+    assert!(fs.has_unsynced_writes());
+    fs.simulate_power_loss().unwrap();
+    assert!(!fs.has_unsynced_writes());
+    assert_eq!(fs.file_size(&handle).unwrap(), 4);
+
+    fs.inject(InjectedFault::ShortWrite);
+    assert_eq!(fs.write_at(&handle, b"hello", 5, 0).unwrap_err().kind(), ErrorKind::WriteZero);
+}
+
+#[test]
+fn torn_writes_keep_whole_sectors() {
+    let memory = MemoryFileSystem::new();
+    let config = FaultInjectionConfig { seed: 7, torn_write_probability: 1.0, sector_size: 4, ..Default::default() };
+    let fs = FaultInjectionFileSystem::new(memory.clone(), config);
+    let handle = fs.open_file(Path::new("f"), CREATE, FileLockType::NoLock).unwrap();
+    fs.write_at(&handle, &[1u8; 64], 64, 2).unwrap();
+    fs.simulate_power_loss().unwrap();
+
+    let contents = read_all(&memory, Path::new("f"));
+    assert!(contents.len() <= 66);
+    // every sector is either fully written or untouched
+    for sector in contents.chunks(4).skip(1) {
+        assert!(sector.iter().all(|&b| b == sector[0]));
+    }
+}
+
+#[test]
+fn read_only_handles_cannot_write() {
+    let fs = FaultInjectionFileSystem::new(MemoryFileSystem::new(), FaultInjectionConfig::default());
+    fs.open_file(Path::new("f"), CREATE, FileLockType::NoLock).unwrap();
+    let handle = fs.open_file(Path::new("f"), FileFlags::READ, FileLockType::NoLock).unwrap();
+    assert_eq!(fs.write_at(&handle, b"ab", 2, 0).unwrap_err().kind(), ErrorKind::PermissionDenied);
+    assert_eq!(fs.write(&handle, b"ab", 2).unwrap_err().kind(), ErrorKind::PermissionDenied);
+    assert!(!fs.has_unsynced_writes());
+}
+
+#[test]
+fn unsynced_writes_follow_the_normalized_path() {
+    let fs = FaultInjectionFileSystem::new(MemoryFileSystem::new(), FaultInjectionConfig::default());
+    fs.create_directory(Path::new("d")).unwrap();
+    let handle = fs.open_file(Path::new("d/./f"), CREATE, FileLockType::NoLock).unwrap();
+    fs.write_at(&handle, b"abcd", 4, 0).unwrap();
+
+    let other = fs.open_file(Path::new("d/e/../f"), FileFlags::READ, FileLockType::NoLock).unwrap();
+    assert_eq!(fs.file_size(&other).unwrap(), 4);
+    fs.move_file(Path::new("d/f"), Path::new("d/g")).unwrap();
+    let moved = fs.open_file(Path::new("d/g"), FileFlags::WRITE, FileLockType::NoLock).unwrap();
+    assert_eq!(fs.file_size(&moved).unwrap(), 4);
+    fs.remove_file(Path::new("./d/g")).unwrap();
+    assert!(!fs.has_unsynced_writes());
+}
+
+#[test]
+fn out_of_range_holes_are_rejected() {
+    let fs = FaultInjectionFileSystem::new(MemoryFileSystem::new(), FaultInjectionConfig::default());
+    let handle = fs.open_file(Path::new("f"), CREATE, FileLockType::NoLock).unwrap();
+    fs.write_at(&handle, b"abcd", 4, 0).unwrap();
+    assert_eq!(fs.punch_hole(&handle, 2, u64::MAX).unwrap_err().kind(), ErrorKind::InvalidInput);
+    assert_eq!(fs.write_at(&handle, b"ab", 2, u64::MAX).unwrap_err().kind(), ErrorKind::InvalidInput);
+}
+
+#[test]
+fn local_writes_reach_the_disk_on_fsync() {
+    let directory = test_directory("fault_fs", "local");
+    let path = directory.join("f");
+    let fs = FaultInjectionFileSystem::new(LocalFileSystem, FaultInjectionConfig::default());
+    let handle = fs.open_file(&path, CREATE, FileLockType::NoLock).unwrap();
+    fs.write_at(&handle, b"abcd", 4, 0).unwrap();
+    assert!(std::fs::read(&path).unwrap().is_empty());
+    fs.fsync(&handle).unwrap();
+    assert_eq!(std::fs::read(&path).unwrap(), b"abcd");
+    std::fs::remove_dir_all(&directory).unwrap();
+}
+
+#[test]
+fn interrupted_checkpoint_falls_back_to_the_previous_one() {
+    let directory = test_directory("fault_fs", "checkpoint");
+    let path = directory.join("db");
+    let faults = FaultInjectionFileSystem::wrap_unified(UnifiedFileSystem::Local(LocalFileSystem), FaultInjectionConfig::default());
+    let fs = Arc::new(UnifiedFileSystem::Plugin(DynFileSystemAdapter::boxed(faults.clone())));
+    {
+        let mut manager = SingleFileBlockManager::new(fs.clone(), &path, false, true, false).unwrap();
+        let mut block = manager.create_block();
+        manager.write(&mut block).unwrap();
+        manager.write_header(&DatabaseHeader { iteration: 0, meta_block: 0, free_list: INVALID_BLOCK, block_count: 0 }).unwrap();
+
+        let mut block = manager.create_block();
+        manager.write(&mut block).unwrap();
+        faults.inject(InjectedFault::FsyncFailure);
+        let header = DatabaseHeader { iteration: 0, meta_block: 1, free_list: INVALID_BLOCK, block_count: 0 };
+        assert!(manager.write_header(&header).is_err());
+        assert!(faults.has_unsynced_writes());
+    }
+    faults.simulate_power_loss().unwrap();
+
+    let local = Arc::new(UnifiedFileSystem::Local(LocalFileSystem));
+    let manager = SingleFileBlockManager::new(local, &path, true, false, false).unwrap();
+    assert_eq!(manager.get_meta_block(), 0);
+    assert_eq!(manager.block_count(), 1);
+    std::fs::remove_dir_all(&directory).unwrap();
+}
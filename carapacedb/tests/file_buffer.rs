@@ -0,0 +1,117 @@
+mod common;
+
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::cell::Cell;
+use std::io::ErrorKind;
+use std::path::Path;
+
+use carapacedb::common::file_buffer::{FileBuffer, FILE_BUFFER_BLOCK_SIZE, FILE_BUFFER_HEADER_SIZE};
+use carapacedb::common::file_system::adapter_fs::DynFileSystemAdapter;
+use carapacedb::common::file_system::memory_fs::MemoryFileSystem;
+use carapacedb::common::file_system::static_fs::LocalFileSystem;
+use carapacedb::common::file_system::{FileFlags, UnifiedFileSystem};
+
+use common::test_directory;
+
+const CREATE: FileFlags = FileFlags::WRITE.union(FileFlags::CREATE);
+
+/// Counts the bytes of block-aligned allocations made and freed on each thread, which
+/// in this test binary are only those of file buffers
+struct CountingAllocator;
+
+thread_local! {
+    static ALLOCATED: Cell<usize> = const { Cell::new(0) };
+    static FREED: Cell<usize> = const { Cell::new(0) };
+}
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        if layout.align() == FILE_BUFFER_BLOCK_SIZE {
+            ALLOCATED.with(|allocated| allocated.set(allocated.get() + layout.size()));
+        }
+        unsafe { System.alloc(layout) }
+    }
+
+    unsafe fn alloc_zeroed(&self, layout: Layout) -> *mut u8 {
+        if layout.align() == FILE_BUFFER_BLOCK_SIZE {
+            ALLOCATED.with(|allocated| allocated.set(allocated.get() + layout.size()));
+        }
+        unsafe { System.alloc_zeroed(layout) }
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        if layout.align() == FILE_BUFFER_BLOCK_SIZE {
+            FREED.with(|freed| freed.set(freed.get() + layout.size()));
+        }
+        unsafe { System.dealloc(ptr, layout) }
+    }
+}
+
+#[global_allocator]
+static ALLOCATOR: CountingAllocator = CountingAllocator;
+
+/// The bytes of file buffers allocated and not yet freed by the current thread
+fn live_buffer_bytes() -> usize {
+    ALLOCATED.with(Cell::get) - FREED.with(Cell::get)
+}
+
+#[test]
+fn buffers_are_block_aligned() {
+    for requested in [0, 1, FILE_BUFFER_BLOCK_SIZE, FILE_BUFFER_BLOCK_SIZE + 1, 262144] {
+        let buffer = FileBuffer::new(requested);
+        assert_eq!(buffer.internal_size() % FILE_BUFFER_BLOCK_SIZE, 0);
+        assert!(buffer.internal_size() >= requested);
+        assert_eq!(buffer.size(), buffer.internal_size() - FILE_BUFFER_HEADER_SIZE);
+        let internal_address = buffer.buffer().as_ptr() as usize - FILE_BUFFER_HEADER_SIZE;
+        assert_eq!(internal_address % FILE_BUFFER_BLOCK_SIZE, 0);
+        assert!(buffer.buffer().iter().all(|byte| *byte == 0));
+    }
+
+    let directory = test_directory("file_buffer", "direct_io");
+    let fs = UnifiedFileSystem::Local(LocalFileSystem);
+    let handle = fs.open_file(&directory.join("db"), CREATE | FileFlags::DIRECT_IO, None).unwrap();
+    let mut buffer = FileBuffer::new(FILE_BUFFER_BLOCK_SIZE);
+    buffer.buffer_mut()[..5].copy_from_slice(b"hello");
+    buffer.write(&handle, FILE_BUFFER_BLOCK_SIZE as u64).unwrap();
+    let mut read_back = FileBuffer::new(FILE_BUFFER_BLOCK_SIZE);
+    read_back.read(&handle, FILE_BUFFER_BLOCK_SIZE as u64).unwrap();
+    assert_eq!(read_back.buffer(), buffer.buffer());
+    drop(handle);
+    std::fs::remove_dir_all(&directory).unwrap();
+}
+
+#[test]
+fn flipped_bytes_fail_the_checksum() {
+    let fs = UnifiedFileSystem::Plugin(DynFileSystemAdapter::boxed(MemoryFileSystem::new()));
+    let handle = fs.open_file(Path::new("db"), CREATE, None).unwrap();
+    let mut buffer = FileBuffer::new(FILE_BUFFER_BLOCK_SIZE);
+    buffer.buffer_mut().fill(0xab);
+    buffer.write(&handle, 0).unwrap();
+    buffer.read(&handle, 0).unwrap();
+
+    for offset in [0, FILE_BUFFER_HEADER_SIZE as u64, FILE_BUFFER_BLOCK_SIZE as u64 - 1] {
+        let mut byte = [0u8; 1];
+        handle.read_at(&mut byte, 1, offset).unwrap();
+        handle.write_at(&[byte[0] ^ 1], 1, offset).unwrap();
+        assert_eq!(buffer.read(&handle, 0).unwrap_err().kind(), ErrorKind::InvalidData);
+        handle.write_at(&byte, 1, offset).unwrap();
+        buffer.read(&handle, 0).unwrap();
+    }
+
+    buffer.clear();
+    assert!(buffer.buffer().iter().all(|byte| *byte == 0));
+}
+
+#[test]
+fn buffers_are_freed_on_drop() {
+    let mut buffer = FileBuffer::new(3 * FILE_BUFFER_BLOCK_SIZE);
+    assert_eq!(live_buffer_bytes(), 3 * FILE_BUFFER_BLOCK_SIZE);
+    buffer.buffer_mut()[0] = 1;
+    drop(buffer);
+    assert_eq!(live_buffer_bytes(), 0);
+
+    let buffers: Vec<FileBuffer> = (1..=4).map(|blocks| FileBuffer::new(blocks * FILE_BUFFER_BLOCK_SIZE)).collect();
+    assert_eq!(live_buffer_bytes(), 10 * FILE_BUFFER_BLOCK_SIZE);
+    drop(buffers);
+    assert_eq!(live_buffer_bytes(), 0);
+}
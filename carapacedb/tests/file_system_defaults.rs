@@ -0,0 +1,129 @@
+use std::io::{ErrorKind, Result};
+use std::path::{Path, PathBuf};
+
+use carapacedb::common::file_system::adapter_fs::DynFileSystemAdapter;
+use carapacedb::common::file_system::dynamic_fs::DynFileSystem;
+use carapacedb::common::file_system::memory_fs::{MemoryFileHandle, MemoryFileSystem};
+use carapacedb::common::file_system::static_fs::{SFileHandle, SFileSystem};
+use carapacedb::common::file_system::{FileFlags, FileLockType};
+
+const CREATE: FileFlags = FileFlags::WRITE.union(FileFlags::CREATE);
+
+/// A file system that implements only the required methods
+#[derive(Debug)]
+struct MinimalFileSystem {
+    inner: MemoryFileSystem,
+}
+
+struct MinimalFileHandle<'a> {
+    fs: &'a MinimalFileSystem,
+    inner: MemoryFileHandle<'a>,
+}
+
+impl<'a> SFileHandle<MinimalFileSystem> for MinimalFileHandle<'a> {
+    fn file_system(&self) -> &MinimalFileSystem {
+        self.fs
+    }
+
+    fn path(&self) -> &Path {
+        self.inner.path()
+    }
+
+    fn close(&mut self) -> Result<()> {
+        self.inner.close()
+    }
+}
+
+impl SFileSystem for MinimalFileSystem {
+    type Handle<'a> = MinimalFileHandle<'a>;
+
+    fn read_at(&self, handle: &Self::Handle<'_>, buffer: &mut [u8], nr_bytes: i64, location: u64) -> Result<()> {
+        self.inner.read_at(&handle.inner, buffer, nr_bytes, location)
+    }
+
+    fn write_at(&self, handle: &Self::Handle<'_>, buffer: &[u8], nr_bytes: i64, location: u64) -> Result<()> {
+        self.inner.write_at(&handle.inner, buffer, nr_bytes, location)
+    }
+
+    fn open_file<'a>(&'a self, path: &Path, flags: FileFlags, lock: FileLockType) -> Result<Self::Handle<'a>> {
+        Ok(MinimalFileHandle { fs: self, inner: self.inner.open_file(path, flags, lock)? })
+    }
+
+    fn set_file_pointer(&self, handle: &Self::Handle<'_>, location: u64) -> Result<()> {
+        self.inner.set_file_pointer(&handle.inner, location)
+    }
+
+    fn read(&self, handle: &Self::Handle<'_>, buffer: &mut [u8], nr_bytes: i64) -> Result<u64> {
+        self.inner.read(&handle.inner, buffer, nr_bytes)
+    }
+
+    fn write(&self, handle: &Self::Handle<'_>, buffer: &[u8], nr_bytes: i64) -> Result<u64> {
+        self.inner.write(&handle.inner, buffer, nr_bytes)
+    }
+
+    fn file_size(&self, handle: &Self::Handle<'_>) -> Result<u64> {
+        self.inner.file_size(&handle.inner)
+    }
+
+    fn directory_exists(&self, path: &Path) -> Result<bool> {
+        self.inner.directory_exists(path)
+    }
+
+    fn file_exists(&self, file_name: &Path) -> Result<bool> {
+        self.inner.file_exists(file_name)
+    }
+
+    fn create_directory(&self, path: &Path) -> Result<()> {
+        self.inner.create_directory(path)
+    }
+
+    fn remove_directory(&self, path: &Path) -> Result<()> {
+        self.inner.remove_directory(path)
+    }
+
+    fn remove_file(&self, file_name: &Path) -> Result<()> {
+        self.inner.remove_file(file_name)
+    }
+
+    fn move_file(&self, src: &Path, dst: &Path) -> Result<()> {
+        self.inner.move_file(src, dst)
+    }
+
+    fn list_files<F>(&self, directory: &Path, callback: F) -> Result<bool> where F: FnMut(String) {
+        self.inner.list_files(directory, callback)
+    }
+
+    fn path_separator(&self) -> &'static str {
+        self.inner.path_separator()
+    }
+
+    fn fsync(&self, handle: &Self::Handle<'_>) -> Result<()> {
+        self.inner.fsync(&handle.inner)
+    }
+
+    fn join_path(&self, l: &Path, r: &Path) -> Result<PathBuf> {
+        self.inner.join_path(l, r)
+    }
+}
+
+#[test]
+fn optional_operations_are_unsupported_by_default() {
+    let fs = MinimalFileSystem { inner: MemoryFileSystem::new() };
+    let handle = fs.open_file(Path::new("f"), CREATE, FileLockType::NoLock).unwrap();
+    fs.write_at(&handle, b"abcd", 4, 0).unwrap();
+    assert_eq!(fs.truncate(&handle, 2).unwrap_err().kind(), ErrorKind::Unsupported);
+    assert_eq!(fs.allocate(&handle, 0, 8).unwrap_err().kind(), ErrorKind::Unsupported);
+    assert_eq!(fs.punch_hole(&handle, 0, 2).unwrap_err().kind(), ErrorKind::Unsupported);
+    assert_eq!(fs.sync_directory(Path::new(".")).unwrap_err().kind(), ErrorKind::Unsupported);
+    assert_eq!(fs.file_size(&handle).unwrap(), 4);
+}
+
+#[test]
+fn adapted_defaults_stay_unsupported() {
+    let fs = DynFileSystemAdapter::new(MinimalFileSystem { inner: MemoryFileSystem::new() });
+    let handle = fs.open_file(Path::new("f"), CREATE, None).unwrap();
+    assert_eq!(fs.truncate(handle.as_ref(), 2).unwrap_err().kind(), ErrorKind::Unsupported);
+    assert_eq!(fs.punch_hole(handle.as_ref(), 0, 2).unwrap_err().kind(), ErrorKind::Unsupported);
+    fs.move_file(Path::new("f"), Path::new("g")).unwrap();
+    assert!(fs.file_exists(Path::new("g")).unwrap());
+}
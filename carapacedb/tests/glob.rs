@@ -0,0 +1,73 @@
+use std::path::{Path, PathBuf};
+
+use carapacedb::common::file_system::glob::glob_match;
+use carapacedb::common::file_system::static_fs::{LocalFileSystem, SFileSystem};
+use carapacedb::common::file_system::virtual_fs::VirtualFileSystem;
+use carapacedb::common::file_system::dynamic_fs::DynFileSystem;
+
+/// A fresh directory with an empty file at each of `files`
+fn test_tree(name: &str, files: &[&str]) -> PathBuf {
+    let root = std::env::temp_dir().join(format!("carapacedb_glob_{}_{}", std::process::id(), name));
+    let _ = std::fs::remove_dir_all(&root);
+    for file in files {
+        let path = root.join(file);
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        std::fs::write(path, "").unwrap();
+    }
+    root
+}
+
+fn relative(root: &Path, paths: Vec<PathBuf>) -> Vec<String> {
+    paths.iter().map(|path| path.strip_prefix(root).unwrap().to_str().unwrap().to_string()).collect()
+}
+
+#[test]
+fn components_match_wildcards_and_classes() {
+    assert!(glob_match("part-*.csv", "part-001.csv"));
+    assert!(!glob_match("part-*.csv", "part-001.csvx"));
+    assert!(glob_match("*a*b", "xxaxxbxb"));
+    assert!(!glob_match("?", ""));
+    assert!(glob_match("[a-c]x", "bx"));
+    assert!(!glob_match("[!a-c]x", "bx"));
+    assert!(glob_match("[", "["));
+}
+
+#[test]
+fn double_star_matches_any_number_of_directories() {
+    let root = test_tree("recursive", &["a.csv", "2026-01/b.csv", "2026-01/c.txt", "2026-02/deep/d.csv"]);
+    let fs = LocalFileSystem;
+    let pattern = format!("{}/**/*.csv", root.display());
+    assert_eq!(relative(&root, SFileSystem::glob(&fs, &pattern).unwrap()), vec!["2026-01/b.csv", "2026-02/deep/d.csv", "a.csv"]);
+    let pattern = format!("{}/2026-*/*", root.display());
+    assert_eq!(relative(&root, SFileSystem::glob(&fs, &pattern).unwrap()), vec!["2026-01/b.csv", "2026-01/c.txt"]);
+    std::fs::remove_dir_all(&root).unwrap();
+}
+
+#[test]
+fn trailing_double_star_matches_files_at_every_level() {
+    let root = test_tree("trailing", &["a.csv", "x/b.csv", "x/y/c.csv"]);
+    let pattern = format!("{}/**", root.display());
+    let expected = vec!["a.csv", "x/b.csv", "x/y/c.csv"];
+    assert_eq!(relative(&root, SFileSystem::glob(&LocalFileSystem, &pattern).unwrap()), expected);
+
+    let vfs = VirtualFileSystem::new();
+    assert_eq!(relative(&root, DynFileSystem::glob(&vfs, &pattern).unwrap()), expected);
+    std::fs::remove_dir_all(&root).unwrap();
+}
+
+#[test]
+fn double_star_does_not_follow_symlinks() {
+    let root = test_tree("symlinks", &["x/a.csv", "elsewhere/b.csv"]);
+    // a cycle, and a link out of the tree
+    std::os::unix::fs::symlink(&root, root.join("x/loop")).unwrap();
+    std::os::unix::fs::symlink(root.join("elsewhere"), root.join("x/link")).unwrap();
+    let pattern = format!("{}/x/**/*.csv", root.display());
+    assert_eq!(relative(&root, SFileSystem::glob(&LocalFileSystem, &pattern).unwrap()), vec!["x/a.csv"]);
+    let pattern = format!("{}/x/**", root.display());
+    assert_eq!(relative(&root, DynFileSystem::glob(&VirtualFileSystem::new(), &pattern).unwrap()), vec!["x/a.csv"]);
+
+    // links are still followed by an explicit path
+    let pattern = format!("{}/x/link/*.csv", root.display());
+    assert_eq!(relative(&root, SFileSystem::glob(&LocalFileSystem, &pattern).unwrap()), vec!["x/link/b.csv"]);
+    std::fs::remove_dir_all(&root).unwrap();
+}
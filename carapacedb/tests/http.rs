@@ -0,0 +1,170 @@
+mod common;
+
+use std::collections::BTreeMap;
+use std::io::{ErrorKind, Read, Write};
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use carapacedb::common::file_system::http_fs::{HttpConfig, HttpTransport, TcpTransport};
+use carapacedb::common::file_system::{FileFlags, UnifiedFileSystem};
+use carapacedb::core::database::DBConfig;
+use common::{Request, Response};
+
+/// Files by path, each with its ETag
+#[derive(Default)]
+struct FileServer {
+    files: BTreeMap<String, (Vec<u8>, String)>,
+    range_requests: usize,
+}
+
+impl FileServer {
+    fn handle(&mut self, request: Request) -> Response {
+        let Some((data, etag)) = self.files.get(&request.path) else { return Response::new(404) };
+        let response = match (request.method.as_str(), request.headers.get("range")) {
+            ("HEAD", _) => Response::new(200).header("Content-Length", data.len()),
+            ("GET", Some(range)) => {
+                self.range_requests += 1;
+                let (start, end) = range.strip_prefix("bytes=").unwrap().split_once('-').unwrap();
+                let (start, end): (usize, usize) = (start.parse().unwrap(), end.parse().unwrap());
+                Response::with_body(206, &data[start..=end])
+            }
+            ("GET", None) => Response::with_body(200, data.clone()),
+            _ => Response::new(405),
+        };
+        response.header("ETag", etag)
+    }
+}
+
+fn file_server(config: HttpConfig) -> (UnifiedFileSystem, String, Arc<Mutex<FileServer>>) {
+    let server = Arc::new(Mutex::new(FileServer::default()));
+    let handler_server = server.clone();
+    let url = common::serve(move |request| handler_server.lock().unwrap().handle(request));
+    let mut config = DBConfig { http: Some(config), ..Default::default() };
+    (config.create_file_system().unwrap(), url, server)
+}
+
+fn small_blocks() -> HttpConfig {
+    HttpConfig { block_size: 4, retry_backoff: Duration::from_millis(1), ..Default::default() }
+}
+
+#[test]
+fn http_files_are_read_through_the_default_file_system() {
+    let (fs, url, server) = file_server(small_blocks());
+    server.lock().unwrap().files.insert("/data.bin".to_string(), (b"0123456789".to_vec(), "\"v1\"".to_string()));
+
+    let path = format!("{}/data.bin", url);
+    assert!(fs.file_exists(Path::new(&path)).unwrap());
+    assert!(!fs.file_exists(Path::new(&format!("{}/missing.bin", url))).unwrap());
+
+    let handle = fs.open_file(Path::new(&path), FileFlags::READ, None).unwrap();
+    assert_eq!(handle.file_size().unwrap(), 10);
+    assert_eq!(handle.file_version().unwrap().as_deref(), Some("\"v1\""));
+    let mut buffer = [0u8; 7];
+    handle.read_at(&mut buffer, 7, 2).unwrap();
+    assert_eq!(&buffer, b"2345678");
+    assert_eq!(server.lock().unwrap().range_requests, 3);
+
+    // the blocks are cached
+    handle.read_at(&mut buffer[..3], 3, 4).unwrap();
+    assert_eq!(&buffer[..3], b"456");
+    assert_eq!(server.lock().unwrap().range_requests, 3);
+
+    assert_eq!(handle.read_at(&mut buffer, 7, 5).unwrap_err().kind(), ErrorKind::UnexpectedEof);
+    let err = fs.open_file(Path::new(&path), FileFlags::WRITE, None).unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::PermissionDenied);
+}
+
+#[test]
+fn changed_file_is_not_served_from_the_blocks_of_its_old_version() {
+    let (fs, url, server) = file_server(small_blocks());
+    let path = format!("{}/data.bin", url);
+    server.lock().unwrap().files.insert("/data.bin".to_string(), (b"aaaaaaaa".to_vec(), "\"v1\"".to_string()));
+    let mut buffer = [0u8; 8];
+    let handle = fs.open_file(Path::new(&path), FileFlags::READ, None).unwrap();
+    handle.read_at(&mut buffer, 8, 0).unwrap();
+    assert_eq!(&buffer, b"aaaaaaaa");
+
+    // same size, new contents
+    server.lock().unwrap().files.insert("/data.bin".to_string(), (b"bbbbbbbb".to_vec(), "\"v2\"".to_string()));
+    let handle = fs.open_file(Path::new(&path), FileFlags::READ, None).unwrap();
+    handle.read_at(&mut buffer, 8, 0).unwrap();
+    assert_eq!(&buffer, b"bbbbbbbb");
+}
+
+#[test]
+fn zero_block_size_is_rejected_on_open() {
+    let (fs, url, server) = file_server(HttpConfig { block_size: 0, ..Default::default() });
+    server.lock().unwrap().files.insert("/data.bin".to_string(), (b"data".to_vec(), "\"v1\"".to_string()));
+    let err = fs.open_file(Path::new(&format!("{}/data.bin", url)), FileFlags::READ, None).unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::InvalidInput);
+}
+
+#[test]
+fn http_is_not_registered_by_default() {
+    let mut config = DBConfig::default();
+    let fs = config.create_file_system().unwrap();
+    assert!(fs.open_file(Path::new("http://127.0.0.1:1/data.bin"), FileFlags::READ, None).is_err());
+}
+
+#[test]
+fn tcp_transport_parses_responses() {
+    let (_, url, server) = file_server(small_blocks());
+    server.lock().unwrap().files.insert("/data.bin".to_string(), (b"0123456789".to_vec(), "\"v1\"".to_string()));
+    let transport = TcpTransport::new(Duration::from_secs(10));
+    let url = format!("{}/data.bin", url);
+
+    let response = transport.request("HEAD", &url, &[], &[]).unwrap();
+    assert_eq!(response.status, 200);
+    assert_eq!(response.header("content-length"), Some("10"));
+    assert_eq!(response.header("etag"), Some("\"v1\""));
+    assert!(response.body.is_empty());
+
+    let response = transport.request("GET", &url, &[("Range", "bytes=3-5".to_string())], &[]).unwrap();
+    assert_eq!(response.status, 206);
+    assert_eq!(response.body, b"345");
+
+    let err = transport.request("GET", "http://127.0.0.1:1/data.bin", &[], &[]).unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::ConnectionRefused);
+}
+
+#[test]
+fn tcp_transport_handles_ipv6_hosts_and_https() {
+    let transport = TcpTransport::new(Duration::from_secs(10));
+    // skipped where the loopback interface has no IPv6 address
+    if let Ok(listener) = std::net::TcpListener::bind("[::1]:0") {
+        let port = listener.local_addr().unwrap().port();
+        let server = std::thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut request = Vec::new();
+            let mut buffer = [0u8; 1024];
+            while !request.ends_with(b"\r\n\r\n") {
+                let count = stream.read(&mut buffer).unwrap();
+                request.extend_from_slice(&buffer[..count]);
+            }
+            stream.write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 2\r\n\r\nok").unwrap();
+            String::from_utf8(request).unwrap()
+        });
+        let response = transport.request("GET", &format!("http://[::1]:{}/x", port), &[], &[]).unwrap();
+        assert_eq!(response.body, b"ok");
+        assert!(server.join().unwrap().contains(&format!("Host: [::1]:{}\r\n", port)));
+    }
+    assert_eq!(transport.request("GET", "http://::1/x", &[], &[]).unwrap_err().kind(), ErrorKind::InvalidInput);
+    assert_eq!(transport.request("GET", "http://[::1/x", &[], &[]).unwrap_err().kind(), ErrorKind::InvalidInput);
+    assert_eq!(transport.request("GET", "ftp://host/x", &[], &[]).unwrap_err().kind(), ErrorKind::Unsupported);
+
+    // a TLS handshake with a plain HTTP server fails instead of hanging
+    let (_, url, _) = file_server(small_blocks());
+    let url = url.replacen("http://", "https://", 1);
+    assert!(transport.request("GET", &format!("{}/data.bin", url), &[], &[]).is_err());
+}
+
+#[test]
+fn tcp_transport_connects_within_the_timeout() {
+    // a non-routable address, where connecting only ends with the timeout or with
+    // an error from an environment without a route
+    let transport = TcpTransport::new(Duration::from_millis(200));
+    let start = std::time::Instant::now();
+    assert!(transport.request("GET", "http://10.255.255.1/x", &[], &[]).is_err());
+    assert!(start.elapsed() < Duration::from_secs(5));
+}
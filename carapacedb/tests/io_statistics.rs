@@ -0,0 +1,81 @@
+use std::path::Path;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use carapacedb::common::file_system::adapter_fs::DynFileSystemAdapter;
+use carapacedb::common::file_system::memory_fs::MemoryFileSystem;
+use carapacedb::common::file_system::stats_fs::IoStatistics;
+use carapacedb::common::file_system::throttle_fs::IoRateLimit;
+use carapacedb::common::file_system::{FileFlags, UnifiedFileSystem};
+use carapacedb::core::database::DBConfig;
+
+const CREATE: FileFlags = FileFlags::WRITE.union(FileFlags::CREATE);
+
+fn memory_file_system(background_write_limit: Option<IoRateLimit>) -> UnifiedFileSystem {
+    let mut config = DBConfig { background_write_limit, ..Default::default() };
+    config.file_systems.push(("mem".to_string(), DynFileSystemAdapter::boxed(MemoryFileSystem::new())));
+    config.create_file_system().unwrap()
+}
+
+#[test]
+fn operations_are_recorded_per_file() {
+    let fs = memory_file_system(None);
+    let statistics = fs.io_statistics().unwrap();
+    let events = Arc::new(AtomicU64::new(0));
+    let counter = events.clone();
+    statistics.set_trace_hook(Some(Box::new(move |_| {
+        counter.fetch_add(1, Ordering::Relaxed);
+    })));
+
+    let path = Path::new("mem://a");
+    let handle = fs.open_file(path, CREATE, None).unwrap();
+    handle.write_at(&[1u8; 100], 100, 0).unwrap();
+    handle.write(&[1u8; 50], 50).unwrap();
+    handle.fsync().unwrap();
+    let mut buffer = [0u8; 10];
+    handle.read_at(&mut buffer, 10, 5).unwrap();
+    assert!(handle.read_at(&mut buffer, 10, 500).is_err());
+
+    let file = statistics.file(path).unwrap();
+    assert_eq!((file.bytes_written, file.writes, file.fsyncs, file.reads, file.bytes_read), (150, 2, 1, 2, 10));
+    assert_eq!(file.read_latency.count(), 2);
+    assert!(file.read_latency.percentile(0.5).is_some());
+    assert_eq!(events.load(Ordering::Relaxed), 5);
+    assert_eq!(statistics.total().bytes_written, 150);
+    statistics.reset();
+    assert_eq!(statistics.file(path).unwrap().writes, 0);
+}
+
+#[test]
+fn trace_hook_can_replace_itself() {
+    let fs = Arc::new(memory_file_system(None));
+    let calls = Arc::new(AtomicU64::new(0));
+    let (hook_fs, hook_calls) = (fs.clone(), calls.clone());
+    fs.io_statistics().unwrap().set_trace_hook(Some(Box::new(move |_| {
+        hook_calls.fetch_add(1, Ordering::Relaxed);
+        hook_fs.io_statistics().unwrap().set_trace_hook(None);
+    })));
+
+    let handle = fs.open_file(Path::new("mem://a"), CREATE, None).unwrap();
+    handle.write_at(b"ab", 2, 0).unwrap();
+    handle.write_at(b"ab", 2, 2).unwrap();
+    assert_eq!(calls.load(Ordering::Relaxed), 1);
+}
+
+#[test]
+fn latencies_exclude_throttling() {
+    let limit = IoRateLimit { bytes_per_second: Some(1000), operations_per_second: None };
+    let fs = memory_file_system(Some(limit));
+    let statistics: &IoStatistics = fs.io_statistics().unwrap();
+    let path = Path::new("mem://bg");
+    let handle = fs.open_file(path, CREATE | FileFlags::BACKGROUND, None).unwrap();
+
+    let start = Instant::now();
+    for i in 0..3 {
+        handle.write_at(&[7u8; 500], 500, i * 500).unwrap();
+    }
+    assert!(start.elapsed() >= Duration::from_millis(400));
+    let slowest = statistics.file(path).unwrap().write_latency.percentile(1.0).unwrap();
+    assert!(slowest < Duration::from_millis(100), "{:?}", slowest);
+}
@@ -0,0 +1,180 @@
+mod common;
+
+use std::io::ErrorKind;
+#[cfg(target_os = "linux")]
+use std::os::unix::fs::MetadataExt;
+
+use carapacedb::common::file_system::static_fs::{LocalFileSystem, SFileSystem};
+use carapacedb::common::file_system::{FileFlags, FileLockType};
+
+use common::test_directory;
+
+const CREATE: FileFlags = FileFlags::WRITE.union(FileFlags::CREATE);
+
+fn pattern(len: usize) -> Vec<u8> {
+    (0..len).map(|i| (i % 251) as u8).collect()
+}
+
+#[test]
+fn concurrent_reads_share_one_handle() {
+    let directory = test_directory("local_fs", "concurrent");
+    let fs = LocalFileSystem;
+    let handle = fs.open_file(&directory.join("f"), CREATE, FileLockType::NoLock).unwrap();
+    let data = pattern(1 << 20);
+    fs.write_at(&handle, &data, data.len() as i64, 0).unwrap();
+
+    std::thread::scope(|scope| {
+        for thread in 0..8usize {
+            let (fs, handle, data) = (&fs, &handle, &data);
+            scope.spawn(move || {
+                let mut buffer = vec![0u8; 4096];
+                for i in 0..256usize {
+                    let location = (thread * 7919 + i * 104729) % (data.len() - buffer.len());
+                    fs.read_at(handle, &mut buffer, 4096, location as u64).unwrap();
+                    assert_eq!(buffer, data[location..location + 4096]);
+                }
+            });
+        }
+    });
+
+    // positional reads leave the file pointer alone
+    let mut buffer = [0u8; 4];
+    assert_eq!(fs.read(&handle, &mut buffer, 4).unwrap(), 4);
+    assert_eq!(buffer, data[..4]);
+    std::fs::remove_dir_all(&directory).unwrap();
+}
+
+#[test]
+fn transfers_honor_nr_bytes() {
+    let directory = test_directory("local_fs", "nr_bytes");
+    let fs = LocalFileSystem;
+    let handle = fs.open_file(&directory.join("f"), CREATE, FileLockType::NoLock).unwrap();
+    fs.write_at(&handle, b"abcdef", 3, 10).unwrap();
+    assert_eq!(fs.file_size(&handle).unwrap(), 13);
+
+    let mut buffer = [b'-'; 6];
+    fs.read_at(&handle, &mut buffer, 2, 11).unwrap();
+    assert_eq!(&buffer, b"bc----");
+    assert_eq!(fs.read_at(&handle, &mut buffer, 7, 0).unwrap_err().kind(), ErrorKind::InvalidInput);
+    assert_eq!(fs.read_at(&handle, &mut buffer, -1, 0).unwrap_err().kind(), ErrorKind::InvalidInput);
+    assert_eq!(fs.write_at(&handle, b"abc", 4, 0).unwrap_err().kind(), ErrorKind::InvalidInput);
+    assert_eq!(fs.file_size(&handle).unwrap(), 13);
+    std::fs::remove_dir_all(&directory).unwrap();
+}
+
+#[test]
+fn short_transfers_continue_until_done() {
+    let directory = test_directory("local_fs", "short");
+    let fs = LocalFileSystem;
+    let handle = fs.open_file(&directory.join("f"), CREATE, FileLockType::NoLock).unwrap();
+
+    // large enough that the kernel may split it into several transfers
+    let data = pattern(64 << 20);
+    fs.write_at(&handle, &data, data.len() as i64, 1).unwrap();
+    let mut buffer = vec![0u8; data.len()];
+    fs.read_at(&handle, &mut buffer, data.len() as i64, 1).unwrap();
+    assert!(buffer == data);
+
+    // the first pread stops at the end of the file, and the next one reads nothing
+    let mut buffer = [0u8; 8];
+    let end = data.len() as u64 + 1;
+    let error = fs.read_at(&handle, &mut buffer, 8, end - 5).unwrap_err();
+    assert_eq!(error.kind(), ErrorKind::UnexpectedEof);
+    assert_eq!(buffer[..5], data[data.len() - 5..]);
+    assert_eq!(fs.read_at(&handle, &mut buffer, 8, end + 100).unwrap_err().kind(), ErrorKind::UnexpectedEof);
+    std::fs::remove_dir_all(&directory).unwrap();
+}
+
+#[test]
+fn mapped_files_are_read_from_the_mapping() {
+    let directory = test_directory("local_fs", "mapped");
+    let path = directory.join("f");
+    let fs = LocalFileSystem;
+    let data = pattern(10000);
+    std::fs::write(&path, &data).unwrap();
+
+    let handle = fs.open_file(&path, FileFlags::READ | FileFlags::MEMORY_MAP, FileLockType::ReadLock).unwrap();
+    assert!(handle.is_mapped());
+    assert_eq!(handle.mapped_slice(100, 50).unwrap(), &data[100..150]);
+    assert_eq!(handle.mapped_slice(9990, 10).unwrap(), &data[9990..]);
+    let mut buffer = [0u8; 50];
+    fs.read_at(&handle, &mut buffer, 50, 100).unwrap();
+    assert_eq!(buffer, data[100..150]);
+
+    // ranges past the end of the mapping are not mapped and fail as with pread
+    assert!(handle.mapped_slice(9990, 11).is_none());
+    assert!(handle.mapped_slice(u64::MAX, 1).is_none());
+    assert_eq!(fs.read_at(&handle, &mut buffer, 50, 9990).unwrap_err().kind(), ErrorKind::UnexpectedEof);
+    assert_eq!(buffer[..10], data[9990..]);
+
+    // without MEMORY_MAP or with direct I/O nothing is mapped
+    let handle = fs.open_file(&path, FileFlags::READ, FileLockType::NoLock).unwrap();
+    assert!(!handle.is_mapped());
+    assert!(handle.mapped_slice(0, 1).is_none());
+    let handle = fs.open_file(&path, FileFlags::READ | FileFlags::MEMORY_MAP | FileFlags::DIRECT_IO, FileLockType::NoLock).unwrap();
+    assert!(!handle.is_mapped());
+    std::fs::remove_dir_all(&directory).unwrap();
+}
+
+#[test]
+fn unmapped_ranges_fall_back_to_pread() {
+    let directory = test_directory("local_fs", "mapped_fallback");
+    let path = directory.join("f");
+    let fs = LocalFileSystem;
+
+    // an empty file cannot be mapped, so its reads use pread
+    let writer = fs.open_file(&path, CREATE, FileLockType::NoLock).unwrap();
+    let empty = fs.open_file(&path, FileFlags::READ | FileFlags::MEMORY_MAP, FileLockType::NoLock).unwrap();
+    assert!(!empty.is_mapped());
+    let data = pattern(8192);
+    fs.write_at(&writer, &data[..4096], 4096, 0).unwrap();
+    let mut buffer = [0u8; 16];
+    fs.read_at(&empty, &mut buffer, 16, 10).unwrap();
+    assert_eq!(buffer, data[10..26]);
+
+    // data appended after mapping lies beyond the mapping and is read with pread
+    let mapped = fs.open_file(&path, FileFlags::READ | FileFlags::MEMORY_MAP, FileLockType::NoLock).unwrap();
+    assert!(mapped.is_mapped());
+    fs.write_at(&writer, &data[4096..], 4096, 4096).unwrap();
+    assert!(mapped.mapped_slice(4090, 16).is_none());
+    fs.read_at(&mapped, &mut buffer, 16, 4090).unwrap();
+    assert_eq!(buffer, data[4090..4106]);
+    fs.read_at(&mapped, &mut buffer, 16, 8000).unwrap();
+    assert_eq!(buffer, data[8000..8016]);
+    std::fs::remove_dir_all(&directory).unwrap();
+}
+
+#[cfg(target_os = "linux")]
+#[test]
+fn files_are_truncated_allocated_and_punched() {
+    let directory = test_directory("local_fs", "space");
+    let path = directory.join("f");
+    let fs = LocalFileSystem;
+    let handle = fs.open_file(&path, CREATE, FileLockType::NoLock).unwrap();
+
+    // allocating extends the file and reserves its space
+    fs.allocate(&handle, 0, 1 << 20).unwrap();
+    assert_eq!(fs.file_size(&handle).unwrap(), 1 << 20);
+    assert!(std::fs::metadata(&path).unwrap().blocks() * 512 >= 1 << 20);
+    fs.allocate(&handle, 4096, 4096).unwrap();
+    assert_eq!(fs.file_size(&handle).unwrap(), 1 << 20);
+
+    // punching keeps the size, reads back zeros and frees the space
+    let data = pattern(1 << 20);
+    fs.write_at(&handle, &data, data.len() as i64, 0).unwrap();
+    fs.fsync(&handle).unwrap();
+    fs.punch_hole(&handle, 65536, 1 << 19).unwrap();
+    assert_eq!(fs.file_size(&handle).unwrap(), 1 << 20);
+    assert!(std::fs::metadata(&path).unwrap().blocks() * 512 <= (1 << 20) - (1 << 19) + 65536);
+    let contents = std::fs::read(&path).unwrap();
+    assert_eq!(contents[..65536], data[..65536]);
+    assert!(contents[65536..65536 + (1 << 19)].iter().all(|byte| *byte == 0));
+    assert_eq!(contents[65536 + (1 << 19)..], data[65536 + (1 << 19)..]);
+
+    fs.truncate(&handle, 100).unwrap();
+    assert_eq!(fs.file_size(&handle).unwrap(), 100);
+    fs.truncate(&handle, 200).unwrap();
+    assert_eq!(std::fs::read(&path).unwrap()[..], [&data[..100], &[0; 100][..]].concat());
+    assert_eq!(fs.allocate(&handle, u64::MAX, 2).unwrap_err().kind(), ErrorKind::InvalidInput);
+    std::fs::remove_dir_all(&directory).unwrap();
+}
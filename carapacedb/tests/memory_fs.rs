@@ -0,0 +1,71 @@
+use std::io::ErrorKind;
+use std::path::Path;
+
+use carapacedb::common::file_system::adapter_fs::DynFileSystemAdapter;
+use carapacedb::common::file_system::dynamic_fs::DynFileSystem;
+use carapacedb::common::file_system::memory_fs::MemoryFileSystem;
+use carapacedb::common::file_system::static_fs::SFileSystem;
+use carapacedb::common::file_system::{FileFlags, FileLockType};
+
+const CREATE: FileFlags = FileFlags::WRITE.union(FileFlags::CREATE);
+
+#[test]
+fn files_and_directories() {
+    let fs = MemoryFileSystem::new();
+    fs.create_directory(Path::new("/db")).unwrap();
+    let handle = fs.open_file(Path::new("/db/x"), CREATE, FileLockType::WriteLock).unwrap();
+    fs.write_at(&handle, b"hello", 5, 10).unwrap();
+    assert_eq!(fs.file_size(&handle).unwrap(), 15);
+    let mut buffer = [0u8; 5];
+    fs.read_at(&handle, &mut buffer, 5, 10).unwrap();
+    assert_eq!(&buffer, b"hello");
+    assert_eq!(fs.read_at(&handle, &mut buffer, 5, 11).unwrap_err().kind(), ErrorKind::UnexpectedEof);
+
+    let mut names = Vec::new();
+    fs.list_files(Path::new("/db"), |name| names.push(name)).unwrap();
+    assert_eq!(names, vec!["x".to_string()]);
+    fs.move_file(Path::new("/db"), Path::new("/db2")).unwrap();
+    assert!(fs.file_exists(Path::new("/db2/./x")).unwrap());
+    fs.remove_directory(Path::new("/db2")).unwrap();
+    assert!(!fs.file_exists(Path::new("/db2/x")).unwrap());
+}
+
+#[test]
+fn locks_do_not_conflict_within_the_process() {
+    let fs = MemoryFileSystem::new();
+    let writer = fs.open_file(Path::new("f"), CREATE, FileLockType::WriteLock).unwrap();
+    let reader = fs.open_file(Path::new("f"), FileFlags::READ, FileLockType::ReadLock).unwrap();
+    let second_writer = fs.open_file(Path::new("f"), FileFlags::WRITE, FileLockType::WriteLock).unwrap();
+    fs.write_at(&second_writer, b"ab", 2, 0).unwrap();
+    assert_eq!(fs.file_size(&reader).unwrap(), 2);
+    assert_eq!(fs.file_size(&writer).unwrap(), 2);
+
+    let error = fs.open_file(Path::new("f"), FileFlags::READ, FileLockType::WriteLock).err().unwrap();
+    assert_eq!(error.kind(), ErrorKind::InvalidInput);
+    assert_eq!(fs.write_at(&reader, b"ab", 2, 0).unwrap_err().kind(), ErrorKind::PermissionDenied);
+}
+
+#[test]
+fn out_of_range_sizes_are_rejected() {
+    let fs = MemoryFileSystem::new();
+    let handle = fs.open_file(Path::new("f"), CREATE, FileLockType::NoLock).unwrap();
+    assert_eq!(fs.write_at(&handle, b"ab", 2, u64::MAX).unwrap_err().kind(), ErrorKind::InvalidInput);
+    assert_eq!(fs.allocate(&handle, u64::MAX, 1).unwrap_err().kind(), ErrorKind::InvalidInput);
+    assert_eq!(fs.punch_hole(&handle, 1, u64::MAX).unwrap_err().kind(), ErrorKind::InvalidInput);
+    assert_eq!(fs.truncate(&handle, 1 << 62).unwrap_err().kind(), ErrorKind::OutOfMemory);
+    assert_eq!(fs.file_size(&handle).unwrap(), 0);
+}
+
+#[test]
+fn plugins_go_through_the_adapter() {
+    let memory = MemoryFileSystem::new();
+    let fs = DynFileSystemAdapter::new(memory.clone());
+    let mut handle = fs.open_file(Path::new("f"), CREATE, None).unwrap();
+    fs.write(handle.as_ref(), b"abc", 3).unwrap();
+    fs.set_file_pointer(handle.as_ref(), 0).unwrap();
+    let mut buffer = [0u8; 3];
+    fs.read(handle.as_ref(), &mut buffer, 3).unwrap();
+    assert_eq!(&buffer, b"abc");
+    handle.close().unwrap();
+    assert!(memory.file_exists(Path::new("f")).unwrap());
+}
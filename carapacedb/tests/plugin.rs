@@ -0,0 +1,78 @@
+use std::env::consts::{DLL_PREFIX, DLL_SUFFIX};
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use carapacedb::common::file_system::FileFlags;
+use carapacedb::common::file_system::plugin_fs::PluginFileSystem;
+use carapacedb::core::database::DBConfig;
+
+/// Builds tests/plugin/memory_plugin.rs as a shared library with the given `--cfg`s
+fn build_plugin(name: &str, cfgs: &[&str]) -> PathBuf {
+    let out_dir = Path::new(env!("CARGO_TARGET_TMPDIR"));
+    let source = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/plugin/memory_plugin.rs");
+    let mut rustc = Command::new(std::env::var("RUSTC").unwrap_or_else(|_| "rustc".into()));
+    rustc.args(["--edition", "2024", "--crate-type", "cdylib", "--crate-name", name])
+        .arg("--out-dir").arg(out_dir)
+        .arg(&source);
+    for cfg in cfgs {
+        rustc.args(["--cfg", cfg]);
+    }
+    let status = rustc.status().unwrap();
+    assert!(status.success(), "building {} failed", source.display());
+    out_dir.join(format!("{}{}{}", DLL_PREFIX, name, DLL_SUFFIX))
+}
+
+#[test]
+fn plugin_serves_files_through_the_virtual_file_system() {
+    let library_path = build_plugin("memory_plugin", &[]);
+    let mut config = DBConfig::default();
+    config.file_system_plugins.push(("mem".into(), library_path));
+    let fs = config.create_file_system().unwrap();
+
+    let path = Path::new("mem://data/a.bin");
+    assert!(!fs.file_exists(path).unwrap());
+    let err = fs.open_file(path, FileFlags::READ, None).unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::NotFound);
+
+    let mut handle = fs.open_file(path, FileFlags::WRITE | FileFlags::CREATE, None).unwrap();
+    handle.write(b"hello", 5).unwrap();
+    handle.write(b" world", 6).unwrap();
+    assert_eq!(handle.file_size().unwrap(), 11);
+    let mut buffer = [0u8; 5];
+    handle.read_at(&mut buffer, 5, 6).unwrap();
+    assert_eq!(&buffer, b"world");
+    assert!(handle.read_at(&mut buffer, 5, 10).is_err());
+
+    let version = handle.file_version().unwrap();
+    assert!(version.is_some());
+    handle.truncate(5).unwrap();
+    assert_ne!(handle.file_version().unwrap(), version);
+    assert_eq!(handle.file_size().unwrap(), 5);
+    assert_eq!(handle.fsync().unwrap_err().kind(), ErrorKind::Unsupported);
+    handle.close().unwrap();
+    handle.close().unwrap();
+
+    fs.open_file(Path::new("mem://data/b.bin"), FileFlags::WRITE | FileFlags::CREATE, None).unwrap();
+    let mut names = vec![];
+    assert!(fs.list_files(Path::new("mem://data"), |name| names.push(name)).unwrap());
+    assert_eq!(names, vec!["a.bin", "b.bin"]);
+
+    fs.remove_file(path).unwrap();
+    assert!(!fs.file_exists(path).unwrap());
+}
+
+#[test]
+fn plugin_with_another_abi_version_is_rejected_before_its_entry_is_called() {
+    // the entry of this build aborts the process if it is called
+    let library_path = build_plugin("wrong_abi_plugin", &["wrong_abi"]);
+    let err = PluginFileSystem::load(&library_path).unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::InvalidData);
+    assert!(err.to_string().contains("ABI version 2, expected 1"), "{}", err);
+}
+
+#[test]
+fn missing_plugin_library_is_not_found() {
+    let err = PluginFileSystem::load(&Path::new("/nonexistent").join(format!("{}plugin{}", DLL_PREFIX, DLL_SUFFIX))).unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::NotFound);
+}
@@ -0,0 +1,192 @@
+//! A file system plugin keeping its files in memory, built by tests/plugin.rs as a
+//! shared library to exercise the loader. Built with `--cfg wrong_abi` it reports an
+//! ABI version the loader does not understand, and aborts if its entry is called anyway.
+
+use std::collections::BTreeMap;
+use std::ffi::{c_char, c_int, c_void, CStr};
+use std::sync::Mutex;
+
+const ENOENT: c_int = 2;
+const EIO: c_int = 5;
+const EINVAL: c_int = 22;
+const CREATE: u16 = 1 << 3;
+
+type Function = Option<unsafe extern "C" fn()>;
+type ListFilesCallback = unsafe extern "C" fn(user_data: *mut c_void, name: *const c_char);
+
+/// Mirrors `FileSystemPlugin`, leaving the functions this plugin does not implement null
+#[repr(C)]
+pub struct FileSystemPlugin {
+    context: *mut c_void,
+    destroy: Option<unsafe extern "C" fn(*mut c_void)>,
+    open_file: Option<unsafe extern "C" fn(*mut c_void, *const c_char, u16, c_int, *mut *mut c_void) -> c_int>,
+    close_file: Option<unsafe extern "C" fn(*mut c_void, *mut c_void) -> c_int>,
+    read_at: Option<unsafe extern "C" fn(*mut c_void, *mut c_void, *mut u8, u64, u64) -> c_int>,
+    write_at: Option<unsafe extern "C" fn(*mut c_void, *mut c_void, *const u8, u64, u64) -> c_int>,
+    file_size: Option<unsafe extern "C" fn(*mut c_void, *mut c_void, *mut u64) -> c_int>,
+    fsync: Function,
+    truncate: Option<unsafe extern "C" fn(*mut c_void, *mut c_void, u64) -> c_int>,
+    allocate: Function,
+    punch_hole: Function,
+    directory_exists: Function,
+    file_exists: Option<unsafe extern "C" fn(*mut c_void, *const c_char, *mut bool) -> c_int>,
+    create_directory: Function,
+    remove_directory: Function,
+    remove_file: Option<unsafe extern "C" fn(*mut c_void, *const c_char) -> c_int>,
+    list_files: Option<unsafe extern "C" fn(*mut c_void, *const c_char, ListFilesCallback, *mut c_void, *mut bool) -> c_int>,
+    sync_directory: Function,
+    move_file: Function,
+    last_modified: Option<unsafe extern "C" fn(*mut c_void, *mut c_void, *mut i64) -> c_int>,
+}
+
+#[derive(Default)]
+struct File {
+    data: Vec<u8>,
+    /// Nanoseconds since the Unix epoch
+    modified: i64,
+}
+
+type Files = Mutex<BTreeMap<String, File>>;
+
+fn now() -> i64 {
+    std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).unwrap().as_nanos() as i64
+}
+
+/// A handle is the boxed name of its file
+type Handle = String;
+
+unsafe fn files<'a>(context: *mut c_void) -> &'a Files {
+    unsafe { &*(context as *const Files) }
+}
+
+unsafe fn name(path: *const c_char) -> String {
+    unsafe { CStr::from_ptr(path) }.to_string_lossy().into_owned()
+}
+
+unsafe fn handle<'a>(handle: *mut c_void) -> &'a Handle {
+    unsafe { &*(handle as *const Handle) }
+}
+
+unsafe extern "C" fn destroy(context: *mut c_void) {
+    drop(unsafe { Box::from_raw(context as *mut Files) });
+}
+
+unsafe extern "C" fn open_file(context: *mut c_void, path: *const c_char, flags: u16, _lock: c_int, out: *mut *mut c_void) -> c_int {
+    let name = unsafe { name(path) };
+    let mut files = unsafe { files(context) }.lock().unwrap();
+    if !files.contains_key(&name) {
+        if flags & CREATE == 0 {
+            return ENOENT;
+        }
+        files.insert(name.clone(), File { data: Vec::new(), modified: now() });
+    }
+    unsafe { *out = Box::into_raw(Box::new(name)) as *mut c_void };
+    0
+}
+
+unsafe extern "C" fn close_file(_context: *mut c_void, handle: *mut c_void) -> c_int {
+    drop(unsafe { Box::from_raw(handle as *mut Handle) });
+    0
+}
+
+unsafe extern "C" fn read_at(context: *mut c_void, file: *mut c_void, buffer: *mut u8, nr_bytes: u64, location: u64) -> c_int {
+    let files = unsafe { files(context) }.lock().unwrap();
+    let Some(File { data, .. }) = files.get(unsafe { handle(file) }) else { return ENOENT };
+    let (start, len) = (location as usize, nr_bytes as usize);
+    if start.checked_add(len).is_none_or(|end| end > data.len()) {
+        return EIO;
+    }
+    unsafe { std::ptr::copy_nonoverlapping(data[start..].as_ptr(), buffer, len) };
+    0
+}
+
+unsafe extern "C" fn write_at(context: *mut c_void, file: *mut c_void, buffer: *const u8, nr_bytes: u64, location: u64) -> c_int {
+    let mut files = unsafe { files(context) }.lock().unwrap();
+    let Some(File { data, modified }) = files.get_mut(unsafe { handle(file) }) else { return ENOENT };
+    let (start, len) = (location as usize, nr_bytes as usize);
+    let Some(end) = start.checked_add(len) else { return EINVAL };
+    if end > data.len() {
+        data.resize(end, 0);
+    }
+    *modified = now();
+    data[start..end].copy_from_slice(unsafe { std::slice::from_raw_parts(buffer, len) });
+    0
+}
+
+unsafe extern "C" fn file_size(context: *mut c_void, file: *mut c_void, size: *mut u64) -> c_int {
+    let files = unsafe { files(context) }.lock().unwrap();
+    let Some(File { data, .. }) = files.get(unsafe { handle(file) }) else { return ENOENT };
+    unsafe { *size = data.len() as u64 };
+    0
+}
+
+unsafe extern "C" fn truncate(context: *mut c_void, file: *mut c_void, new_size: u64) -> c_int {
+    let mut files = unsafe { files(context) }.lock().unwrap();
+    let Some(File { data, modified }) = files.get_mut(unsafe { handle(file) }) else { return ENOENT };
+    data.resize(new_size as usize, 0);
+    *modified = now();
+    0
+}
+
+unsafe extern "C" fn last_modified(context: *mut c_void, file: *mut c_void, modified: *mut i64) -> c_int {
+    let files = unsafe { files(context) }.lock().unwrap();
+    let Some(file) = files.get(unsafe { handle(file) }) else { return ENOENT };
+    unsafe { *modified = file.modified };
+    0
+}
+
+unsafe extern "C" fn file_exists(context: *mut c_void, path: *const c_char, exists: *mut bool) -> c_int {
+    let files = unsafe { files(context) }.lock().unwrap();
+    unsafe { *exists = files.contains_key(&name(path)) };
+    0
+}
+
+unsafe extern "C" fn remove_file(context: *mut c_void, path: *const c_char) -> c_int {
+    let mut files = unsafe { files(context) }.lock().unwrap();
+    match files.remove(&unsafe { name(path) }) {
+        Some(_) => 0,
+        None => ENOENT,
+    }
+}
+
+unsafe extern "C" fn list_files(context: *mut c_void, directory: *const c_char, callback: ListFilesCallback, user_data: *mut c_void, found: *mut bool) -> c_int {
+    let prefix = format!("{}/", unsafe { name(directory) });
+    let files = unsafe { files(context) }.lock().unwrap();
+    let mut any = false;
+    for name in files.keys().filter_map(|name| name.strip_prefix(&prefix)) {
+        let name = std::ffi::CString::new(name).unwrap();
+        unsafe { callback(user_data, name.as_ptr()) };
+        any = true;
+    }
+    unsafe { *found = any };
+    0
+}
+
+#[unsafe(no_mangle)]
+pub extern "C" fn carapacedb_file_system_plugin_abi_version() -> u32 {
+    if cfg!(wrong_abi) { 2 } else { 1 }
+}
+
+/// # Safety
+///
+/// `plugin` must point to a zeroed `FileSystemPlugin`
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn carapacedb_file_system_plugin(plugin: *mut FileSystemPlugin) -> c_int {
+    if cfg!(wrong_abi) {
+        std::process::abort();
+    }
+    let plugin = unsafe { &mut *plugin };
+    plugin.context = Box::into_raw(Box::new(Files::default())) as *mut c_void;
+    plugin.destroy = Some(destroy);
+    plugin.open_file = Some(open_file);
+    plugin.close_file = Some(close_file);
+    plugin.read_at = Some(read_at);
+    plugin.write_at = Some(write_at);
+    plugin.file_size = Some(file_size);
+    plugin.truncate = Some(truncate);
+    plugin.file_exists = Some(file_exists);
+    plugin.remove_file = Some(remove_file);
+    plugin.list_files = Some(list_files);
+    plugin.last_modified = Some(last_modified);
+    0
+}
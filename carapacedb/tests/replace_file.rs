@@ -0,0 +1,84 @@
+mod common;
+
+use std::io::{Error, ErrorKind};
+use std::path::Path;
+
+use carapacedb::common::file_system::adapter_fs::DynFileSystemAdapter;
+use carapacedb::common::file_system::dynamic_fs::DynFileSystem;
+use carapacedb::common::file_system::memory_fs::MemoryFileSystem;
+use carapacedb::common::file_system::static_fs::{LocalFileSystem, SFileSystem};
+use carapacedb::common::file_system::{FileFlags, UnifiedFileSystem};
+
+use common::{local_file_system, test_directory};
+
+fn read(fs: &UnifiedFileSystem, path: &Path) -> Vec<u8> {
+    let handle = fs.open_file(path, FileFlags::READ, None).unwrap();
+    let size = handle.file_size().unwrap();
+    let mut buffer = vec![0u8; size as usize];
+    handle.read_at(&mut buffer, size as i64, 0).unwrap();
+    buffer
+}
+
+#[test]
+fn replaced_files_hold_the_new_contents() {
+    let directory = test_directory("replace_file", "success");
+    let fs = local_file_system();
+    let path = directory.join("db");
+    std::fs::write(&path, b"old contents").unwrap();
+
+    fs.replace_file(&path, |handle| handle.write(b"new", 3)).unwrap();
+    assert_eq!(read(&fs, &path), b"new");
+    assert!(!directory.join("db.tmp").exists());
+
+    // files that do not exist yet are created
+    let created = directory.join("created");
+    LocalFileSystem.replace_file(&created, |handle| LocalFileSystem.write_at(handle, b"abc", 3, 0)).unwrap();
+    assert_eq!(std::fs::read(&created).unwrap(), b"abc");
+
+    let memory = DynFileSystemAdapter::new(MemoryFileSystem::new());
+    memory.replace_file(Path::new("x"), &mut |handle| memory.write_at(handle, b"abc", 3, 0)).unwrap();
+    assert!(memory.file_exists(Path::new("x")).unwrap());
+    assert!(!memory.file_exists(Path::new("x.tmp")).unwrap());
+    std::fs::remove_dir_all(&directory).unwrap();
+}
+
+#[test]
+fn failed_writes_leave_the_target_untouched() {
+    let directory = test_directory("replace_file", "failed");
+    let fs = local_file_system();
+    let path = directory.join("db");
+    std::fs::write(&path, b"old contents").unwrap();
+
+    let error = fs.replace_file(&path, |handle| {
+        handle.write(b"partial", 7)?;
+        Err(Error::other("write failed"))
+    }).unwrap_err();
+    assert_eq!(error.to_string(), "write failed");
+    assert_eq!(read(&fs, &path), b"old contents");
+    assert!(!directory.join("db.tmp").exists());
+
+    let missing = directory.join("missing");
+    assert!(fs.replace_file(&missing, |_| Err(Error::other("write failed"))).is_err());
+    assert!(!missing.exists());
+    assert!(!directory.join("missing.tmp").exists());
+    std::fs::remove_dir_all(&directory).unwrap();
+}
+
+#[test]
+fn stale_temporary_files_are_replaced() {
+    let directory = test_directory("replace_file", "stale");
+    let fs = local_file_system();
+    let path = directory.join("db");
+    std::fs::write(&path, b"old contents").unwrap();
+    // left behind by an attempt that crashed before renaming it
+    std::fs::write(directory.join("db.tmp"), b"stale contents that are longer").unwrap();
+
+    fs.replace_file(&path, |handle| handle.write(b"new", 3)).unwrap();
+    assert_eq!(read(&fs, &path), b"new");
+    assert!(!directory.join("db.tmp").exists());
+
+    // the temporary file is created next to the target, so its directory must exist
+    let error = fs.replace_file(&directory.join("none").join("db"), |_| Ok(())).unwrap_err();
+    assert_eq!(error.kind(), ErrorKind::NotFound);
+    std::fs::remove_dir_all(&directory).unwrap();
+}
@@ -0,0 +1,270 @@
+mod common;
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::io::ErrorKind;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use carapacedb::common::file_system::http_fs::HttpConfig;
+use carapacedb::common::file_system::s3_fs::S3Config;
+use carapacedb::common::file_system::{FileFlags, UnifiedFileSystem};
+use carapacedb::common::sha256::{sha256, to_hex};
+use carapacedb::core::database::DBConfig;
+use common::{Request, Response};
+
+#[derive(Default)]
+struct ObjectStore {
+    objects: BTreeMap<String, Vec<u8>>,
+    uploads: BTreeMap<String, BTreeMap<usize, Vec<u8>>>,
+    next_upload: usize,
+    aborted_uploads: usize,
+    /// The number of the next requests to fail, by method
+    failures: BTreeMap<&'static str, usize>,
+    /// The number of the next requests to fail with 503 Service Unavailable, by method
+    unavailable: BTreeMap<&'static str, usize>,
+    requests: BTreeMap<String, usize>,
+}
+
+impl ObjectStore {
+    fn list(&self, bucket: &str, request: &Request) -> Response {
+        let prefix = format!("{}/{}", bucket, request.query.get("prefix").map_or("", String::as_str));
+        let delimited = request.query.contains_key("delimiter");
+        let max_keys: usize = request.query.get("max-keys").map_or(1000, |max| max.parse().unwrap());
+        let mut xml = String::from("<?xml version=\"1.0\"?><ListBucketResult><IsTruncated>false</IsTruncated>");
+        let mut prefixes = BTreeSet::new();
+        let mut count = 0;
+        for path in self.objects.keys().filter(|path| path.starts_with(&prefix)) {
+            if count >= max_keys {
+                break;
+            }
+            let key = &path[bucket.len() + 1..];
+            let rest = &path[prefix.len()..];
+            if delimited && rest.contains('/') {
+                let common_prefix = format!("{}{}/", &key[..key.len() - rest.len()], rest.split('/').next().unwrap());
+                if prefixes.insert(common_prefix.clone()) {
+                    xml.push_str(&format!("<CommonPrefixes><Prefix>{}</Prefix></CommonPrefixes>", common_prefix));
+                    count += 1;
+                }
+            } else {
+                xml.push_str(&format!("<Contents><Key>{}</Key></Contents>", key.replace('&', "&amp;")));
+                count += 1;
+            }
+        }
+        xml.push_str("</ListBucketResult>");
+        Response::with_body(200, xml)
+    }
+
+    fn handle(&mut self, request: Request) -> Response {
+        assert!(request.headers["authorization"].starts_with("AWS4-HMAC-SHA256 Credential=AK/"));
+        assert_eq!(request.headers["x-amz-content-sha256"], to_hex(&sha256(&request.body)));
+        let method = request.method.as_str();
+        *self.requests.entry(method.to_string()).or_default() += 1;
+        if let Some(failures) = self.failures.get_mut(method).filter(|failures| **failures > 0) {
+            *failures -= 1;
+            return Response::new(400);
+        }
+        if let Some(failures) = self.unavailable.get_mut(method).filter(|failures| **failures > 0) {
+            *failures -= 1;
+            return Response::new(503);
+        }
+
+        let path = request.path[1..].to_string();
+        let bucket = path.split('/').next().unwrap().to_string();
+        let query = &request.query;
+        match method {
+            "HEAD" => match self.objects.get(&path) {
+                Some(object) => Response::new(200).header("Content-Length", object.len()),
+                None => Response::new(404),
+            },
+            "GET" if query.contains_key("list-type") => self.list(&bucket, &request),
+            "GET" => match self.objects.get(&path) {
+                Some(object) => {
+                    let range = request.headers["range"].strip_prefix("bytes=").unwrap();
+                    let (start, end) = range.split_once('-').unwrap();
+                    let (start, end): (usize, usize) = (start.parse().unwrap(), end.parse().unwrap());
+                    Response::with_body(206, &object[start..=end])
+                }
+                None => Response::new(404),
+            },
+            "PUT" if query.contains_key("partNumber") => {
+                let part_number: usize = query["partNumber"].parse().unwrap();
+                self.uploads.get_mut(&query["uploadId"]).unwrap().insert(part_number, request.body);
+                Response::new(200).header("ETag", format!("\"etag{}\"", part_number))
+            }
+            "PUT" => {
+                let data = match request.headers.get("x-amz-copy-source") {
+                    Some(source) => self.objects[&common::percent_decode(&source[1..])].clone(),
+                    None => request.body,
+                };
+                self.objects.insert(path, data);
+                Response::new(200)
+            }
+            "POST" if query.contains_key("uploads") => {
+                let upload_id = format!("upload&{}", self.next_upload);
+                self.next_upload += 1;
+                self.uploads.insert(upload_id.clone(), BTreeMap::new());
+                let xml = format!(
+                    "<InitiateMultipartUploadResult><UploadId>{}</UploadId></InitiateMultipartUploadResult>",
+                    upload_id.replace('&', "&amp;")
+                );
+                Response::with_body(200, xml)
+            }
+            "POST" => {
+                let parts = self.uploads.remove(&query["uploadId"]).unwrap();
+                let complete = String::from_utf8(request.body).unwrap();
+                assert_eq!(complete.matches("<Part>").count(), parts.len());
+                self.objects.insert(path, parts.into_values().flatten().collect());
+                Response::with_body(200, "<CompleteMultipartUploadResult/>")
+            }
+            "DELETE" if query.contains_key("uploadId") => {
+                self.uploads.remove(&query["uploadId"]).unwrap();
+                self.aborted_uploads += 1;
+                Response::new(204)
+            }
+            "DELETE" => {
+                self.objects.remove(&path);
+                Response::new(204)
+            }
+            _ => Response::new(400),
+        }
+    }
+}
+
+fn object_store() -> (UnifiedFileSystem, Arc<Mutex<ObjectStore>>) {
+    let store = Arc::new(Mutex::new(ObjectStore::default()));
+    let server_store = store.clone();
+    let endpoint = common::serve(move |request| server_store.lock().unwrap().handle(request));
+
+    let s3 = S3Config {
+        endpoint,
+        access_key_id: "AK".to_string(),
+        secret_access_key: "SK".to_string(),
+        part_size: 1000,
+        http: HttpConfig { block_size: 512, retry_backoff: Duration::from_millis(1), ..Default::default() },
+        ..Default::default()
+    };
+    let mut config = DBConfig { s3: Some(s3), ..Default::default() };
+    (config.create_file_system().unwrap(), store)
+}
+
+fn test_data(len: u32) -> Vec<u8> {
+    (0..len).map(|i| (i % 251) as u8).collect()
+}
+
+#[test]
+fn s3_write_read_list_and_remove() {
+    let (fs, _) = object_store();
+    let data = test_data(2500);
+    let path = Path::new("s3://bucket/dir/sub/big.bin");
+    let mut handle = fs.open_file(path, FileFlags::WRITE | FileFlags::CREATE, None).unwrap();
+    handle.write(&data[..1200], 1200).unwrap();
+    handle.write_at(&data[1200..], 1300, 1200).unwrap();
+    assert_eq!(handle.write_at(&data, 1, 0).unwrap_err().kind(), ErrorKind::Unsupported);
+    handle.close().unwrap();
+    drop(handle);
+
+    let small = Path::new("s3://bucket/dir/a b&c.txt");
+    let mut handle = fs.open_file(small, FileFlags::WRITE | FileFlags::CREATE, None).unwrap();
+    handle.write(b"hello", 5).unwrap();
+    handle.close().unwrap();
+
+    assert!(fs.file_exists(path).unwrap());
+    assert!(fs.file_exists(small).unwrap());
+    assert!(!fs.file_exists(Path::new("s3://bucket/dir")).unwrap());
+    assert!(fs.directory_exists(Path::new("s3://bucket/dir")).unwrap());
+    assert!(fs.directory_exists(Path::new("s3://bucket/dir/sub/")).unwrap());
+    assert!(!fs.directory_exists(Path::new("s3://bucket/di")).unwrap());
+
+    let handle = fs.open_file(path, FileFlags::READ, None).unwrap();
+    assert_eq!(handle.file_size().unwrap(), 2500);
+    let mut buffer = vec![0; 2000];
+    handle.read_at(&mut buffer, 2000, 300).unwrap();
+    assert_eq!(buffer, data[300..2300]);
+
+    let mut names = Vec::new();
+    assert!(fs.list_files(Path::new("s3://bucket/dir"), |name| names.push(name)).unwrap());
+    names.sort();
+    assert_eq!(names, ["a b&c.txt", "sub"]);
+    assert_eq!(fs.glob("s3://bucket/dir/*/*.bin").unwrap(), [path]);
+
+    let moved = Path::new("s3://bucket/other/x.txt");
+    fs.move_file(small, moved).unwrap();
+    assert!(!fs.file_exists(small).unwrap());
+    let handle = fs.open_file(moved, FileFlags::READ, None).unwrap();
+    let mut buffer = [0; 5];
+    handle.read(&mut buffer, 5).unwrap();
+    assert_eq!(&buffer, b"hello");
+
+    fs.remove_directory(Path::new("s3://bucket/dir")).unwrap();
+    assert!(!fs.directory_exists(Path::new("s3://bucket/dir")).unwrap());
+    assert_eq!(fs.remove_file(path).unwrap_err().kind(), ErrorKind::NotFound);
+}
+
+#[test]
+fn s3_failed_part_aborts_the_upload() {
+    let (fs, store) = object_store();
+    let data = test_data(2500);
+    let path = Path::new("s3://bucket/failed.bin");
+    let mut handle = fs.open_file(path, FileFlags::WRITE | FileFlags::CREATE, None).unwrap();
+    handle.write(&data[..1200], 1200).unwrap();
+
+    store.lock().unwrap().failures.insert("PUT", 1);
+    assert!(handle.write(&data[1200..], 1300).is_err());
+    assert!(handle.write(&data[..1], 1).is_err());
+    assert!(handle.fsync().is_err());
+    assert!(handle.close().is_err());
+    drop(handle);
+
+    let store = store.lock().unwrap();
+    assert_eq!(store.aborted_uploads, 1);
+    assert!(store.uploads.is_empty());
+    assert!(store.objects.is_empty());
+}
+
+#[test]
+fn s3_failed_put_is_retried_by_the_next_sync() {
+    let (fs, store) = object_store();
+    let path = Path::new("s3://bucket/small.txt");
+    let mut handle = fs.open_file(path, FileFlags::WRITE | FileFlags::CREATE, None).unwrap();
+    handle.write(b"hello", 5).unwrap();
+
+    store.lock().unwrap().failures.insert("PUT", 1);
+    assert!(handle.fsync().is_err());
+    assert!(!fs.file_exists(path).unwrap());
+    handle.fsync().unwrap();
+    handle.close().unwrap();
+    assert_eq!(store.lock().unwrap().objects["bucket/small.txt"], b"hello");
+}
+
+#[test]
+fn s3_dropped_handle_publishes_nothing() {
+    let (fs, store) = object_store();
+    let data = test_data(2500);
+    let handle = fs.open_file(Path::new("s3://bucket/multipart.bin"), FileFlags::WRITE | FileFlags::CREATE, None).unwrap();
+    handle.write(&data, 2500).unwrap();
+    drop(handle);
+    let handle = fs.open_file(Path::new("s3://bucket/single.bin"), FileFlags::WRITE | FileFlags::CREATE, None).unwrap();
+    handle.write(&data[..10], 10).unwrap();
+    drop(handle);
+
+    let store = store.lock().unwrap();
+    assert_eq!(store.aborted_uploads, 1);
+    assert!(store.uploads.is_empty());
+    assert!(store.objects.is_empty());
+}
+
+#[test]
+fn s3_dropped_handle_aborts_once() {
+    let (fs, store) = object_store();
+    let data = test_data(2500);
+    let handle = fs.open_file(Path::new("s3://bucket/multipart.bin"), FileFlags::WRITE | FileFlags::CREATE, None).unwrap();
+    handle.write(&data, 2500).unwrap();
+    store.lock().unwrap().unavailable.insert("DELETE", 1);
+    drop(handle);
+
+    let store = store.lock().unwrap();
+    assert_eq!(store.requests["DELETE"], 1);
+    assert_eq!(store.aborted_uploads, 0);
+    assert!(store.objects.is_empty());
+}
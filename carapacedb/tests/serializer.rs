@@ -0,0 +1,180 @@
+use std::io::{ErrorKind, Result};
+
+use carapacedb::common::buffered_serializer::{BufferedDeserializer, BufferedSerializer};
+use carapacedb::common::json_serializer::{from_json, to_json, JsonValue};
+use carapacedb::common::serializer::{Deserializable, Deserializer, ObjectReader, Serializable, Serializer};
+use carapacedb::parser::parsed_data::alter_table_info::RenameColumnInfo;
+
+#[derive(Serializable, Deserializable, Debug, PartialEq, Default)]
+struct Record {
+    #[serialize(id = 1)]
+    id: u128,
+    #[serialize(id = 2)]
+    ratio: f64,
+    #[serialize(id = 3)]
+    flag: bool,
+    #[serialize(id = 4)]
+    name: String,
+    #[serialize(id = 5)]
+    lists: Vec<Vec<i8>>,
+    #[serialize(id = 6)]
+    comment: Option<String>,
+    #[serialize(id = 7, default)]
+    count: u32,
+    #[serialize(id = 8)]
+    nested: Option<Option<u8>>,
+    #[serialize(id = 9)]
+    raw: Raw,
+}
+
+/// A positional record with raw data
+#[derive(Debug, PartialEq, Default)]
+struct Raw(u32, String, [u8; 3]);
+
+impl Serializable for Raw {
+    fn serialize<S: Serializer>(&self, serializer: &mut S) -> Result<()> {
+        serializer.write(self.0)?;
+        serializer.write_string(&self.1)?;
+        serializer.write_data(&self.2)
+    }
+}
+
+impl Deserializable for Raw {
+    fn deserialize<D: Deserializer>(deserializer: &mut D) -> Result<Self> {
+        let number = deserializer.read()?;
+        let text = deserializer.read_string()?;
+        let mut data = [0u8; 3];
+        deserializer.read_data(&mut data)?;
+        Ok(Raw(number, text, data))
+    }
+}
+
+/// An older version of an object that does not know its later fields
+struct Version1 {
+    name: String,
+}
+
+impl Deserializable for Version1 {
+    fn deserialize<D: Deserializer>(deserializer: &mut D) -> Result<Self> {
+        let mut object = deserializer.read_object()?;
+        Ok(Version1 { name: object.read_required_field(1, "name")? })
+    }
+}
+
+struct Version2 {
+    name: String,
+    values: Vec<u64>,
+}
+
+impl Serializable for Version2 {
+    fn serialize<S: Serializer>(&self, serializer: &mut S) -> Result<()> {
+        serializer.begin_object()?;
+        serializer.write_field(1, "name", &self.name)?;
+        serializer.write_field_with_default(2, "values", &self.values)?;
+        serializer.end_object()
+    }
+}
+
+fn to_binary<T: Serializable>(value: &T) -> Vec<u8> {
+    let mut serializer = BufferedSerializer::new();
+    value.serialize(&mut serializer).unwrap();
+    serializer.into_data()
+}
+
+fn from_binary<T: Deserializable>(data: &[u8]) -> Result<T> {
+    let mut deserializer = BufferedDeserializer::new(data);
+    T::deserialize(&mut deserializer)
+}
+
+fn record() -> Record {
+    Record {
+        id: u128::MAX,
+        ratio: 0.1 + 0.2,
+        flag: true,
+        name: "q\"\n\u{1}é😀".to_string(),
+        lists: vec![vec![], vec![-1, 2]],
+        comment: None,
+        count: 0,
+        nested: Some(None),
+        raw: Raw(7, "x".to_string(), [0, 255, 16]),
+    }
+}
+
+#[test]
+fn binary_round_trips_through_json() {
+    let binary = to_binary(&record());
+    let decoded: Record = from_binary(&binary).unwrap();
+    assert_eq!(decoded, record());
+
+    let json = to_json(&decoded).unwrap();
+    let back: Record = from_json(&json).unwrap();
+    assert_eq!(back, record());
+    assert_eq!(to_binary(&back), binary);
+}
+
+#[test]
+fn json_matches_the_golden_form() {
+    let expected = r#"{
+  "id": 340282366920938463463374607431768211455,
+  "ratio": 0.30000000000000004,
+  "flag": true,
+  "name": "q\"\n\u0001é😀",
+  "lists": [
+    [],
+    [
+      -1,
+      2
+    ]
+  ],
+  "nested": null,
+  "raw": {
+    "$sequence": [
+      7,
+      "x",
+      "00ff10"
+    ]
+  }
+}"#;
+    assert_eq!(to_json(&record()).unwrap(), expected);
+
+    let info = RenameColumnInfo::new("s", "t", "a", "b");
+    assert_eq!(from_json::<RenameColumnInfo>(&to_json(&info).unwrap()).unwrap(), info);
+}
+
+#[test]
+fn present_optionals_keep_their_nesting() {
+    for value in [None, Some(None), Some(Some(None)), Some(Some(Some(5u8)))] {
+        let json = to_json(&value).unwrap();
+        assert_eq!(from_json::<Option<Option<Option<u8>>>>(&json).unwrap(), value, "{}", json);
+        let binary = to_binary(&value);
+        assert_eq!(to_binary(&from_json::<Option<Option<Option<u8>>>>(&json).unwrap()), binary);
+    }
+    assert_eq!(to_json(&Some(None::<u8>)).unwrap(), "{\n  \"$some\": null\n}");
+}
+
+#[test]
+fn unknown_fields_are_skipped() {
+    let version2 = Version2 { name: "x".to_string(), values: vec![1, 2] };
+    let mut binary = to_binary(&version2);
+    binary.push(42);
+    let mut deserializer = BufferedDeserializer::new(&binary);
+    assert_eq!(Version1::deserialize(&mut deserializer).unwrap().name, "x");
+    assert_eq!(deserializer.read::<u8>().unwrap(), 42);
+
+    let json = to_json(&version2).unwrap();
+    assert_eq!(from_json::<Version1>(&json).unwrap().name, "x");
+}
+
+#[test]
+fn invalid_input_is_rejected() {
+    assert_eq!(from_binary::<Version1>(&[0xff, 0xff, 0x03]).err().unwrap().kind(), ErrorKind::InvalidData);
+    assert_eq!(from_json::<RenameColumnInfo>("{\"base\": 1}").err().unwrap().kind(), ErrorKind::InvalidData);
+    assert!(from_json::<RenameColumnInfo>("{").is_err());
+    assert!(from_json::<bool>("5").is_err());
+    assert!(JsonValue::parse("01").is_err());
+    assert!(JsonValue::parse(&"[".repeat(1000)).is_err());
+    assert_eq!(
+        JsonValue::parse("[\"\\ud83d\\ude00\", 1e5]").unwrap(),
+        JsonValue::Array(vec![JsonValue::String("😀".to_string()), JsonValue::Number("1e5".to_string())])
+    );
+}
@@ -0,0 +1,75 @@
+use std::io::ErrorKind;
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+use carapacedb::common::file_system::adapter_fs::DynFileSystemAdapter;
+use carapacedb::common::file_system::memory_fs::MemoryFileSystem;
+use carapacedb::common::file_system::throttle_fs::{IoRateLimit, RateLimiter};
+use carapacedb::common::file_system::{FileFlags, UnifiedFileSystem};
+use carapacedb::core::database::DBConfig;
+
+const CREATE: FileFlags = FileFlags::WRITE.union(FileFlags::CREATE);
+
+fn throttled_file_system(limit: IoRateLimit) -> std::io::Result<UnifiedFileSystem> {
+    let mut config = DBConfig { background_write_limit: Some(limit), ..Default::default() };
+    config.file_systems.push(("mem".to_string(), DynFileSystemAdapter::boxed(MemoryFileSystem::new())));
+    config.create_file_system()
+}
+
+#[test]
+fn only_background_writes_are_throttled() {
+    let fs = throttled_file_system(IoRateLimit { bytes_per_second: Some(1000), operations_per_second: Some(100) }).unwrap();
+    let buffer = [7u8; 500];
+    let foreground = fs.open_file(Path::new("mem://fg"), CREATE, None).unwrap();
+    let start = Instant::now();
+    for i in 0..10 {
+        foreground.write_at(&buffer, 500, i * 500).unwrap();
+    }
+    assert!(start.elapsed() < Duration::from_millis(100));
+
+    let background = fs.open_file(Path::new("mem://bg"), CREATE | FileFlags::BACKGROUND, None).unwrap();
+    let start = Instant::now();
+    for i in 0..4 {
+        background.write_at(&buffer, 500, i * 500).unwrap();
+    }
+    // 2000 bytes with a bucket of 1000 bytes take about one second
+    let elapsed = start.elapsed();
+    assert!(elapsed >= Duration::from_millis(900) && elapsed < Duration::from_millis(1500), "{:?}", elapsed);
+    let mut read = [0u8; 500];
+    background.read_at(&mut read, 500, 1500).unwrap();
+    assert_eq!(read, buffer);
+
+    fs.write_limiter().unwrap().set_limit(IoRateLimit::default()).unwrap();
+    let start = Instant::now();
+    for i in 0..10 {
+        background.write_at(&buffer, 500, i * 500).unwrap();
+    }
+    assert!(start.elapsed() < Duration::from_millis(100));
+}
+
+#[test]
+fn zero_rates_are_rejected() {
+    let zero_bytes = IoRateLimit { bytes_per_second: Some(0), operations_per_second: None };
+    let zero_operations = IoRateLimit { bytes_per_second: None, operations_per_second: Some(0) };
+    assert_eq!(throttled_file_system(zero_bytes).err().unwrap().kind(), ErrorKind::InvalidInput);
+    assert_eq!(RateLimiter::new(zero_operations).err().unwrap().kind(), ErrorKind::InvalidInput);
+
+    let limiter = RateLimiter::new(IoRateLimit::default()).unwrap();
+    assert_eq!(limiter.set_limit(zero_bytes).unwrap_err().kind(), ErrorKind::InvalidInput);
+    assert_eq!(limiter.limit(), IoRateLimit::default());
+}
+
+#[test]
+fn invalid_writes_take_no_tokens() {
+    let fs = throttled_file_system(IoRateLimit { bytes_per_second: Some(1000), operations_per_second: None }).unwrap();
+    let handle = fs.open_file(Path::new("mem://bg"), CREATE | FileFlags::BACKGROUND, None).unwrap();
+    let buffer = [7u8; 500];
+    assert_eq!(handle.write_at(&buffer, -1, 0).unwrap_err().kind(), ErrorKind::InvalidInput);
+    assert_eq!(handle.write_at(&buffer, 100_000, 0).unwrap_err().kind(), ErrorKind::InvalidInput);
+
+    // the bucket is still full, so a write of its size goes through right away
+    let start = Instant::now();
+    handle.write_at(&buffer, 500, 0).unwrap();
+    handle.write_at(&buffer, 500, 500).unwrap();
+    assert!(start.elapsed() < Duration::from_millis(100));
+}
@@ -0,0 +1,11 @@
+use carapacedb::common::serializer::Serializable;
+
+#[derive(Serializable)]
+struct Info {
+    #[serialize(id = 1)]
+    name: String,
+    #[serialize(id = 1)]
+    value: u32,
+}
+
+fn main() {}
@@ -0,0 +1,9 @@
+use carapacedb::common::serializer::Serializable;
+
+#[derive(Serializable)]
+enum Kind {
+    Table,
+    View,
+}
+
+fn main() {}
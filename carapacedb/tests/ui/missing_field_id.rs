@@ -0,0 +1,8 @@
+use carapacedb::common::serializer::Serializable;
+
+#[derive(Serializable)]
+struct Info {
+    name: String,
+}
+
+fn main() {}
@@ -0,0 +1,10 @@
+use carapacedb::common::serializer::Serializable;
+
+#[derive(Serializable)]
+enum Alter {
+    #[serialize(id = 1)]
+    Rename { #[serialize(id = 1)] name: String },
+    Drop,
+}
+
+fn main() {}
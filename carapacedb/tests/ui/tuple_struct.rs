@@ -0,0 +1,6 @@
+use carapacedb::common::serializer::Serializable;
+
+#[derive(Serializable)]
+struct Pair(#[serialize(id = 1)] u32, #[serialize(id = 2)] u32);
+
+fn main() {}
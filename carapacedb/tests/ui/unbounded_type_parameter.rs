@@ -0,0 +1,15 @@
+use carapacedb::common::buffered_serializer::BufferedSerializer;
+use carapacedb::common::serializer::Serializable;
+
+#[derive(Serializable)]
+struct Wrapper<T> {
+    #[serialize(id = 1)]
+    value: T,
+}
+
+struct Opaque;
+
+fn main() {
+    let mut serializer = BufferedSerializer::new();
+    Wrapper { value: Opaque }.serialize(&mut serializer).unwrap();
+}
@@ -0,0 +1,80 @@
+#![cfg(target_os = "linux")]
+
+use std::path::PathBuf;
+
+use carapacedb::common::file_system::static_fs::SFileSystem;
+use carapacedb::common::file_system::uring_fs::UringFileSystem;
+use carapacedb::common::file_system::{FileFlags, FileLockType};
+
+const BLOCK_SIZE: usize = 4096;
+
+fn test_file(name: &str) -> PathBuf {
+    let path = std::env::temp_dir().join(format!("carapacedb_uring_{}_{}", std::process::id(), name));
+    let _ = std::fs::remove_file(&path);
+    path
+}
+
+/// Writes and reads back `blocks` blocks at block `first` in one batch each
+fn write_and_read_blocks(fs: &UringFileSystem, handle: &<UringFileSystem as SFileSystem>::Handle<'_>, first: usize, blocks: usize) {
+    let written: Vec<Vec<u8>> = (first..first + blocks).map(|i| vec![i as u8; BLOCK_SIZE]).collect();
+    let mut batch = fs.batch();
+    for (i, block) in written.iter().enumerate() {
+        batch.write_at(handle, block, ((first + i) * BLOCK_SIZE) as u64);
+    }
+    assert!(batch.submit_and_wait().into_iter().all(|result| result.is_ok()));
+
+    let mut read: Vec<Vec<u8>> = vec![vec![0; BLOCK_SIZE]; blocks];
+    let mut batch = fs.batch();
+    for (i, block) in read.iter_mut().enumerate() {
+        batch.read_at(handle, block, ((first + i) * BLOCK_SIZE) as u64);
+    }
+    assert!(batch.submit_and_wait().into_iter().all(|result| result.is_ok()));
+    assert_eq!(read, written);
+}
+
+fn batches_transfer_whole_buffers(fs: UringFileSystem, name: &str) {
+    let path = test_file(name);
+    let handle = fs.open_file(&path, FileFlags::WRITE | FileFlags::CREATE, FileLockType::NoLock).unwrap();
+    // more requests than fit in the submission queue at once
+    write_and_read_blocks(&fs, &handle, 0, 300);
+
+    let mut tail = vec![0u8; 10];
+    let mut batch = fs.batch();
+    batch.read_at(&handle, &mut tail, (300 * BLOCK_SIZE - 5) as u64);
+    assert!(batch.submit_and_wait()[0].is_err());
+
+    let mut buffer = [0u8; 4];
+    fs.read_at(&handle, &mut buffer, 4, (7 * BLOCK_SIZE) as u64).unwrap();
+    assert_eq!(buffer, [7; 4]);
+    drop(handle);
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn uring_batches_transfer_whole_buffers() {
+    batches_transfer_whole_buffers(UringFileSystem::new(), "batches");
+}
+
+#[test]
+fn fallback_batches_transfer_whole_buffers() {
+    batches_transfer_whole_buffers(UringFileSystem::fallback(), "fallback");
+}
+
+#[test]
+fn batches_of_several_threads_run_at_the_same_time() {
+    let fs = UringFileSystem::new();
+    let path = test_file("threads");
+    let handle = fs.open_file(&path, FileFlags::WRITE | FileFlags::CREATE, FileLockType::NoLock).unwrap();
+    std::thread::scope(|scope| {
+        for thread in 0..4 {
+            let (fs, handle) = (&fs, &handle);
+            scope.spawn(move || {
+                for round in 0..10 {
+                    write_and_read_blocks(fs, handle, (thread * 10 + round) * 8, 8);
+                }
+            });
+        }
+    });
+    drop(handle);
+    std::fs::remove_file(&path).unwrap();
+}
@@ -0,0 +1,69 @@
+mod common;
+
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+
+use carapacedb::common::file_system::adapter_fs::DynFileSystemAdapter;
+use carapacedb::common::file_system::dynamic_fs::DynFileSystem;
+use carapacedb::common::file_system::memory_fs::MemoryFileSystem;
+use carapacedb::common::file_system::virtual_fs::{VirtualFileSystem, MAX_REGISTRATIONS};
+use carapacedb::common::file_system::FileFlags;
+
+use common::test_directory;
+
+const CREATE: FileFlags = FileFlags::WRITE.union(FileFlags::CREATE);
+
+#[test]
+fn built_in_schemes_are_registered() {
+    let vfs = VirtualFileSystem::new();
+    assert_eq!(vfs.schemes(), vec!["file", "memory", "gzip"]);
+
+    let handle = vfs.open_file(Path::new("memory://x"), CREATE, None).unwrap();
+    vfs.write_at(handle.as_ref(), b"abc", 3, 0).unwrap();
+    assert_eq!(handle.path(), Path::new("memory://x"));
+    assert!(vfs.file_exists(Path::new("memory://x")).unwrap());
+    assert!(!vfs.file_exists(Path::new("x")).unwrap());
+
+    let directory = test_directory("virtual_fs", "gzip");
+    let path = directory.join("data.gz");
+    let gzip_path = PathBuf::from(format!("gzip://{}", path.display()));
+    let mut handle = vfs.open_file(&gzip_path, CREATE, None).unwrap();
+    vfs.write(handle.as_ref(), b"hello", 5).unwrap();
+    handle.close().unwrap();
+    assert_eq!(&std::fs::read(&path).unwrap()[..2], [0x1f, 0x8b]);
+
+    let handle = vfs.open_file(&gzip_path, FileFlags::READ, None).unwrap();
+    let mut buffer = [0u8; 5];
+    vfs.read(handle.as_ref(), &mut buffer, 5).unwrap();
+    assert_eq!(&buffer, b"hello");
+    std::fs::remove_dir_all(&directory).unwrap();
+
+    let error = vfs.open_file(Path::new("s3://bucket/key"), FileFlags::READ, None).err().unwrap();
+    assert_eq!(error.kind(), ErrorKind::Unsupported);
+}
+
+#[test]
+fn replaced_file_systems_outlive_their_handles() {
+    let vfs = VirtualFileSystem::new();
+    let handle = vfs.open_file(Path::new("memory://x"), CREATE, None).unwrap();
+    vfs.write_at(handle.as_ref(), b"abc", 3, 0).unwrap();
+
+    vfs.register("memory", DynFileSystemAdapter::boxed(MemoryFileSystem::new())).unwrap();
+    let mut buffer = [0u8; 3];
+    vfs.read_at(handle.as_ref(), &mut buffer, 3, 0).unwrap();
+    assert_eq!(&buffer, b"abc");
+    assert!(!vfs.file_exists(Path::new("memory://x")).unwrap());
+    assert_eq!(vfs.schemes(), vec!["file", "memory", "gzip"]);
+}
+
+#[test]
+fn registrations_are_bounded() {
+    let vfs = VirtualFileSystem::new();
+    assert_eq!(vfs.register("not a scheme", DynFileSystemAdapter::boxed(MemoryFileSystem::new())).unwrap_err().kind(), ErrorKind::InvalidInput);
+    for _ in vfs.schemes().len()..MAX_REGISTRATIONS {
+        vfs.register("memory", DynFileSystemAdapter::boxed(MemoryFileSystem::new())).unwrap();
+    }
+    let error = vfs.register("memory", DynFileSystemAdapter::boxed(MemoryFileSystem::new())).unwrap_err();
+    assert_eq!(error.kind(), ErrorKind::QuotaExceeded);
+    assert!(vfs.open_file(Path::new("memory://x"), CREATE, None).is_ok());
+}